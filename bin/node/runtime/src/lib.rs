@@ -78,8 +78,8 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	// and set impl_version to equal spec_version. If only runtime
 	// implementation changes and behavior does not, then leave spec_version as
 	// is and increment impl_version.
-	spec_version: 198,
-	impl_version: 198,
+	spec_version: 201,
+	impl_version: 201,
 	apis: RUNTIME_API_VERSIONS,
 };
 
@@ -538,6 +538,14 @@ impl htlc::Trait for Runtime {
 	type Event = Event;
 }
 
+parameter_types! {
+	pub const MpcRequestDeposit: Balance = 1 * DOLLARS;
+	pub const MpcRequestTimeout: BlockNumber = 10 * MINUTES;
+	pub const MpcMaxPayloadLength: usize = 1024;
+	pub const MpcMaxPendingRequests: usize = 64;
+	pub const MpcMaxRequestsPerAccount: u32 = 4;
+}
+
 impl mpc::Trait for Runtime {
 	type Event = Event;
 	type Call = Call;
@@ -546,6 +554,13 @@ impl mpc::Trait for Runtime {
 		Runtime,
 		UncheckedExtrinsic
 	>;
+	type Currency = Balances;
+	type RequestDeposit = MpcRequestDeposit;
+	type RequestTimeout = MpcRequestTimeout;
+	type Slashed = Treasury;
+	type MaxPayloadLength = MpcMaxPayloadLength;
+	type MaxPendingRequests = MpcMaxPendingRequests;
+	type MaxRequestsPerAccount = MpcMaxRequestsPerAccount;
 }
 
 construct_runtime!(
@@ -580,7 +595,7 @@ construct_runtime!(
 		RandomnessCollectiveFlip: pallet_randomness_collective_flip::{Module, Call, Storage},
 		Nicks: pallet_nicks::{Module, Call, Storage, Event<T>},
 		Htlc: htlc::{Module, Call, Storage, Event<T>},
-		Mpc: mpc::{Module, Call, Storage, Event<T>}
+		Mpc: mpc::{Module, Call, Storage, Config, Event<T>}
                 // Lightning: lightning::{Module, Call, Storage, ValidateUnsigned},
 	}
 );
@@ -27,7 +27,7 @@ use substrate_telemetry::TelemetryEndpoints;
 
 use hb_node_runtime::{
 	GenesisConfig,  BalancesConfig,
-	SudoConfig, IndicesConfig, SystemConfig,ContractsConfig, WASM_BINARY
+	SudoConfig, IndicesConfig, SystemConfig,ContractsConfig, KeygenConfig, WASM_BINARY
 };
 //use hb_node_runtime::SessionKeys;
 use hb_node_runtime::constants::currency::DOLLARS;
@@ -37,7 +37,8 @@ use hb_node_runtime::constants::currency::DOLLARS;
 use hb_node_runtime::constants::currency::MILLICENTS;
 use sp_runtime::{ traits::{Verify, IdentifyAccount}};
 pub use hb_node_primitives::{AccountId, Balance, Signature};
-use hb_node_runtime::SessionConfig;
+use hb_node_runtime::{SessionConfig, StakerStatus, StakingConfig};
+use sp_runtime::Perbill;
 
 const STAGING_TELEMETRY_URL: &str = "wss://telemetry.polkadot.io/submit/";
 
@@ -105,6 +106,10 @@ fn staging_testnet_config_genesis() -> GenesisConfig {
 		session:  Some(SessionConfig {
 			keys: Vec::new(),
 		}),
+		staking: Some(StakingConfig::default()),
+		mpc: Some(KeygenConfig {
+			threshold: 1,
+		}),
   /*		badger: Some(BadgerConfig {
 
 		}),*/
@@ -276,6 +281,18 @@ pub fn testnet_genesis(
 				(x.0.clone(), SessionKeys { hbbft: x.1.clone() })
 			}).collect::<Vec<_>>(),
 		}),
+		staking: Some(StakingConfig {
+			current_era: 0,
+			validator_count: initial_authorities.len() as u32,
+			minimum_validator_count: initial_authorities.len() as u32,
+			// validators bond as their own controllers so offences can be slashed
+			stakers: initial_authorities.iter().map(|x| {
+				(x.0.clone(), x.0.clone(), STASH / 2, StakerStatus::Validator)
+			}).collect(),
+			invulnerables: Vec::new(),
+			slash_reward_fraction: Perbill::from_percent(10),
+			.. Default::default()
+		}),
 		contracts: Some(ContractsConfig {
 			current_schedule: contracts::Schedule {
 				enable_println, // this should only be enabled on development chains
@@ -286,5 +303,8 @@ pub fn testnet_genesis(
 		sudo: Some(SudoConfig {
 			key: root_key,
 		}),
+		mpc: Some(KeygenConfig {
+			threshold: 1,
+		}),
 	}
 }
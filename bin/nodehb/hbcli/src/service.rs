@@ -18,7 +18,8 @@
 
 //! Service and ServiceFactory implementation. Specialized wrapper over substrate service.
 
-use badger::{self, badger_import_queue, run_honey_badger, Config as BadgerConfig};
+use badger::{self, badger_import_queue, badger_light_import_queue, run_honey_badger, Config as BadgerConfig};
+use badger::aux_store::GenesisAuthoritySetProvider;
 use badger_primitives::app::Public;
 use badger_primitives::app::Signature;
 use client::{self, LongestChain};
@@ -95,6 +96,9 @@ macro_rules! new_full_start {
 		//let mut import_setup = None;
 		let inherent_data_providers = inherents::InherentDataProviders::new();
 		let mut import_setup =None;
+		let badger_status = badger::rpc::SharedBadgerStatus::default();
+		let rpc_status = badger_status.clone();
+		let deny_unsafe = badger::rpc::DenyUnsafe::from_interfaces(&[$config.rpc_http, $config.rpc_ws]);
 		let builder = sc_service::ServiceBuilder::new_full::<
 			hb_node_primitives::Block, hb_node_runtime::RuntimeApi, hb_node_executor::Executor
 		>($config)?
@@ -116,21 +120,23 @@ macro_rules! new_full_start {
 		let (block_import, import_rx) = badger::block_importer(client.clone(), &*client.clone(), select_chain.unwrap(),).expect("Invalid setup. QWOP.");
 		//let justification_import = block_import.clone();
 		import_setup=Some( (block_import.clone(),import_rx));
+		let authority_set = block_import.authority_set.clone();
         badger_import_queue::<_, _, Public, Signature>(
           Box::new(block_import),
           None,
           None,
           client,
+          authority_set,
           inherent_data_providers.clone(),
         )
         .map_err(Into::into)
 			
 			})?
 			.with_rpc_extensions_key(|client, pool, _backend,_fetcher, _remote_blockchain,ks| -> Result<RpcExtension, _>  {
-				Ok(hb_node_rpc::create(client, pool,ks))
+				Ok(hb_node_rpc::create(client, pool,ks,rpc_status,deny_unsafe))
 			})?;
 
-		(builder,  import_setup,inherent_data_providers)
+		(builder,  import_setup,inherent_data_providers,badger_status)
 	}}
 }
 
@@ -162,7 +168,7 @@ macro_rules! new_full {
 		// never actively participate in any consensus process.
 		let participates_in_consensus = is_authority && !$config.sentry_mode;
 
-		let (builder, import_setup, inherent_data_providers) = new_full_start!($config);
+		let (builder, import_setup, inherent_data_providers, badger_status) = new_full_start!($config);
     let back = builder.backend().clone();
 		
 		// Dht event channel from the network to the authority discovery module. Use bounded channel to ensure
@@ -173,7 +179,9 @@ macro_rules! new_full {
 		//	mpsc::channel::<DhtEvent>(10_000);
       let service = builder
       .with_network_protocol(|_| Ok(crate::service::NodeProtocol::new()))?
-      .with_opt_finality_proof_provider(|_client, _| Ok(None))?
+      .with_finality_proof_provider(|client, backend|
+        Ok(Arc::new(badger::FinalityProofProvider::new(backend, client)) as _)
+      )?
       .build()?;
 
 		//($with_startup_data)(&block_import, &babe_link);
@@ -194,10 +202,13 @@ macro_rules! new_full {
       let bc=BadgerConfig{
 		  name: Some(node_name.to_string()),
           batch_size:20,  
+          batch_policy: Arc::new(badger::FillBlocks),
 	  };
 	  let (b_i,i_rx)=import_setup.expect("Should be initialized");
+	  badger_status.set_executor(Arc::new(service.spawn_task_handle()));
       let badger = run_honey_badger(
         client,
+        back.clone(),
 		t_pool,
 		bc,
         //BadgerConfig::from_json_file_with_name(nconf, &node_name).unwrap(),
@@ -215,6 +226,7 @@ macro_rules! new_full {
 		i_rx,
 		node_key,
 		dev_seed,
+		badger_status,
 	  )?;    
 	  let mpc = mpc::run_mpc_task(service.client(), back, service.network(),  service.spawn_task_handle())?;
 	 service.spawn_essential_task(mpc);
@@ -305,30 +317,35 @@ pub fn new_light<C: Send + Default + 'static>(config: NodeConfiguration<C>)
 		let maintainable_pool = txpool_api::MaintainableTransactionPool::new(pool, maintainer);
 		Ok(maintainable_pool)}
 		)?
-		.with_import_queue(|_config, client, mut _select_chain, _transaction_pool| {
-      #[allow(deprecated)]
-      // let fprb = Box::new(DummyFinalityProofRequestBuilder::default()) as Box<_>;
-      let block_import = client.clone();
-      //let justification_import = block_import.clone();
-
-		//	let finality_proof_import = grandpa_block_import.clone();
-		//	let finality_proof_request_builder =
-	//			finality_proof_import.create_finality_proof_request_builder();
-
-  badger_import_queue::<_, _, Public, Signature>(
-    Box::new(block_import),
-    None,
-    None,
-    client,
-    inherent_data_providers.clone(),
-  )
-  .map_err(Into::into)
+		.with_import_queue_and_fprb(|_config, client, backend, fetcher, _select_chain, _transaction_pool| {
+      let fetch_checker = fetcher
+        .map(|fetcher| fetcher.checker().clone())
+        .ok_or_else(|| "Trying to start light import queue without active fetch checker")?;
+      let authority_set = badger::aux_store::loads_auth_set(&*client, || client.get())?;
+      let light_import = badger::light_block_import(
+        client.clone(),
+        backend,
+        authority_set.clone(),
+        Arc::new(fetch_checker),
+      );
+      let finality_proof_request_builder = light_import.create_finality_proof_request_builder();
+
+      let import_queue = badger_light_import_queue::<_, _, Public, Signature>(
+        Box::new(light_import.clone()),
+        Box::new(light_import),
+        client,
+        authority_set,
+        inherent_data_providers.clone(),
+      )?;
 
+      Ok((import_queue, finality_proof_request_builder))
 		})?
 		.with_network_protocol(|_| Ok(NodeProtocol::new()))?
-    .with_opt_finality_proof_provider(|_client, _| Ok(None))?  //may need to add it
+		.with_finality_proof_provider(|client, backend|
+			Ok(Arc::new(badger::FinalityProofProvider::new(backend, client)) as _)
+		)?
 		.with_rpc_extensions_key(|client, pool, _backend,_fetcher, _remote_blockchain,ks| -> Result<RpcExtension, _>  {
-			Ok(hb_node_rpc::create(client, pool,ks))
+			Ok(hb_node_rpc::create(client, pool,ks,badger::rpc::SharedBadgerStatus::default(),badger::rpc::DenyUnsafe::Yes))
 		})?
 		.build()?;
 
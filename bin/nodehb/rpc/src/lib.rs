@@ -37,7 +37,7 @@ use keystore::KeyStorePtr;
 use sc_api::{AuxStore};//Backend
 use txpool_api::TransactionPool;
 /// Instantiate all RPC extensions.
-pub fn create<C, P, M>(client: Arc<C>, pool: Arc<P>,keystore:KeyStorePtr) -> jsonrpc_core::IoHandler<M> where
+pub fn create<C, P, M>(client: Arc<C>, pool: Arc<P>,keystore:KeyStorePtr,badger_status:badger::rpc::SharedBadgerStatus,deny_unsafe:badger::rpc::DenyUnsafe) -> jsonrpc_core::IoHandler<M> where
 C: ProvideRuntimeApi,
 	C: client::blockchain::HeaderBackend<Block>,
 	C: Send + Sync + 'static,
@@ -46,13 +46,13 @@ C: ProvideRuntimeApi,
 //	C::Api: srml_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance, UncheckedExtrinsic>,
 	//F: client::light::fetcher::Fetcher<Block> + 'static,
 	P: TransactionPool + 'static,
-	M: jsonrpc_core::Metadata + Default,
+	M: jsonrpc_core::Metadata + jsonrpc_pubsub::PubSubMetadata + Default,
 	C: AuxStore+badger::aux_store::GenesisAuthoritySetProvider<Block>,
 {
 	use substrate_frame_rpc_system::{FullSystem,  SystemApi};//LightSystem
 	use srml_contracts_rpc::{Contracts, ContractsApi};
 	//use srml_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApi};
-	use badger::rpc::{BadgerRpcApi,BadgerRpcCaller};
+	use badger::rpc::{BadgerRpcApi,BadgerRpcCaller,BadgerStatusApi,BadgerStatusRpc};
 
 	let mut io = jsonrpc_core::IoHandler::default();
 	io.extend_with(
@@ -61,11 +61,14 @@ C: ProvideRuntimeApi,
 	io.extend_with(
 		ContractsApi::to_delegate(Contracts::new(client.clone()))
 	);
-	let callr: BadgerRpcCaller<C,Block> = BadgerRpcCaller::new(client.clone(),keystore.clone());
+	let callr: BadgerRpcCaller<C,Block> = BadgerRpcCaller::new(client.clone(),keystore.clone(),badger_status.clone(),deny_unsafe);
 	let del=BadgerRpcApi::<AccountId>::to_delegate(callr);
 	io.extend_with(
 		del
 		
 	);
+	io.extend_with(
+		BadgerStatusApi::to_delegate(BadgerStatusRpc::<M>::new(badger_status))
+	);
 	io
 }
@@ -26,7 +26,7 @@ include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 use rstd::prelude::*;
 
-//use badger_primitives::AuthorityId as BadgerId;
+use badger_primitives::AuthorityId as BadgerId;
 use badger_primitives::AuthorityList as BadgerList;
 pub use contracts;
 pub use contracts::Gas;
@@ -40,7 +40,8 @@ use inherents::{CheckInherentsResult,InherentData};
 use elections::VoteIndex;
 use finality_tracker::{DEFAULT_REPORT_LATENCY, DEFAULT_WINDOW_SIZE};
 use primitives::OpaqueMetadata;
-use sp_runtime::traits::{BlakeTwo256, Block as BlockT, NumberFor, StaticLookup,OpaqueKeys};
+use sp_runtime::traits::{BlakeTwo256, Block as BlockT, ConvertInto, NumberFor, StaticLookup,OpaqueKeys};
+use sp_runtime::curve::PiecewiseLinear;
 use sp_runtime::transaction_validity::TransactionValidity;
 use support::{ weights::Weight};
 use sp_runtime::ApplyExtrinsicResult;
@@ -51,6 +52,7 @@ use version::RuntimeVersion;
 use sp_api::impl_runtime_apis;
 
 pub use balances::Call as BalancesCall;
+pub use staking::StakerStatus;
 
 
 pub use sp_runtime::{Perbill, Permill};
@@ -63,7 +65,7 @@ pub use timestamp::Call as TimestampCall;
 
 /// Implementations of some helper traits passed into runtime modules as associated types.
 pub mod impls;
-use impls::{  LinearWeightToFee, TargetedFeeAdjustment};//Author,CurrencyToVoteHandler
+use impls::{ CurrencyToVoteHandler, LinearWeightToFee, TargetedFeeAdjustment};//Author
 
 /// Constant values used within the runtime.
 pub mod constants;
@@ -78,8 +80,8 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	// and set impl_version to equal spec_version. If only runtime
 	// implementation changes and behavior does not, then leave spec_version as
 	// is and increment impl_version.
-	spec_version: 140,
-	impl_version: 140,
+	spec_version: 148,
+	impl_version: 148,
 	apis: RUNTIME_API_VERSIONS,
 };
 
@@ -258,16 +260,86 @@ impl sudo::Trait for Runtime {
 	type Proposal = Call;
 }
 
+use system::offchain::TransactionSubmitter;
+
+parameter_types! {
+	pub const SessionPeriod: BlockNumber = EPOCH_DURATION_IN_BLOCKS;
+	pub const MaxBindingLifetime: BlockNumber = 7 * DAYS;
+}
+
 impl pallet_badger::Trait for Runtime {
 	type Event = Event;
+	type Call = Call;
+	type SubmitTransaction = TransactionSubmitter<BadgerId, Runtime, UncheckedExtrinsic>;
+	type ReportEquivocation = Offences;
+	// the committee is elected by staking at the start of every era; every session ending goes
+	// through staking so eras advance, and its exposures are kept for offence reports
+	type ElectedValidators = session::historical::NoteHistoricalRoot<Self, Staking>;
+	type SessionPeriod = SessionPeriod;
+	type MaxBindingLifetime = MaxBindingLifetime;
+}
+
+impl offences::Trait for Runtime {
+	type Event = Event;
+	type IdentificationTuple = session::historical::IdentificationTuple<Self>;
+	type OnOffenceHandler = Staking;
+}
+
+staking_reward_curve::build! {
+	const REWARD_CURVE: PiecewiseLinear<'static> = curve!(
+		min_inflation: 0_025_000,
+		max_inflation: 0_100_000,
+		ideal_stake: 0_500_000,
+		falloff: 0_050_000,
+		max_piece_count: 40,
+		test_precision: 0_005_000,
+	);
+}
+
+parameter_types! {
+	pub const SessionsPerEra: sp_staking::SessionIndex = 6;
+	pub const BondingDuration: staking::EraIndex = 24 * 28;
+	// Badger sessions end on validator set changes rather than on time, so slash right away
+	pub const SlashDeferDuration: staking::EraIndex = 0;
+	pub const RewardCurve: &'static PiecewiseLinear<'static> = &REWARD_CURVE;
+}
+
+impl staking::Trait for Runtime {
+	type Currency = Balances;
+	type Time = Timestamp;
+	type CurrencyToVote = CurrencyToVoteHandler;
+	type RewardRemainder = ();
+	type Event = Event;
+	type Slash = (); // burn slashed funds
+	type Reward = (); // rewards are minted from the void
+	type SessionsPerEra = SessionsPerEra;
+	type BondingDuration = BondingDuration;
+	type SlashDeferDuration = SlashDeferDuration;
+	type SlashCancelOrigin = system::EnsureRoot<AccountId>;
+	type SessionInterface = Self;
+	type RewardCurve = RewardCurve;
+}
+
+parameter_types! {
+	pub const MpcRequestDeposit: Balance = 1 * DOLLARS;
+	pub const MpcRequestTimeout: BlockNumber = 10 * MINUTES;
+	pub const MpcMaxPayloadLength: usize = 1024;
+	pub const MpcMaxPendingRequests: usize = 64;
+	pub const MpcMaxRequestsPerAccount: u32 = 4;
 }
-use system::offchain::TransactionSubmitter;
 
 type SubmitTransaction = TransactionSubmitter<mpc::crypto::Public, Runtime, UncheckedExtrinsic>;
 impl mpc::Trait for Runtime {
 	type Event = Event;
 	type Call = Call;
 	type SubmitTransaction = SubmitTransaction;
+	type Currency = Balances;
+	type RequestDeposit = MpcRequestDeposit;
+	type RequestTimeout = MpcRequestTimeout;
+	type Slashed = (); // burn slashed deposits
+	type MaxPayloadLength = MpcMaxPayloadLength;
+	type MaxPendingRequests = MpcMaxPendingRequests;
+	type MaxRequestsPerAccount = MpcMaxRequestsPerAccount;
 }
 
 parameter_types! {
@@ -290,11 +362,13 @@ construct_runtime!(
 		System: system::{Module, Call, Storage, Config, Event},
 		Timestamp: timestamp::{Module, Call, Storage, Inherent},
 		Authorship: authorship::{Module, Call, Storage, Inherent},
-		Badger: pallet_badger::{Module, Call, Storage, Event},
+		Badger: pallet_badger::{Module, Call, Storage, Event<T>, ValidateUnsigned},
 		Session: session::{Module, Call, Storage, Event, Config<T>},
-		Keygen: mpc::{Module, Call, Storage, Event<T>},
+		Keygen: mpc::{Module, Call, Storage, Config, Event<T>},
 		Indices: indices,
 		Balances: balances,
+		Staking: staking,
+		Offences: offences::{Module, Call, Storage, Event},
 		Contracts: contracts,
 		FinalityTracker: finality_tracker::{Module, Call, Inherent},
 		RandomnessCollectiveFlip: randomness_collective_flip::{Module, Call, Storage},
@@ -330,11 +404,16 @@ impl session::Trait for Runtime {
 	type Event = Event;
 	type Keys = SessionKeys;
 	type ValidatorId = <Self as system::Trait>::AccountId;
-	type ValidatorIdOf = ();
+	type ValidatorIdOf = ConvertInto;
 	type SelectInitialValidators = ();
 	type DisabledValidatorsThreshold = DisabledValidatorsThreshold;
 }
 
+impl session::historical::Trait for Runtime {
+	type FullIdentification = staking::Exposure<AccountId, Balance>;
+	type FullIdentificationOf = staking::ExposureOf<Runtime>;
+}
+
 
 /// The address format for describing accounts.
 pub type Address = <Indices as StaticLookup>::Source;
@@ -27,7 +27,9 @@ use runtime_primitives::{
 };
 //use fork_tree::ForkTree;
 use runtime_primitives::traits::{Block as BlockT, };
-use log::{info, };
+use log::{info, warn};
+use badger::crypto::PublicKey;
+use crate::communication::gossip::{BadgerFullJustification, public_key_from_slice};
 use badger_primitives::{AuthorityList,SetId};
 use keystore::KeyStorePtr;
 use badger_primitives::AuthorityPair;
@@ -42,6 +44,14 @@ const AUTHORITY_SET_KEY: &[u8] = b"hbbft_authorities";
 
 const VOTE_KEY: &[u8] = b"hbbft_current_vote"; //to save current vote for change?  change is a list of public keys, same as auth set 
 
+const CHECKPOINT_KEY: &[u8] = b"hbbft_checkpoint";
+
+const COMMITTEE_KEY_KEY: &[u8] = b"hbbft_committee_key";
+
+const SPLIT_HEADERS_KEY: &[u8] = b"hbbft_split_headers";
+
+const OVERFLOW_KEY: &[u8] = b"hbbft_overflow";
+
 const CURRENT_VERSION: u32 = 0;
 
 
@@ -66,39 +76,77 @@ pub(crate) fn load_decode<B: AuxStore, T: Decode>(backend: &B, key: &[u8]) -> Cl
 }
 pub  struct BadgerSharedAuthoritySet {
 	pub inner: Arc<RwLock<AuthoritySet>>,
+	/// Master key of the committee that signed the latest justification we accepted
+	pub committee_key: Arc<RwLock<Option<PublicKey>>>,
 }
 impl BadgerSharedAuthoritySet
 {
+	/// Check a justification against the known committee key and authorities.
+	/// Returns the key it was signed with.
+	pub fn check_justification<B:BlockT>(&self, just: &BadgerFullJustification<B>) -> Option<PublicKey>
+	{
+		let key = self.committee_key.read().clone();
+		just.check(key.as_ref(), &self.inner.read().current_authorities)
+	}
+
+	/// Remember the key of the committee signing justifications. Returns true if it changed.
+	pub fn note_committee_key(&self, key: PublicKey) -> bool
+	{
+		let mut current = self.committee_key.write();
+		if current.as_ref() == Some(&key)
+		{
+			return false;
+		}
+		info!("New committee key {:?}", &key);
+		*current = Some(key);
+		true
+	}
+
 	pub fn verify_full_justification<B:BlockT>(&self, just_dat:Justification) ->bool
 	{
-		use crate::communication::gossip::BadgerFullJustification;
 		let just: BadgerFullJustification<B>= match Decode::decode(&mut &just_dat[..])
 		{
 			Ok(dat) => dat,
 			Err(_) => return false,
 		};
-	  if !just.verify()
-	  {
-		return false;
-	  }
-   
-	 let mut count_total=0;
-	 let mut count_accepted=0;
-	 for authority in self.inner.read().current_authorities.iter()
-	 {
-	   count_total+=1;
-	   if just.commits.iter().find( |x| 
-		 {
-		  x.validator==*authority
-	   } ).is_some()
-	   {
-		 count_accepted+=1;
-	   }
-	 }
-	 let tolerated= count_total - badger::util::max_faulty(count_total);
-	  
-	 count_accepted>=tolerated
-	  }
+		self.check_justification(&just).is_some()
+	}
+
+	/// Verify a justification and check that it was issued for the block with the given hash.
+	pub fn verify_block_justification<B:BlockT>(&self, hash: &B::Hash, just_dat: &Justification) ->bool
+	{
+		match BadgerFullJustification::<B>::decode(&mut &just_dat[..])
+		{
+			Ok(just) if just.hash == *hash => self.check_justification(&just).is_some(),
+			_ => false,
+		}
+	}
+
+	/// Like `verify_block_justification`, and adopt the committee key the justification
+	/// attests to, if any, writing it to `backend`.
+	pub fn import_block_justification<B:BlockT, A:AuxStore>(&self, hash: &B::Hash, just_dat: &Justification, backend: &A) ->bool
+	{
+		let key = match BadgerFullJustification::<B>::decode(&mut &just_dat[..])
+		{
+			Ok(just) if just.hash == *hash => self.check_justification(&just),
+			_ => None,
+		};
+		match key
+		{
+			Some(key) =>
+			{
+				if self.note_committee_key(key.clone())
+				{
+					if let Err(e) = update_committee_key(&key, |insert| backend.insert_aux(insert, &[]))
+					{
+						warn!("Couldn't write committee key to disk {:?}", e);
+					}
+				}
+				true
+			},
+			None => false,
+		}
+	}
 }
 
 impl Clone for BadgerSharedAuthoritySet
@@ -106,19 +154,39 @@ impl Clone for BadgerSharedAuthoritySet
 	fn clone(&self) -> Self {
 		BadgerSharedAuthoritySet {
 			inner: self.inner.clone(),
+			committee_key: self.committee_key.clone(),
 		}
 	}
 }
 impl From<AuthoritySet> for BadgerSharedAuthoritySet {
 	fn from(set: AuthoritySet) -> Self {
-		BadgerSharedAuthoritySet { inner: Arc::new(RwLock::new(set)) }
+		BadgerSharedAuthoritySet { inner: Arc::new(RwLock::new(set)), committee_key: Arc::new(RwLock::new(None)) }
 	}
 }
+/// State of the running QueueingHoneyBadger instance, written after every batch
+/// so that a restarted validator can rejoin at the same epoch.
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct BadgerCheckpoint
+{
+	/// Set the checkpoint belongs to, checkpoints of older sets are ignored
+	pub set_id: SetId,
+	/// Era and epoch of the next batch
+	pub era: u64,
+	pub epoch: u64,
+	/// Our pending change vote, bincode-encoded `Change<NodeId>`
+	pub vote: Option<Vec<u8>>,
+	/// Transactions we have input that are not yet in a block
+	pub queued_transactions: Vec<Vec<u8>>,
+	/// Network ids of the other authorities, so the node can be rebuilt before they greet us again
+	pub peers: Vec<(AuthorityId, Vec<u8>)>,
+}
+
 /// Persistent data kept between runs.
 pub struct BadgerPersistentData
  {
     pub authority_set: BadgerSharedAuthoritySet,
     pub change_vote: Option<BadgerSharedAuthoritySet>,
+    pub checkpoint: Option<BadgerCheckpoint>,
 }
 
 pub fn loads_auth_set< B, G>(backend: &B,
@@ -138,8 +206,7 @@ pub fn loads_auth_set< B, G>(backend: &B,
             )?
             {
 
-                return Ok( set.into(),
-					);
+                return shared_with_committee_key(backend, set);
             }
             
 		},
@@ -152,7 +219,17 @@ pub fn loads_auth_set< B, G>(backend: &B,
 
 	let genesis_authorities = genesis_authorities()?;
 	let genesis_set=AuthoritySet{current_authorities:genesis_authorities, set_id:0,self_id:Default::default()};
-	return Ok(genesis_set.into());
+	return shared_with_committee_key(backend, genesis_set);
+}
+
+fn shared_with_committee_key<B: AuxStore>(backend: &B, set: AuthoritySet) -> ClientResult<BadgerSharedAuthoritySet>
+{
+	let shared: BadgerSharedAuthoritySet = set.into();
+	if let Some(bytes) = load_decode::<_, Vec<u8>>(backend, COMMITTEE_KEY_KEY)?
+	{
+		*shared.committee_key.write() = public_key_from_slice(&bytes);
+	}
+	Ok(shared)
 }
 
 /// Load or initialize persistent data from backend.
@@ -173,6 +250,7 @@ pub fn load_persistent_badger< B, G>(
         Some(aset) =>Some(aset.into()),
         None =>None
     };
+    let checkpoint: Option<BadgerCheckpoint> = load_decode(backend, CHECKPOINT_KEY)?;
 
 
 	match version {
@@ -185,8 +263,9 @@ pub fn load_persistent_badger< B, G>(
             {
 
                 return Ok(BadgerPersistentData {
-					authority_set: set.into(),
+					authority_set: shared_with_committee_key(backend, set)?,
 					change_vote: change_vote.into(),
+					checkpoint: checkpoint,
 				});
             }
             
@@ -218,7 +297,8 @@ pub fn load_persistent_badger< B, G>(
 
 	Ok(BadgerPersistentData {
 		authority_set: genesis_set.into(),
-		change_vote:None
+		change_vote:None,
+		checkpoint:None,
 	})
 }
 
@@ -234,6 +314,17 @@ pub fn update_authority_set<F, R>(
 	write_aux(&[(AUTHORITY_SET_KEY, &encoded_set[..])])
 }
 
+/// Update the committee key on disk after a change.
+pub fn update_committee_key<F, R>(
+	key: &PublicKey,
+	write_aux: F
+) -> R where
+	F: FnOnce(&[(&'static [u8], &[u8])]) -> R,
+{
+	let encoded_key = key.to_bytes().to_vec().encode();
+	write_aux(&[(COMMITTEE_KEY_KEY, &encoded_key[..])])
+}
+
 /// Update the authority set on disk after a change.
 pub fn update_vote<F, R,D>(
 	vote_set: &Option<AuthoritySet>,
@@ -255,6 +346,64 @@ pub fn update_vote<F, R,D>(
     }
 }
 
+/// Update the Badger checkpoint on disk after a batch.
+pub fn update_checkpoint<F, R>(
+	checkpoint: &BadgerCheckpoint,
+	write_aux: F
+) -> R where
+	F: FnOnce(&[(&'static [u8], &[u8])]) -> R,
+{
+	let encoded = checkpoint.encode();
+	write_aux(&[(CHECKPOINT_KEY, &encoded[..])])
+}
+
+
+/// Record the blocks of a split batch imported ahead of its justified last block.
+pub fn update_split_headers<H: Encode, F, R>(
+	headers: &[H],
+	write_aux: F
+) -> R where
+	F: FnOnce(&[(&'static [u8], &[u8])]) -> R,
+{
+	let encoded = headers.encode();
+	write_aux(&[(SPLIT_HEADERS_KEY, &encoded[..])])
+}
+
+/// Blocks of a split batch that were imported but not finalized before a restart.
+pub fn load_split_headers<B: AuxStore, H: Decode>(backend: &B) -> Vec<H> {
+	match load_decode::<_, Vec<H>>(backend, SPLIT_HEADERS_KEY) {
+		Ok(Some(headers)) => headers,
+		Ok(None) => Vec::new(),
+		Err(e) => {
+			warn!("Couldn't load split headers {:?}", e);
+			Vec::new()
+		}
+	}
+}
+
+/// Record the transactions carried over to the block following the block `after`.
+pub fn update_overflow<H: Encode, F, R>(
+	after: &H,
+	transactions: &[Vec<u8>],
+	write_aux: F
+) -> R where
+	F: FnOnce(&[(&'static [u8], &[u8])]) -> R,
+{
+	let encoded = (after, transactions).encode();
+	write_aux(&[(OVERFLOW_KEY, &encoded[..])])
+}
+
+/// Transactions carried over by `FillBlocks` when we last built a block, with the hash of
+/// that block.
+pub fn load_overflow<B: AuxStore, H: Decode>(backend: &B) -> Option<(H, Vec<Vec<u8>>)> {
+	match load_decode::<_, (H, Vec<Vec<u8>>)>(backend, OVERFLOW_KEY) {
+		Ok(overflow) => overflow,
+		Err(e) => {
+			warn!("Couldn't load overflow {:?}", e);
+			None
+		}
+	}
+}
 
 #[cfg(test)]
 pub(crate) fn load_authorities<B: AuxStore, H: Decode, N: Decode>(backend: &B)
@@ -0,0 +1,290 @@
+//! Byzantine behaviour for validators under test.
+//!
+//! A validator with an `Adversary` set rewrites what it sends right before the messages
+//! leave `BadgerStateMachine::extract_state`, that is after the output of
+//! `BadgerNode::process_step`, keygen and justifications has been buffered. Its own state
+//! stays honest, so whatever the other validators see is the effect of the rewritten traffic alone.
+
+use std::collections::{BTreeSet, VecDeque};
+
+use badger::crypto::SecretKey;
+use badger::sync_key_gen::{Ack, Part, SyncKeyGen};
+use badger_primitives::{BadgerPreRuntime, HBBFT_ENGINE_ID};
+use network::PeerId;
+use parity_codec::{Decode, Encode};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use runtime_primitives::generic::{Digest, DigestItem};
+use runtime_primitives::traits::{Block as BlockT, Hash as HashT, Header as HeaderT, One};
+use sc_api::AuxStore;
+use substrate_primitives::crypto::Pair;
+
+use super::gossip::{BadgerCatchUpResponse, BadgerJustification, BadgerJustifiedBlock, BadgeredMessage, GossipMessage};
+use super::{
+  BadgerStateMachine, BlockPusherMaker, LocalTarget, NetClient, NodeId, SyncKeyGenMessage, SyncKeyGenPacket, QHB,
+};
+
+/// How many of our earlier messages `Replay` sends again along with each new batch of output
+pub const REPLAY_WINDOW: usize = 16;
+
+#[derive(Clone, Debug)]
+pub enum AdversaryMode
+{
+  /// Sign a second, conflicting block hash for every justification we send
+  EquivocateJustification,
+  /// Send a Part of the wrong degree and Acks for a proposer that does not exist
+  InvalidKeyGen,
+  /// Tag every keygen message with a later attempt, as if our keygen had timed out
+  SkipKeyGenAttempt,
+  /// Never send anything to these peers
+  DropTo(BTreeSet<PeerId>),
+  /// Send our recent messages again along with every new one
+  Replay,
+  /// End every catch up response with a made up block that claims to continue the last batch
+  ForgeContinuedBlock,
+}
+
+pub struct Adversary<B: BlockT>
+{
+  pub mode: AdversaryMode,
+  /// Recently sent messages, SCALE-encoded, for `Replay`
+  sent: VecDeque<(LocalTarget<B>, Vec<u8>)>,
+  /// Hashes of the blocks made up by `ForgeContinuedBlock`
+  pub forged: Vec<B::Hash>,
+}
+
+impl<B: BlockT> Adversary<B>
+{
+  pub fn new(mode: AdversaryMode) -> Self
+  {
+    Adversary {
+      mode: mode,
+      sent: VecDeque::new(),
+      forged: Vec::new(),
+    }
+  }
+}
+
+impl<B: BlockT, Cl, BPM, Aux> BadgerStateMachine<B, QHB, Cl, BPM, Aux>
+where
+  Cl: NetClient<B>,
+  B::Hash: Ord,
+  BPM: BlockPusherMaker<B>,
+  Aux: AuxStore + Send + Sync + 'static,
+{
+  /// Rewrite outgoing messages according to our adversary mode, if we have one.
+  pub fn misbehave(&mut self, out: Vec<(LocalTarget<B>, GossipMessage<B>)>) -> Vec<(LocalTarget<B>, GossipMessage<B>)>
+  {
+    let mode = match self.adversary
+    {
+      Some(ref adversary) => adversary.mode.clone(),
+      None => return out,
+    };
+    match mode
+    {
+      AdversaryMode::EquivocateJustification => self.equivocate(out),
+      AdversaryMode::InvalidKeyGen => out.into_iter().map(|(target, msg)| (target, self.corrupt_keygen(msg))).collect(),
+      AdversaryMode::SkipKeyGenAttempt => out.into_iter().map(|(target, msg)| (target, self.skip_attempt(msg))).collect(),
+      AdversaryMode::DropTo(peers) => out
+        .into_iter()
+        .filter_map(|(target, msg)| drop_to(&peers, target).map(|target| (target, msg)))
+        .collect(),
+      AdversaryMode::Replay => self.replay(out),
+      AdversaryMode::ForgeContinuedBlock => out.into_iter().map(|(target, msg)| (target, self.forge_continued(msg))).collect(),
+    }
+  }
+
+  fn equivocate(&mut self, out: Vec<(LocalTarget<B>, GossipMessage<B>)>) -> Vec<(LocalTarget<B>, GossipMessage<B>)>
+  {
+    self.load_origin();
+    let pair = self.cached_origin.clone().unwrap();
+    let mut ret = Vec::new();
+    for (target, msg) in out
+    {
+      if let (&LocalTarget::AllExcept(_), &GossipMessage::JustificationData(ref just)) = (&target, &msg)
+      {
+        if just.validator == pair.public()
+        {
+          let hash = <<B::Header as HeaderT>::Hashing as HashT>::hash(&(b"equivocation", &just.hash).encode());
+          let forged = BadgerJustification::<B> {
+            hash: hash.clone(),
+            validator: pair.public(),
+            sgn: pair.sign(&hash.encode()),
+            share: just.share.clone(),
+            key_sgn: just.key_sgn.clone(),
+          };
+          ret.push((target.clone(), GossipMessage::JustificationData(forged)));
+        }
+      }
+      ret.push((target, msg));
+    }
+    ret
+  }
+
+  fn corrupt_keygen(&mut self, msg: GossipMessage<B>) -> GossipMessage<B>
+  {
+    let packet: Option<SyncKeyGenPacket> = match msg
+    {
+      GossipMessage::KeygenData(ref wrapped) => bincode::deserialize(&wrapped.data).ok(),
+      _ => None,
+    };
+    let packet = match packet
+    {
+      Some(p) => p,
+      None => return msg,
+    };
+    let corrupted = match packet.msg
+    {
+      SyncKeyGenMessage::Part(_) =>
+      {
+        SyncKeyGenMessage::Part(self.wrong_degree_part().expect("Adversary could not build a wrong-degree Part"))
+      }
+      SyncKeyGenMessage::Ack(src, ack) => SyncKeyGenMessage::Ack(src, unknown_proposer_ack(&ack)),
+      complaint @ SyncKeyGenMessage::Complaint(_) => complaint,
+    };
+    let data = bincode::serialize(&SyncKeyGenPacket {
+      attempt: packet.attempt,
+      msg: corrupted,
+    })
+    .expect("Serialize error in adversary");
+    GossipMessage::KeygenData(BadgeredMessage::new(self.cached_origin.clone().unwrap(), &data))
+  }
+
+  fn skip_attempt(&mut self, msg: GossipMessage<B>) -> GossipMessage<B>
+  {
+    let packet: SyncKeyGenPacket = match msg
+    {
+      GossipMessage::KeygenData(ref wrapped) => match bincode::deserialize(&wrapped.data)
+      {
+        Ok(p) => p,
+        Err(_) => return msg,
+      },
+      _ => return msg,
+    };
+    let data = bincode::serialize(&SyncKeyGenPacket {
+      attempt: packet.attempt.saturating_add(1),
+      msg: packet.msg,
+    })
+    .expect("Serialize error in adversary");
+    GossipMessage::KeygenData(BadgeredMessage::new(self.cached_origin.clone().unwrap(), &data))
+  }
+
+  /// A Part committing to a polynomial of the wrong degree, rejected by every validator alike
+  fn wrong_degree_part(&mut self) -> Option<Part>
+  {
+    self.load_origin();
+    let mut rng = StdRng::from_rng(&mut self.rng).ok()?;
+    let secr: SecretKey = bincode::deserialize(&self.cached_origin.as_ref().unwrap().to_raw_vec()).ok()?;
+    let thresh = badger::util::max_faulty(self.persistent.authority_set.inner.read().current_authorities.len());
+    let (_, part) = SyncKeyGen::new(
+      self.config.my_peer_id.clone().into(),
+      secr,
+      self.keygen_validators(),
+      thresh + 1,
+      &mut rng,
+    )
+    .ok()?;
+    part
+  }
+
+  /// Append a block on top of the last one of a catch up response, tagged as continuing its
+  /// batch and carrying the last block's valid justification.
+  fn forge_continued(&mut self, msg: GossipMessage<B>) -> GossipMessage<B>
+  {
+    let mut data = match msg
+    {
+      GossipMessage::CatchUpResponse(ref response) => response.data.clone(),
+      _ => return msg,
+    };
+    let last = match data.blocks.last()
+    {
+      Some(last) => last.clone(),
+      None => return msg,
+    };
+    let parent = last.block.header();
+    let mut digest = Digest::default();
+    digest.push(DigestItem::PreRuntime(HBBFT_ENGINE_ID, BadgerPreRuntime::BatchContinues.encode()));
+    let header = <B::Header as HeaderT>::new(
+      *parent.number() + One::one(),
+      parent.extrinsics_root().clone(),
+      parent.state_root().clone(),
+      parent.hash(),
+      digest,
+    );
+    self.adversary.as_mut().unwrap().forged.push(header.hash());
+    data.blocks.push(BadgerJustifiedBlock {
+      block: B::new(header, Vec::new()),
+      justification: last.justification,
+    });
+    self.load_origin();
+    GossipMessage::CatchUpResponse(BadgerCatchUpResponse::new(self.cached_origin.as_ref().unwrap(), data))
+  }
+
+  fn replay(&mut self, out: Vec<(LocalTarget<B>, GossipMessage<B>)>) -> Vec<(LocalTarget<B>, GossipMessage<B>)>
+  {
+    if out.is_empty()
+    {
+      return out;
+    }
+    let adversary = self.adversary.as_mut().unwrap();
+    let replayed: Vec<_> = adversary
+      .sent
+      .iter()
+      .filter_map(|(target, data)| GossipMessage::<B>::decode(&mut &data[..]).ok().map(|msg| (target.clone(), msg)))
+      .collect();
+    for (target, msg) in out.iter()
+    {
+      if let LocalTarget::Keep(_) = target
+      {
+        continue;
+      }
+      adversary.sent.push_back((target.clone(), msg.encode()));
+      if adversary.sent.len() > REPLAY_WINDOW
+      {
+        adversary.sent.pop_front();
+      }
+    }
+    let mut ret = out;
+    ret.extend(replayed);
+    ret
+  }
+}
+
+/// `ack` with its proposer index replaced by one no validator has
+fn unknown_proposer_ack(ack: &Ack) -> Ack
+{
+  // an Ack serializes as its proposer index followed by the encrypted values
+  let mut data = bincode::serialize(ack).expect("Serialize error in adversary");
+  for b in data.iter_mut().take(8)
+  {
+    *b = 0xff;
+  }
+  let patched: Ack = bincode::deserialize(&data).expect("Ack layout changed, adversary cannot patch its proposer");
+  assert!(patched != *ack, "Ack layout changed, adversary did not patch its proposer");
+  patched
+}
+
+fn drop_to<B: BlockT>(peers: &BTreeSet<PeerId>, target: LocalTarget<B>) -> Option<LocalTarget<B>>
+{
+  match target
+  {
+    LocalTarget::Nodes(nodes) =>
+    {
+      let rest: BTreeSet<NodeId> = nodes.into_iter().filter(|n| !peers.contains(&n.0)).collect();
+      if rest.is_empty()
+      {
+        None
+      }
+      else
+      {
+        Some(LocalTarget::Nodes(rest))
+      }
+    }
+    LocalTarget::AllExcept(mut except) =>
+    {
+      except.extend(peers.iter().map(|p| NodeId { 0: p.clone() }));
+      Some(LocalTarget::AllExcept(except))
+    }
+    keep => Some(keep),
+  }
+}
@@ -0,0 +1,71 @@
+//! How the transactions of an agreed batch are laid out in blocks.
+//!
+//! Every batch output by HoneyBadger is turned into a block, which may not be able to hold
+//! all of its transactions because of the weight and length limits of the runtime. What
+//! happens to the transactions left out is decided by a `BatchToBlockPolicy`, chosen through
+//! `Config::batch_policy`.
+//!
+//! When a batch is split, every block but the last one carries a
+//! `BadgerPreRuntime::BatchContinues` digest and no justification of its own. The
+//! justification of the last block finalizes all of them.
+
+use std::fmt::Debug;
+
+use super::BadgerTransaction;
+
+/// What to do with the transactions of a batch that did not fit into its block
+pub enum Leftover
+{
+  /// Leave them out. They are still in the pools of the validators that contributed them
+  /// and will be contributed again.
+  Drop,
+  /// Put them in front of the transactions of the next batch
+  Carry(Vec<BadgerTransaction>),
+  /// Build another block on top, finalized with the same justification
+  NextBlock(Vec<BadgerTransaction>),
+}
+
+pub trait BatchToBlockPolicy: Send + Sync + Debug
+{
+  /// Decide what happens to `txs`, the transactions of the current batch that did not
+  /// fit into its block, in batch order.
+  fn leftover(&self, txs: Vec<BadgerTransaction>) -> Leftover;
+}
+
+/// Exactly one block per batch, whatever does not fit is dropped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OneBlockPerBatch;
+
+impl BatchToBlockPolicy for OneBlockPerBatch
+{
+  fn leftover(&self, _txs: Vec<BadgerTransaction>) -> Leftover
+  {
+    Leftover::Drop
+  }
+}
+
+/// Fill blocks up to the limits, carrying what does not fit over to the block of the next batch.
+/// The carried transactions are saved along with the block they follow, and sent to nodes
+/// catching up to it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FillBlocks;
+
+impl BatchToBlockPolicy for FillBlocks
+{
+  fn leftover(&self, txs: Vec<BadgerTransaction>) -> Leftover
+  {
+    Leftover::Carry(txs)
+  }
+}
+
+/// Split batches that do not fit into one block over several consecutive blocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SplitBatches;
+
+impl BatchToBlockPolicy for SplitBatches
+{
+  fn leftover(&self, txs: Vec<BadgerTransaction>) -> Leftover
+  {
+    Leftover::NextBlock(txs)
+  }
+}
@@ -0,0 +1,483 @@
+//! Catch up for nodes that fell behind the rest of the network.
+//!
+//! A node that notices (through `SyncGossip` or an external block import) that the
+//! network has finalized blocks far ahead of its own best block asks a validator for a
+//! range of justified blocks. Each block is checked against the authority set that was
+//! current at its height, imported and finalized. Blocks of a batch split over several
+//! blocks come with the justification of the last one. They are only imported once the
+//! block that ends the batch follows them in the same response and its own justification
+//! checks out, and are finalized along with it.
+//! Once the node reaches the responder's best block it re-enters `BadgerState::Badger`
+//! at the responder's epoch, or `BadgerState::Observer` if it is not an authority. The
+//! transactions the responder carries over to the next block under `FillBlocks` come along,
+//! so that the node builds the same block as the network.
+
+use std::collections::BTreeSet;
+use std::time::Instant;
+
+use badger::crypto::PublicKey;
+use badger_primitives::{AuthorityId, AuthorityList, BadgerPreRuntime, HBBFT_ENGINE_ID};
+use consensus_common::{BlockImportParams, BlockOrigin, ForkChoiceStrategy};
+use log::{info, warn};
+use parity_codec::{Decode, Encode};
+use runtime_primitives::generic::{BlockId, OpaqueDigestItemId};
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT, NumberFor, One};
+use sc_api::AuxStore;
+use sc_network_ranting::ValidationResult;
+
+use super::gossip::{
+  BadgerCatchUpData, BadgerCatchUpRequest, BadgerCatchUpResponse, BadgerFullJustification, BadgerJustifiedBlock,
+  CATCH_UP_REQUEST_TIMEOUT, CATCH_UP_THRESHOLD, MAX_CATCH_UP_BLOCKS,
+};
+use super::{
+  BadgerState, BadgerStateMachine, BlockPusherMaker, GossipMessage, LocalTarget, NetClient, QHB,
+};
+use crate::aux_store;
+
+/// An outstanding catch up.
+pub struct CatchUpProgress<B: BlockT>
+{
+  /// Best block number announced by the network
+  pub target: NumberFor<B>,
+  /// Validator we asked
+  pub peer: AuthorityId,
+  /// When the last request went out
+  pub requested_at: Instant,
+}
+
+impl<B: BlockT, Cl, BPM, Aux> BadgerStateMachine<B, QHB, Cl, BPM, Aux>
+where
+  Cl: NetClient<B>,
+  B::Hash: Ord,
+  BPM: BlockPusherMaker<B>,
+  Aux: AuxStore + Send + Sync + 'static,
+{
+  /// Returns true if `num` is far enough ahead of our best block to warrant catching up.
+  pub fn is_behind(&self, num: NumberFor<B>) -> bool
+  {
+    num > self.client.info().best_number + CATCH_UP_THRESHOLD.into()
+  }
+
+  /// Ask a validator for the blocks between our best block and `target`.
+  /// If `peer` is not given, a connected validator is chosen.
+  pub fn request_catch_up(&mut self, target: NumberFor<B>, peer: Option<AuthorityId>)
+  {
+    if let BadgerState::KeyGen(_) = self.state
+    {
+      // nothing has been finalized before genesis keygen completes
+      return;
+    }
+    if let Some(ref progress) = self.sync_state.catch_up
+    {
+      if progress.target >= target && self.clock.elapsed(progress.requested_at) < CATCH_UP_REQUEST_TIMEOUT
+      {
+        return;
+      }
+    }
+    let peer = match peer.or_else(|| self.catch_up_candidate())
+    {
+      Some(p) => p,
+      None =>
+      {
+        info!("No validator to catch up from");
+        return;
+      }
+    };
+    let peer_id = match self.peers.badgerid_to_peerid(&peer)
+    {
+      Some(p) => p,
+      None =>
+      {
+        info!("Unknown peer for catch up source {:?}", &peer);
+        return;
+      }
+    };
+    self.load_origin();
+
+    let from = self.client.info().best_number + One::one();
+    let mut to = target;
+    let max_to = from + (MAX_CATCH_UP_BLOCKS - 1).into();
+    if to > max_to
+    {
+      to = max_to;
+    }
+    info!("Catching up blocks {:?}..{:?} of {:?} from {:?}", &from, &to, &target, &peer);
+
+    if let BadgerState::Badger(_) = self.state
+    {
+      // our instance is stale, it will be rebuilt at the network's epoch when we are done
+      self.mech.queued_block = None;
+      self.mech.split_headers.clear();
+      self.mech.pending_batch = None;
+      self.mech.queued_batches.clear();
+    }
+    self.state = BadgerState::CatchingUp;
+
+    let request = BadgerCatchUpRequest::<B>::new(self.cached_origin.as_ref().unwrap(), from, to);
+    let mut target_set = BTreeSet::new();
+    target_set.insert(peer_id.into());
+    self
+      .output_message_buffer
+      .push((LocalTarget::Nodes(target_set), GossipMessage::CatchUpRequest(request)));
+    self.sync_state.catch_up = Some(CatchUpProgress {
+      target: target,
+      peer: peer,
+      requested_at: self.clock.now(),
+    });
+  }
+
+  fn catch_up_candidate(&self) -> Option<AuthorityId>
+  {
+    let aset = self.persistent.authority_set.inner.read();
+    self
+      .peers
+      .connected_badgerid_list()
+      .into_iter()
+      .find(|x| *x != aset.self_id && aset.current_authorities.contains(x))
+  }
+
+  /// Serve a catch up request with the justified blocks we have.
+  pub fn process_catch_up_request(&mut self, request: &BadgerCatchUpRequest<B>) -> (ValidationResult<B>, bool)
+  {
+    let peer_id = match self.peers.badgerid_to_peerid(&request.source)
+    {
+      Some(p) => p,
+      None =>
+      {
+        info!("Catch up request from unknown peer {:?}", &request.source);
+        return (ValidationResult::Discard, false);
+      }
+    };
+    let info = self.client.info();
+    let mut to = request.range.to;
+    if to > info.finalized_number
+    {
+      to = info.finalized_number;
+    }
+    let max_to = request.range.from + (MAX_CATCH_UP_BLOCKS - 1).into();
+    if to > max_to
+    {
+      to = max_to;
+    }
+
+    let mut blocks = Vec::new();
+    let mut num = request.range.from;
+    while num <= to
+    {
+      match self.justified_block(num)
+      {
+        Some(block) => blocks.push(block),
+        None => break,
+      }
+      num = num + One::one();
+    }
+    info!("Serving {:?} catch up blocks to {:?}", blocks.len(), &request.source);
+
+    self.load_origin();
+    let overflow = match self.mech.overflow_after
+    {
+      Some(ref after) if *after == info.finalized_hash => Some((after.clone(), self.mech.overflow.iter().cloned().collect())),
+      _ => None,
+    };
+    let data = BadgerCatchUpData {
+      blocks: blocks,
+      best: info.finalized_number,
+      next_epoch: self.sync_state.next_epoch,
+      overflow: overflow,
+    };
+    let response = BadgerCatchUpResponse::new(self.cached_origin.as_ref().unwrap(), data);
+    let mut target_set = BTreeSet::new();
+    target_set.insert(peer_id.into());
+    self
+      .output_message_buffer
+      .push((LocalTarget::Nodes(target_set), GossipMessage::CatchUpResponse(response)));
+    (ValidationResult::Discard, false)
+  }
+
+  fn justified_block(&self, num: NumberFor<B>) -> Option<BadgerJustifiedBlock<B>>
+  {
+    let hash = self.client.block_hash(num).ok()??;
+    let id = BlockId::Hash(hash);
+    let header = self.client.header(&id).ok()??;
+    let body = self.client.body(&id).ok()??;
+    let just = match self.client.justification(&id).ok()?
+    {
+      Some(just) => just,
+      None if Self::continues_batch(&header) => self.batch_justification(num)?,
+      None => return None,
+    };
+    let justification = BadgerFullJustification::<B>::decode(&mut &just[..]).ok()?;
+    Some(BadgerJustifiedBlock {
+      block: B::new(header, body),
+      justification: justification,
+    })
+  }
+
+  /// Justification of the block that ends the split batch `num` belongs to
+  fn batch_justification(&self, num: NumberFor<B>) -> Option<Vec<u8>>
+  {
+    let mut num = num;
+    for _ in 0..MAX_CATCH_UP_BLOCKS
+    {
+      num = num + One::one();
+      let id = BlockId::Hash(self.client.block_hash(num).ok()??);
+      if let Some(just) = self.client.justification(&id).ok()?
+      {
+        return Some(just);
+      }
+      if !Self::continues_batch(&self.client.header(&id).ok()??)
+      {
+        return None;
+      }
+    }
+    None
+  }
+
+  /// Import the justified blocks from a catch up response, in order.
+  pub fn process_catch_up_response(&mut self, response: &BadgerCatchUpResponse<B>) -> (ValidationResult<B>, bool)
+  {
+    match self.sync_state.catch_up
+    {
+      Some(ref progress) if progress.peer == response.source =>
+      {}
+      Some(_) =>
+      {
+        warn!("Unsolicited catch up response from {:?}", &response.source);
+        return (ValidationResult::Discard, false);
+      }
+      None => return (ValidationResult::Discard, false),
+    }
+    let mut authorities: AuthorityList = self.persistent.authority_set.inner.read().current_authorities.clone();
+    let mut committee_key = self.persistent.authority_set.committee_key.read().clone();
+    let info = self.client.info();
+    let mut parent = (info.best_number, info.best_hash);
+    // blocks of a split batch, waiting for the block that ends it
+    let mut batch: Vec<&BadgerJustifiedBlock<B>> = Vec::new();
+
+    for jblock in response.data.blocks.iter()
+    {
+      let header = jblock.block.header();
+      if *header.number() <= info.best_number
+      {
+        continue;
+      }
+      if *header.number() != parent.0 + One::one() || *header.parent_hash() != parent.1
+      {
+        warn!("Catch up block {:?} does not extend our best block", header.hash());
+        break;
+      }
+      let hash = header.hash();
+      parent = (*header.number(), hash.clone());
+      if jblock.justification.hash != hash
+      {
+        if !Self::continues_batch(header)
+        {
+          warn!("Catch up block {:?} is not finalized by its justification", &hash);
+          break;
+        }
+        // its justification proves nothing about it until the block it names turns up
+        batch.push(jblock);
+        continue;
+      }
+      let key = match jblock.justification.check(committee_key.as_ref(), &authorities)
+      {
+        Some(key) => key,
+        None =>
+        {
+          warn!("Invalid justification for catch up block {:?}", &hash);
+          break;
+        }
+      };
+      batch.push(jblock);
+      // a set announced in a split batch takes over once the batch is finalized
+      let mut pending_set = None;
+      let mut imported = true;
+      for jblock in batch.drain(..)
+      {
+        if !self.import_justified(jblock)
+        {
+          imported = false;
+          break;
+        }
+        if let Some(new_set) = Self::changed_validators(jblock.block.header())
+        {
+          info!("Authority set changed at {:?} during catch up", jblock.block.header().number());
+          pending_set = Some(new_set);
+        }
+      }
+      if !imported
+      {
+        break;
+      }
+      if committee_key.as_ref() != Some(&key)
+      {
+        self.adopt_committee_key(key.clone());
+        committee_key = Some(key);
+      }
+      if let Some(new_set) = pending_set.take()
+      {
+        authorities = new_set;
+        self.adopt_authority_set(authorities.clone());
+      }
+    }
+    if !batch.is_empty()
+    {
+      info!("Dropping {:?} catch up blocks of a batch the response does not finish", batch.len());
+    }
+
+    if let Some(epoch) = response.data.next_epoch
+    {
+      self.sync_state.next_epoch = Some(epoch);
+    }
+    if let Some((ref after, ref txs)) = response.data.overflow
+    {
+      // the next block of the network starts with them, we only have the responder's word
+      // for it, like for the epoch
+      if *after == self.client.info().best_hash
+      {
+        self.mech.overflow = txs.iter().cloned().collect();
+        self.save_overflow(after.clone());
+      }
+    }
+    let our_best = self.client.info().best_number;
+    let target = {
+      let progress = self.sync_state.catch_up.as_ref().unwrap();
+      if response.data.best > progress.target
+      {
+        response.data.best
+      }
+      else
+      {
+        progress.target
+      }
+    };
+    if our_best >= target
+    {
+      self.finish_catch_up();
+    }
+    else
+    {
+      // force a fresh request for the next range
+      self.sync_state.catch_up = None;
+      self.request_catch_up(target, Some(response.source.clone()));
+    }
+    (ValidationResult::Discard, false)
+  }
+
+  pub(super) fn import_justified(&mut self, jblock: &BadgerJustifiedBlock<B>) -> bool
+  {
+    let (header, body) = jblock.block.clone().deconstruct();
+    let hash = header.hash();
+    let import_block: BlockImportParams<B> = BlockImportParams {
+      origin: BlockOrigin::Own,
+      header,
+      justification: None,
+      post_digests: vec![],
+      body: Some(body),
+      finalized: false,
+      allow_missing_state: true,
+      auxiliary: Vec::new(),
+      fork_choice: ForkChoiceStrategy::LongestChain,
+      import_existing: false,
+    };
+    if let Err(e) = self.block_maker.import_block(import_block)
+    {
+      warn!(target: "badger", "Error importing catch up block {:?}: {:?}", &hash, e);
+      return false;
+    }
+    if jblock.justification.hash != hash
+    {
+      // finalized by the last block of its batch
+      return true;
+    }
+    if !(self.finalizer)(&hash, Some(jblock.justification.encode()))
+    {
+      warn!("Failed finalization of catch up block {:?}", &hash);
+      return false;
+    }
+    true
+  }
+
+  fn continues_batch(header: &B::Header) -> bool
+  {
+    let id = OpaqueDigestItemId::PreRuntime(&HBBFT_ENGINE_ID);
+    header
+      .digest()
+      .logs()
+      .iter()
+      .filter_map(|x| x.try_to::<BadgerPreRuntime>(id))
+      .any(|x| x == BadgerPreRuntime::BatchContinues)
+  }
+
+  pub(super) fn changed_validators(header: &B::Header) -> Option<AuthorityList>
+  {
+    let id = OpaqueDigestItemId::PreRuntime(&HBBFT_ENGINE_ID);
+    header
+      .digest()
+      .logs()
+      .iter()
+      .filter_map(|x| x.try_to::<BadgerPreRuntime>(id))
+      .filter_map(|x| match x
+      {
+        BadgerPreRuntime::ValidatorsChanged(set) => Some(set),
+        BadgerPreRuntime::BatchContinues => None,
+      })
+      .next()
+  }
+
+  /// Record an authority set we learned about while catching up.
+  pub(super) fn adopt_authority_set(&mut self, authorities: AuthorityList)
+  {
+    let mut aset = self.persistent.authority_set.inner.write();
+    aset.current_authorities = authorities;
+    aset.set_id = aset.set_id + 1;
+    if let Err(e) = aux_store::update_authority_set(&aset, |insert| self.aux_backend.insert_aux(insert, &[]))
+    {
+      warn!("Couldn't write to disk, potentially inconsistent state {:?}", e);
+    }
+  }
+
+  pub(super) fn adopt_committee_key(&mut self, key: PublicKey)
+  {
+    if self.persistent.authority_set.note_committee_key(key.clone())
+    {
+      if let Err(e) = aux_store::update_committee_key(&key, |insert| self.aux_backend.insert_aux(insert, &[]))
+      {
+        warn!("Couldn't write to disk, potentially inconsistent state {:?}", e);
+      }
+    }
+  }
+
+  fn finish_catch_up(&mut self)
+  {
+    info!(
+      "Catch up complete at {:?}, resuming at epoch {:?}",
+      self.client.info().best_number,
+      &self.sync_state.next_epoch
+    );
+    self.sync_state.catch_up = None;
+    self.config.is_observer = !self.is_authority();
+    if self.config.is_observer
+    {
+      self.state = BadgerState::Observer;
+      return;
+    }
+    if !self.load_key_share()
+    {
+      // the set changed while we were behind, only a join plan can give us a share of it
+      warn!("No key share for the current authority set, waiting for a join plan");
+      self.state = BadgerState::Observer;
+      return;
+    }
+    if self.all_validators_known()
+    {
+      let mut msgs = self.proceed_to_badger();
+      self.output_message_buffer.append(&mut msgs);
+    }
+    else
+    {
+      // proceed_to_keygen finds the share once every validator is connected and resumes Badger
+      self.state = BadgerState::AwaitingValidators;
+    }
+  }
+}
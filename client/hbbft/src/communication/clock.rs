@@ -0,0 +1,30 @@
+//! Time as seen by the engine's timers.
+//!
+//! The keygen, decryption share, join plan and catch up timers compare what they recorded
+//! against `Clock::now` instead of `Instant::now`, so that the simulator can run them on its
+//! virtual time. A running node uses `SystemClock`.
+
+use std::time::{Duration, Instant};
+
+pub trait Clock: Send + Sync
+{
+  fn now(&self) -> Instant;
+
+  /// Time passed since `earlier`, zero if `earlier` is in the future
+  fn elapsed(&self, earlier: Instant) -> Duration
+  {
+    self.now().saturating_duration_since(earlier)
+  }
+}
+
+/// Wall-clock time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock
+{
+  fn now(&self) -> Instant
+  {
+    Instant::now()
+  }
+}
@@ -0,0 +1,336 @@
+//! Threshold-encrypted transactions.
+//!
+//! A transaction submitted through `author_submitEncryptedExtrinsic` is a `Ciphertext` of the
+//! encoded extrinsic under the master key of the current validator set. It is ordered by Badger
+//! as an opaque `BadgerTransaction`, so nobody learns its content before the batch is agreed.
+//! Once a batch containing encrypted transactions is output, every validator broadcasts its
+//! decryption shares, and the block is built as soon as `threshold + 1` valid shares have been
+//! combined for each of them. Our shares are resent every `DECRYPTION_RESEND_INTERVAL` until
+//! then.
+//!
+//! What goes into the block must not depend on when shares happen to arrive at a validator,
+//! so a transaction is only ever dropped for its agreed content: a ciphertext that does not
+//! decode or fails `Ciphertext::verify` is dropped by every validator alike, and any other one
+//! waits for `threshold + 1` valid shares, which the honest validators always provide.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use badger::crypto::{Ciphertext, DecryptionShare};
+use badger_primitives::AuthorityId;
+use log::{debug, info, warn};
+use runtime_primitives::traits::Block as BlockT;
+use sc_api::AuxStore;
+use sc_network_ranting::ValidationResult;
+use serde::{Deserialize, Serialize};
+use substrate_primitives::hashing::blake2_256;
+
+use super::gossip::BadgeredMessage;
+use super::{
+  BadgerState, BadgerStateMachine, BadgerTransaction, BatchType, BlockPusherMaker, GossipMessage, LocalTarget,
+  NetClient, NodeId, QHB,
+};
+
+/// Marks a `BadgerTransaction` as threshold-encrypted. No encoded extrinsic starts with it,
+/// as `b'b'` is not a valid extrinsic version.
+pub const ENCRYPTED_TX_PREFIX: &[u8] = b"hbenc\0";
+
+/// Upper bound on transactions we hold decryption shares for
+const MAX_PENDING_DECRYPTIONS: usize = 1024;
+
+/// Upper bound on share messages kept while we are not running Badger
+const MAX_BUFFERED_SHARE_MESSAGES: usize = 256;
+
+/// How often we resend our shares while a batch waits for decryption
+pub const DECRYPTION_RESEND_INTERVAL: Duration = Duration::from_secs(5);
+
+pub type TxHash = [u8; 32];
+
+/// Decryption shares of one validator for the encrypted transactions of a batch
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DecryptionShares
+{
+  pub shares: Vec<(TxHash, DecryptionShare)>,
+}
+
+pub fn is_encrypted(tx: &[u8]) -> bool
+{
+  tx.starts_with(ENCRYPTED_TX_PREFIX)
+}
+
+/// Wrap a ciphertext into a `BadgerTransaction`
+pub fn wrap_encrypted(ct: &Ciphertext) -> BadgerTransaction
+{
+  let mut tx = ENCRYPTED_TX_PREFIX.to_vec();
+  tx.extend(bincode::serialize(ct).expect("Ciphertext serialization cannot fail"));
+  tx
+}
+
+fn unwrap_encrypted(tx: &[u8]) -> Option<Ciphertext>
+{
+  let ct: Ciphertext = bincode::deserialize(&tx[ENCRYPTED_TX_PREFIX.len()..]).ok()?;
+  if ct.verify()
+  {
+    Some(ct)
+  }
+  else
+  {
+    None
+  }
+}
+
+impl<B: BlockT, Cl, BPM, Aux> BadgerStateMachine<B, QHB, Cl, BPM, Aux>
+where
+  Cl: NetClient<B>,
+  B::Hash: Ord,
+  BPM: BlockPusherMaker<B>,
+  Aux: AuxStore + Send + Sync + 'static,
+{
+  /// Broadcast our decryption shares for the encrypted transactions in `batch`.
+  /// Returns true if the batch has to wait for shares from other validators.
+  pub fn start_decryption(&mut self, batch: &BatchType) -> bool
+  {
+    let mut pending = Vec::new();
+    for tx in batch.clone().into_tx_iter().filter(|tx| is_encrypted(tx))
+    {
+      let hash = blake2_256(&tx);
+      if self.mech.decrypted.contains_key(&hash)
+      {
+        continue;
+      }
+      match unwrap_encrypted(&tx)
+      {
+        Some(ct) => pending.push((hash, ct)),
+        None =>
+        {
+          // decided by the agreed bytes alone, every validator drops it the same way
+          info!("Dropping invalid encrypted transaction");
+          self.mech.decrypted.insert(hash, None);
+        }
+      }
+    }
+    if pending.is_empty()
+    {
+      return false;
+    }
+    let (our_index, shares) = match self.state
+    {
+      BadgerState::Badger(ref node) =>
+      {
+        let netinfo = node.algo.inner().netinfo();
+        let shares: Vec<(TxHash, DecryptionShare)> = match netinfo.secret_key_share()
+        {
+          Some(sks) => pending
+            .iter()
+            .filter_map(|(hash, ct)| sks.decrypt_share(ct).map(|s| (*hash, s)))
+            .collect(),
+          None => Vec::new(),
+        };
+        (netinfo.node_index(netinfo.our_id()), shares)
+      }
+      _ => (None, Vec::new()),
+    };
+    for (hash, ct) in pending.into_iter()
+    {
+      self.mech.encrypted.insert(hash, ct);
+    }
+    if let Some(idx) = our_index
+    {
+      self.add_shares(idx, shares.clone());
+    }
+    let buffered: Vec<_> = self.mech.buffered_shares.drain(..).collect();
+    for (originator, received) in buffered.into_iter()
+    {
+      if let Some(idx) = self.validator_index(&originator)
+      {
+        self.add_shares(idx, received);
+      }
+    }
+    if !shares.is_empty()
+    {
+      self.broadcast_decryption_shares(shares.clone());
+    }
+    if self.try_decrypt()
+    {
+      return false;
+    }
+    self.mech.own_shares = shares;
+    self.mech.last_share_resend = self.clock.now();
+    true
+  }
+
+  fn broadcast_decryption_shares(&mut self, shares: Vec<(TxHash, DecryptionShare)>)
+  {
+    self.load_origin();
+    let msg = BadgeredMessage::new(
+      self.cached_origin.as_ref().unwrap().clone(),
+      &bincode::serialize(&DecryptionShares { shares: shares }).expect("Serialize error in decryption shares"),
+    );
+    self
+      .output_message_buffer
+      .push((LocalTarget::AllExcept(BTreeSet::new()), GossipMessage::DecryptionShares(msg)));
+  }
+
+  /// Index of a validator of the running Badger instance
+  fn validator_index(&self, originator: &AuthorityId) -> Option<usize>
+  {
+    let sender: NodeId = self.peers.inverse.get(originator)?.clone().into();
+    match self.state
+    {
+      BadgerState::Badger(ref node) => node.algo.inner().netinfo().node_index(&sender),
+      _ => None,
+    }
+  }
+
+  fn add_shares(&mut self, index: usize, shares: Vec<(TxHash, DecryptionShare)>)
+  {
+    for (hash, share) in shares.into_iter()
+    {
+      if self.mech.decrypted.contains_key(&hash)
+      {
+        continue;
+      }
+      if !self.mech.decryption_shares.contains_key(&hash) && self.mech.decryption_shares.len() >= MAX_PENDING_DECRYPTIONS
+      {
+        warn!("Too many pending decryptions, dropping share");
+        continue;
+      }
+      self.mech.decryption_shares.entry(hash).or_insert_with(BTreeMap::new).insert(index, share);
+    }
+  }
+
+  /// Called periodically. Resends our shares for validators that missed them. The batch keeps
+  /// waiting however long it takes, giving up locally would build a different block.
+  pub fn on_decryption_timer(&mut self)
+  {
+    if self.mech.awaiting_decryption.is_none()
+    {
+      return;
+    }
+    if self.clock.elapsed(self.mech.last_share_resend) > DECRYPTION_RESEND_INTERVAL && !self.mech.own_shares.is_empty()
+    {
+      debug!("Resending our decryption shares");
+      self.mech.last_share_resend = self.clock.now();
+      let shares = self.mech.own_shares.clone();
+      self.broadcast_decryption_shares(shares);
+    }
+  }
+
+  /// Turn the batch that waited for decryption into a block.
+  fn finish_decryption(&mut self)
+  {
+    self.mech.own_shares.clear();
+    if let Some(batch) = self.mech.awaiting_decryption.take()
+    {
+      let res = self.batch_to_block(batch);
+      self.handle_batch_result(res);
+    }
+  }
+
+  /// Combine the shares we have. Returns true if nothing is left to decrypt.
+  fn try_decrypt(&mut self) -> bool
+  {
+    let pk_set = match self.state
+    {
+      BadgerState::Badger(ref node) => node.algo.inner().netinfo().public_key_set().clone(),
+      _ => return false,
+    };
+    let threshold = pk_set.threshold();
+    let hashes: Vec<TxHash> = self.mech.encrypted.keys().cloned().collect();
+    for hash in hashes
+    {
+      let ct = self.mech.encrypted[&hash].clone();
+      let valid: BTreeMap<usize, DecryptionShare> = match self.mech.decryption_shares.get(&hash)
+      {
+        Some(shares) => shares
+          .iter()
+          .filter(|(idx, share)| pk_set.public_key_share(**idx).verify_decryption_share(share, &ct))
+          .map(|(idx, share)| (*idx, share.clone()))
+          .collect(),
+        None => continue,
+      };
+      if valid.len() <= threshold
+      {
+        continue;
+      }
+      match pk_set.decrypt(valid.iter().map(|(idx, share)| (*idx, share)), &ct)
+      {
+        Ok(plain) =>
+        {
+          debug!("Decrypted transaction {:?}", &hash);
+          self.mech.decrypted.insert(hash, Some(plain));
+        }
+        Err(e) =>
+        {
+          // verified shares of a verified ciphertext always combine, keep waiting rather than
+          // drop it on our own
+          warn!("Could not combine decryption shares {:?}", e);
+          continue;
+        }
+      }
+      self.mech.encrypted.remove(&hash);
+      self.mech.decryption_shares.remove(&hash);
+    }
+    self.mech.encrypted.is_empty()
+  }
+
+  /// Plaintext for a transaction of the batch being turned into a block
+  pub fn take_decrypted(&mut self, tx: BadgerTransaction) -> Option<BadgerTransaction>
+  {
+    if !is_encrypted(&tx)
+    {
+      return Some(tx);
+    }
+    self.mech.decrypted.remove(&blake2_256(&tx)).and_then(|x| x)
+  }
+
+  pub fn process_decryption_shares(&mut self, msg: &BadgeredMessage) -> (ValidationResult<B>, bool)
+  {
+    if !msg.verify()
+    {
+      return (ValidationResult::Punish(-8), false);
+    }
+    if msg.originator == self.config.my_auth_id
+    {
+      return (ValidationResult::Discard, false);
+    }
+    let payload: DecryptionShares = match bincode::deserialize(&msg.data)
+    {
+      Ok(p) => p,
+      Err(_) => return (ValidationResult::Punish(-2), false),
+    };
+    match self.state
+    {
+      BadgerState::Badger(_) =>
+      {}
+      _ =>
+      {
+        // the batch may be output once we are running, keep them until then
+        if self.mech.buffered_shares.len() < MAX_BUFFERED_SHARE_MESSAGES
+        {
+          self.mech.buffered_shares.push((msg.originator.clone(), payload.shares));
+        }
+        return (ValidationResult::Discard, false);
+      }
+    }
+    if !self.peers.inverse.contains_key(&msg.originator)
+    {
+      return (ValidationResult::Discard, true);
+    }
+    let index = match self.validator_index(&msg.originator)
+    {
+      Some(i) => i,
+      None =>
+      {
+        info!("Decryption shares from non-validator {:?}", &msg.originator);
+        return (ValidationResult::Discard, false);
+      }
+    };
+    self.add_shares(index, payload.shares);
+    if self.mech.awaiting_decryption.is_some() && self.try_decrypt()
+    {
+      self.finish_decryption();
+    }
+    (ValidationResult::Discard, false)
+  }
+}
@@ -0,0 +1,143 @@
+//! Misbehaviour reporting.
+//!
+//! Validators that sign two blocks of the same height, or that `hbbft` puts in the fault log
+//! of a `Step`, are turned into `BadgerFaultReport`s. The client leaves them in offchain
+//! storage and the runtime's offchain worker submits them, where they end up as
+//! `HoneyBadgerEquivocation` offences.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use badger_primitives::{
+  AuthorityId, AuthoritySignature, BadgerFaultReport, EquivocationProof, FaultReport, SignedFaultReport,
+};
+use log::warn;
+use parity_codec::Encode;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT, NumberFor, SaturatedConversion, Saturating};
+use sc_api::AuxStore;
+use substrate_primitives::crypto::Pair;
+
+use super::gossip::BadgerJustification;
+use super::{BadgerState, BadgerStateMachine, BlockPusherMaker, NetClient, QHB};
+
+/// How many blocks below the finalized one we still look for conflicting justifications
+const EQUIVOCATION_WINDOW: u32 = 256;
+
+/// Upper bound on reports waiting to be stored
+const MAX_PENDING_REPORTS: usize = 256;
+
+pub struct FaultTracker<B: BlockT>
+{
+  /// The block each validator signed at a height, `None` once it was reported for it
+  signed: BTreeMap<(NumberFor<B>, AuthorityId), Option<(B::Header, AuthoritySignature)>>,
+  /// (set id, offender) pairs we already reported a protocol fault for
+  reported: BTreeSet<(u32, AuthorityId)>,
+  pending: Vec<BadgerFaultReport<B::Header>>,
+}
+
+impl<B: BlockT> FaultTracker<B>
+{
+  pub fn new() -> Self
+  {
+    FaultTracker {
+      signed: BTreeMap::new(),
+      reported: BTreeSet::new(),
+      pending: Vec::new(),
+    }
+  }
+
+  fn push(&mut self, report: BadgerFaultReport<B::Header>)
+  {
+    if self.pending.len() >= MAX_PENDING_REPORTS
+    {
+      warn!("Too many fault reports pending, dropping {:?}", report);
+      return;
+    }
+    self.pending.push(report);
+  }
+}
+
+impl<B: BlockT, Cl, BPM, Aux> BadgerStateMachine<B, QHB, Cl, BPM, Aux>
+where
+  Cl: NetClient<B>,
+  B::Hash: Ord,
+  BPM: BlockPusherMaker<B>,
+  Aux: AuxStore + Send + Sync + 'static,
+{
+  /// Remember the block `just` signs, and report its validator if it signed another block
+  /// of the same height before.
+  pub fn check_equivocation(&mut self, just: &BadgerJustification<B>, header: &B::Header)
+  {
+    let finalized = self.client.info().finalized_number;
+    let oldest = finalized.saturating_sub(EQUIVOCATION_WINDOW.into());
+    let faults = &mut self.faults;
+    faults.signed.retain(|(num, _), _| *num >= oldest);
+    if *header.number() < oldest
+    {
+      return;
+    }
+    let key = (*header.number(), just.validator.clone());
+    let first = match faults.signed.get(&key)
+    {
+      None =>
+      {
+        faults.signed.insert(key, Some((header.clone(), just.sgn.clone())));
+        return;
+      }
+      Some(None) => return,
+      Some(Some(first)) => first.clone(),
+    };
+    if first.0.hash() == just.hash
+    {
+      return;
+    }
+    warn!("Validator {:?} signed two blocks at height {:?}", &just.validator, header.number());
+    faults.signed.insert(key, None);
+    faults.push(BadgerFaultReport::Equivocation(EquivocationProof {
+      offender: just.validator.clone(),
+      first: first,
+      second: (header.clone(), just.sgn.clone()),
+    }));
+  }
+
+  /// Turn the faults `hbbft` logged since the last call into signed reports
+  pub fn collect_faults(&mut self)
+  {
+    let faulty = match self.state
+    {
+      BadgerState::Badger(ref mut node) => std::mem::replace(&mut node.faulty, Vec::new()),
+      _ => return,
+    };
+    if faulty.is_empty() || !self.is_authority()
+    {
+      return;
+    }
+    self.load_origin();
+    let pair = self.cached_origin.clone().unwrap();
+    let set_id = self.persistent.authority_set.inner.read().set_id;
+    let at = self.client.info().best_number.saturated_into::<u64>();
+    for node_id in faulty
+    {
+      let offender = match self.peers.inner.get(&node_id.0).and_then(|info| info.id.clone())
+      {
+        Some(id) => id,
+        None => continue,
+      };
+      if offender == pair.public() || !self.faults.reported.insert((set_id, offender.clone()))
+      {
+        continue;
+      }
+      let report = FaultReport {
+        offender: offender,
+        reporter: pair.public(),
+        at: at,
+      };
+      let sig = pair.sign(&report.encode());
+      self.faults.push(BadgerFaultReport::Fault(SignedFaultReport { report: report, sig: sig }));
+    }
+  }
+
+  pub fn take_fault_reports(&mut self) -> Vec<BadgerFaultReport<B::Header>>
+  {
+    std::mem::replace(&mut self.faults.pending, Vec::new())
+  }
+}
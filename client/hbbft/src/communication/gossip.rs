@@ -1,6 +1,8 @@
 use runtime_primitives::traits::Block as BlockT;
 //use network::consensus_gossip::{self as network_gossip, MessageIntent, ValidatorContext};
-use badger_primitives::{AuthorityId, AuthorityPair, AuthoritySignature};
+use badger_primitives::{AuthorityId, AuthorityPair, AuthoritySignature, SetId};
+use badger::crypto::{Ciphertext, PublicKey, PublicKeySet, SecretKey, Signature, SignatureShare};
+use threshold_crypto::{PK_SIZE, SIG_SIZE};
 use network::PeerId; //config::Roles,
 use parity_codec::{Decode, Encode};
 
@@ -12,18 +14,20 @@ use log::info;
 use rand::{rngs::OsRng, Rng};
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::time::Duration;
 use substrate_primitives::crypto::Pair; //RuntimeAppPublic
-                                        //use std::time::{ Instant};//Duration
 
 use runtime_primitives::traits::NumberFor;
 use app_crypto::RuntimeAppPublic;
 
 //const REBROADCAST_AFTER: Duration = Duration::from_secs(60 * 5);
-//const CATCH_UP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+pub const CATCH_UP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 //const CATCH_UP_PROCESS_TIMEOUT: Duration = Duration::from_secs(15);
-/// Maximum number of rounds we are behind a peer before issuing a
+/// Maximum number of blocks we are behind a peer before issuing a
 /// catch up request.
-//const CATCH_UP_THRESHOLD: u64 = 2;
+pub const CATCH_UP_THRESHOLD: u32 = 2;
+/// Maximum number of blocks sent in a single catch up response.
+pub const MAX_CATCH_UP_BLOCKS: u32 = 64;
 
 //const KEEP_RECENT_ROUNDS: usize = 3;
 
@@ -44,7 +48,17 @@ pub enum GossipMessage<Block: BlockT>
   JustificationData(BadgerJustification<Block>),
 
   /// Full block justification data to facilitate initial sync 
-  SyncGossip(BadgerSyncGossip<Block>)
+  SyncGossip(BadgerSyncGossip<Block>),
+  /// Request for a range of justified blocks, sent when we fall behind
+  CatchUpRequest(BadgerCatchUpRequest<Block>),
+  /// Justified blocks sent in reply to a catch up request
+  CatchUpResponse(BadgerCatchUpResponse<Block>),
+  /// Decryption shares for the encrypted transactions of a batch
+  DecryptionShares(BadgeredMessage),
+  /// Join plan for observers added to the validator set
+  JoinPlan(BadgerJoinPlan),
+  /// Badger data for a single validator, encrypted to its node key
+  SealedBadgerData(SealedBadgeredMessage),
 }
 
 #[derive(Encode, Decode, Debug,Clone)]
@@ -53,6 +67,10 @@ pub struct BadgerJustification<Block: BlockT>
 	pub hash: Block::Hash,
   pub validator:AuthorityId,
   pub sgn: AuthoritySignature,
+  /// Threshold signature share over `hash.encode()`, as `SignatureShare::to_bytes`
+  pub share: Vec<u8>,
+  /// Signature over the committee public key the share belongs to, used for key attestations
+  pub key_sgn: AuthoritySignature,
 }
 
 
@@ -65,12 +83,24 @@ pub struct BadgerAuthCommit
   pub sgn: AuthoritySignature,
 }
 
+/// Master public key of a committee, vouched for by its members
+#[derive(Encode, Decode, Debug,Clone)]
+pub struct BadgerKeyAttestation
+{
+  /// `PublicKey::to_bytes` of the committee key
+  pub public_key: Vec<u8>,
+  /// Signatures of authorities over `public_key`
+  pub commits: Vec<BadgerAuthCommit>,
+}
 
 #[derive(Encode, Decode, Debug,Clone)]
 pub struct BadgerFullJustification<Block: BlockT> 
 {
 	pub hash: Block::Hash,
-  pub commits:Vec<BadgerAuthCommit>,
+  /// Combined threshold signature of the committee over `hash.encode()`, as `Signature::to_bytes`
+  pub sig: Vec<u8>,
+  /// Set on the first justification signed by a new committee key
+  pub key: Option<BadgerKeyAttestation>,
 }
 
 
@@ -91,9 +121,9 @@ pub struct BadgerSyncGossip<Block: BlockT>
 
 impl<Block: BlockT> BadgerSyncGossip<Block>
 {
+  /// Checks the sender signature only, the justification needs the committee key
   pub fn verify(&self) -> bool
   {
-    if ! self.data.justification.verify() {return false};
     badger_primitives::app::Public::verify(&self.source, &self.data.encode(), &self.sgn)
 
   }
@@ -115,19 +145,175 @@ impl<Block: BlockT> BadgerSyncGossip<Block>
 
 
 
+impl BadgerKeyAttestation
+{
+  pub fn public_key(&self) -> Option<PublicKey>
+  {
+    public_key_from_slice(&self.public_key)
+  }
+
+  /// Check that all commits are valid and that more than `max_faulty` members of
+  /// `authorities` vouched for the key, so at least one of them is honest.
+  pub fn verify(&self, authorities: &[AuthorityId]) -> bool
+  {
+    if self.public_key().is_none()
+    {
+      return false;
+    }
+    for commit in self.commits.iter()
+    {
+      if !badger_primitives::app::Public::verify(&commit.validator, &self.public_key, &commit.sgn)
+      {
+        return false;
+      }
+    }
+    let count_accepted = authorities
+      .iter()
+      .filter(|authority| self.commits.iter().any(|x| x.validator == **authority))
+      .count();
+    count_accepted > badger::util::max_faulty(authorities.len())
+  }
+}
+
 impl<Block: BlockT> BadgerFullJustification<Block>
+{
+  /// Check the threshold signature against the committee key `key`.
+  pub fn verify(&self, key: &PublicKey) -> bool
+  {
+    if self.sig.len() != SIG_SIZE
+    {
+      return false;
+    }
+    let mut bytes = [0u8; SIG_SIZE];
+    bytes.copy_from_slice(&self.sig);
+    match Signature::from_bytes(bytes)
+    {
+      Ok(sig) => key.verify(&sig, &self.hash.encode()),
+      Err(_) => false,
+    }
+  }
+
+  /// Check the justification with the key it attests to, or with `known` if it carries none.
+  /// Attestations need to be vouched for by `authorities`.
+  /// Returns the key the justification was signed with.
+  pub fn check(&self, known: Option<&PublicKey>, authorities: &[AuthorityId]) -> Option<PublicKey>
+  {
+    let key = match self.key
+    {
+      Some(ref attestation) if attestation.verify(authorities) => attestation.public_key()?,
+      Some(_) => return None,
+      None => known?.clone(),
+    };
+    if self.verify(&key)
+    {
+      Some(key)
+    }
+    else
+    {
+      None
+    }
+  }
+}
+
+pub fn public_key_from_slice(bytes: &[u8]) -> Option<PublicKey>
+{
+  if bytes.len() != PK_SIZE
+  {
+    return None;
+  }
+  let mut arr = [0u8; PK_SIZE];
+  arr.copy_from_slice(bytes);
+  PublicKey::from_bytes(arr).ok()
+}
+
+pub fn signature_share_from_slice(bytes: &[u8]) -> Option<SignatureShare>
+{
+  if bytes.len() != SIG_SIZE
+  {
+    return None;
+  }
+  let mut arr = [0u8; SIG_SIZE];
+  arr.copy_from_slice(bytes);
+  SignatureShare::from_bytes(arr).ok()
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerCatchUpRange<Block: BlockT>
+{
+  pub from: NumberFor<Block>,
+  pub to: NumberFor<Block>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerCatchUpRequest<Block: BlockT>
+{
+  pub range: BadgerCatchUpRange<Block>,
+  pub source: AuthorityId,
+  pub sgn: AuthoritySignature,
+}
+
+impl<Block: BlockT> BadgerCatchUpRequest<Block>
 {
   pub fn verify(&self) -> bool
   {
-    let enc=self.hash.encode();
-    for commit in self.commits.iter(){
-     if ! badger_primitives::app::Public::verify(&commit.validator, &enc, &commit.sgn)
-     {
-       return false;
-     }
+    badger_primitives::app::Public::verify(&self.source, &self.range.encode(), &self.sgn)
+  }
+  pub fn new(originator: &AuthorityPair, from: NumberFor<Block>, to: NumberFor<Block>) -> BadgerCatchUpRequest<Block>
+  {
+    let range = BadgerCatchUpRange { from: from, to: to };
+    let sig = originator.sign(&range.encode());
+    BadgerCatchUpRequest {
+      range: range,
+      source: originator.public(),
+      sgn: sig,
+    }
+  }
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerJustifiedBlock<Block: BlockT>
+{
+  pub block: Block,
+  pub justification: BadgerFullJustification<Block>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerCatchUpData<Block: BlockT>
+{
+  pub blocks: Vec<BadgerJustifiedBlock<Block>>,
+  /// Number of the responder's best block, so we know if more requests are needed
+  pub best: NumberFor<Block>,
+  /// (era, epoch) of the next batch the responder expects, if it is running Badger
+  pub next_epoch: Option<(u64, u64)>,
+  /// Transactions the responder carries over to the block after its best block, with the hash
+  /// of that block
+  pub overflow: Option<(Block::Hash, Vec<Vec<u8>>)>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerCatchUpResponse<Block: BlockT>
+{
+  pub data: BadgerCatchUpData<Block>,
+  pub source: AuthorityId,
+  pub sgn: AuthoritySignature,
+}
+
+impl<Block: BlockT> BadgerCatchUpResponse<Block>
+{
+  /// Checks the responder signature only, each block justification is checked
+  /// against the authority set at its height while importing.
+  pub fn verify(&self) -> bool
+  {
+    badger_primitives::app::Public::verify(&self.source, &self.data.encode(), &self.sgn)
+  }
+  pub fn new(originator: &AuthorityPair, data: BadgerCatchUpData<Block>) -> BadgerCatchUpResponse<Block>
+  {
+    let sig = originator.sign(&data.encode());
+    BadgerCatchUpResponse {
+      data: data,
+      source: originator.public(),
+      sgn: sig,
     }
-    return true;
-   
   }
 }
 
@@ -135,10 +321,70 @@ impl<Block: BlockT> BadgerFullJustification<Block>
 
 impl<Block: BlockT> BadgerJustification<Block>
 {
+  pub fn new(originator: &AuthorityPair, hash: Block::Hash, share: &SignatureShare, key: &PublicKey) -> BadgerJustification<Block>
+  {
+    BadgerJustification {
+      hash: hash.clone(),
+      validator: originator.public(),
+      sgn: originator.sign(&hash.encode()),
+      share: share.to_bytes().to_vec(),
+      key_sgn: originator.sign(&key.to_bytes()),
+    }
+  }
+
   pub fn verify(&self) -> bool
   {
     badger_primitives::app::Public::verify(&self.validator, &self.hash.encode(), &self.sgn)
   }
+
+  /// Check the share and the key signature against `key_set`, `index` being the index of
+  /// the validator in it.
+  pub fn verify_share(&self, key_set: &PublicKeySet, index: usize) -> bool
+  {
+    let share = match signature_share_from_slice(&self.share)
+    {
+      Some(share) => share,
+      None => return false,
+    };
+    key_set.public_key_share(index).verify(&share, &self.hash.encode())
+      && badger_primitives::app::Public::verify(&self.validator, &key_set.public_key().to_bytes().to_vec(), &self.key_sgn)
+  }
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerJoinPlanData
+{
+  /// Authority set the change was voted in
+  pub set_id: SetId,
+  /// Authorities the change adds to the set
+  pub added: Vec<AuthorityId>,
+  /// `JoinPlan` of the change, bincode encoded
+  pub plan: Vec<u8>,
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct BadgerJoinPlan
+{
+  pub data: BadgerJoinPlanData,
+  pub source: AuthorityId,
+  pub sgn: AuthoritySignature,
+}
+
+impl BadgerJoinPlan
+{
+  pub fn verify(&self) -> bool
+  {
+    badger_primitives::app::Public::verify(&self.source, &self.data.encode(), &self.sgn)
+  }
+  pub fn new(originator: &AuthorityPair, data: BadgerJoinPlanData) -> BadgerJoinPlan
+  {
+    let sig = originator.sign(&data.encode());
+    BadgerJoinPlan {
+      data: data,
+      source: originator.public(),
+      sgn: sig,
+    }
+  }
 }
 
 impl<B:BlockT> GossipMessage<B>
@@ -152,6 +398,11 @@ impl<B:BlockT> GossipMessage<B>
       GossipMessage::Session(data) => data.verify(),
       GossipMessage::JustificationData(data) =>data.verify(),
       GossipMessage::SyncGossip(data) =>data.verify(),
+      GossipMessage::CatchUpRequest(data) =>data.verify(),
+      GossipMessage::CatchUpResponse(data) =>data.verify(),
+      GossipMessage::DecryptionShares(data) =>data.verify(),
+      GossipMessage::JoinPlan(data) =>data.verify(),
+      GossipMessage::SealedBadgerData(data) =>data.verify(),
     }
   }
 }
@@ -183,6 +434,68 @@ impl BadgeredMessage
   }
 }
 
+/// Badger message for a single validator, encrypted to its node key.
+///
+/// The signature covers the recipient and the ciphertext, so a relay can neither read, forge
+/// nor redirect the message. The originator is repeated inside the plaintext, so a relay
+/// can't sign someone else's ciphertext as its own either.
+#[derive(Debug, Encode, Decode)]
+pub struct SealedBadgeredMessage
+{
+  pub uid: u64,
+  pub recipient: AuthorityId,
+  /// bincode-serialized `Ciphertext` of `(originator, data).encode()`
+  pub ciphertext: Vec<u8>,
+  pub originator: AuthorityId,
+  pub sig: AuthoritySignature,
+}
+
+impl SealedBadgeredMessage
+{
+  pub fn seal(originator: &AuthorityPair, recipient: &AuthorityId, data: &[u8]) -> SealedBadgeredMessage
+  {
+    let key: PublicKey = recipient.clone().into();
+    let ct = key.encrypt(&(originator.public(), data).encode());
+    let ciphertext = bincode::serialize(&ct).expect("Serialize error in sealed message");
+    let uid = OsRng::new().unwrap().gen::<u64>();
+    let sig = originator.sign(&(uid, recipient, &ciphertext).encode());
+    SealedBadgeredMessage {
+      uid: uid,
+      recipient: recipient.clone(),
+      ciphertext: ciphertext,
+      originator: originator.public(),
+      sig: sig,
+    }
+  }
+
+  pub fn verify(&self) -> bool
+  {
+    badger_primitives::app::Public::verify(
+      &self.originator,
+      &(self.uid, &self.recipient, &self.ciphertext).encode(),
+      &self.sig,
+    )
+  }
+
+  /// Decrypt with the recipient's secret key. `None` if the message is not for `secret`
+  /// or was tampered with.
+  pub fn open(&self, secret: &SecretKey) -> Option<Vec<u8>>
+  {
+    if AuthorityId::from(secret.public_key()) != self.recipient
+    {
+      return None;
+    }
+    let ct: Ciphertext = bincode::deserialize(&self.ciphertext).ok()?;
+    let plain = secret.decrypt(&ct)?;
+    let (originator, data): (AuthorityId, Vec<u8>) = Decode::decode(&mut &plain[..]).ok()?;
+    if originator != self.originator
+    {
+      return None;
+    }
+    Some(data)
+  }
+}
+
 #[derive(Debug, Encode, Decode)]
 pub struct SessionData
 {
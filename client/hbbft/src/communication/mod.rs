@@ -1,7 +1,7 @@
-use badger::dynamic_honey_badger::DynamicHoneyBadger;
+use badger::dynamic_honey_badger::{DynamicHoneyBadger, JoinPlan};
 use badger::queueing_honey_badger::QueueingHoneyBadger;
 use badger::sender_queue::{Message as BMessage, SenderQueue};
-use badger::sync_key_gen::{Ack, AckOutcome, Part, PartOutcome, PubKeyMap, SyncKeyGen};//AckFault
+use badger::sync_key_gen::{Ack, AckFault, AckOutcome, Part, PartFault, PartOutcome, PubKeyMap, SyncKeyGen};
 use keystore::KeyStorePtr;
 use runtime_primitives::generic::BlockId;
 use sc_api::{AuxStore};
@@ -11,6 +11,7 @@ use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 //use consensus_common::{self, BlockImportParams, BlockOrigin, ForkChoiceStrategy, SelectChain, Error as ConsensusError,};
 use gossip::BadgerSyncGossip;
 use gossip::BadgerJustification;
@@ -21,7 +22,7 @@ use runtime_primitives::{
 };
 use sc_network_ranting::RawMessage;
 use badger::dynamic_honey_badger::ChangeState;
-use badger_primitives::BadgerPreRuntime;
+use badger_primitives::{BadgerFaultReport, BadgerPreRuntime};
 use runtime_primitives::traits::{NumberFor,One};
 use consensus_common::evaluation;
 use sc_network_ranting::{ Network as RantingNetwork};
@@ -29,10 +30,10 @@ use sc_network_ranting::{ Network as RantingNetwork};
 use sc_network_ranting::ValidationResult;
 use badger_primitives::ConsensusLog;
 use gossip::BadgerSyncData;
-use gossip::{BadgerFullJustification,BadgerAuthCommit};
+use gossip::{BadgerFullJustification,BadgerAuthCommit,BadgerKeyAttestation};
 //use badger::dynamic_honey_badger::KeyGenMessage::Ack;
 use crate::aux_store::BadgerPersistentData;
-use badger::crypto::{PublicKey, PublicKeySet, SecretKey, SecretKeyShare}; //PublicKeyShare, Signature
+use badger::crypto::{Ciphertext, DecryptionShare, PublicKey, PublicKeySet, SecretKey, SecretKeyShare, SignatureShare}; //PublicKeyShare, Signature
 use badger::{dynamic_honey_badger::Change, ConsensusProtocol, CpStep, NetworkInfo, Target};
 use futures03::channel::{mpsc, oneshot};
 use futures03::prelude::*;
@@ -42,14 +43,15 @@ use badger::honey_badger::EncryptionSchedule;
 use log::{debug, info, trace, warn,error};
 use parity_codec::{Decode, Encode};
 use parking_lot::RwLock;
-use rand::{rngs::OsRng, };
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 //
 //};//
 pub use badger_primitives::HBBFT_ENGINE_ID;
 use badger_primitives::{AuthorityId, AuthorityList, AuthorityPair};
-use gossip::{BadgeredMessage, GossipMessage, Peers,  SessionData, SessionMessage};
+use gossip::{BadgeredMessage, GossipMessage, Peers, SealedBadgeredMessage, SessionData, SessionMessage};
 use network::config::Roles;
 use sc_network_ranting::ValidatorContext;
 use sc_network_ranting::RantingEngine;
@@ -63,8 +65,27 @@ use substrate_primitives::crypto::Pair;
 use substrate_telemetry::{telemetry, CONSENSUS_DEBUG};
 
 pub mod gossip;
+pub mod batch_policy;
+use batch_policy::{BatchToBlockPolicy, Leftover};
+mod catch_up;
+pub use catch_up::CatchUpProgress;
+mod clock;
+use clock::Clock;
+mod decrypt;
+pub use decrypt::{is_encrypted, wrap_encrypted, ENCRYPTED_TX_PREFIX};
+mod faults;
+mod observer;
+#[cfg(test)]
+pub(crate) mod adversary;
+#[cfg(test)]
+mod sim;
+#[cfg(test)]
+mod tests;
 
 use crate::Error;
+use crate::rpc::{
+  change_state_name, BadgerControl, BadgerIntrospect, BadgerStatus, ChangeInfo, ChangeStateInfo, PendingJustification,
+};
 
 pub const MAX_DELAYED_JUSTIFICATIONS:u64=10 ;
 //use badger_primitives::NodeId;
@@ -94,15 +115,41 @@ pub enum BlockPushResult
   InvalidData,
   Pushed,
   BlockFull,
-  BlockError
+  /// The transaction is invalid and is banned from the pool
+  BlockError,
+  /// The transaction can't be applied on top of this block, e.g. its nonce is ahead of the
+  /// sender's, but it may become valid later and stays in the pool
+  Skipped,
+}
 
+/// Classify an extrinsic `BlockBuilder::push` refused. Only a transaction the runtime finds
+/// invalid for good is banned: one that is valid in the future only came before a transaction it
+/// depends on, which another validator may have contributed to the same batch.
+pub fn push_error_result(e: &sp_blockchain::Error) -> BlockPushResult
+{
+  use runtime_primitives::transaction_validity::{InvalidTransaction, TransactionValidityError};
+  match e
+  {
+    sp_blockchain::Error::ApplyExtrinsicFailed(sp_blockchain::ApplyExtrinsicFailed::Validity(e)) => match e
+    {
+      _ if e.exhausted_resources() => BlockPushResult::BlockFull,
+      TransactionValidityError::Invalid(InvalidTransaction::Future) => BlockPushResult::Skipped,
+      TransactionValidityError::Invalid(_) => BlockPushResult::BlockError,
+      TransactionValidityError::Unknown(_) => BlockPushResult::Skipped,
+    },
+    _ => BlockPushResult::Skipped,
+  }
 }
 
 
 
 pub trait BlockPusherMaker<B:BlockT> :Send+Sync
 {
-  fn process_all(&mut self,is: BlockId<B>,digest:generic::Digest<B::Hash>,locked: &mut VecDeque<BadgerTransaction>,batched: impl Iterator<Item=BadgerTransaction>) ->Result<B,()>;
+  /// Build a block on `is` out of `txs`, returns it with the transactions that did not fit, in order,
+  /// and the ones that failed to apply
+  fn process_all(&mut self,is: BlockId<B>,digest:generic::Digest<B::Hash>,txs: impl Iterator<Item=BadgerTransaction>) ->Result<(B,Vec<BadgerTransaction>,Vec<BadgerTransaction>),()>;
+  /// Ban transactions that failed to apply, so that they are not contributed again
+  fn report_rejected(&self, rejected: Vec<BadgerTransaction>);
   //fn make_pusher<'a>(&self,is: BlockId<B> , digest: generic::Digest<B::Hash> )->Result<Self::Pusher,()>;
   fn best_chain(&self)->Result< <B as BlockT>::Header,()>;
   fn import_block(&self,import_block: BlockImportParams<B>) ->Result<(),()>;
@@ -193,6 +240,33 @@ pub type BadgerTransaction = Vec<u8>;
 pub type QHB = SenderQueue<QueueingHoneyBadger<BadgerTransaction, NodeId, Vec<BadgerTransaction>>>;
 pub type BatchType=<QHB as ConsensusProtocol>::Output;
 
+fn change_info(change: &Change<NodeId>) -> ChangeInfo
+{
+  match change
+  {
+    Change::NodeChange(keys) => ChangeInfo::NodeChange(
+      keys
+        .iter()
+        .map(|(_, k)| {
+          let id: AuthorityId = k.clone().into();
+          format!("0x{}", hex::encode(id.encode()))
+        })
+        .collect(),
+    ),
+    Change::EncryptionSchedule(e) => ChangeInfo::EncryptionSchedule(e.clone().into()),
+  }
+}
+
+/// (era, epoch) of the batch that follows `batch`. A completed change starts a new era.
+pub fn next_epoch_after(batch: &BatchType) -> (u64, u64)
+{
+  match batch.change()
+  {
+    ChangeState::Complete(_) => (batch.epoch() + 1, 0),
+    _ => (batch.era(), batch.epoch() - batch.era() + 1),
+  }
+}
+
 pub struct BadgerNode<B: BlockT, D>
 where
   D: ConsensusProtocol<NodeId = NodeId>, //specialize to avoid some of the confusion
@@ -201,7 +275,7 @@ where
   //id: PeerId,
   node_id: NodeId,
   algo: D,
-  main_rng: OsRng,
+  main_rng: StdRng,
 
   //peers: Peers,
   //authorities: Vec<AuthorityId>,
@@ -213,6 +287,12 @@ where
   pub out_queue: VecDeque<SourcedMessage<D,B>>,
   /// The values this node has output so far, with timestamps.
   pub outputs: VecDeque<D::Output>,
+  /// Our vote for the current era, if any
+  pub pending_vote: Option<Change<NodeId>>,
+  /// Transactions we have input that have not been output in a batch yet
+  pub pending_transactions: VecDeque<BadgerTransaction>,
+  /// Nodes `hbbft` logged a fault for, not reported yet
+  pub faulty: Vec<NodeId>,
   _block: PhantomData<B>,
 }
 
@@ -235,6 +315,16 @@ pub struct KeyGenState
   pub expected_acks: VecDeque<(NodeId, VecDeque<NodeId>)>,
   pub buffered_messages: Vec<(NodeId, SyncKeyGenMessage)>,
   pub is_done: bool,
+  /// Keygen round, bumped every time keygen is restarted after a timeout
+  pub attempt: u32,
+  /// Part and Acks we generated, resent periodically for validators that missed them
+  pub own_messages: Vec<SyncKeyGenMessage>,
+  /// Validators that already moved on to the next attempt
+  pub next_attempt: BTreeSet<NodeId>,
+  pub last_progress: Instant,
+  pub last_resend: Instant,
+  pub rng: StdRng,
+  pub clock: Arc<dyn Clock>,
   //pub ack: Option<(NodeId,AckOutcome)>,
   //pub step:KeyGenStep,
   //pub keyset:PublicKeySet,
@@ -295,7 +385,7 @@ impl KeyGenState
           return false;
         }
       }
-      SyncKeyGenMessage::Ack(src, _) =>
+      SyncKeyGenMessage::Ack(src, _) | SyncKeyGenMessage::Complaint(src) =>
       {
         if !self.expected_parts.is_empty()
         {
@@ -316,13 +406,13 @@ impl KeyGenState
   }
   pub fn process_part(&mut self, sender: &NodeId, part: Part) -> Vec<SyncKeyGenMessage>
   {
-    let mut rng = rand::rngs::OsRng::new().expect("Could not open OS random number generator.");
-    let outcome = match self.keygen.handle_part(sender, part, &mut rng)
+    let outcome = match self.keygen.handle_part(sender, part, &mut self.rng)
     {
       Ok(outcome) => outcome,
       Err(e) =>
       {
         warn!("Failed processing part from {:?} {:?}", sender, e);
+        self.drop_faulty(sender);
         return Vec::new();
       }
     };
@@ -334,9 +424,17 @@ impl KeyGenState
         self.expected_parts.pop_front();
         ret.push(SyncKeyGenMessage::Ack(sender.clone(), ack));
       }
+      PartOutcome::Invalid(ref fault) if is_row_fault(fault) =>
+      {
+        // the others may have got a valid row, the Part stays and we tell them we can't ack it
+        warn!("Invalid row in the Part from {:?} :{:?}", sender, fault);
+        self.expected_parts.pop_front();
+        ret.push(SyncKeyGenMessage::Complaint(sender.clone()));
+      }
       PartOutcome::Invalid(fault) =>
       {
         warn!("Faulty Part from {:?} :{:?}", sender, fault);
+        self.drop_faulty(sender);
       }
       PartOutcome::Valid(None) =>
       {
@@ -356,6 +454,7 @@ impl KeyGenState
       Err(e) =>
       {
         info!("Invalid Ack from {:?} : {:?}", sender, e);
+        self.drop_faulty(sender);
         return;
       }
     };
@@ -363,12 +462,30 @@ impl KeyGenState
     {
       AckOutcome::Valid =>
       {}
+      AckOutcome::Invalid(ref fault) if is_value_fault(fault) =>
+      {
+        // the Ack is counted alike everywhere, only our own share lacks its value
+        info!("Invalid value in the Ack from {:?}: {:?}", sender, fault);
+      }
       AckOutcome::Invalid(fault) =>
       {
         info!("Could not process Ack: {:?}", fault);
+        self.drop_faulty(sender);
         return;
       }
     }
+    self.next_ack_processed();
+  }
+
+  /// `sender` could not ack the Part of `src`, it counts as one ack less for that Part
+  pub fn process_complaint(&mut self, sender: &NodeId, src: &NodeId)
+  {
+    info!("{:?} could not ack the Part of {:?}", sender, src);
+    self.next_ack_processed();
+  }
+
+  fn next_ack_processed(&mut self)
+  {
     self.expected_acks[0].1.pop_front();
     if self.expected_acks[0].1.is_empty()
     {
@@ -379,6 +496,38 @@ impl KeyGenState
       }
     }
   }
+  /// Stop waiting for a validator that sent an invalid part or ack.
+  ///
+  /// Gossip delivers keygen messages in any order, but they are only processed in the order of
+  /// `expected_parts` and `expected_acks`, which is the same everywhere; the rest is buffered.
+  /// This is only called for faults found in the message itself, which every validator that
+  /// processes the same message finds too. Faults in the values encrypted to us alone are
+  /// handled through `SyncKeyGenMessage::Complaint` instead, so every honest validator drops
+  /// the same validators at the same point and ends up with the same key set. A validator that
+  /// signs different messages for the same slot is not caught here.
+  pub fn drop_faulty(&mut self, who: &NodeId)
+  {
+    warn!("Ignoring further keygen messages of {:?}", who);
+    if self.expected_parts.contains(who)
+    {
+      // its part was never accepted, nobody will ack it
+      self.expected_parts.retain(|x| x != who);
+      self.expected_acks.retain(|(src, _)| src != who);
+    }
+    for (_, ackers) in self.expected_acks.iter_mut()
+    {
+      ackers.retain(|x| x != who);
+    }
+    self.buffered_messages.retain(|(snd, _)| snd != who);
+    if self.expected_acks.is_empty()
+    {
+      self.is_done = true;
+    }
+    else
+    {
+      self.clean_queues();
+    }
+  }
   pub fn maybe_buffer(&mut self, sender: &NodeId, msg: SyncKeyGenMessage)
   {
     if let SyncKeyGenMessage::Part(_) = msg
@@ -391,6 +540,15 @@ impl KeyGenState
 
     self.buffered_messages.push((sender.clone(), msg));
   }
+  /// Validators we are still waiting on
+  pub fn missing(&self) -> Vec<NodeId>
+  {
+    if !self.expected_parts.is_empty()
+    {
+      return self.expected_parts.iter().cloned().collect();
+    }
+    self.expected_acks.iter().map(|(src, _)| src.clone()).collect()
+  }
   /// process incoming message and return generated acks, if any
   pub fn process_message(&mut self, sender: &NodeId, msg: SyncKeyGenMessage) -> Vec<SyncKeyGenMessage>
   {
@@ -417,7 +575,9 @@ loop
       {
         SyncKeyGenMessage::Part(parted) => ret.append(&mut self.process_part(&spl, parted)),
         SyncKeyGenMessage::Ack(src, ack) => self.process_ack(&spl, &src, ack),
+        SyncKeyGenMessage::Complaint(src) => self.process_complaint(&spl, &src),
       };
+      self.last_progress = self.clock.now();
       let index = self
         .buffered_messages
         .iter()
@@ -447,6 +607,26 @@ loop
   }
 }
 
+/// Faults in the row of a Part encrypted to us, other validators may have got a valid row
+fn is_row_fault(fault: &PartFault) -> bool
+{
+  match fault
+  {
+    PartFault::DecryptRow | PartFault::DeserializeRow | PartFault::RowCommitment => true,
+    _ => false,
+  }
+}
+
+/// Faults in the value of an Ack encrypted to us, other validators may have got a valid value
+fn is_value_fault(fault: &AckFault) -> bool
+{
+  match fault
+  {
+    AckFault::DecryptValue | AckFault::DeserializeValue | AckFault::ValueCommitment => true,
+    _ => false,
+  }
+}
+
 //#[derive(Encode,Decode)]
 pub struct SharedConfig
 {
@@ -456,6 +636,7 @@ pub struct SharedConfig
   pub my_peer_id: PeerId,
   pub my_auth_id: AuthorityId,
   pub batch_size: u64,
+  pub batch_policy: Arc<dyn BatchToBlockPolicy>,
 }
 
 pub enum BadgerState<B: BlockT, D>
@@ -473,15 +654,39 @@ where
   /// Running completed Badger node
   Badger(BadgerNode<B, D>),
 
-  /// need a Join Plan to become observer
-  AwaitingJoinPlan,
+  /// Following finalized blocks outside of the authority set, until a join plan adds us
+  Observer,
+
+  /// Importing justified blocks from peers after falling behind
+  CatchingUp,
+}
+
+impl<B: BlockT, D> BadgerState<B, D>
+where
+  D: ConsensusProtocol<NodeId = NodeId>,
+  D::Message: Serialize + DeserializeOwned,
+{
+  pub fn name(&self) -> &'static str
+  {
+    match self
+    {
+      BadgerState::AwaitingValidators => "AwaitingValidators",
+      BadgerState::InitialSync => "InitialSync",
+      BadgerState::KeyGen(_) => "KeyGen",
+      BadgerState::Badger(_) => "Badger",
+      BadgerState::Observer => "Observer",
+      BadgerState::CatchingUp => "CatchingUp",
+    }
+  }
 }
 
 pub struct JustificationCollector<B:BlockT>
 {
  /// contemporary authorities for this block hash
  pub contemp_auth:Option<Vec<AuthorityId>>,
- pub justification:Vec<BadgerJustification<B>>
+ pub justification:Vec<BadgerJustification<B>>,
+ /// committee key set the shares are checked against, and the index of each authority in it
+ pub keys:Option<(PublicKeySet, BTreeMap<AuthorityId, usize>)>,
 }
 use network::ClientHandle as NetClient;
 pub use sp_blockchain::{HeaderBackend,HeaderMetadata};
@@ -489,9 +694,26 @@ pub use sp_blockchain::{HeaderBackend,HeaderMetadata};
 pub struct BatchBlockMechanics<B:BlockT>
 {
   queued_block:Option<B>, //block awaiting finalization
+  /// blocks of a split batch imported ahead of queued_block, finalized along with it
+  split_headers:Vec<B::Header>,
+  /// transactions `FillBlocks` carries over to the block of the next batch
   overflow:VecDeque<BadgerTransaction>,
+  /// block the overflow was left by, the next block has to be built on top of it
+  overflow_after:Option<B::Hash>,
   queued_batches:VecDeque<BatchType>,
   pending_batch:Option<BatchType>,
+  /// batch waiting for its encrypted transactions to be decrypted
+  awaiting_decryption:Option<BatchType>,
+  /// ciphertexts of awaiting_decryption we don't have enough shares for yet
+  encrypted:BTreeMap<decrypt::TxHash, Ciphertext>,
+  decryption_shares:BTreeMap<decrypt::TxHash, BTreeMap<usize, DecryptionShare>>,
+  /// None if the ciphertext is invalid
+  decrypted:BTreeMap<decrypt::TxHash, Option<BadgerTransaction>>,
+  /// our shares for awaiting_decryption, resent until it is decrypted
+  own_shares:Vec<(decrypt::TxHash, DecryptionShare)>,
+  last_share_resend:Instant,
+  /// shares received while we were not running Badger yet
+  buffered_shares:Vec<(AuthorityId, Vec<(decrypt::TxHash, DecryptionShare)>)>,
 
 }
 pub struct ValidatorSync<B:BlockT>
@@ -507,7 +729,19 @@ pub struct BadgerSyncState<B:BlockT>
 //  pub our_best_block_num: NumberFor<B>,//we might not be a validator... 
 //  pub our_best_block_hash: B::Hash,
   pub initial_sync_done:bool,
-  pub validators:Vec<ValidatorSync<B>>
+  pub validators:Vec<ValidatorSync<B>>,
+  /// (era, epoch) of the next batch, used to resume Badger at the network's epoch
+  pub next_epoch: Option<(u64, u64)>,
+  /// Outstanding catch up, if we fell behind
+  pub catch_up: Option<CatchUpProgress<B>>,
+  /// Vote restored from a checkpoint, cast again once Badger is running
+  pub resumed_vote: Option<Change<NodeId>>,
+  /// Change state reported by the last batch
+  pub change_state: ChangeState<NodeId>,
+  /// Join plans received while observing, the latest of every authority
+  pub join_plans: BTreeMap<AuthorityId, gossip::BadgerJoinPlanData>,
+  /// Join plan we sent for the change in progress and when, resent by the timer
+  pub sent_join_plan: Option<(gossip::BadgerJoinPlan, Instant)>,
 }
 
 
@@ -537,6 +771,15 @@ where
   pub sync_state:BadgerSyncState<B>,
   pub output_message_buffer:Vec<(LocalTarget<B>, GossipMessage<B>)>,
   pub finalizer: Box<dyn FnMut( &B::Hash,Option<Justification>)->bool+Send+Sync>,
+  /// Seeds the randomness of keygen and of every Badger instance we start
+  pub rng: StdRng,
+  /// Time our timers run on
+  pub clock: Arc<dyn Clock>,
+  /// Misbehaviour of other validators we have seen
+  pub faults: faults::FaultTracker<B>,
+  /// Misbehaviour applied to everything we send, for fault injection tests
+  #[cfg(test)]
+  pub adversary: Option<adversary::Adversary<B>>,
 }
 
 const MAX_QUEUE_LEN: usize = 1024;
@@ -556,8 +799,24 @@ pub enum SyncKeyGenMessage
 {
   Part(Part),
   Ack(NodeId, Ack),
+  /// Sent instead of an `Ack` for the Part of this proposer when the row encrypted to us
+  /// is invalid
+  Complaint(NodeId),
+}
+
+/// SyncKeyGen message tagged with the keygen attempt it belongs to
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncKeyGenPacket
+{
+  pub attempt: u32,
+  pub msg: SyncKeyGenMessage,
 }
 
+/// Resend our keygen messages if nothing happened for this long
+pub const KEYGEN_RESEND_INTERVAL: Duration = Duration::from_secs(10);
+/// Restart keygen if nothing happened for this long
+pub const KEYGEN_TIMEOUT: Duration = Duration::from_secs(60);
+
 /* pub struct BadgerContainer<Block: BlockT, N: Network<Block>,Cl>
 where
 Cl:NetClient<Block>,
@@ -801,12 +1060,23 @@ BPM:BlockPusherMaker<B>,
 Aux:AuxStore+Send+Sync+'static,
 {
   pub fn new(
-    keystore: KeyStorePtr, self_peer: PeerId, batch_size: u64, persist: BadgerPersistentData,
-    client:Arc<Cl>,finalizer: Box<dyn FnMut( &B::Hash,Option<Justification>)->bool+Send+Sync>,bbld:BPM,astore:Aux
+    keystore: KeyStorePtr, self_peer: PeerId, batch_size: u64, batch_policy: Arc<dyn BatchToBlockPolicy>,
+    persist: BadgerPersistentData, client:Arc<Cl>,finalizer: Box<dyn FnMut( &B::Hash,Option<Justification>)->bool+Send+Sync>,bbld:BPM,astore:Aux,
+    rng: StdRng, clock: Arc<dyn Clock>,
   ) -> BadgerStateMachine<B, QHB,Cl,BPM,Aux>
   {
     let ap: AuthorityPair;
     let is_ob: bool;
+    let mut next_epoch = None;
+    let mut resumed_vote = None;
+    let mut queued_transactions = Vec::new();
+    let mut peers = Peers::new();
+    let split_headers = aux_store::load_split_headers(&astore);
+    let (overflow_after, overflow) = match aux_store::load_overflow::<_, B::Hash>(&astore)
+    {
+      Some((after, txs)) => (Some(after), txs.into_iter().collect()),
+      None => (None, VecDeque::new()),
+    };
 
     {
       let aset = persist.authority_set.inner.read();
@@ -816,19 +1086,41 @@ Aux:AuxStore+Send+Sync+'static,
         .expect("Needs private key to work (bsm:new)");
       is_ob = !aset.current_authorities.contains(&aset.self_id);
       info!("SELFID: {:?} {:?}", &aset.self_id, &ap.public());
+      if let Some(ref cp) = persist.checkpoint
+      {
+        if cp.set_id == aset.set_id
+        {
+          info!("Resuming from checkpoint at era {:?} epoch {:?}", cp.era, cp.epoch);
+          next_epoch = Some((cp.era, cp.epoch));
+          resumed_vote = cp.vote.as_ref().and_then(|v| bincode::deserialize(v).ok());
+          queued_transactions = cp.queued_transactions.iter().take(MAX_QUEUE_LEN).cloned().collect();
+          for (auth, peer) in cp.peers.iter()
+          {
+            if let Ok(peer) = PeerId::from_bytes(peer.clone())
+            {
+              peers.update_id(&peer, auth.clone());
+            }
+          }
+        }
+        else
+        {
+          info!("Ignoring checkpoint of set {:?}, current set is {:?}", cp.set_id, aset.set_id);
+        }
+      }
     }
-    BadgerStateMachine {
-      state: BadgerState::AwaitingValidators,
-      peers: Peers::new(),
+    let mut bsm = BadgerStateMachine {
+      state: if is_ob { BadgerState::Observer } else { BadgerState::AwaitingValidators },
+      peers: peers,
       config: SharedConfig {
         is_observer: is_ob,
         secret_share: None,
         keyset: None,
         my_peer_id: self_peer.clone(),
         batch_size: batch_size,
+        batch_policy: batch_policy,
         my_auth_id: ap.public(),
       },
-      queued_transactions: Vec::new(),
+      queued_transactions: queued_transactions,
       keystore: keystore.clone(),
       cached_origin: Some(ap),
       persistent: persist,
@@ -840,19 +1132,47 @@ Aux:AuxStore+Send+Sync+'static,
       block_maker:bbld,
       mech: BatchBlockMechanics {
         queued_block:None,
-        overflow:VecDeque::new(),
+        split_headers:split_headers,
+        overflow:overflow,
+        overflow_after:overflow_after,
         queued_batches:VecDeque::new(),
         pending_batch:None,
+        awaiting_decryption:None,
+        encrypted:BTreeMap::new(),
+        decryption_shares:BTreeMap::new(),
+        decrypted:BTreeMap::new(),
+        own_shares:Vec::new(),
+        last_share_resend:clock.now(),
+        buffered_shares:Vec::new(),
       },
       sync_state:
         BadgerSyncState {
           initial_sync_done:false,
-          validators:Vec::new()
+          validators:Vec::new(),
+          next_epoch:next_epoch,
+          catch_up:None,
+          resumed_vote:resumed_vote,
+          change_state:ChangeState::None,
+          join_plans:BTreeMap::new(),
+          sent_join_plan:None,
         }
       ,
       finalizer:finalizer,
       output_message_buffer:Vec::new(),
+      rng:rng,
+      clock:clock,
+      faults:faults::FaultTracker::new(),
+      #[cfg(test)]
+      adversary:None,
+    };
+    // a restarted validator goes straight back to the epoch it stopped at
+    if !is_ob && bsm.sync_state.next_epoch.is_some() && bsm.all_validators_known() && bsm.load_key_share()
+    {
+      info!("Restoring Badger from checkpoint");
+      let mut msgs = bsm.proceed_to_badger();
+      bsm.output_message_buffer.append(&mut msgs);
     }
+    bsm
     /*pub struct ValidatorSync<B:BlockT>
 {
   pub best_block_num: NumberFor<B>,
@@ -955,6 +1275,7 @@ pub struct BadgerSyncState<B:BlockT>
       let mut aset = self.persistent.authority_set.inner.write();
       aset.current_authorities = new_validators.iter().map(|(_, v)| v.clone()).collect();
       aset.set_id = nex_set;
+      self.config.is_observer = !aset.current_authorities.contains(&aset.self_id);
       match aux_store::update_authority_set(&aset, |insert| self.aux_backend.insert_aux(insert, &[]))
       {
         Ok(_) =>
@@ -985,31 +1306,85 @@ pub struct BadgerSyncState<B:BlockT>
       if let Some(batch)=obatch
       {
         info!("Pushing Batch with epoch {:?}",batch.epoch());
+       self.sync_state.next_epoch=Some(next_epoch_after(&batch));
+       self.sync_state.change_state=batch.change().clone();
+       match batch.change()
+       {
+         ChangeState::InProgress(Change::NodeChange(_)) => {},
+         // the change is over, stop resending its join plan
+         _ => self.sync_state.sent_join_plan = None,
+       }
+       self.save_checkpoint(&batch);
        self.mech.queued_batches.push_back(batch);
       }
-      if  self.mech.queued_block.is_some()
+      if  self.mech.queued_block.is_some() || self.mech.awaiting_decryption.is_some()
       {
         return;
       }
       let mbatch=self.mech.queued_batches.pop_front();
       if let Some(batch)=mbatch
       {
-     
-        match self.batch_to_block(batch) 
-        {
-        BatchProcResult::EmitJustification(hash,alist,logs) => 
-         {   
-          self.initiate_block_justification(hash,alist);
-          self.process_extracted(logs);
-           },
-         BatchProcResult::Completed(logs) =>{ self.process_extracted(logs); },
-         _ =>{}
-          }
+        let res=self.batch_to_block(batch);
+        self.handle_batch_result(res);
       }
     }
 
   }
 
+  pub fn handle_batch_result(&mut self, res:BatchProcResult<B>)
+  {
+    match res
+    {
+      BatchProcResult::EmitJustification(hash,alist,logs) => 
+      {   
+        self.initiate_block_justification(hash,alist);
+        self.process_extracted(logs);
+      },
+      BatchProcResult::Completed(logs) =>{ self.process_extracted(logs); },
+      _ =>{}
+    }
+  }
+
+  /// Write the state needed to resume after `batch` to the aux store.
+  fn save_checkpoint(&mut self, batch: &BatchType)
+  {
+    let node = match self.state
+    {
+      BadgerState::Badger(ref mut node) => node,
+      _ => return,
+    };
+    node.committed(batch);
+    let (era, epoch) = next_epoch_after(batch);
+    let mut set_id = self.persistent.authority_set.inner.read().set_id;
+    if let ChangeState::Complete(Change::NodeChange(_)) = batch.change()
+    {
+      // the new set is adopted when the batch is turned into a block
+      set_id = set_id + 1;
+    }
+    // the overflow is agreed on and saved apart, inputting it again would include it twice
+    let queued_transactions = node.pending_transactions.iter().cloned().collect();
+    let checkpoint = aux_store::BadgerCheckpoint {
+      set_id: set_id,
+      era: era,
+      epoch: epoch,
+      vote: node
+        .pending_vote
+        .as_ref()
+        .map(|v| bincode::serialize(v).expect("Serialize error in checkpoint")),
+      queued_transactions: queued_transactions,
+      peers: self
+        .peers
+        .inverse
+        .iter()
+        .map(|(auth, peer)| (auth.clone(), peer.clone().into_bytes()))
+        .collect(),
+    };
+    if let Err(e) = aux_store::update_checkpoint(&checkpoint, |insert| self.aux_backend.insert_aux(insert, &[]))
+    {
+      warn!("Couldn't write checkpoint to disk {:?}", e);
+    }
+  }
+
   pub fn pre_finalize(&mut self, hash: &B::Hash,justne: BadgerFullJustification<B>) -> BatchProcResult<B>
   {
    
@@ -1024,48 +1399,15 @@ pub struct BadgerSyncState<B:BlockT>
 	  {
 		if *hash== block.header().hash()
 		{
-      let mut ret=Vec::<ExtractedLogs<B>>::new();
-      
       let (header, body) = block.deconstruct();
-
-      //let header_num = header.number().clone();
-      //let mut parent_hash = header.parent_hash().clone();
-      let id = OpaqueDigestItemId::Consensus(&HBBFT_ENGINE_ID);
-
-      /*let filter_log = |log: ConsensusLog<NumberFor<B>>| match log {
-        ConsensusLog::ScheduledChange(change) => Some(change),
-        _ => None, convert_f
-      };*/
-    
-      // find the consensus digests with the right ID which converts to
-      // the right kind of consensus log.
-      let badger_logs:Vec<_>=header.digest().logs().iter().map(
-        |x| x.try_to(id)).filter( |x:&Option<ConsensusLog>| x.is_some()).map(|x| x.unwrap()).collect();
-      let  sid;	
-      {
-        sid=self.persistent.authority_set.inner.read().self_id.clone();
-      }
-      for log in badger_logs
-      {
-        //TODO!
-        match log
-        {
-          ConsensusLog::VoteChangeSet(my_id,changeset) =>
-          {
-          if sid==my_id
-           {
-             info!("Log detected, VOTING {:?}",&changeset);
-             ret.push(ExtractedLogs::ValidatorVote(changeset));
-             info!("VOTE REGISTERED");
-           }
-          },
-          ConsensusLog::NotifyChangedSet(newset) =>
-          {
-            info!("Notified of new set {:?}, doing nothing for now",&newset);
-          }
-
-        }
-      }
+      // blocks imported ahead for the same batch are finalized along with this one
+      let split = std::mem::replace(&mut self.mech.split_headers, Vec::new());
+      self.save_split_headers();
+      let ret: Vec<ExtractedLogs<B>> = split
+        .iter()
+        .chain(std::iter::once(&header))
+        .flat_map(|h| self.extract_logs(h))
+        .collect();
         	let import_block: BlockImportParams<B> = BlockImportParams {
 				origin: BlockOrigin::Own,
 				header,
@@ -1095,6 +1437,17 @@ pub struct BadgerSyncState<B:BlockT>
         warn!("Failed finalization...");
 				return BatchProcResult::Nothing;
       }
+      if let Some(key) = justne.key.as_ref().and_then(|attestation| attestation.public_key())
+      {
+        let aux = &self.aux_backend;
+        if self.persistent.authority_set.note_committee_key(key.clone())
+        {
+          if let Err(e) = aux_store::update_committee_key(&key, |insert| aux.insert_aux(insert, &[]))
+          {
+            warn!("Couldn't write committee key to disk {:?}", e);
+          }
+        }
+      }
       let  pair = self.cached_origin.clone().unwrap();
       let gsp=BadgerSyncGossip::new(&pair,justne,pnumber);
       self.output_message_buffer.push((LocalTarget::AllExcept(BTreeSet::new()),GossipMessage::SyncGossip(gsp.clone())));
@@ -1105,7 +1458,7 @@ pub struct BadgerSyncState<B:BlockT>
 			{
         let mbatch;
         {
-          mbatch=lmech.queued_batches.pop_front();
+          mbatch=self.mech.queued_batches.pop_front();
         }
 			if let Some(batch)=mbatch
 			{
@@ -1119,10 +1472,60 @@ pub struct BadgerSyncState<B:BlockT>
     BatchProcResult::Nothing
   }
 
+  /// Votes and notifications for us in the consensus logs of `header`
+  fn extract_logs(&self, header: &B::Header) -> Vec<ExtractedLogs<B>>
+  {
+    let mut ret=Vec::<ExtractedLogs<B>>::new();
+    let id = OpaqueDigestItemId::Consensus(&HBBFT_ENGINE_ID);
+    // find the consensus digests with the right ID which converts to
+    // the right kind of consensus log.
+    let badger_logs:Vec<_>=header.digest().logs().iter().map(
+      |x| x.try_to(id)).filter( |x:&Option<ConsensusLog>| x.is_some()).map(|x| x.unwrap()).collect();
+    let  sid;	
+    {
+      sid=self.persistent.authority_set.inner.read().self_id.clone();
+    }
+    for log in badger_logs
+    {
+      match log
+      {
+        ConsensusLog::VoteChangeSet(my_id,changeset) =>
+        {
+        if sid==my_id
+         {
+           info!("Log detected, VOTING {:?}",&changeset);
+           ret.push(ExtractedLogs::ValidatorVote(changeset));
+           info!("VOTE REGISTERED");
+         }
+        },
+        ConsensusLog::NotifyChangedSet(newset) =>
+        {
+          // an elected committee, every validator votes for it
+          let aset = self.persistent.authority_set.inner.read();
+          let current: BTreeSet<_> = aset.current_authorities.iter().collect();
+          let elected: BTreeSet<_> = newset.iter().collect();
+          if current.contains(&sid) && current != elected
+          {
+            info!("Elected set {:?}, VOTING", &newset);
+            ret.push(ExtractedLogs::ValidatorVote(newset));
+          }
+        }
+
+      }
+    }
+    ret
+  }
+
 pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
     {  
       //let lmech=&mut self.mech;
 
+    if self.start_decryption(&batch)
+    {
+      info!("Waiting for decryption shares of batch {:?}", batch.epoch());
+      self.mech.awaiting_decryption=Some(batch);
+      return BatchProcResult::Nothing;
+    }
     let mut inherent_digests = generic::Digest { logs: vec![] };
     {
     self.mech.pending_batch=Some(batch.clone());
@@ -1161,10 +1564,14 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
        },
        ChangeState::Complete(Change::EncryptionSchedule(_)) => {},//don't care?
      }
-     match batch.join_plan()
+     if let (Some(plan), ChangeState::InProgress(Change::NodeChange(pubkeymap))) = (batch.join_plan(), batch.change())
      {
-       Some(plan) =>{}, //todo: emit plan if there are waiting nodes
-       None =>{}
+       let added = pubkeymap
+         .iter()
+         .map(|(_, v)| Into::<AuthorityId>::into(v.clone()))
+         .filter(|v| !auth_list.contains(v))
+         .collect();
+       self.broadcast_join_plan(plan, added);
      }
      let chain_head = match self.block_maker.best_chain() {
       Ok(x) => x,
@@ -1177,12 +1584,36 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
     let pnumber = *chain_head.number();
     let parent_id = BlockId::hash(parent_hash);
 
+    if let Some(ref after) = self.mech.overflow_after
+    {
+      if *after != parent_hash && !self.mech.overflow.is_empty()
+      {
+        // the network carried over whatever did not fit into `after`, which we don't know
+        warn!(
+          "Dropping {:?} transactions carried over from {:?}, our best block is {:?}",
+          self.mech.overflow.len(),
+          after,
+          parent_hash
+        );
+        self.mech.overflow.clear();
+      }
+    }
+    // validators propose independently, so contributions may share transactions
+    let epoch = batch.epoch();
+    let decrypted: Vec<BadgerTransaction> = batch.into_tx_iter().filter_map(|tx| self.take_decrypted(tx)).collect();
+    let total = decrypted.len();
+    let mut seen: BTreeSet<BadgerTransaction> = self.mech.overflow.iter().cloned().collect();
+    let txs: Vec<BadgerTransaction> = decrypted.into_iter().filter(|tx| seen.insert(tx.clone())).collect();
+    if txs.len() < total
     {
-      let mut locked=&mut self.mech.overflow;
-    let block=  match self.block_maker.process_all(parent_id,inherent_digests,&mut locked,batch.into_tx_iter())
+      debug!("Dropped {:?} duplicate transactions from batch {:?}", total - txs.len(), epoch);
+    }
+    {
+    let txs: Vec<BadgerTransaction> = self.mech.overflow.drain(..).chain(txs.into_iter()).collect();
+    let (block, parent_hash, pnumber) = match self.build_batch_blocks(parent_id, parent_hash, pnumber, inherent_digests, txs)
       {
-        Ok(val) => val,
-        Err(_) => {
+        Some(val) => val,
+        None => {
           warn!("Error in block creation");
           return BatchProcResult::Nothing;
         }
@@ -1204,6 +1635,7 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
           }	
 
       let header_hash = block.header().hash();
+      self.save_overflow(header_hash.clone());
       //self.handler.emit_justification(&header_hash,auth_list.clone());
       {
         self.mech.queued_block=Some(block)
@@ -1213,6 +1645,99 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
     }
   }
 
+  /// Write the headers of the split batch in progress to the aux store, so that a restarted
+  /// node still finalizes them along with the last block.
+  fn save_split_headers(&mut self)
+  {
+    if let Err(e) =
+      aux_store::update_split_headers(&self.mech.split_headers, |insert| self.aux_backend.insert_aux(insert, &[]))
+    {
+      warn!("Couldn't write split headers to disk {:?}", e);
+    }
+  }
+
+  /// Write the overflow left by the block `after` to the aux store, so that a restarted node
+  /// builds the next block with it.
+  pub(super) fn save_overflow(&mut self, after: B::Hash)
+  {
+    let txs: Vec<BadgerTransaction> = self.mech.overflow.iter().cloned().collect();
+    if let Err(e) = aux_store::update_overflow(&after, &txs, |insert| self.aux_backend.insert_aux(insert, &[]))
+    {
+      warn!("Couldn't write overflow to disk {:?}", e);
+    }
+    self.mech.overflow_after = Some(after);
+  }
+
+  /// Build the block(s) of a batch on top of `parent_id`, leaving what does not fit to the
+  /// batch policy. Blocks of a split batch but the last are imported right away, the last
+  /// is returned with its parent, to be justified.
+  fn build_batch_blocks(
+    &mut self, mut parent_id: BlockId<B>, mut parent_hash: B::Hash, mut pnumber: NumberFor<B>,
+    mut digests: generic::Digest<B::Hash>, mut txs: Vec<BadgerTransaction>,
+  ) -> Option<(B, B::Hash, NumberFor<B>)>
+  {
+    loop
+    {
+      let (block, leftover, rejected) =
+        self.block_maker.process_all(parent_id, digests.clone(), txs.clone().into_iter()).ok()?;
+      self.block_maker.report_rejected(rejected.clone());
+      if leftover.is_empty()
+      {
+        return Some((block, parent_hash, pnumber));
+      }
+      let dropped = leftover.len();
+      match self.config.batch_policy.leftover(leftover)
+      {
+        Leftover::Drop =>
+        {
+          info!("Dropping {:?} transactions that did not fit in the block", dropped);
+          return Some((block, parent_hash, pnumber));
+        }
+        Leftover::Carry(rest) =>
+        {
+          self.mech.overflow.extend(rest);
+          return Some((block, parent_hash, pnumber));
+        }
+        Leftover::NextBlock(rest) =>
+        {
+          // what did not fit is a suffix of `txs`, and digests don't count against the limits,
+          // so the marked block holds the same transactions
+          txs.truncate(txs.len() - dropped);
+          // only the transactions that made it in, so nothing is reported twice
+          txs.retain(|tx| !rejected.contains(tx));
+          let mut marked = digests.clone();
+          marked
+            .logs
+            .push(DigestItem::PreRuntime(HBBFT_ENGINE_ID, BadgerPreRuntime::BatchContinues.encode()));
+          let (block, _, _) = self.block_maker.process_all(parent_id, marked, txs.into_iter()).ok()?;
+          let (header, body) = block.deconstruct();
+          info!("Batch continues after block {:?} {:?}", header.number(), header.hash());
+          parent_hash = header.hash();
+          pnumber = *header.number();
+          parent_id = BlockId::hash(parent_hash);
+          let import_block: BlockImportParams<B> = BlockImportParams {
+            origin: BlockOrigin::Own,
+            header: header.clone(),
+            justification: None,
+            post_digests: vec![],
+            body: Some(body),
+            finalized: false,
+            allow_missing_state: true,
+            auxiliary: Vec::new(),
+            fork_choice: ForkChoiceStrategy::LongestChain,
+            import_existing: false,
+          };
+          self.block_maker.import_block(import_block).ok()?;
+          self.mech.split_headers.push(header);
+          self.save_split_headers();
+          // changes of the batch are announced by its first block only
+          digests = generic::Digest { logs: vec![] };
+          txs = rest;
+        }
+      }
+    }
+  }
+
 
   #[inline]
   pub fn load_origin(&mut self)
@@ -1241,6 +1766,14 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
         return;
          }
     };
+    if let BadgerState::Observer = self.state
+    {
+      if let Some(body) = bli.body.take()
+      {
+        self.observe_block(B::new(bli.header, body), just);
+      }
+      return;
+    }
     let number=bli.header.number().clone();
     let chain_head = match self.block_maker.best_chain() {
       Ok(x) => x,
@@ -1254,6 +1787,10 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
     if number!=next_block 
     {
       info!("Not next block: our next : {:?}, arrival :{:?}",next_block,number);
+      if self.is_behind(number)
+      {
+        self.request_catch_up(number, None);
+      }
       return;
     }
     //if ! self.ve
@@ -1389,6 +1926,18 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
       warn!("Should never be the case! - no authorities for block");
       return;
     }
+    if let Some((ref key_set, ref indices)) = existing.keys
+    {
+      match indices.get(&n_jst.validator)
+      {
+        Some(idx) if n_jst.verify_share(key_set, *idx) => {},
+        _ =>
+        {
+          info!("Invalid justification share from {:?}", &n_jst.validator);
+          return;
+        }
+      }
+    }
     if existing.justification.iter().find(|x| x.validator==n_jst.validator).is_none()
      {
       if existing.contemp_auth.as_ref().unwrap().iter().find(|&x| *x==n_jst.validator).is_some()
@@ -1410,28 +1959,39 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
      info!("Justification too old, too lazy to validate");
      return false;
    }
-   if !just.justification.verify()
-   {
-     return false;
-   }
+   self.persistent.authority_set.check_justification(&just.justification).is_some()
+ }
 
-  let mut count_total=0;
-  let mut count_accepted=0;
-  for authority in self.persistent.authority_set.inner.read().current_authorities.iter()
+  /// Key set of the running committee, and the index in it of each of `auth_list`
+  fn committee_keys(&self, auth_list: &[AuthorityId]) -> Option<(PublicKeySet, BTreeMap<AuthorityId, usize>)>
   {
-    count_total+=1;
-    if just.justification.commits.iter().find( |x| 
-      {
-       x.validator==*authority
-    } ).is_some()
+    let netinfo = match self.state
+    {
+      BadgerState::Badger(ref node) => node.algo.inner().netinfo(),
+      _ => return None,
+    };
+    let mut indices = BTreeMap::new();
+    for auth in auth_list.iter()
     {
-      count_accepted+=1;
+      let node_id: NodeId = if *auth == self.config.my_auth_id
+      {
+        netinfo.our_id().clone()
+      }
+      else
+      {
+        match self.peers.inverse.get(auth)
+        {
+          Some(p) => p.clone().into(),
+          None => continue,
+        }
+      };
+      if let Some(idx) = netinfo.node_index(&node_id)
+      {
+        indices.insert(auth.clone(), idx);
+      }
     }
+    Some((netinfo.public_key_set().clone(), indices))
   }
-  let tolerated= count_total - badger::util::max_faulty(count_total);
-   
-  count_accepted>=tolerated
-   }
   pub fn check_justification_completion(&mut self,hkey: &B::Hash)->BatchProcResult<B>
   {
     if !self.is_authority()
@@ -1445,42 +2005,82 @@ pub fn batch_to_block(&mut self, batch:BatchType ) ->BatchProcResult<B>
       None => {return  BatchProcResult::Nothing;}
     };
 
-    let authn=existing.contemp_auth.as_ref().unwrap().len();
-    let tolerated= authn - badger::util::max_faulty(authn);
-    let collected=existing.justification.len();
-    info!("justificatioN collected: {:?} tolerated: {:?} hash: {:?}",collected,tolerated,hkey);
-    if collected>=tolerated
-    {//justification complete
-      let hash=existing.justification[0].hash.clone();
-      info!("Justification complete for {:?}",&hash);
-      let full = BadgerFullJustification::<B>
-      {
-        hash:hash.clone(),
-        commits: existing.justification.drain(..).map(|x| BadgerAuthCommit{validator:x.validator,sgn:x.sgn}).collect()
+    let (sig, key) = {
+      let (key_set, indices) = match existing.keys
+      {
+        Some(ref keys) => keys,
+        None => return BatchProcResult::Nothing,
       };
-
-      let res=self.pre_finalize(hkey,full);
-
-      info!("Justification complete for {:?}",hkey);
-      
-      for tpl in self.delayed_justifications.iter_mut()
+      // shares are checked when collected
+      let shares: BTreeMap<usize, SignatureShare> = existing
+        .justification
+        .iter()
+        .filter_map(|x| Some((*indices.get(&x.validator)?, gossip::signature_share_from_slice(&x.share)?)))
+        .collect();
+      info!("justificatioN collected: {:?} threshold: {:?} hash: {:?}",shares.len(),key_set.threshold(),hkey);
+      if shares.len() <= key_set.threshold()
       {
-        (*tpl).0+=1;
+        return BatchProcResult::Nothing;
       }
-      //retain only justifications for some recent blocks
-      self.delayed_justifications.retain(|x| x.0<MAX_DELAYED_JUSTIFICATIONS);
-      res
-    }
-    else
+      let sig = match key_set.combine_signatures(shares.iter().map(|(idx, share)| (*idx, share)))
+      {
+        Ok(sig) => sig,
+        Err(e) =>
+        {
+          warn!("Could not combine signature shares {:?}", e);
+          return BatchProcResult::Nothing;
+        }
+      };
+      let public_key = key_set.public_key();
+      let key = if self.persistent.authority_set.committee_key.read().as_ref() == Some(&public_key)
+      {
+        None
+      }
+      else
+      {
+        // first block of a new committee, others learn its key from this justification
+        let attestation = BadgerKeyAttestation {
+          public_key: public_key.to_bytes().to_vec(),
+          commits: existing.justification.iter().map(|x| BadgerAuthCommit{validator:x.validator.clone(),sgn:x.key_sgn.clone()}).collect(),
+        };
+        if !attestation.verify(&self.persistent.authority_set.inner.read().current_authorities)
+        {
+          info!("Waiting for more validators to vouch for the committee key");
+          return BatchProcResult::Nothing;
+        }
+        Some(attestation)
+      };
+      existing.justification.clear();
+      (sig, key)
+    };
+    //justification complete
+    let full = BadgerFullJustification::<B>
+    {
+      hash:hkey.clone(),
+      sig:sig.to_bytes().to_vec(),
+      key:key,
+    };
+
+    let res=self.pre_finalize(hkey,full);
+
+    info!("Justification complete for {:?}",hkey);
+    
+    for tpl in self.delayed_justifications.iter_mut()
     {
-      BatchProcResult::Nothing
+      (*tpl).0+=1;
     }
+    //retain only justifications for some recent blocks
+    self.delayed_justifications.retain(|x| x.0<MAX_DELAYED_JUSTIFICATIONS);
+    res
   }
 
   pub fn extract_state(&mut self)->Vec<(LocalTarget<B>,GossipMessage<B>)>
   {
 //self.output_message_buffer
-std::mem::replace(&mut self.output_message_buffer,Vec::new())
+    let out = std::mem::replace(&mut self.output_message_buffer,Vec::new());
+    #[cfg(test)]
+    let out = self.misbehave(out);
+    out
   }
   pub fn  flush_state(&mut self)
   {
@@ -1491,27 +2091,49 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
       if let BadgerState::Badger(ref mut state) = &mut self.state
       {
         debug!("BaDGER!! Flushing {} messages_net", &state.out_queue.len());
-        drain = state
-          .out_queue
-          .drain(..)
-          .map(|x| {
-            (
-              x.target,
-              GossipMessage::BadgerData(BadgeredMessage::new(pair.clone(), &x.message)),
-            )
-          })
-          .collect();
-      }
-      self.output_message_buffer.append(&mut drain);
-
-  }
-
-  //pub fn initiate_block_justification(&mut self,n_jst:BadgerJustification<B>,auth_list:AuthorityList) ->BatchProcResult<B>
+        for x in state.out_queue.drain(..)
+        {
+          match x.target
+          {
+            // targeted messages carry secret shares, seal each copy to its recipient
+            LocalTarget::Nodes(set) =>
+            {
+              for node in set.into_iter()
+              {
+                let recipient = if node.0 == self.config.my_peer_id
+                {
+                  Some(self.config.my_auth_id.clone())
+                }
+                else
+                {
+                  self.peers.inner.get(&node.0).and_then(|p| p.id.clone())
+                };
+                match recipient
+                {
+                  Some(auth) =>
+                  {
+                    let sealed = SealedBadgeredMessage::seal(&pair, &auth, &x.message);
+                    let mut target = BTreeSet::new();
+                    target.insert(node);
+                    drain.push((LocalTarget::Nodes(target), GossipMessage::SealedBadgerData(sealed)));
+                  }
+                  None => warn!("No node key known for {:?}, dropping targeted message", &node),
+                }
+              }
+            }
+            target => drain.push((target, GossipMessage::BadgerData(BadgeredMessage::new(pair.clone(), &x.message)))),
+          }
+        }
+      }
+      self.output_message_buffer.append(&mut drain);
+      self.collect_faults();
+  }
+
+  //pub fn initiate_block_justification(&mut self,n_jst:BadgerJustification<B>,auth_list:AuthorityList) ->BatchProcResult<B>
   pub fn initiate_block_justification(&mut self,hkey: B::Hash,auth_list:AuthorityList) 
   {
     self.load_origin();
     let pair=self.cached_origin.as_ref().unwrap().clone();
-    let authid=pair.public();
     
 //////////////////////////////////////
     let mut n_hash=hkey;
@@ -1521,20 +2143,22 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
     loop 
     {
     info!("Emitting Justification {:?}",&n_hash);
-    let sgn;
-    sgn = pair.sign(&n_hash.encode());
-      
-    
-  let n_jst= BadgerJustification::<B>
-   {
-    hash:n_hash.clone(),
-     validator:authid.clone(),
-     sgn:sgn 
-   };
-   let  existing=self.justification_collector.entry(hkey.clone()).or_insert(
+    let keys = self.committee_keys(&n_list);
+    let share = match self.state
+    {
+      BadgerState::Badger(ref node) => node.algo.inner().netinfo().secret_key_share().map(|sks| sks.sign(&n_hash.encode())),
+      _ => None,
+    };
+    let n_jst = match (share, keys.as_ref())
+    {
+      (Some(share), Some((key_set, _))) => Some(BadgerJustification::<B>::new(&pair, n_hash.clone(), &share, &key_set.public_key())),
+      _ => None,
+    };
+   let  existing=self.justification_collector.entry(n_hash.clone()).or_insert(
     JustificationCollector {
     contemp_auth:Some(n_list),
     justification:Vec::new(),
+    keys:keys,
      });
      if existing.contemp_auth.is_none()
      {
@@ -1547,7 +2171,7 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
       let mut remaining:Vec<_>=Vec::new();     
       for jst in self.delayed_justifications.drain(..)
        {
-       if jst.1.hash==n_jst.hash
+       if jst.1.hash==n_hash
         {
         //justification
         Self::process_justification(jst.1,existing)
@@ -1558,19 +2182,27 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
         }
        }
        self.delayed_justifications=remaining;
+       if let Some(ref n_jst) = n_jst
+       {
        Self::process_justification(n_jst.clone(), existing);
+       }
       }
-      if self.is_authority() //don't emit if we are not authority
+      match n_jst
       {
-      self.output_message_buffer.push((LocalTarget::Keep(n_jst.hash.clone()), GossipMessage::JustificationData(n_jst.clone())) );
-     
-      self.output_message_buffer.push((LocalTarget::AllExcept(BTreeSet::new()), GossipMessage::JustificationData(n_jst)) );
+        Some(n_jst) if self.is_authority() => //don't emit if we are not authority
+        {
+        self.output_message_buffer.push((LocalTarget::Keep(n_jst.hash.clone()), GossipMessage::JustificationData(n_jst.clone())) );
+       
+        self.output_message_buffer.push((LocalTarget::AllExcept(BTreeSet::new()), GossipMessage::JustificationData(n_jst)) );
+        },
+        Some(_) => {},
+        None => warn!("No key share to sign {:?} with", &n_hash),
       }
      
-      let cres =self.check_justification_completion(&hkey);
+      let cres =self.check_justification_completion(&n_hash);
       match cres
       {
-       BatchProcResult::Completed(vc) =>  { self.justification_collector.remove(&hkey); self.process_extracted(vc); break},
+       BatchProcResult::Completed(vc) =>  { self.justification_collector.remove(&n_hash); self.process_extracted(vc); break},
        BatchProcResult::EmitJustification(hash,alist,logs) => {  
                        self.process_extracted(logs); 
                        n_hash=hash;
@@ -1604,7 +2236,12 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
   {
     match &mut self.state
     {
-      BadgerState::AwaitingValidators | BadgerState::AwaitingJoinPlan | BadgerState::KeyGen(_) | BadgerState::InitialSync =>
+      BadgerState::Observer =>
+      {
+        // transactions reach the validators through the transaction pool gossip
+        Ok(())
+      }
+      BadgerState::AwaitingValidators | BadgerState::KeyGen(_) | BadgerState::InitialSync | BadgerState::CatchingUp =>
       {
         match self.queue_transaction(tx)
         {
@@ -1645,8 +2282,21 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
       self.config.my_peer_id.clone(),
       self.keystore.clone(),
       &self.peers,
+      self.sync_state.next_epoch,
+      StdRng::from_rng(&mut self.rng).expect("Seeding from another StdRng cannot fail"),
     );
     self.state = BadgerState::Badger(node);
+    if let Some(vote) = self.sync_state.resumed_vote.take()
+    {
+      info!("Recasting vote from checkpoint {:?}", &vote);
+      if let BadgerState::Badger(ref mut node) = self.state
+      {
+        if let Err(e) = node.vote_for(vote)
+        {
+          info!("Error recasting vote {:?}", e);
+        }
+      }
+    }
     let bypass: Vec<_> = self.queued_transactions.drain(..).collect();
     for tx in bypass.into_iter()
     {
@@ -1695,7 +2345,8 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
              BadgerFullJustification
              {
                hash:info.best_hash,
-               commits:Vec::new(),
+               sig:Vec::new(),
+               key:None,
              }
           }
           else
@@ -1718,79 +2369,60 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
     self.load_origin();
 
     //check if we already have the necessary keys
+    if self.load_key_share()
+    {
+      return self.proceed_to_badger();
+    }
+    let self_id = self.persistent.authority_set.inner.read().self_id.clone();
+    if self
+      .keystore
+      .read()
+      .get_aux_by_type::<BadgerAuxCrypto>(app_crypto::key_types::HB_NODE, &self_id.encode())
+      .is_ok()
+    {
+      // keys of an earlier set
+      self
+        .keystore
+        .write()
+        .delete_aux(app_crypto::key_types::HB_NODE, &self_id.encode())
+        .expect("Could not delete keystore");
+    }
+    info!("Keygen: no key share for the current set, regenerating");
+    self.start_keygen(0)
+  }
+
+  /// Load the key share we stored for the current authority set into the config.
+  /// Returns false if we have none, e.g. the set changed while we were away.
+  pub fn load_key_share(&mut self) -> bool
+  {
     let aset = self.persistent.authority_set.inner.read().clone();
-    let mut should_badger = false;
-    let should_regen = match self
+    match self
       .keystore
       .read()
       .get_aux_by_type::<BadgerAuxCrypto>(app_crypto::key_types::HB_NODE, &aset.self_id.encode())
     {
-      Ok(data) =>
+      Ok(ref data) if data.set_id == aset.set_id =>
       {
-        if aset.set_id == data.set_id
-        {
-          self.config.keyset = Some(data.key_set);
-          self.config.secret_share = match data.secret_share
-          {
-            Some(ss) => Some(ss.0),
-            None => None,
-          };
-          should_badger = true;
-          false
-        }
-        else
-        {
-          self
-            .keystore
-            .write()
-            .delete_aux(app_crypto::key_types::HB_NODE, &aset.self_id.encode())
-            .expect("Could not delete keystore");
-
-          true
-        }
+        self.config.keyset = Some(data.key_set.clone());
+        self.config.secret_share = data.secret_share.as_ref().map(|ss| ss.0.clone());
+        true
       }
-      Err(_) => true,
-    };
-    if should_badger
-    {
-      return self.proceed_to_badger();
+      _ => false,
     }
-    info!("Keygen: should regen :{:?}", &should_regen);
-    if should_regen
+  }
+
+  /// Start a fresh SyncKeyGen round among the current authorities.
+  pub fn start_keygen(&mut self, attempt: u32) -> Vec<(LocalTarget<B>, GossipMessage<B>)>
+  {
+    self.load_origin();
+    let aset = self.persistent.authority_set.inner.read().clone();
+    info!("Starting keygen attempt {:?}", attempt);
     {
-      let mut rng = rand::rngs::OsRng::new().expect("Could not open OS random number generator.");
+      let mut rng = StdRng::from_rng(&mut self.rng).expect("Seeding from another StdRng cannot fail");
       let secr: SecretKey = bincode::deserialize(&self.cached_origin.as_ref().unwrap().to_raw_vec()).unwrap();
       info!("Our secret key : {:?} pub: {:?}", &secr, &secr.public_key());
       let thresh = badger::util::max_faulty(aset.current_authorities.len());
-      let val_pub_keys: PubKeyMap<NodeId> = Arc::new(
-        aset
-          .current_authorities
-          .iter()
-          .map(|x| {
-            (if *x == self.cached_origin.as_ref().unwrap().public()
-            {
-              (
-                std::convert::Into::<PeerIdW>::into(self.config.my_peer_id.clone()),
-                x.clone().into(),
-              )
-            }
-            else
-            {
-              (
-                std::convert::Into::<PeerIdW>::into(
-                  self
-                    .peers
-                    .inverse
-                    .get(x)
-                    .expect("All validators should be mapped at this point")
-                    .clone(),
-                ),
-                x.clone().into(),
-              )
-            })
-          })
-          .collect(),
-      );
+      let val_pub_keys = self.keygen_validators();
       info!("VAL_PUB {:?} {:?}", &val_pub_keys, &self.config.my_peer_id);
       let (skg, part) = SyncKeyGen::new(
         self.config.my_peer_id.clone().into(),
@@ -1812,6 +2444,13 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
         expected_parts: template.clone(),
         expected_acks: template.iter().map(|x| (x.clone(), template.clone())).collect(),
         is_done: false,
+        attempt: attempt,
+        own_messages: Vec::new(),
+        next_attempt: BTreeSet::new(),
+        last_progress: self.clock.now(),
+        last_resend: self.clock.now(),
+        rng: rng,
+        clock: self.clock.clone(),
       };
       let mut ret = vec![];
       if let Some(parted) = part
@@ -1824,31 +2463,202 @@ std::mem::replace(&mut self.output_message_buffer,Vec::new())
           acks.append(&mut state.process_message(&pid, acks[0].clone()));
         }
         acks.push(SyncKeyGenMessage::Part(parted.clone()));
-        ret = acks
-          .into_iter()
-          .map(|msg| {
-            (
-              LocalTarget::AllExcept(BTreeSet::new()),
-              GossipMessage::KeygenData(BadgeredMessage::new(
-                self.cached_origin.as_ref().unwrap().clone(),
-                &bincode::serialize(&msg).expect("Serialize error in inital keygen processing"),
-              )),
-            )
-          })
-          .collect();
+        state.own_messages = acks.clone();
+        ret = self.keygen_gossip(attempt, acks);
       }
 
       self.state = BadgerState::KeyGen(state);
       info!("Returning {:?} values", ret.len());
       ret
     }
-    else
+  }
+
+  /// Public keys of the current authorities, keyed by node id, as SyncKeyGen expects them
+  fn keygen_validators(&self) -> PubKeyMap<NodeId>
+  {
+    let aset = self.persistent.authority_set.inner.read();
+    Arc::new(
+      aset
+        .current_authorities
+        .iter()
+        .map(|x| {
+          if *x == self.cached_origin.as_ref().unwrap().public()
+          {
+            (
+              std::convert::Into::<PeerIdW>::into(self.config.my_peer_id.clone()),
+              x.clone().into(),
+            )
+          }
+          else
+          {
+            (
+              std::convert::Into::<PeerIdW>::into(
+                self
+                  .peers
+                  .inverse
+                  .get(x)
+                  .expect("All validators should be mapped at this point")
+                  .clone(),
+              ),
+              x.clone().into(),
+            )
+          }
+        })
+        .collect(),
+    )
+  }
+
+  fn keygen_gossip(&self, attempt: u32, msgs: Vec<SyncKeyGenMessage>) -> Vec<(LocalTarget<B>, GossipMessage<B>)>
+  {
+    msgs
+      .into_iter()
+      .map(|msg| {
+        (
+          LocalTarget::AllExcept(BTreeSet::new()),
+          GossipMessage::KeygenData(BadgeredMessage::new(
+            self.cached_origin.as_ref().unwrap().clone(),
+            &bincode::serialize(&SyncKeyGenPacket { attempt: attempt, msg: msg })
+              .expect("Serialize error in keygen processing"),
+          )),
+        )
+      })
+      .collect()
+  }
+
+  /// Called periodically. Resends our keygen messages for validators that were not
+  /// around when we first sent them, and restarts keygen if it stalls completely.
+  pub fn on_keygen_timer(&mut self)
+  {
+    let (attempt, resend) = match self.state
     {
-      //no need to regenerate, use existing saved data...
-      Vec::new()
+      BadgerState::KeyGen(ref mut step) =>
+      {
+        if step.is_done
+        {
+          return;
+        }
+        if step.clock.elapsed(step.last_progress) > KEYGEN_TIMEOUT
+        {
+          let next = match step.attempt.checked_add(1)
+          {
+            Some(next) => next,
+            None => return,
+          };
+          warn!(
+            "Keygen attempt {:?} timed out waiting for {:?}, restarting",
+            step.attempt,
+            step.missing()
+          );
+          (next, None)
+        }
+        else if step.clock.elapsed(step.last_resend) > KEYGEN_RESEND_INTERVAL
+        {
+          info!("Keygen waiting for {:?}, resending our messages", step.missing());
+          step.last_resend = step.clock.now();
+          (step.attempt, Some(step.own_messages.clone()))
+        }
+        else
+        {
+          return;
+        }
+      }
+      _ => return,
+    };
+    let mut msgs = match resend
+    {
+      Some(own) => self.keygen_gossip(attempt, own),
+      None => self.start_keygen(attempt),
+    };
+    self.output_message_buffer.append(&mut msgs);
+  }
+
+  /// Snapshot for the introspection RPC
+  pub fn status(&self) -> BadgerStatus
+  {
+    let aset = self.persistent.authority_set.inner.read();
+    let to_hex = |id: &AuthorityId| format!("0x{}", hex::encode(id.encode()));
+    let aux = self
+      .keystore
+      .read()
+      .get_aux_by_type::<BadgerAuxCrypto>(app_crypto::key_types::HB_NODE, &aset.self_id.encode())
+      .ok();
+    BadgerStatus {
+      state: self.state.name().to_string(),
+      era: self.sync_state.next_epoch.map(|(era, _)| era),
+      epoch: self.sync_state.next_epoch.map(|(era, epoch)| era + epoch),
+      set_id: aset.set_id,
+      authorities: aset.current_authorities.iter().map(to_hex).collect(),
+      key_set_id: aux.as_ref().map(|a| a.set_id),
+      key_set_public_key: aux.as_ref().map(|a| format!("0x{}", hex::encode(a.key_set.public_key().to_bytes()))),
+      connected_validators: self.peers.connected_badgerid_list().iter().map(to_hex).collect(),
+      queued_transactions: self.queued_transactions.len(),
+      overflow_transactions: self.mech.overflow.len(),
+      pending_transactions: match self.state
+      {
+        BadgerState::Badger(ref node) => node.pending_transactions.len(),
+        _ => 0,
+      },
+      pending_justifications: self
+        .justification_collector
+        .iter()
+        .map(|(hash, col)| PendingJustification {
+          hash: format!("0x{}", hex::encode(hash.as_ref())),
+          collected: col.justification.len(),
+        })
+        .collect(),
     }
   }
 
+  /// Change state of the last batch and our own vote, for the RPC
+  pub fn change_state_info(&self) -> ChangeStateInfo
+  {
+    let change = match self.sync_state.change_state
+    {
+      ChangeState::None => None,
+      ChangeState::InProgress(ref c) | ChangeState::Complete(ref c) => Some(change_info(c)),
+    };
+    let our_vote = match self.state
+    {
+      BadgerState::Badger(ref node) => node.pending_vote.as_ref().map(change_info),
+      _ => None,
+    };
+    ChangeStateInfo {
+      state: change_state_name(&self.sync_state.change_state).to_string(),
+      change: change,
+      our_vote: our_vote,
+    }
+  }
+
+  /// Check if every validator in the current set has announced its session
+  pub fn all_validators_known(&self) -> bool
+  {
+    let ln = self.persistent.authority_set.inner.read().current_authorities.len();
+    let mut cur;
+    {
+      let iaset = self.persistent.authority_set.inner.read();
+      cur = iaset
+        .current_authorities
+        .iter()
+        .filter(|&n| self.peers.inverse.contains_key(n))
+        .count();
+      if self.is_authority() &&
+        !self
+          .peers
+          .inverse
+          .contains_key(&self.cached_origin.as_ref().unwrap().public())
+      {
+        cur = cur + 1;
+      }
+    }
+    info!(
+      "Currently {:?} validators of {:?}, {:?} inverses",
+      cur,
+      ln,
+      &self.peers.inverse.len()
+    );
+    cur == ln
+  }
+
   pub fn is_authority(&self) -> bool
   {
     let aset = self.persistent.authority_set.inner.read();
@@ -1954,31 +2764,7 @@ else
           //debug!("Adding session key for {:?} :{:}")
           if let BadgerState::AwaitingValidators = self.state
           {
-            let ln = self.persistent.authority_set.inner.read().current_authorities.len();
-            let mut cur;
-            {
-              let iaset = self.persistent.authority_set.inner.read();
-              cur = iaset
-                .current_authorities
-                .iter()
-                .filter(|&n| self.peers.inverse.contains_key(n))
-                .count();
-              if self.is_authority() &&
-                !self
-                  .peers
-                  .inverse
-                  .contains_key(&self.cached_origin.as_ref().unwrap().public())
-              {
-                cur = cur + 1;
-              }
-            }
-            info!(
-              "Currently {:?} validators of {:?}, {:?} inverses",
-              cur,
-              ln,
-              &self.peers.inverse.len()
-            );
-            if cur == ln
+            if self.all_validators_known()
             {
               ret_msgs = self.proceed_to_keygen();
             }
@@ -2004,6 +2790,11 @@ else
       },
       GossipMessage::SyncGossip(sync) =>
       {
+        if self.is_behind(sync.data.num) && sync.verify() &&
+          self.persistent.authority_set.inner.read().current_authorities.contains(&sync.source)
+        {
+          self.request_catch_up(sync.data.num, Some(sync.source.clone()));
+        }
         match &self.state
         {
           BadgerState::AwaitingValidators =>
@@ -2012,9 +2803,9 @@ else
           self.process_sync_message(sync.clone());
           return (ValidationResult::Discard,false);
           },
-          BadgerState::AwaitingJoinPlan =>
+          BadgerState::Observer =>
           {
-            self.process_sync_message(sync.clone());
+            self.observe_sync(sync);
             return (ValidationResult::Discard,false);
           },
           BadgerState::InitialSync => 
@@ -2030,6 +2821,11 @@ else
           {
             return (ValidationResult::Discard,true);
           },
+          BadgerState::CatchingUp =>
+          {
+            self.process_sync_message(sync.clone());
+            return (ValidationResult::Discard,false);
+          },
           BadgerState::Badger(_node)=>
           {
             if !self.is_authority()
@@ -2081,11 +2877,60 @@ else
           }
         };
         info!("Originator: {:?}, Peer: {:?}", &wkgen.originator, &orid);
+        let restart = match &mut self.state
+        {
+          BadgerState::KeyGen(step) =>
+          {
+            match bincode::deserialize::<SyncKeyGenPacket>(&wkgen.data)
+            {
+              Ok(packet) =>
+              {
+                if packet.attempt < step.attempt
+                {
+                  info!("Stale keygen message of attempt {:?}", packet.attempt);
+                  return (ValidationResult::Discard,false);
+                }
+                if packet.attempt == step.attempt
+                {
+                  None
+                }
+                else if Some(packet.attempt) == step.attempt.checked_add(1)
+                {
+                  // someone timed out before us, join their attempt once f+1 did so
+                  // a single faulty validator cannot keep restarting keygen
+                  step.next_attempt.insert(orid.clone());
+                  if step.next_attempt.len() > step.threshold
+                  {
+                    Some(packet.attempt)
+                  }
+                  else
+                  {
+                    return (ValidationResult::Discard,true);
+                  }
+                }
+                else
+                {
+                  info!("Keygen message of attempt {:?} too far ahead of ours", packet.attempt);
+                  return (ValidationResult::Discard,false);
+                }
+              }
+              Err(_) => None,
+            }
+          }
+          _ => None,
+        };
+        if let Some(attempt) = restart
+        {
+          let mut msgs = self.start_keygen(attempt);
+          self.output_message_buffer.append(&mut msgs);
+        }
+        let own_acks;
+        let cur_attempt;
         if let BadgerState::KeyGen(ref mut step) = &mut self.state
         {
-          let k_message: SyncKeyGenMessage = match bincode::deserialize(&wkgen.data)
+          let k_message: SyncKeyGenMessage = match bincode::deserialize::<SyncKeyGenPacket>(&wkgen.data)
           {
-            Ok(data) => data,
+            Ok(data) => data.msg,
             Err(_) =>
             {
               warn!("Keygen message should be correct");
@@ -2093,8 +2938,11 @@ else
             }
           };
           info!("Msg: {:?}",&k_message);
+          cur_attempt = step.attempt;
         
           let acks = step.process_message(&orid, k_message);
+          step.own_messages.extend(acks.iter().cloned());
+          own_acks = acks;
           if step.is_done
           {
             info!("Initial keygen ready, generating... ");
@@ -2131,24 +2979,6 @@ else
             }
             let mut msgs=self.proceed_to_badger();
             self.output_message_buffer.append(&mut msgs);
-            (ValidationResult::Discard,false)
-          }
-          else
-          {
-            let mut ret = acks
-              .into_iter()
-              .map(|msg| {
-                (
-                  LocalTarget::AllExcept(BTreeSet::new()),
-                  GossipMessage::KeygenData(BadgeredMessage::new(
-                    self.cached_origin.as_ref().unwrap().clone(),
-                    &bincode::serialize(&msg).expect("Serialize error in  keygen processing"),
-                  )),
-                )
-              })
-              .collect();
-              self.output_message_buffer.append(&mut ret);
-              (ValidationResult::Discard,false)
           }
         }
         else
@@ -2162,66 +2992,17 @@ else
           // propagate once?
           return (ValidationResult::Discard,false);
         }
+        let mut ret = self.keygen_gossip(cur_attempt, own_acks);
+        self.output_message_buffer.append(&mut ret);
+        (ValidationResult::Discard,false)
       }
       GossipMessage::BadgerData(bdat) =>
       {
-        let orid: PeerIdW = match self.peers.inverse.get(&bdat.originator)
-        {
-          Some(dat) => dat.clone().into(),
-          None =>
-          {
-            if bdat.originator==self.config.my_auth_id
-            {
-              self.config.my_peer_id.clone().into()
-            }
-            else
-            {
-            info!("Unknown originator for {:?}", &bdat.originator);
-            return (ValidationResult::Discard,true);
-            }
-          }
-        };
-        //we actually need to process observer state updates if we want to use SendQueue
-
-        match self.state
-        {
-          BadgerState::Badger(ref mut badger) =>
-          {
-            info!("BadGER: got gossip message uid: {}", &bdat.uid,);
-            if let Ok(msg) = bincode::deserialize::<<QHB as ConsensusProtocol>::Message>(&bdat.data)
-            {
-              match badger.handle_message(&orid, msg)
-              {
-                Ok(_) =>
-                {
-                  //send is handled separately. trigger propose? or leave it for stream
-                  debug!("BadGER: decoded gossip message");
-
-                  return (ValidationResult::Discard,false);
-                }
-                Err(e) =>
-                {
-                  info!("Error handling badger message {:?}", e);
-                  telemetry!(CONSENSUS_DEBUG; "afg.err_handling_msg"; "err" => ?format!("{}", e));
-                  return (ValidationResult::Discard,false);
-                }
-              }
-            }
-            else
-            {
-              return (ValidationResult::Discard,false);
-            }
-          }
-          BadgerState::KeyGen(_) | BadgerState::AwaitingValidators =>
-          {
-            return (ValidationResult::Discard,true);
-          }
-          _ =>
-          {
-            warn!("Discarding badger message");
-            return (ValidationResult::Punish(-1),false);
-          }
-        }
+        self.process_badger_data(&bdat.originator, bdat.uid, &bdat.data)
+      },
+      GossipMessage::SealedBadgerData(sealed) =>
+      {
+        self.process_sealed_badger_data(sealed)
       },
       GossipMessage::JustificationData(just) =>
       {
@@ -2245,7 +3026,7 @@ else
          {
            if let Some(hdr)=opt
            {
-           
+           self.check_equivocation(just, &hdr);
            if *hdr.number()<=finalized_number
             {
               //we already have justification, hopefully
@@ -2296,6 +3077,116 @@ else
          }
          //justification flood -automatic...
          (ValidationResult::Discard,false)
+      },
+      GossipMessage::CatchUpRequest(request) =>
+      {
+        self.process_catch_up_request(request)
+      },
+      GossipMessage::CatchUpResponse(response) =>
+      {
+        self.process_catch_up_response(response)
+      },
+      GossipMessage::DecryptionShares(shares) =>
+      {
+        self.process_decryption_shares(shares)
+      },
+      GossipMessage::JoinPlan(plan) =>
+      {
+        self.process_join_plan(plan)
+      }
+    }
+  }
+  /// Feed badger data from `originator` into our node
+  fn process_badger_data(&mut self, originator: &AuthorityId, uid: u64, data: &[u8]) -> (ValidationResult<B>,bool)
+  {
+    let orid: PeerIdW = match self.peers.inverse.get(originator)
+    {
+      Some(dat) => dat.clone().into(),
+      None =>
+      {
+        if *originator==self.config.my_auth_id
+        {
+          self.config.my_peer_id.clone().into()
+        }
+        else
+        {
+        info!("Unknown originator for {:?}", originator);
+        return (ValidationResult::Discard,true);
+        }
+      }
+    };
+    //we actually need to process observer state updates if we want to use SendQueue
+
+    match self.state
+    {
+      BadgerState::Badger(ref mut badger) =>
+      {
+        info!("BadGER: got gossip message uid: {}", uid,);
+        if let Ok(msg) = bincode::deserialize::<<QHB as ConsensusProtocol>::Message>(data)
+        {
+          match badger.handle_message(&orid, msg)
+          {
+            Ok(_) =>
+            {
+              //send is handled separately. trigger propose? or leave it for stream
+              debug!("BadGER: decoded gossip message");
+
+              return (ValidationResult::Discard,false);
+            }
+            Err(e) =>
+            {
+              info!("Error handling badger message {:?}", e);
+              telemetry!(CONSENSUS_DEBUG; "afg.err_handling_msg"; "err" => ?format!("{}", e));
+              return (ValidationResult::Discard,false);
+            }
+          }
+        }
+        else
+        {
+          return (ValidationResult::Discard,false);
+        }
+      }
+      BadgerState::KeyGen(_) | BadgerState::AwaitingValidators =>
+      {
+        return (ValidationResult::Discard,true);
+      }
+      BadgerState::CatchingUp | BadgerState::Observer =>
+      {
+        // we will join at a later epoch
+        return (ValidationResult::Discard,false);
+      }
+      _ =>
+      {
+        warn!("Discarding badger message");
+        return (ValidationResult::Punish(-1),false);
+      }
+    }
+  }
+
+  /// Sealed badger data is only readable by its recipient, anyone else drops it
+  fn process_sealed_badger_data(&mut self, sealed: &SealedBadgeredMessage) -> (ValidationResult<B>,bool)
+  {
+    if sealed.recipient != self.config.my_auth_id
+    {
+      return (ValidationResult::Discard,false);
+    }
+    self.load_origin();
+    let secret: SecretKey = match bincode::deserialize(&self.cached_origin.as_ref().unwrap().to_raw_vec())
+    {
+      Ok(key) => key,
+      Err(_) =>
+      {
+        warn!("Could not load our node key to open sealed data");
+        return (ValidationResult::Discard,false);
+      }
+    };
+    match sealed.open(&secret)
+    {
+      Some(data) => self.process_badger_data(&sealed.originator, sealed.uid, &data),
+      None =>
+      {
+        info!("Sealed data from {:?} could not be opened", &sealed.originator);
+        (ValidationResult::Punish(-8),false)
       }
     }
   }
@@ -2365,7 +3256,11 @@ else
     let mut map: BTreeMap<PeerIdW, PublicKey> = BTreeMap::new();
     for au in auths.into_iter()
     {
-      if let Some(pid) = self.peers.inverse.get(&au)
+      if au == self.config.my_auth_id
+      {
+        map.insert(self.config.my_peer_id.clone().into(), au.into());
+      }
+      else if let Some(pid) = self.peers.inverse.get(&au)
       {
         map.insert(pid.clone().into(), au.into());
       }
@@ -2504,6 +3399,11 @@ impl<B: BlockT> BadgerNode<B, QHB>
   ) -> Result<CpStep<QHB>, badger::sender_queue::Error<badger::queueing_honey_badger::Error>>
   {
     info!("BaDGER pushing transaction {:?}", &tx);
+    if self.pending_transactions.len() >= MAX_QUEUE_LEN
+    {
+      self.pending_transactions.pop_front();
+    }
+    self.pending_transactions.push_back(tx.clone());
     let ret = self.algo.push_transaction(tx, &mut self.main_rng);
     info!("BaDGER pushed: complete ");
     ret
@@ -2521,19 +3421,13 @@ impl<B: BlockT> BadgerNode<B, QHB>
 {
   pub fn new(
     batch_size: usize, sks: Option<SecretKeyShare>, validator_set: AuthorityList, pkset: PublicKeySet,
-    auth_id: AuthorityId, self_id: PeerId, keystore: KeyStorePtr, peers: &Peers,
+    auth_id: AuthorityId, self_id: PeerId, keystore: KeyStorePtr, peers: &Peers, start_epoch: Option<(u64, u64)>,
+    mut rng: StdRng,
   ) -> BadgerNode<B, QHB>
   {
-    let mut rng = OsRng::new().unwrap();
 
     //let ap:app_crypto::hbbft_thresh::Public=hex!["946252149ad70604cf41e4b30db13861c919d7ed4e8f9bd049958895c6151fab8a9b0b027ad3372befe22c222e9b733f"].into();
-    let secr: SecretKey = match keystore
-      .read()
-      .key_pair_by_type::<AuthorityPair>(&auth_id, app_crypto::key_types::HB_NODE)
-    {
-      Ok(key) => bincode::deserialize(&key.to_raw_vec()).expect("Stored key should be correct"),
-      Err(_) => panic!("SHould really have key at this point"),
-    };
+    let secr = Self::secret_key(&keystore, &auth_id);
     let mut vset: Vec<NodeId> = validator_set
       .iter()
       .cloned()
@@ -2549,6 +3443,9 @@ impl<B: BlockT> BadgerNode<B, QHB>
       })
       .collect();
     vset.sort();
+    // observers are not part of the network, the sender queue learns about joining nodes from the batches
+    let our_id: NodeId = self_id.clone().into();
+    let others: Vec<NodeId> = vset.iter().filter(|x| **x != our_id).cloned().collect();
 
     let ni = NetworkInfo::<NodeId>::new(self_id.clone().into(), sks, (pkset).clone(), vset);
 
@@ -2568,16 +3465,65 @@ impl<B: BlockT> BadgerNode<B, QHB>
       })
       .collect();
 
-    let dhb = DynamicHoneyBadger::builder().build(ni, secr, Arc::new(val_map));
+    let mut dhb_builder = DynamicHoneyBadger::builder();
+    if let Some((era, epoch)) = start_epoch
+    {
+      info!("Starting at era {:?} epoch {:?}", era, epoch);
+      dhb_builder.era(era).epoch(epoch);
+    }
+    let dhb = dhb_builder.build(ni, secr, Arc::new(val_map));
     let (qhb, qhb_step) = QueueingHoneyBadger::builder(dhb)
       .batch_size(batch_size)
       .build(&mut rng)
       .expect("instantiate QueueingHoneyBadger");
+    Self::assemble(qhb, vec![qhb_step], self_id, others, rng)
+  }
+
+  /// Node joining an existing network as a DynamicHoneyBadger observer through `join_plan`.
+  /// It gets its key share when the change adding it completes.
+  pub fn new_joining(
+    batch_size: usize, join_plan: JoinPlan<NodeId>, validator_set: AuthorityList, auth_id: AuthorityId, self_id: PeerId,
+    keystore: KeyStorePtr, peers: &Peers, mut rng: StdRng,
+  ) -> Result<BadgerNode<B, QHB>, &'static str>
+  {
+    let others: Vec<NodeId> = validator_set
+      .iter()
+      .filter_map(|x| peers.inverse.get(x))
+      .map(|x| x.clone().into())
+      .collect();
+    let secr = Self::secret_key(&keystore, &auth_id);
+    let (dhb, dhb_step) = DynamicHoneyBadger::new_joining(self_id.clone().into(), secr, join_plan, &mut rng)
+      .map_err(|_| "Invalid join plan")?;
+    let (qhb, qhb_step) = QueueingHoneyBadger::builder(dhb)
+      .batch_size(batch_size)
+      .build(&mut rng)
+      .map_err(|_| "Could not instantiate QueueingHoneyBadger")?;
+    Ok(Self::assemble(qhb, vec![dhb_step, qhb_step], self_id, others, rng))
+  }
 
-    let (sq, mut step) =
-      SenderQueue::builder(qhb, peers.inner.keys().map(|x| (*x).clone().into())).build(self_id.clone().into());
-    let output = step.extend_with(qhb_step, |fault| fault, BMessage::from);
-    assert!(output.is_empty());
+  fn secret_key(keystore: &KeyStorePtr, auth_id: &AuthorityId) -> SecretKey
+  {
+    match keystore
+      .read()
+      .key_pair_by_type::<AuthorityPair>(auth_id, app_crypto::key_types::HB_NODE)
+    {
+      Ok(key) => bincode::deserialize(&key.to_raw_vec()).expect("Stored key should be correct"),
+      Err(_) => panic!("SHould really have key at this point"),
+    }
+  }
+
+  fn assemble(
+    qhb: QueueingHoneyBadger<BadgerTransaction, NodeId, Vec<BadgerTransaction>>,
+    init_steps: Vec<CpStep<DynamicHoneyBadger<Vec<BadgerTransaction>, NodeId>>>,
+    self_id: PeerId, others: Vec<NodeId>, rng: StdRng,
+  ) -> BadgerNode<B, QHB>
+  {
+    let (sq, mut step) = SenderQueue::builder(qhb, others.into_iter()).build(self_id.clone().into());
+    for init_step in init_steps
+    {
+      let output = step.extend_with(init_step, |fault| fault, BMessage::from);
+      assert!(output.is_empty());
+    }
     let out_queue = step
       .messages
       .into_iter()
@@ -2602,6 +3548,9 @@ impl<B: BlockT> BadgerNode<B, QHB>
       //in_queue: VecDeque::new(),
       out_queue: out_queue,
       outputs: outputs,
+      pending_vote: None,
+      pending_transactions: VecDeque::new(),
+      faulty: Vec::new(),
       _block: PhantomData,
     };
     node
@@ -2621,6 +3570,11 @@ impl<B: BlockT> BadgerNode<B, QHB>
       .collect();
     self.outputs.extend(step.output.into_iter());
     debug!("BaDGER!! OK message, outputs: {} ", self.outputs.len());
+    for fault in step.fault_log.0
+    {
+      warn!("BaDGER!! Fault by {:?}: {:?}", &fault.node_id, &fault.kind);
+      self.faulty.push(fault.node_id);
+    }
     for (target, message) in out_msgs
     {
       self.out_queue.push_back(SourcedMessage {
@@ -2634,10 +3588,19 @@ impl<B: BlockT> BadgerNode<B, QHB>
   }
   pub fn vote_change_encryption_schedule(&mut self, e: EncryptionSchedule) -> Result<(), &'static str>
   {
-    match self.algo.vote_for(Change::EncryptionSchedule(e), &mut self.main_rng)
+    self.vote_for(Change::EncryptionSchedule(e))
+  }
+  pub fn vote_for_validators(&mut self, new_vals: BTreeMap<PeerIdW, PublicKey>) -> Result<(), &'static str>
+  {
+    self.vote_for(Change::NodeChange(Arc::new(new_vals)))
+  }
+  pub fn vote_for(&mut self, change: Change<NodeId>) -> Result<(), &'static str>
+  {
+    match self.algo.vote_for(change.clone(), &mut self.main_rng)
     {
       Ok(step) =>
       {
+        self.pending_vote = Some(change);
         return self.process_step(step);
       }
       Err(e) =>
@@ -2647,22 +3610,19 @@ impl<B: BlockT> BadgerNode<B, QHB>
       }
     }
   }
-  pub fn vote_for_validators(&mut self, new_vals: BTreeMap<PeerIdW, PublicKey>) -> Result<(), &'static str>
+  /// Forget what `batch` committed: its transactions and, if it completed a change, our vote.
+  pub fn committed(&mut self, batch: &BatchType)
   {
-    match self
-      .algo
-      .vote_for(Change::NodeChange(Arc::new(new_vals)), &mut self.main_rng)
+    if let ChangeState::Complete(ref change) = batch.change()
     {
-      Ok(step) =>
+      // other validators' changes can complete while ours is still being voted on
+      if self.pending_vote.as_ref() == Some(change)
       {
-        return self.process_step(step);
-      }
-      Err(e) =>
-      {
-        info!("Error voting: {:?}", e);
-        return Err("Error voting");
+        self.pending_vote = None;
       }
     }
+    let txs: BTreeSet<BadgerTransaction> = batch.clone().into_tx_iter().collect();
+    self.pending_transactions.retain(|tx| !txs.contains(tx));
   }
   pub fn handle_message(&mut self, who: &NodeId, msg: <QHB as ConsensusProtocol>::Message) -> Result<(), &'static str>
   {
@@ -2879,12 +3839,12 @@ Aux:AuxStore+Send+Sync+'static
     info!("BaDGER!! Exit flush {:?}", thread::current().id());
   }
   /// Create a new gossip-validator.
-  pub fn new(keystore: KeyStorePtr, self_peer: PeerId, batch_size: u64, persist: BadgerPersistentData, client:Arc<Cl>,flizer:Box<dyn FnMut( &Block::Hash,Option<Justification>)->bool+Send+Sync>,
-            bpusher:BPM,astore:Aux) -> Self
+  pub fn new(keystore: KeyStorePtr, self_peer: PeerId, batch_size: u64, batch_policy: Arc<dyn BatchToBlockPolicy>, persist: BadgerPersistentData, client:Arc<Cl>,flizer:Box<dyn FnMut( &Block::Hash,Option<Justification>)->bool+Send+Sync>,
+            bpusher:BPM,astore:Aux,rng:StdRng,clock:Arc<dyn Clock>) -> Self
   {
     Self {
       inner: RwLock::new(BadgerStateMachine::<Block, QHB,Cl,BPM,Aux>::new(
-        keystore, self_peer, batch_size, persist,client,flizer,bpusher,astore
+        keystore, self_peer, batch_size, batch_policy, persist,client,flizer,bpusher,astore,rng,clock
       )),
       pending_messages: RwLock::new(BTreeMap::new()),
     }
@@ -2910,6 +3870,11 @@ Aux:AuxStore+Send+Sync+'static
     rd.is_authority()
   }
 
+  pub fn take_fault_reports(&self) -> Vec<BadgerFaultReport<Block::Header>>
+  {
+    self.inner.write().take_fault_reports()
+  }
+
   pub fn push_transaction(&self, tx: Vec<u8>, net:  &mut dyn ValidatorContext<Block> ) -> Result<(), Error>
   {
 
@@ -2936,6 +3901,30 @@ Aux:AuxStore+Send+Sync+'static
       self.flush_message(&mut Vec::new(), net);
     }
   }
+  pub fn vote_for_validators(&self, auths: Vec<AuthorityId>, net: &mut dyn ValidatorContext<Block>) -> Result<(), &'static str>
+  {
+    let res = self.inner.write().vote_for_validators(auths);
+    self.flush_message(&mut Vec::new(), net);
+    res
+  }
+  pub fn vote_change_encryption_schedule(&self, e: EncryptionSchedule, net: &mut dyn ValidatorContext<Block>) -> Result<(), &'static str>
+  {
+    let res = self.inner.write().vote_change_encryption_schedule(e);
+    self.flush_message(&mut Vec::new(), net);
+    res
+  }
+  pub fn on_timer(&self, net: &mut dyn ValidatorContext<Block>)
+  {
+    {
+      let mut inner = self.inner.write();
+      inner.on_keygen_timer();
+      inner.on_decryption_timer();
+      inner.on_join_plan_timer();
+    }
+    {
+      self.flush_message(&mut Vec::new(), net);
+    }
+  }
   
 
 /*  pub fn do_emit_justification(&self,hash:&Block::Hash, net:  &mut dyn ValidatorContext<Block>,auth_list:AuthorityList)
@@ -3231,6 +4220,16 @@ BPM:BlockPusherMaker<B>+'static,
   {
   self.node.on_block_imported(blki);//num,hash);
   }
+  /// Drive timeouts, should be called periodically
+  pub fn on_timer(&self)
+  {
+    self.engine.with_lock(|en| self.node.on_timer(en));
+  }
+  /// Misbehaviour seen since the last call, to be submitted on chain
+  pub fn take_fault_reports(&self) -> Vec<BadgerFaultReport<B::Header>>
+  {
+    self.node.take_fault_reports()
+  }
   /// Create a new NetworkBridge to the given NetworkService. Returns the service
   /// handle and a future that must be polled to completion to finish startup.
   /// If a voter set state is given it registers previous round votes with the
@@ -3241,7 +4240,9 @@ BPM:BlockPusherMaker<B>+'static,
     executor: &impl futures03::task::Spawn,
   ) -> (Self, impl futures03::future::Future<Output = ()> + Send + Unpin)
   {
-    let validator = BadgerGossipValidator::new(keystore, service.local_id().clone(), config.batch_size.into(), persist,client,flizer,bpusher,astore);
+    let rng = StdRng::from_rng(rand::rngs::OsRng::new().expect("Could not open OS random number generator."))
+      .expect("Could not seed random number generator");
+    let validator = BadgerGossipValidator::new(keystore, service.local_id().clone(), config.batch_size.into(), config.batch_policy.clone(), persist,client,flizer,bpusher,astore,rng,Arc::new(clock::SystemClock));
     let validator_arc = Arc::new(validator);
     let engine=RantingEngine::new(service, executor,HBBFT_ENGINE_ID, validator_arc.clone() );
    
@@ -3268,6 +4269,50 @@ BPM:BlockPusherMaker<B>+'static,
 
 }
 
+impl<B: BlockT, Cl,BPM,Aux> BadgerIntrospect for NetworkBridge<B, Cl,BPM,Aux>
+where
+Cl:NetClient<B>+'static,
+B::Hash:Ord,
+Aux:AuxStore+Send+Sync+'static,
+BPM:BlockPusherMaker<B>+'static,
+{
+  fn status(&self) -> BadgerStatus
+  {
+    self.node.inner.read().status()
+  }
+}
+
+impl<B: BlockT, Cl,BPM,Aux> BadgerControl for NetworkBridge<B, Cl,BPM,Aux>
+where
+Cl:NetClient<B>+'static,
+B::Hash:Ord,
+Aux:AuxStore+Send+Sync+'static,
+BPM:BlockPusherMaker<B>+'static,
+{
+  fn vote_for_validators(&self, authorities: Vec<AuthorityId>) -> Result<(), String>
+  {
+    self
+      .engine
+      .with_lock(|en| self.node.vote_for_validators(authorities, en))
+      .map_err(|e| e.to_string())
+  }
+  fn vote_change_encryption_schedule(&self, schedule: EncryptionSchedule) -> Result<(), String>
+  {
+    self
+      .engine
+      .with_lock(|en| self.node.vote_change_encryption_schedule(schedule, en))
+      .map_err(|e| e.to_string())
+  }
+  fn change_state(&self) -> ChangeStateInfo
+  {
+    self.node.inner.read().change_state_info()
+  }
+  fn submit_encrypted_transaction(&self, ct: Ciphertext) -> Result<(), String>
+  {
+    self.send_out(vec![wrap_encrypted(&ct)]).map_err(|e| format!("{:?}", e))
+  }
+}
+
 impl<B: BlockT, Cl,BPM,Aux> Clone for NetworkBridge<B, Cl,BPM,Aux>
 where
 Cl:NetClient<B>,
@@ -0,0 +1,230 @@
+//! Observer nodes.
+//!
+//! Nodes outside of the authority set run in `BadgerState::Observer`. They hold no key
+//! share and take no part in DynamicHoneyBadger, they only follow finalized blocks: blocks
+//! from block sync are imported once their justification checks out against the current
+//! authority set, anything else (gaps, blocks of split batches, `SyncGossip` announcing
+//! blocks we don't have) is fetched through catch up.
+//!
+//! When a vote adds an observer to the validator set, the validators send it the `JoinPlan`
+//! of the change, and again every `JOIN_PLAN_RESEND_INTERVAL` until the change completes.
+//! The observer joins DynamicHoneyBadger once f+1 authorities sent it the same plan, and
+//! becomes a validator once the change completes.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use badger::dynamic_honey_badger::JoinPlan;
+use badger_primitives::AuthorityId;
+use log::{debug, info, warn};
+use parity_codec::Encode;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use runtime_primitives::traits::{Block as BlockT, Header as HeaderT, One};
+use sc_api::AuxStore;
+use sc_network_ranting::ValidationResult;
+
+use super::gossip::{BadgerFullJustification, BadgerJoinPlan, BadgerJoinPlanData, BadgerJustifiedBlock, BadgerSyncGossip};
+use super::{
+  BadgerNode, BadgerState, BadgerStateMachine, BlockPusherMaker, GossipMessage, LocalTarget, NetClient, NodeId, QHB,
+};
+
+/// How often the join plan of a change in progress is sent again
+pub const JOIN_PLAN_RESEND_INTERVAL: Duration = Duration::from_secs(10);
+
+impl<B: BlockT, Cl, BPM, Aux> BadgerStateMachine<B, QHB, Cl, BPM, Aux>
+where
+  Cl: NetClient<B>,
+  B::Hash: Ord,
+  BPM: BlockPusherMaker<B>,
+  Aux: AuxStore + Send + Sync + 'static,
+{
+  /// Catch up to a block announced by a validator.
+  pub fn observe_sync(&mut self, sync: &BadgerSyncGossip<B>)
+  {
+    if sync.data.num <= self.client.info().best_number || !sync.verify()
+    {
+      return;
+    }
+    if !self.persistent.authority_set.inner.read().current_authorities.contains(&sync.source)
+    {
+      return;
+    }
+    self.request_catch_up(sync.data.num, Some(sync.source.clone()));
+  }
+
+  /// Import a block received through block sync.
+  pub fn observe_block(&mut self, block: B, justification: BadgerFullJustification<B>)
+  {
+    let info = self.client.info();
+    let header = block.header().clone();
+    if *header.number() <= info.best_number
+    {
+      return;
+    }
+    if *header.number() != info.best_number + One::one()
+      || *header.parent_hash() != info.best_hash
+      || justification.hash != header.hash()
+    {
+      // blocks of split batches come with the justification of the last one through catch up
+      self.request_catch_up(*header.number(), None);
+      return;
+    }
+    let authorities = self.persistent.authority_set.inner.read().current_authorities.clone();
+    let committee_key = self.persistent.authority_set.committee_key.read().clone();
+    let key = match justification.check(committee_key.as_ref(), &authorities)
+    {
+      Some(key) => key,
+      None =>
+      {
+        warn!("Invalid justification for observed block {:?}", header.hash());
+        return;
+      }
+    };
+    if !self.import_justified(&BadgerJustifiedBlock {
+      block: block,
+      justification: justification,
+    })
+    {
+      return;
+    }
+    if committee_key.as_ref() != Some(&key)
+    {
+      self.adopt_committee_key(key);
+    }
+    if let Some(new_set) = Self::changed_validators(&header)
+    {
+      info!("Authority set changed at {:?}", header.number());
+      self.adopt_authority_set(new_set);
+      if self.is_authority()
+      {
+        warn!("Added to the authority set without a join plan, can't take part until the next change");
+      }
+    }
+  }
+
+  /// Send the join plan of an in progress change to the authorities it adds.
+  pub fn broadcast_join_plan(&mut self, plan: JoinPlan<NodeId>, added: Vec<AuthorityId>)
+  {
+    if added.is_empty() || !self.is_authority()
+    {
+      return;
+    }
+    self.load_origin();
+    let data = BadgerJoinPlanData {
+      set_id: self.persistent.authority_set.inner.read().set_id,
+      added: added,
+      plan: bincode::serialize(&plan).expect("Serialize error in join plan"),
+    };
+    if let Some((ref sent, _)) = self.sync_state.sent_join_plan
+    {
+      if sent.data.encode() == data.encode()
+      {
+        // already sent, on_join_plan_timer sends it again
+        return;
+      }
+    }
+    info!("Sending join plan to {:?}", &data.added);
+    let msg = BadgerJoinPlan::new(self.cached_origin.as_ref().unwrap(), data);
+    self.sync_state.sent_join_plan = Some((msg.clone(), self.clock.now()));
+    self
+      .output_message_buffer
+      .push((LocalTarget::AllExcept(BTreeSet::new()), GossipMessage::JoinPlan(msg)));
+  }
+
+  /// Called periodically. Sends the join plan of the change in progress again, for joining
+  /// nodes that missed it.
+  pub fn on_join_plan_timer(&mut self)
+  {
+    let msg = match self.sync_state.sent_join_plan
+    {
+      Some((ref msg, ref mut at)) if self.clock.elapsed(*at) > JOIN_PLAN_RESEND_INTERVAL =>
+      {
+        *at = self.clock.now();
+        msg.clone()
+      }
+      _ => return,
+    };
+    debug!("Resending join plan to {:?}", &msg.data.added);
+    self
+      .output_message_buffer
+      .push((LocalTarget::AllExcept(BTreeSet::new()), GossipMessage::JoinPlan(msg)));
+  }
+
+  /// Join DynamicHoneyBadger if `msg` is a join plan adding us to the validator set.
+  pub fn process_join_plan(&mut self, msg: &BadgerJoinPlan) -> (ValidationResult<B>, bool)
+  {
+    if !msg.verify()
+    {
+      return (ValidationResult::Punish(-8), false);
+    }
+    {
+      let aset = self.persistent.authority_set.inner.read();
+      if !aset.current_authorities.contains(&msg.source) || msg.data.set_id != aset.set_id
+      {
+        return (ValidationResult::Discard, false);
+      }
+    }
+    match self.state
+    {
+      BadgerState::Observer =>
+      {}
+      _ => return (ValidationResult::Discard, false),
+    }
+    if !msg.data.added.contains(&self.config.my_auth_id)
+    {
+      return (ValidationResult::Discard, false);
+    }
+    let plan: JoinPlan<NodeId> = match bincode::deserialize(&msg.data.plan)
+    {
+      Ok(plan) => plan,
+      Err(_) =>
+      {
+        warn!("Undecodable join plan from {:?}", &msg.source);
+        return (ValidationResult::Punish(-8), false);
+      }
+    };
+    // a single authority could hand us a made up plan, wait until f+1 agree on it
+    self.sync_state.join_plans.insert(msg.source.clone(), msg.data.clone());
+    let agreeing = {
+      let authorities = &self.persistent.authority_set.inner.read().current_authorities;
+      let encoded = msg.data.encode();
+      let count = self
+        .sync_state
+        .join_plans
+        .iter()
+        .filter(|(source, data)| authorities.contains(source) && data.encode() == encoded)
+        .count();
+      count > badger::util::max_faulty(authorities.len())
+    };
+    if !agreeing
+    {
+      info!("Join plan from {:?}, waiting for more authorities to send it", &msg.source);
+      return (ValidationResult::Discard, false);
+    }
+    self.load_origin();
+    match BadgerNode::<B, QHB>::new_joining(
+      self.config.batch_size as usize,
+      plan,
+      self.persistent.authority_set.inner.read().current_authorities.clone(),
+      self.cached_origin.as_ref().unwrap().public(),
+      self.config.my_peer_id.clone(),
+      self.keystore.clone(),
+      &self.peers,
+      StdRng::from_rng(&mut self.rng).expect("Seeding from another StdRng cannot fail"),
+    )
+    {
+      Ok(node) =>
+      {
+        info!("Joining the validator set with the join plan of {:?}", &msg.source);
+        self.sync_state.join_plans.clear();
+        self.state = BadgerState::Badger(node);
+      }
+      Err(e) =>
+      {
+        warn!("Could not join with the plan of {:?}: {:?}", &msg.source, e);
+      }
+    }
+    (ValidationResult::Discard, false)
+  }
+}
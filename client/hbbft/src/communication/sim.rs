@@ -0,0 +1,858 @@
+//! Deterministic in-process network of Badger validators, for tests.
+//!
+//! Every simulated node runs a `BadgerGossipValidator` on top of its own test client,
+//! keystore and aux store, exactly as `run_honey_badger` wires it up, minus the real
+//! network. Whatever a validator sends through its `ValidatorContext` is put on a
+//! virtual wire and delivered later according to `SimConfig`: random delay, random
+//! drop, optional reordering per link, partitions and crashed nodes.
+//!
+//! Delays and drops on the wire are drawn from an RNG seeded by `SimConfig::seed`, and every
+//! node gets its own RNG derived from the same seed for keygen and Badger. The engine's timers
+//! run on the virtual time through a `SimClock`, so a network of genesis validators finalizes
+//! the same chain every time it is run with the same seed and inputs. Message ids and the
+//! encryption of sealed messages still use the OS RNG, which changes neither what is delivered
+//! when nor what is agreed, and observers generate their node key on startup.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use consensus_common::BlockImport;
+use keystore::KeyStorePtr;
+use log::{info, warn};
+use network::config::{identity, Roles};
+use network::PeerId;
+use parity_codec::{Decode, Encode};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use runtime_primitives::generic::{self, BlockId};
+use sc_api::backend::Finalizer;
+use sc_network_ranting::{Validator, ValidatorContext};
+use substrate_primitives::crypto::Pair;
+use test_client::runtime::{Block, Extrinsic, Hash, Header};
+
+use badger_primitives::{AuthorityId, AuthorityPair};
+use consensus_common::BlockImportParams;
+
+use super::adversary::{Adversary, AdversaryMode};
+use super::batch_policy::{BatchToBlockPolicy, FillBlocks};
+use super::clock::Clock;
+use super::{push_error_result, BadgerGossipValidator, BadgerTransaction, BlockPushResult, BlockPusherMaker, NetClient};
+use crate::aux_store;
+use crate::Cwrap;
+
+pub type SimClient = test_client::Client<test_client::Backend>;
+pub type SimAux = Cwrap<test_client::Backend, test_client::Executor, Block, test_client::runtime::RuntimeApi>;
+pub type SimValidator = BadgerGossipValidator<Block, SimClient, SimBlockMaker, SimAux>;
+
+/// Behaviour of the simulated wire. Delays are in virtual milliseconds.
+#[derive(Clone, Debug)]
+pub struct SimConfig
+{
+  /// Seed of the wire's RNG, and of the RNGs of the nodes
+  pub seed: u64,
+  pub min_delay: u64,
+  pub max_delay: u64,
+  /// Probability that any single message is lost
+  pub drop_rate: f64,
+  /// Allow a message to overtake an earlier one on the same link
+  pub reorder: bool,
+  /// Virtual time between two `on_timer` calls on every node
+  pub timer_interval: u64,
+  pub batch_size: u64,
+  pub batch_policy: Arc<dyn BatchToBlockPolicy>,
+  /// Number of transactions that fit in a simulated block
+  pub block_capacity: usize,
+}
+
+impl Default for SimConfig
+{
+  fn default() -> Self
+  {
+    SimConfig {
+      seed: 0,
+      min_delay: 1,
+      max_delay: 50,
+      drop_rate: 0.0,
+      reorder: true,
+      timer_interval: 1000,
+      batch_size: 8,
+      batch_policy: Arc::new(FillBlocks),
+      block_capacity: usize::max_value(),
+    }
+  }
+}
+
+/// Builds blocks straight on the node's test client.
+pub struct SimBlockMaker
+{
+  client: Arc<SimClient>,
+  /// Number of transactions that fit in a block
+  capacity: usize,
+  /// Transactions reported as rejected, that a pool would have banned
+  banned: Arc<Mutex<Vec<BadgerTransaction>>>,
+}
+
+impl BlockPusherMaker<Block> for SimBlockMaker
+{
+  fn process_all(
+    &mut self, is: BlockId<Block>, digest: generic::Digest<Hash>, txs: impl Iterator<Item = BadgerTransaction>,
+  ) -> Result<(Block, Vec<BadgerTransaction>, Vec<BadgerTransaction>), ()>
+  {
+    let mut builder = self.client.new_block_at(&is, digest).map_err(|_| ())?;
+    let mut pushed = 0;
+    let mut leftover = Vec::new();
+    let mut rejected = Vec::new();
+    for tx in txs
+    {
+      if pushed >= self.capacity
+      {
+        leftover.push(tx);
+        continue;
+      }
+      pushed += 1;
+      match Extrinsic::decode(&mut &tx[..])
+      {
+        Ok(ext) =>
+        {
+          if let Err(e) = builder.push(ext)
+          {
+            info!("Simulated block rejected transaction: {:?}", e);
+            if let BlockPushResult::BlockError = push_error_result(&e)
+            {
+              rejected.push(tx);
+            }
+          }
+        }
+        Err(_) => info!("Simulated block got undecodable transaction"),
+      }
+    }
+    builder.bake().map(|block| (block, leftover, rejected)).map_err(|_| ())
+  }
+
+  fn report_rejected(&self, rejected: Vec<BadgerTransaction>)
+  {
+    self.banned.lock().extend(rejected);
+  }
+
+  fn best_chain(&self) -> Result<Header, ()>
+  {
+    let info = NetClient::info(&*self.client);
+    match NetClient::header(&*self.client, &BlockId::Hash(info.best_hash))
+    {
+      Ok(Some(header)) => Ok(header),
+      _ => Err(()),
+    }
+  }
+
+  fn import_block(&self, import_block: BlockImportParams<Block>) -> Result<(), ()>
+  {
+    let mut importer = &*self.client;
+    match importer.import_block(import_block, Default::default())
+    {
+      Ok(_) => Ok(()),
+      Err(e) =>
+      {
+        warn!("Simulated import failed: {:?}", e);
+        Err(())
+      }
+    }
+  }
+}
+
+/// Virtual time of the network, as seen by the engine's timers.
+pub struct SimClock
+{
+  start: Instant,
+  /// Virtual milliseconds since `start`
+  now: AtomicU64,
+}
+
+impl Clock for SimClock
+{
+  fn now(&self) -> Instant
+  {
+    self.start + Duration::from_millis(self.now.load(Ordering::SeqCst))
+  }
+}
+
+/// A message on the wire, ordered by delivery time and then by send order.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Envelope
+{
+  at: u64,
+  seq: u64,
+  from: usize,
+  to: usize,
+  data: Vec<u8>,
+}
+
+/// Counters for the whole run.
+#[derive(Default, Debug, Clone)]
+pub struct SimStats
+{
+  pub sent: u64,
+  pub dropped: u64,
+  pub delivered: u64,
+}
+
+/// The simulated transport shared by all nodes.
+struct Wire
+{
+  config: SimConfig,
+  rng: StdRng,
+  now: u64,
+  clock: Arc<SimClock>,
+  seq: u64,
+  queue: BinaryHeap<Reverse<Envelope>>,
+  /// Last scheduled delivery per (from, to), used when reordering is off
+  link_clock: HashMap<(usize, usize), u64>,
+  peer_index: HashMap<PeerId, usize>,
+  /// Partition each node is in
+  group: Vec<usize>,
+  crashed: Vec<bool>,
+  stats: SimStats,
+}
+
+impl Wire
+{
+  fn advance(&mut self, to: u64)
+  {
+    self.now = to;
+    self.clock.now.store(to, Ordering::SeqCst);
+  }
+
+  fn connected(&self, a: usize, b: usize) -> bool
+  {
+    a != b && !self.crashed[a] && !self.crashed[b] && self.group[a] == self.group[b]
+  }
+
+  /// Reachability is checked when a message is sent: messages already in flight
+  /// when a partition starts are still delivered.
+  fn send(&mut self, from: usize, to: usize, data: Vec<u8>)
+  {
+    self.stats.sent += 1;
+    if !self.connected(from, to) || self.rng.gen_bool(self.config.drop_rate)
+    {
+      self.stats.dropped += 1;
+      return;
+    }
+    let delay = self.rng.gen_range(self.config.min_delay, self.config.max_delay + 1);
+    let mut at = self.now + delay;
+    if !self.config.reorder
+    {
+      let last = self.link_clock.entry((from, to)).or_insert(0);
+      if at < *last
+      {
+        at = *last;
+      }
+      *last = at;
+    }
+    self.seq += 1;
+    self.queue.push(Reverse(Envelope {
+      at: at,
+      seq: self.seq,
+      from: from,
+      to: to,
+      data: data,
+    }));
+  }
+}
+
+/// `ValidatorContext` handed to node `from` while it handles an event.
+struct SimContext<'a>
+{
+  from: usize,
+  wire: &'a mut Wire,
+  kept: &'a mut HashMap<Hash, Vec<u8>>,
+}
+
+impl<'a> ValidatorContext<Block> for SimContext<'a>
+{
+  fn broadcast_except(&mut self, except: HashSet<PeerId>, message: Vec<u8>)
+  {
+    let mut targets: Vec<usize> = self
+      .wire
+      .peer_index
+      .iter()
+      .filter(|(p, _)| !except.contains(p))
+      .map(|(_, i)| *i)
+      .filter(|i| *i != self.from)
+      .collect();
+    // HashMap order is random, sort to keep the schedule reproducible
+    targets.sort();
+    for to in targets
+    {
+      self.wire.send(self.from, to, message.clone());
+    }
+  }
+
+  fn send_to_set(&mut self, set: HashSet<PeerId>, message: Vec<u8>)
+  {
+    let mut targets: Vec<usize> = set
+      .iter()
+      .filter_map(|p| self.wire.peer_index.get(p).cloned())
+      .filter(|i| *i != self.from)
+      .collect();
+    targets.sort();
+    for to in targets
+    {
+      self.wire.send(self.from, to, message.clone());
+    }
+  }
+
+  fn send_single(&mut self, who: &PeerId, message: Vec<u8>)
+  {
+    if let Some(to) = self.wire.peer_index.get(who).cloned()
+    {
+      self.wire.send(self.from, to, message);
+    }
+  }
+
+  fn keep(&mut self, cell: Hash, message: Vec<u8>)
+  {
+    self.kept.insert(cell, message);
+  }
+
+  fn get_kept(&self, cell: &Hash) -> Option<Vec<u8>>
+  {
+    self.kept.get(cell).cloned()
+  }
+}
+
+pub struct SimNode
+{
+  pub peer_id: PeerId,
+  pub auth_id: AuthorityId,
+  pub client: Arc<SimClient>,
+  pub keystore: KeyStorePtr,
+  pub validator: Arc<SimValidator>,
+  banned: Arc<Mutex<Vec<BadgerTransaction>>>,
+  kept: HashMap<Hash, Vec<u8>>,
+  /// Number of times the node was restarted, keeps the RNG of each run apart
+  restarts: u64,
+}
+
+/// Start a validator on `client`, from whatever its aux store holds.
+fn spawn_validator(
+  config: &SimConfig, clock: Arc<SimClock>, rng: StdRng, client: Arc<SimClient>, keystore: KeyStorePtr,
+  peer_id: PeerId, genesis: Vec<AuthorityId>, banned: Arc<Mutex<Vec<BadgerTransaction>>>,
+) -> (SimValidator, AuthorityId)
+{
+  let persistent =
+    aux_store::load_persistent_badger(&Cwrap { client: client.clone() }, move || Ok(genesis), keystore.clone())
+      .expect("Could not initialize simulated aux store");
+  let auth_id = persistent.authority_set.inner.read().self_id.clone();
+  let fclient = client.clone();
+  let finalizer = Box::new(move |hash: &Hash, justification| {
+    fclient.finalize_block(BlockId::Hash(hash.clone()), justification, true).is_ok()
+  });
+  let validator = SimValidator::new(
+    keystore,
+    peer_id,
+    config.batch_size,
+    config.batch_policy.clone(),
+    persistent,
+    client.clone(),
+    finalizer,
+    SimBlockMaker { client: client.clone(), capacity: config.block_capacity, banned: banned },
+    Cwrap { client: client },
+    rng,
+    clock,
+  );
+  (validator, auth_id)
+}
+
+/// N validators over a simulated network, driven one event at a time.
+pub struct SimNetwork
+{
+  pub nodes: Vec<SimNode>,
+  wire: Wire,
+  next_timer: u64,
+  /// Authorities of the genesis block
+  genesis: Vec<AuthorityId>,
+}
+
+impl SimNetwork
+{
+  /// Start `n` genesis validators and connect them all to each other.
+  pub fn new(n: usize, config: SimConfig) -> Self
+  {
+    Self::with_observers(n, 0, config)
+  }
+
+  /// Start `n` genesis validators followed by `observers` nodes outside of the authority set,
+  /// all connected to each other.
+  pub fn with_observers(validators: usize, observers: usize, config: SimConfig) -> Self
+  {
+    let n = validators + observers;
+    let keystores: Vec<KeyStorePtr> = (0..n).map(|_| keystore::Store::new_in_memory()).collect();
+    // observers get a key generated on first startup
+    let authorities: Vec<AuthorityId> = keystores
+      .iter()
+      .take(validators)
+      .enumerate()
+      .map(|(i, ks)| {
+        ks.write()
+          .insert_ephemeral_from_seed_by_type::<AuthorityPair>(&format!("//BadgerSim{}", i), app_crypto::key_types::HB_NODE)
+          .expect("Could not insert simulated key")
+          .public()
+      })
+      .collect();
+
+    let clock = Arc::new(SimClock {
+      start: Instant::now(),
+      now: AtomicU64::new(0),
+    });
+    let mut nodes = Vec::new();
+    for (i, keystore) in keystores.into_iter().enumerate()
+    {
+      let client = Arc::new(test_client::new());
+      let mut secret = [0u8; 32];
+      secret[0] = i as u8 + 1;
+      let secret = identity::ed25519::SecretKey::from_bytes(&mut secret).expect("Valid simulated node key");
+      let peer_id = identity::Keypair::Ed25519(secret.into()).public().into_peer_id();
+      let banned = Arc::new(Mutex::new(Vec::new()));
+      let (validator, auth_id) = spawn_validator(
+        &config,
+        clock.clone(),
+        StdRng::seed_from_u64(config.seed ^ ((i as u64 + 1) << 32)),
+        client.clone(),
+        keystore.clone(),
+        peer_id.clone(),
+        authorities.clone(),
+        banned.clone(),
+      );
+      nodes.push(SimNode {
+        peer_id: peer_id,
+        auth_id: auth_id,
+        client: client,
+        keystore: keystore,
+        validator: Arc::new(validator),
+        banned: banned,
+        kept: HashMap::new(),
+        restarts: 0,
+      });
+    }
+
+    let wire = Wire {
+      rng: StdRng::seed_from_u64(config.seed),
+      config: config,
+      now: 0,
+      clock: clock,
+      seq: 0,
+      queue: BinaryHeap::new(),
+      link_clock: HashMap::new(),
+      peer_index: nodes.iter().enumerate().map(|(i, n)| (n.peer_id.clone(), i)).collect(),
+      group: vec![0; n],
+      crashed: vec![false; n],
+      stats: SimStats::default(),
+    };
+    let mut net = SimNetwork {
+      next_timer: wire.config.timer_interval,
+      nodes: nodes,
+      wire: wire,
+      genesis: authorities,
+    };
+    for a in 0..n
+    {
+      for b in 0..n
+      {
+        if a != b
+        {
+          net.connect(a, b);
+        }
+      }
+    }
+    net
+  }
+
+  pub fn config_mut(&mut self) -> &mut SimConfig
+  {
+    &mut self.wire.config
+  }
+
+  pub fn now(&self) -> u64
+  {
+    self.wire.now
+  }
+
+  pub fn stats(&self) -> SimStats
+  {
+    self.wire.stats.clone()
+  }
+
+  /// Run `f` on node `i` with a context that sends through the wire, then feed any
+  /// batches the node produced back into it, as `BadgerStream` does.
+  fn with_node<F>(&mut self, i: usize, f: F)
+  where
+    F: FnOnce(&SimValidator, &mut dyn ValidatorContext<Block>),
+  {
+    let node = &mut self.nodes[i];
+    let mut ctx = SimContext {
+      from: i,
+      wire: &mut self.wire,
+      kept: &mut node.kept,
+    };
+    f(&*node.validator, &mut ctx);
+    while let Some(batch) = node.validator.pop_output()
+    {
+      node.validator.process_batch(batch, &mut ctx);
+    }
+  }
+
+  /// Tell `a` that `b` connected.
+  fn connect(&mut self, a: usize, b: usize)
+  {
+    let who = self.nodes[b].peer_id.clone();
+    self.with_node(a, |v, ctx| v.new_peer(ctx, &who, Roles::AUTHORITY));
+  }
+
+  /// Tell `a` that `b` disconnected.
+  fn disconnect(&mut self, a: usize, b: usize)
+  {
+    let who = self.nodes[b].peer_id.clone();
+    self.with_node(a, |v, ctx| v.peer_disconnected(ctx, &who));
+  }
+
+  /// Move nodes into the given groups, nodes not listed form one more group.
+  /// Links that go down or come up are reported to both ends.
+  pub fn partition(&mut self, groups: &[&[usize]])
+  {
+    let n = self.nodes.len();
+    let mut group = vec![groups.len(); n];
+    for (g, members) in groups.iter().enumerate()
+    {
+      for &i in members.iter()
+      {
+        group[i] = g;
+      }
+    }
+    self.regroup(group);
+  }
+
+  /// Remove all partitions.
+  pub fn heal(&mut self)
+  {
+    let n = self.nodes.len();
+    self.regroup(vec![0; n]);
+  }
+
+  fn regroup(&mut self, group: Vec<usize>)
+  {
+    let n = self.nodes.len();
+    let before: Vec<Vec<bool>> = (0..n).map(|a| (0..n).map(|b| self.wire.connected(a, b)).collect()).collect();
+    self.wire.group = group;
+    self.relink(before);
+  }
+
+  /// Crash node `i`: it stops receiving, sending and ticking.
+  pub fn crash(&mut self, i: usize)
+  {
+    let n = self.nodes.len();
+    let before: Vec<Vec<bool>> = (0..n).map(|a| (0..n).map(|b| self.wire.connected(a, b)).collect()).collect();
+    self.wire.crashed[i] = true;
+    self.relink(before);
+  }
+
+  /// Restart node `i` from its client and aux store, as a validator process started again on
+  /// the same database. A crashed node comes back up.
+  pub fn restart(&mut self, i: usize)
+  {
+    if !self.wire.crashed[i]
+    {
+      self.crash(i);
+    }
+    let n = self.nodes.len();
+    let before: Vec<Vec<bool>> = (0..n).map(|a| (0..n).map(|b| self.wire.connected(a, b)).collect()).collect();
+    let node = &mut self.nodes[i];
+    node.restarts += 1;
+    let rng = StdRng::seed_from_u64(self.wire.config.seed ^ ((i as u64 + 1) << 32) ^ (node.restarts << 48));
+    let (validator, _) = spawn_validator(
+      &self.wire.config,
+      self.wire.clock.clone(),
+      rng,
+      node.client.clone(),
+      node.keystore.clone(),
+      node.peer_id.clone(),
+      self.genesis.clone(),
+      node.banned.clone(),
+    );
+    node.validator = Arc::new(validator);
+    node.kept.clear();
+    self.wire.crashed[i] = false;
+    self.relink(before);
+  }
+
+  /// Number of transactions node `i` carries over to its next block.
+  pub fn overflow_len(&self, i: usize) -> usize
+  {
+    self.nodes[i].validator.inner.read().mech.overflow.len()
+  }
+
+  /// True if node `i` has no batch waiting to be turned into a block or to be finalized.
+  pub fn is_idle(&self, i: usize) -> bool
+  {
+    let inner = self.nodes[i].validator.inner.read();
+    inner.mech.queued_block.is_none() && inner.mech.awaiting_decryption.is_none() && inner.mech.queued_batches.is_empty()
+  }
+
+  fn relink(&mut self, before: Vec<Vec<bool>>)
+  {
+    let n = self.nodes.len();
+    for a in 0..n
+    {
+      if self.wire.crashed[a]
+      {
+        continue;
+      }
+      for b in 0..n
+      {
+        if a == b
+        {
+          continue;
+        }
+        let now = self.wire.connected(a, b);
+        if before[a][b] && !now
+        {
+          self.disconnect(a, b);
+        }
+        else if !before[a][b] && now
+        {
+          self.connect(a, b);
+        }
+      }
+    }
+  }
+
+  pub fn is_crashed(&self, i: usize) -> bool
+  {
+    self.wire.crashed[i]
+  }
+
+  /// Nodes that have not crashed.
+  pub fn live(&self) -> Vec<usize>
+  {
+    (0..self.nodes.len()).filter(|i| !self.wire.crashed[*i]).collect()
+  }
+
+  /// Submit a transaction at node `i`.
+  pub fn submit(&mut self, i: usize, tx: BadgerTransaction)
+  {
+    self.with_node(i, |v, ctx| {
+      if let Err(e) = v.push_transaction(tx, ctx)
+      {
+        info!("Simulated submit failed: {:?}", e);
+      }
+    });
+  }
+
+  /// Submit `count` distinct `IncludeData` extrinsics at every live node.
+  pub fn submit_load(&mut self, count: usize)
+  {
+    for i in self.live()
+    {
+      for k in 0..count
+      {
+        let payload = format!("sim-{}-{}-{}", i, k, self.wire.now);
+        self.submit(i, Extrinsic::IncludeData(payload.into_bytes()).encode());
+      }
+    }
+  }
+
+  fn fire_timers(&mut self)
+  {
+    for i in self.live()
+    {
+      self.with_node(i, |v, ctx| v.on_timer(ctx));
+    }
+  }
+
+  /// Deliver the next message, returns false if the wire is empty.
+  pub fn step(&mut self) -> bool
+  {
+    let env = match self.wire.queue.pop()
+    {
+      Some(Reverse(env)) => env,
+      None => return false,
+    };
+    while self.next_timer <= env.at
+    {
+      let at = self.next_timer;
+      self.wire.advance(at);
+      self.next_timer += self.wire.config.timer_interval;
+      self.fire_timers();
+    }
+    self.wire.advance(env.at);
+    if self.wire.crashed[env.to]
+    {
+      self.wire.stats.dropped += 1;
+      return true;
+    }
+    self.wire.stats.delivered += 1;
+    let from = self.nodes[env.from].peer_id.clone();
+    let data = env.data;
+    self.with_node(env.to, |v, ctx| {
+      v.validate(ctx, &from, &data);
+    });
+    true
+  }
+
+  /// Run until `done` holds or virtual time passes `deadline`, returns whether `done` held.
+  pub fn run_until<F>(&mut self, deadline: u64, mut done: F) -> bool
+  where
+    F: FnMut(&SimNetwork) -> bool,
+  {
+    loop
+    {
+      if done(self)
+      {
+        return true;
+      }
+      if self.wire.now > deadline || !self.step()
+      {
+        return done(self);
+      }
+    }
+  }
+
+  /// Make node `i` misbehave from now on.
+  pub fn set_adversary(&mut self, i: usize, mode: AdversaryMode)
+  {
+    self.nodes[i].validator.inner.write().adversary = Some(Adversary::new(mode));
+  }
+
+  /// Blocks node `i` made up as a `ForgeContinuedBlock` adversary.
+  pub fn forged_blocks(&self, i: usize) -> Vec<Hash>
+  {
+    match self.nodes[i].validator.inner.read().adversary
+    {
+      Some(ref adversary) => adversary.forged.clone(),
+      None => Vec::new(),
+    }
+  }
+
+  /// Extrinsics node `i` reported as rejected while building blocks.
+  pub fn banned(&self, i: usize) -> Vec<Extrinsic>
+  {
+    self.nodes[i]
+      .banned
+      .lock()
+      .iter()
+      .filter_map(|tx| Extrinsic::decode(&mut &tx[..]).ok())
+      .collect()
+  }
+
+  /// True if node `i` imported the block, finalized or not.
+  pub fn has_block(&self, i: usize, hash: &Hash) -> bool
+  {
+    match NetClient::header(&*self.nodes[i].client, &BlockId::Hash(hash.clone()))
+    {
+      Ok(Some(_)) => true,
+      _ => false,
+    }
+  }
+
+  pub fn peer_id(&self, i: usize) -> PeerId
+  {
+    self.nodes[i].peer_id.clone()
+  }
+
+  pub fn auth_id(&self, i: usize) -> AuthorityId
+  {
+    self.nodes[i].auth_id.clone()
+  }
+
+  /// Have node `i` vote for `authorities` as the new validator set.
+  pub fn vote_for_validators(&mut self, i: usize, authorities: Vec<AuthorityId>)
+  {
+    self.with_node(i, |v, ctx| {
+      if let Err(e) = v.vote_for_validators(authorities, ctx)
+      {
+        warn!("Simulated node {:?} could not vote: {:?}", i, e);
+      }
+    });
+  }
+
+  pub fn state_name(&self, i: usize) -> &'static str
+  {
+    self.nodes[i].validator.inner.read().state.name()
+  }
+
+  /// True once all the given nodes run Badger.
+  pub fn all_running(&self, nodes: &[usize]) -> bool
+  {
+    nodes.iter().all(|i| self.state_name(*i) == "Badger")
+  }
+
+  pub fn finalized_number(&self, i: usize) -> u64
+  {
+    NetClient::info(&*self.nodes[i].client).finalized_number
+  }
+
+  /// Hashes of the finalized blocks of node `i`, from block 1 up.
+  pub fn finalized_chain(&self, i: usize) -> Vec<Hash>
+  {
+    let client = &*self.nodes[i].client;
+    let best = NetClient::info(client).finalized_number;
+    let mut chain = Vec::new();
+    let mut num = 1;
+    while num <= best
+    {
+      chain.push(
+        NetClient::block_hash(client, num)
+          .expect("Simulated client error")
+          .expect("Finalized block is missing"),
+      );
+      num += 1;
+    }
+    chain
+  }
+
+  /// Bodies of the finalized blocks of node `i`, from block 1 up.
+  pub fn finalized_bodies(&self, i: usize) -> Vec<Vec<Extrinsic>>
+  {
+    let client = &*self.nodes[i].client;
+    self
+      .finalized_chain(i)
+      .into_iter()
+      .map(|hash| {
+        client
+          .body(&BlockId::Hash(hash))
+          .expect("Simulated client error")
+          .expect("Finalized block body is missing")
+      })
+      .collect()
+  }
+
+  /// Extrinsics of the finalized blocks of node `i`, in chain order.
+  pub fn finalized_extrinsics(&self, i: usize) -> Vec<Extrinsic>
+  {
+    self.finalized_bodies(i).into_iter().flatten().collect()
+  }
+
+  /// Lowest finalized block number among the given nodes.
+  pub fn min_finalized(&self, nodes: &[usize]) -> u64
+  {
+    nodes.iter().map(|i| self.finalized_number(*i)).min().unwrap_or(0)
+  }
+
+  /// Panic unless the finalized chains of the given nodes are prefixes of each other.
+  pub fn assert_agreement(&self, nodes: &[usize])
+  {
+    let chains: Vec<_> = nodes.iter().map(|i| (*i, self.finalized_chain(*i))).collect();
+    for (a, ca) in chains.iter()
+    {
+      for (b, cb) in chains.iter()
+      {
+        for (num, (ha, hb)) in ca.iter().zip(cb.iter()).enumerate()
+        {
+          assert_eq!(ha, hb, "nodes {} and {} finalized different blocks at {}", a, b, num + 1);
+        }
+      }
+    }
+  }
+}
@@ -14,237 +14,455 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Tests for the communication portion of the GRANDPA crate.
-
-use futures::prelude::*;
-use futures::sync::mpsc;
-use keyring::AuthorityKeyring;
-use network::consensus_gossip as network_gossip;
-use network::test::{Block, Hash};
-use network_gossip::Validator;
-use parity_codec::Encode;
+//! Liveness and agreement tests for the Badger engine over the simulated network.
+
+use std::collections::BTreeSet;
 use std::sync::Arc;
-use tokio::runtime::current_thread;
 
-use super::gossip::{self, GossipValidator};
-use super::{AuthorityId, Round, SetId, VoterSet};
-use crate::environment::SharedVoterSetState;
+use badger::crypto::SecretKey;
+use badger_primitives::AuthorityPair;
+use parity_codec::Encode;
+use substrate_primitives::crypto::Pair;
+use keyring::AccountKeyring;
+use test_client::runtime::{Extrinsic, Transfer};
 
-enum Event
-{
-  MessagesFor(
-    Hash,
-    mpsc::UnboundedSender<network_gossip::TopicNotification>,
-  ),
-  RegisterValidator(Arc<dyn network_gossip::Validator<Block>>),
-  GossipMessage(Hash, Vec<u8>, bool),
-  SendMessage(Vec<network::PeerId>, Vec<u8>),
-  Report(network::PeerId, i32),
-  Announce(Hash),
-}
+use super::adversary::AdversaryMode;
+use super::batch_policy::{OneBlockPerBatch, SplitBatches};
+use super::gossip::SealedBadgeredMessage;
+use super::sim::{SimConfig, SimNetwork};
 
-#[derive(Clone)]
-struct TestNetwork
+/// Virtual milliseconds any single scenario may take
+const DEADLINE: u64 = 600_000;
+
+// starts `n` validators and waits for genesis keygen to finish on all of them
+fn start(n: usize, config: SimConfig) -> SimNetwork
 {
-  sender: mpsc::UnboundedSender<Event>,
+  let _ = env_logger::try_init();
+  let mut net = SimNetwork::new(n, config);
+  let all: Vec<usize> = (0..n).collect();
+  assert!(net.run_until(DEADLINE, |net| net.all_running(&all)), "genesis keygen did not complete");
+  net
 }
 
-impl super::Network<Block> for TestNetwork
+// feeds transactions until the given nodes finalize `blocks` more blocks
+fn finalize_more(net: &mut SimNetwork, nodes: &[usize], blocks: u64) -> bool
 {
-  type In = mpsc::UnboundedReceiver<network_gossip::TopicNotification>;
-
-  /// Get a stream of messages for a specific gossip topic.
-  fn messages_for(&self, topic: Hash) -> Self::In
+  let target = net.min_finalized(nodes) + blocks;
+  let deadline = net.now() + DEADLINE;
+  while net.now() < deadline
   {
-    let (tx, rx) = mpsc::unbounded();
-    let _ = self.sender.unbounded_send(Event::MessagesFor(topic, tx));
-
-    rx
+    let started = net.now();
+    net.submit_load(4);
+    if net.run_until(deadline, |net| net.min_finalized(nodes) >= target)
+    {
+      return true;
+    }
+    if net.now() == started
+    {
+      // nothing left on the wire and no time passed, we are stuck
+      return false;
+    }
   }
+  net.min_finalized(nodes) >= target
+}
 
-  /// Register a gossip validator.
-  fn register_validator(&self, validator: Arc<dyn network_gossip::Validator<Block>>)
+#[test]
+fn honest_nodes_finalize_same_blocks()
+{
+  for seed in 0..3
   {
-    let _ = self
-      .sender
-      .unbounded_send(Event::RegisterValidator(validator));
+    let mut net = start(4, SimConfig { seed: seed, ..Default::default() });
+    let all = net.live();
+    assert!(finalize_more(&mut net, &all, 3), "no progress with seed {}", seed);
+    net.assert_agreement(&all);
   }
+}
 
-  /// Gossip a message out to all connected peers.
-  ///
-  /// Force causes it to be sent to all peers, even if they've seen it already.
-  /// Only should be used in case of consensus stall.
-  fn gossip_message(&self, topic: Hash, data: Vec<u8>, force: bool)
-  {
-    let _ = self
-      .sender
-      .unbounded_send(Event::GossipMessage(topic, data, force));
-  }
+#[test]
+fn same_seed_finalizes_same_chain()
+{
+  let run = || {
+    let mut net = start(4, SimConfig { seed: 29, ..Default::default() });
+    let all = net.live();
+    assert!(finalize_more(&mut net, &all, 3));
+    all.iter().map(|i| net.finalized_chain(*i)).collect::<Vec<_>>()
+  };
+  assert_eq!(run(), run());
+}
 
-  /// Send a message to a bunch of specific peers, even if they've seen it already.
-  fn send_message(&self, who: Vec<network::PeerId>, data: Vec<u8>)
-  {
-    let _ = self.sender.unbounded_send(Event::SendMessage(who, data));
-  }
+#[test]
+fn agreement_without_reordering_and_long_delays()
+{
+  let mut net = start(
+    4,
+    SimConfig {
+      seed: 7,
+      min_delay: 50,
+      max_delay: 400,
+      reorder: false,
+      ..Default::default()
+    },
+  );
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3));
+  net.assert_agreement(&all);
+}
 
-  /// Register a message with the gossip service, it isn't broadcast right
-  /// away to any peers, but may be sent to new peers joining or when asked to
-  /// broadcast the topic. Useful to register previous messages on node
-  /// startup.
-  fn register_gossip_message(&self, _topic: Hash, _data: Vec<u8>)
+#[test]
+fn progress_with_f_crashed_nodes()
+{
+  let mut net = start(4, SimConfig { seed: 3, ..Default::default() });
+  net.crash(3);
+  let live = net.live();
+  assert!(finalize_more(&mut net, &live, 3), "3 of 4 validators should make progress");
+  net.assert_agreement(&live);
+}
+
+#[test]
+fn partition_without_quorum_stalls_then_recovers()
+{
+  let mut net = start(4, SimConfig { seed: 11, ..Default::default() });
+  let all = net.live();
+  // let everything in flight settle
+  net.run_until(net.now() + DEADLINE, |_| false);
+  let before: Vec<u64> = all.iter().map(|i| net.finalized_number(*i)).collect();
+
+  net.partition(&[&[0, 1], &[2, 3]]);
+  net.submit_load(4);
+  net.run_until(net.now() + DEADLINE / 10, |_| false);
+  let during: Vec<u64> = all.iter().map(|i| net.finalized_number(*i)).collect();
+  assert_eq!(before, during, "a minority partition finalized a block");
+
+  net.heal();
+  assert!(finalize_more(&mut net, &all, 1), "no progress after the partition healed");
+  net.assert_agreement(&all);
+}
+
+#[test]
+fn transaction_contributed_by_everyone_is_included_once()
+{
+  let mut net = start(4, SimConfig { seed: 9, ..Default::default() });
+  let all = net.live();
+  let tx = Extrinsic::IncludeData(b"everyone".to_vec());
+  for i in all.iter()
   {
-    // NOTE: only required to restore previous state on startup
-    //       not required for tests currently
+    net.submit(*i, tx.encode());
   }
+  assert!(finalize_more(&mut net, &all, 2));
+  net.assert_agreement(&all);
+  let included = net.finalized_extrinsics(0).into_iter().filter(|x| *x == tx).count();
+  assert_eq!(included, 1);
+}
 
-  /// Report a peer's cost or benefit after some action.
-  fn report(&self, who: network::PeerId, cost_benefit: i32)
+#[test]
+fn transaction_ahead_of_its_sender_is_not_banned()
+{
+  let mut net = start(4, SimConfig { seed: 30, ..Default::default() });
+  let all = net.live();
+  let transfer = |from: AccountKeyring, nonce: u64| {
+    Transfer {
+      from: from.into(),
+      to: AccountKeyring::Bob.into(),
+      amount: 1,
+      nonce: nonce,
+    }
+    .into_signed_tx()
+  };
+  let first = transfer(AccountKeyring::Alice, 0);
+  let second = transfer(AccountKeyring::Alice, 1);
+  // signed by Alice on behalf of Charlie, no block can ever include it
+  let forged = match transfer(AccountKeyring::Alice, 0)
   {
-    let _ = self.sender.unbounded_send(Event::Report(who, cost_benefit));
-  }
+    Extrinsic::Transfer(mut tx, signature) =>
+    {
+      tx.from = AccountKeyring::Charlie.into();
+      Extrinsic::Transfer(tx, signature)
+    }
+    _ => unreachable!(),
+  };
 
-  /// Inform peers that a block with given hash should be downloaded.
-  fn announce(&self, block: Hash)
+  // the second transfer of Alice is ordered before her first one
+  net.submit(0, second.encode());
+  net.submit(1, forged.encode());
+  assert!(finalize_more(&mut net, &all, 2));
+  net.submit(1, first.encode());
+  assert!(finalize_more(&mut net, &all, 2));
+  // a pool that kept the second transfer contributes it again
+  net.submit(2, second.encode());
+  assert!(finalize_more(&mut net, &all, 2));
+  net.assert_agreement(&all);
+
+  for i in all.iter()
   {
-    let _ = self.sender.unbounded_send(Event::Announce(block));
+    assert_eq!(net.banned(*i), vec![forged.clone()], "node {} banned the wrong transactions", i);
   }
+  let transfers: Vec<Extrinsic> = net
+    .finalized_extrinsics(0)
+    .into_iter()
+    .filter(|x| match x
+    {
+      Extrinsic::Transfer(..) => true,
+      _ => false,
+    })
+    .collect();
+  assert_eq!(transfers, vec![first, second]);
 }
 
-impl network_gossip::ValidatorContext<Block> for TestNetwork
+#[test]
+fn split_batches_respect_block_capacity()
 {
-  fn broadcast_topic(&mut self, _: Hash, _: bool) {}
+  let mut net = start(
+    4,
+    SimConfig {
+      seed: 13,
+      batch_policy: Arc::new(SplitBatches),
+      block_capacity: 2,
+      ..Default::default()
+    },
+  );
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 4));
+  net.assert_agreement(&all);
+  let bodies = net.finalized_bodies(0);
+  assert!(bodies.iter().all(|body| body.len() <= 2));
+}
 
-  fn broadcast_message(&mut self, _: Hash, _: Vec<u8>, _: bool) {}
+// panics if node `i` finalized any extrinsic twice
+fn assert_included_once(net: &SimNetwork, i: usize)
+{
+  let included = net.finalized_extrinsics(i);
+  let distinct: BTreeSet<Vec<u8>> = included.iter().map(|x| x.encode()).collect();
+  assert_eq!(distinct.len(), included.len(), "node {} finalized a transaction twice", i);
+}
 
-  fn send_message(&mut self, who: &network::PeerId, data: Vec<u8>)
-  {
-    <Self as super::Network<Block>>::send_message(self, vec![who.clone()], data);
-  }
+#[test]
+fn restarted_validator_keeps_carried_over_transactions()
+{
+  let mut net = start(4, SimConfig { seed: 31, block_capacity: 2, ..Default::default() });
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 2));
+
+  // restart node 3 between two batches, while it carries transactions over to the next block
+  net.submit_load(4);
+  assert!(
+    net.run_until(net.now() + DEADLINE, |net| net.overflow_len(3) > 0 && net.is_idle(3)),
+    "node 3 never carried transactions over"
+  );
+  let overflow = net.overflow_len(3);
+  net.restart(3);
+  assert_eq!(net.overflow_len(3), overflow, "node 3 lost the transactions it carried over");
+
+  assert!(finalize_more(&mut net, &all, 3), "no progress after the restart");
+  net.assert_agreement(&all);
+  assert_included_once(&net, 3);
+}
 
-  fn send_topic(&mut self, _: &network::PeerId, _: Hash, _: bool) {}
+#[test]
+fn validator_catching_up_gets_carried_over_transactions()
+{
+  let mut net = start(4, SimConfig { seed: 32, block_capacity: 2, ..Default::default() });
+  let all = net.live();
+  net.crash(3);
+  assert!(finalize_more(&mut net, &[0, 1, 2], 4));
+  // let the others finish their blocks, so their overflow follows their best block
+  net.run_until(net.now() + DEADLINE / 10, |_| false);
+
+  net.restart(3);
+  let deadline = net.now() + DEADLINE;
+  assert!(
+    net.run_until(deadline, |net| net.finalized_number(3) >= net.finalized_number(0) && net.all_running(&[3])),
+    "node 3 did not catch up"
+  );
+  assert_eq!(net.overflow_len(3), net.overflow_len(0));
+
+  assert!(finalize_more(&mut net, &all, 2), "no progress after catching up");
+  net.assert_agreement(&all);
+  assert_included_once(&net, 3);
 }
 
-struct Tester
+#[test]
+fn one_block_per_batch_makes_progress()
 {
-  net_handle: super::NetworkBridge<Block, TestNetwork>,
-  gossip_validator: Arc<GossipValidator<Block>>,
-  events: mpsc::UnboundedReceiver<Event>,
+  let mut net = start(
+    4,
+    SimConfig {
+      seed: 17,
+      batch_policy: Arc::new(OneBlockPerBatch),
+      block_capacity: 1,
+      ..Default::default()
+    },
+  );
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3));
+  net.assert_agreement(&all);
 }
 
-impl Tester
+#[test]
+fn observer_follows_finalized_blocks()
 {
-  fn filter_network_events<F>(self, mut pred: F) -> impl Future<Item = Self, Error = ()>
-  where
-    F: FnMut(Event) -> bool,
+  let _ = env_logger::try_init();
+  let mut net = SimNetwork::with_observers(4, 1, SimConfig { seed: 23, ..Default::default() });
+  let validators: Vec<usize> = (0..4).collect();
+  assert!(net.run_until(DEADLINE, |net| net.all_running(&validators)), "genesis keygen did not complete");
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3), "observer did not follow the validators");
+  net.assert_agreement(&all);
+  assert_ne!(net.state_name(4), "Badger");
+}
+
+#[test]
+fn voted_in_observer_joins_badger()
+{
+  let _ = env_logger::try_init();
+  let mut net = SimNetwork::with_observers(4, 1, SimConfig { seed: 27, ..Default::default() });
+  let validators: Vec<usize> = (0..4).collect();
+  assert!(net.run_until(DEADLINE, |net| net.all_running(&validators)), "genesis keygen did not complete");
+  let new_set: Vec<_> = (0..5).map(|i| net.auth_id(i)).collect();
+  for i in validators.iter()
   {
-    let mut s = Some(self);
-    futures::future::poll_fn(move || {
-      loop
-      {
-        match s.as_mut().unwrap().events.poll().expect("concluded early")
-        {
-          Async::Ready(None) => panic!("concluded early"),
-          Async::Ready(Some(item)) =>
-          {
-            if pred(item)
-            {
-              return Ok(Async::Ready(s.take().unwrap()));
-            }
-          }
-          Async::NotReady => return Ok(Async::NotReady),
-        }
-      }
-    })
+    net.vote_for_validators(*i, new_set.clone());
+  }
+  // batches carry the change through, keep them coming until the observer joins
+  let deadline = net.now() + DEADLINE;
+  while net.state_name(4) != "Badger" && net.now() < deadline
+  {
+    let started = net.now();
+    net.submit_load(4);
+    if !net.run_until(deadline, |net| net.state_name(4) == "Badger") && net.now() == started
+    {
+      break;
+    }
   }
+  assert_eq!(net.state_name(4), "Badger", "observer did not join after being voted in");
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3));
+  net.assert_agreement(&all);
 }
 
-// some random config (not really needed)
-fn config() -> crate::Config
+#[test]
+fn lossy_network_never_disagrees()
 {
-  crate::Config {
-    gossip_duration: std::time::Duration::from_millis(10),
-    justification_period: 256,
-    local_key: None,
-    name: None,
-  }
+  let mut net = start(4, SimConfig { seed: 5, ..Default::default() });
+  net.config_mut().drop_rate = 0.02;
+  let all = net.live();
+  // lost messages are not resent, so only safety is checked here
+  let _ = finalize_more(&mut net, &all, 3);
+  net.assert_agreement(&all);
+  assert!(net.stats().dropped > 0);
 }
 
-// dummy voter set state
-fn voter_set_state() -> SharedVoterSetState<Block>
-{
-  use crate::authorities::AuthoritySet;
-  use crate::environment::{CompletedRound, CompletedRounds, HasVoted, VoterSetState};
-  use grandpa::round::State as RoundState;
-  use substrate_primitives::H256;
-
-  let state = RoundState::genesis((H256::zero(), 0));
-  let base = state.prevote_ghost.unwrap();
-  let voters = AuthoritySet::genesis(Vec::new());
-  let set_state = VoterSetState::Live {
-    completed_rounds: CompletedRounds::new(
-      CompletedRound {
-        state,
-        number: 0,
-        votes: Vec::new(),
-        base,
-      },
-      0,
-      &voters,
-    ),
-    current_round: HasVoted::No,
-  };
+#[test]
+fn equivocating_justifications_do_not_stop_finality()
+{
+  let mut net = start(4, SimConfig { seed: 21, ..Default::default() });
+  net.set_adversary(0, AdversaryMode::EquivocateJustification);
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3));
+  net.assert_agreement(&all);
+}
 
-  set_state.into()
+#[test]
+fn invalid_keygen_messages_are_ignored()
+{
+  let _ = env_logger::try_init();
+  let mut net = SimNetwork::new(4, SimConfig { seed: 22, ..Default::default() });
+  net.set_adversary(3, AdversaryMode::InvalidKeyGen);
+  let honest = vec![0, 1, 2];
+  assert!(
+    net.run_until(DEADLINE, |net| net.all_running(&honest)),
+    "honest validators did not finish keygen"
+  );
+  assert!(finalize_more(&mut net, &honest, 3));
+  net.assert_agreement(&honest);
 }
 
-// needs to run in a tokio runtime.
-fn make_test_network() -> (impl Future<Item = Tester, Error = ()>, TestNetwork)
+#[test]
+fn single_validator_cannot_restart_keygen()
 {
-  let (tx, rx) = mpsc::unbounded();
-  let net = TestNetwork { sender: tx };
+  let _ = env_logger::try_init();
+  let mut net = SimNetwork::new(4, SimConfig { seed: 26, ..Default::default() });
+  net.set_adversary(3, AdversaryMode::SkipKeyGenAttempt);
+  let honest = vec![0, 1, 2];
+  assert!(
+    net.run_until(DEADLINE, |net| net.all_running(&honest)),
+    "honest validators followed a keygen attempt announced by one validator"
+  );
+  assert!(finalize_more(&mut net, &honest, 3));
+  net.assert_agreement(&honest);
+}
 
-  #[derive(Clone)]
-  struct Exit;
+#[test]
+fn validator_dropping_messages_to_a_peer_is_tolerated()
+{
+  // genesis keygen needs every part, so the adversary only starts dropping once it is done
+  let mut net = start(4, SimConfig { seed: 23, ..Default::default() });
+  let mut victims = BTreeSet::new();
+  victims.insert(net.peer_id(1));
+  net.set_adversary(0, AdversaryMode::DropTo(victims));
+  let all = net.live();
+  assert!(finalize_more(&mut net, &[1, 2, 3], 3));
+  net.assert_agreement(&all);
+}
 
-  impl Future for Exit
-  {
-    type Item = ();
-    type Error = ();
+#[test]
+fn replayed_messages_are_harmless()
+{
+  let mut net = start(4, SimConfig { seed: 24, ..Default::default() });
+  net.set_adversary(2, AdversaryMode::Replay);
+  let all = net.live();
+  assert!(finalize_more(&mut net, &all, 3));
+  net.assert_agreement(&all);
+}
 
-    fn poll(&mut self) -> Poll<(), ()>
-    {
-      Ok(Async::NotReady)
-    }
+#[test]
+fn forged_continued_block_is_not_imported_during_catch_up()
+{
+  let mut net = start(4, SimConfig { seed: 28, ..Default::default() });
+  let all = net.live();
+  let ahead = vec![0, 1, 2];
+  // only catch up responses are rewritten, so whoever node 3 asks forges a block
+  for i in ahead.iter()
+  {
+    net.set_adversary(*i, AdversaryMode::ForgeContinuedBlock);
   }
-
-  let (bridge, startup_work) =
-    super::NetworkBridge::new(net.clone(), config(), voter_set_state(), Exit);
-
-  (
-    startup_work.map(move |()| Tester {
-      gossip_validator: bridge.validator.clone(),
-      net_handle: bridge,
-      events: rx,
-    }),
-    net,
-  )
+  net.partition(&[&[0, 1, 2], &[3]]);
+  assert!(finalize_more(&mut net, &ahead, 4));
+  net.heal();
+  let behind = net.min_finalized(&ahead) - net.finalized_number(3);
+  assert!(finalize_more(&mut net, &[3], behind), "node 3 did not catch up");
+  net.assert_agreement(&all);
+
+  let forged: Vec<_> = ahead.iter().flat_map(|i| net.forged_blocks(*i)).collect();
+  assert!(!forged.is_empty(), "no catch up response was forged");
+  assert!(forged.iter().all(|hash| !net.has_block(3, hash)), "node 3 imported a forged block");
 }
 
-fn make_ids(keys: &[AuthorityKeyring]) -> Vec<(AuthorityId, u64)>
+fn node_key(seed: u8) -> (AuthorityPair, SecretKey)
 {
-  keys
-    .iter()
-    .map(|key| AuthorityId(key.to_raw_public()))
-    .map(|id| (id, 1))
-    .collect()
+  let pair = AuthorityPair::from_seed(&[seed; 32]);
+  let secret = bincode::deserialize(&pair.to_raw_vec()).unwrap();
+  (pair, secret)
 }
 
-struct NoopContext;
-
-impl network_gossip::ValidatorContext<Block> for NoopContext
+#[test]
+fn sealed_badger_data_is_private_to_recipient()
 {
-  fn broadcast_topic(&mut self, _: Hash, _: bool) {}
-  fn broadcast_message(&mut self, _: Hash, _: Vec<u8>, _: bool) {}
-  fn send_message(&mut self, _: &network::PeerId, _: Vec<u8>) {}
-  fn send_topic(&mut self, _: &network::PeerId, _: Hash, _: bool) {}
+  let (sender, _) = node_key(1);
+  let (recipient, recipient_secret) = node_key(2);
+  let (relay, relay_secret) = node_key(3);
+
+  let sealed = SealedBadgeredMessage::seal(&sender, &recipient.public(), b"secret row");
+  assert!(sealed.verify());
+  assert_eq!(sealed.open(&recipient_secret), Some(b"secret row".to_vec()));
+  assert_eq!(sealed.open(&relay_secret), None);
+
+  // a relay can neither redirect the message nor claim it as its own
+  let mut redirected = SealedBadgeredMessage::seal(&sender, &recipient.public(), b"secret row");
+  redirected.recipient = relay.public();
+  assert!(!redirected.verify());
+  let mut stolen = SealedBadgeredMessage::seal(&sender, &recipient.public(), b"secret row");
+  stolen.originator = relay.public();
+  stolen.sig = relay.sign(&(stolen.uid, &stolen.recipient, &stolen.ciphertext).encode());
+  assert!(stolen.verify());
+  assert_eq!(stolen.open(&recipient_secret), None);
 }
@@ -0,0 +1,730 @@
+//! Badger block finality proof generation and check.
+//!
+//! Finality of block B is proved by providing:
+//! 1) the `BadgerFullJustification` for the descendant block F;
+//! 2) headers sub-chain (B; F] if B != F;
+//! 3) proof of the `HBBFT_AUTHORITIES_KEY` storage at block F if the set changes at block F.
+//!
+//! Justifications are threshold signatures of the committee, checked against its master key.
+//! The first justification signed with a new key carries an attestation of that key by the
+//! authorities, so those justifications are returned as well.
+//!
+//! Every block produced by Badger carries a justification, so F is normally B itself, unless
+//! the authority set or the committee key changed in between. Let U be the last finalized block
+//! known to the caller. If they changed several times in the (U; F] interval, one fragment is
+//! returned for every change and they must be verified in-order, each one with the set and key
+//! established by the previous fragment.
+
+use std::iter;
+use std::sync::Arc;
+use log::{trace, warn};
+
+use parity_codec::{Decode, Encode};
+use sp_blockchain::{Backend as BlockchainBackend, Error as ClientError, Result as ClientResult};
+use sc_api::{Backend, CallExecutor};
+use client::Client;
+use client::light::fetcher::{FetchChecker, RemoteReadRequest, StorageProof};
+use runtime_primitives::{
+	Justification, generic::BlockId,
+	traits::{Block as BlockT, Header as HeaderT, One},
+};
+use substrate_primitives::{H256, Blake2Hasher, storage::StorageKey};
+use substrate_telemetry::{telemetry, CONSENSUS_INFO};
+use badger::crypto::PublicKey;
+use badger_primitives::{AuthorityId, AuthorityList, HBBFT_AUTHORITIES_KEY};
+
+use crate::communication::gossip::BadgerFullJustification;
+
+/// Maximum number of fragments that we want to return in a single prove_finality call.
+const MAX_FRAGMENTS_IN_PROOF: usize = 8;
+
+/// Badger authority set getter, used by the finality proof provider.
+pub trait AuthoritySetGetter<Block: BlockT>: Send + Sync {
+	/// Read HBBFT_AUTHORITIES_KEY from storage at given block.
+	fn authorities(&self, block: &BlockId<Block>) -> ClientResult<AuthorityList>;
+	/// Prove storage read of HBBFT_AUTHORITIES_KEY at given block.
+	fn prove_authorities(&self, block: &BlockId<Block>) -> ClientResult<StorageProof>;
+}
+
+/// Client-based implementation of AuthoritySetGetter.
+impl<B, E, Block: BlockT<Hash=H256>, RA> AuthoritySetGetter<Block> for Client<B, E, Block, RA>
+	where
+		B: Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
+		RA: Send + Sync,
+{
+	fn authorities(&self, block: &BlockId<Block>) -> ClientResult<AuthorityList> {
+		let storage_key = StorageKey(HBBFT_AUTHORITIES_KEY.to_vec());
+		self.storage(block, &storage_key)?
+			.and_then(|encoded| AuthorityList::decode(&mut encoded.0.as_slice()).ok())
+			.ok_or(ClientError::InvalidAuthoritiesSet)
+	}
+
+	fn prove_authorities(&self, block: &BlockId<Block>) -> ClientResult<StorageProof> {
+		self.read_proof(block, iter::once(HBBFT_AUTHORITIES_KEY))
+	}
+}
+
+/// Badger authority set checker, used by light clients to check the proofs above.
+pub trait AuthoritySetChecker<Block: BlockT>: Send + Sync {
+	/// Check storage read proof of HBBFT_AUTHORITIES_KEY at given block.
+	fn check_authorities_proof(
+		&self,
+		hash: Block::Hash,
+		header: Block::Header,
+		proof: StorageProof,
+	) -> ClientResult<AuthorityList>;
+}
+
+/// FetchChecker-based implementation of AuthoritySetChecker.
+impl<Block: BlockT> AuthoritySetChecker<Block> for Arc<dyn FetchChecker<Block>> {
+	fn check_authorities_proof(
+		&self,
+		hash: Block::Hash,
+		header: Block::Header,
+		proof: StorageProof,
+	) -> ClientResult<AuthorityList> {
+		let storage_key = HBBFT_AUTHORITIES_KEY.to_vec();
+		let request = RemoteReadRequest {
+			block: hash,
+			header,
+			keys: vec![storage_key.clone()],
+			retry_count: None,
+		};
+
+		self.check_read_proof(&request, proof)
+			.and_then(|results| {
+				results.get(&storage_key)
+					.and_then(|maybe_encoded| maybe_encoded.as_ref())
+					.and_then(|encoded| AuthorityList::decode(&mut encoded.as_slice()).ok())
+					.ok_or(ClientError::InvalidAuthoritiesSet)
+			})
+	}
+}
+
+/// Finality proof provider for serving network requests.
+pub struct FinalityProofProvider<B, Block: BlockT<Hash=H256>> {
+	backend: Arc<B>,
+	authority_provider: Arc<dyn AuthoritySetGetter<Block>>,
+}
+
+impl<B, Block: BlockT<Hash=H256>> FinalityProofProvider<B, Block>
+	where B: Backend<Block, Blake2Hasher> + Send + Sync + 'static
+{
+	/// Create new finality proof provider using:
+	///
+	/// - backend for accessing blockchain data;
+	/// - authority_provider for reading and proving the authority set.
+	pub fn new(
+		backend: Arc<B>,
+		authority_provider: Arc<dyn AuthoritySetGetter<Block>>,
+	) -> Self {
+		FinalityProofProvider { backend, authority_provider }
+	}
+}
+
+impl<B, Block> network::FinalityProofProvider<Block> for FinalityProofProvider<B, Block>
+	where
+		Block: BlockT<Hash=H256>,
+		B: Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+{
+	fn prove_finality(
+		&self,
+		for_block: Block::Hash,
+		request: &[u8],
+	) -> Result<Option<Vec<u8>>, ClientError> {
+		let request: FinalityProofRequest<Block::Hash> = Decode::decode(&mut &request[..])
+			.map_err(|e| {
+				warn!(target: "badger", "Unable to decode finality proof request: {}", e.what());
+				ClientError::Backend(format!("Invalid finality proof request"))
+			})?;
+		match request {
+			FinalityProofRequest::Original(request) => prove_finality::<Block, _>(
+				&*self.backend.blockchain(),
+				&*self.authority_provider,
+				request.last_finalized,
+				for_block,
+			),
+		}
+	}
+}
+
+/// The effects of block finality.
+#[derive(Debug, PartialEq)]
+pub struct FinalityEffects<Header: HeaderT> {
+	/// The (ordered) set of headers that could be imported.
+	pub headers_to_import: Vec<Header>,
+	/// The hash of the block that could be finalized.
+	pub block: Header::Hash,
+	/// The justification for the block.
+	pub justification: Justification,
+	/// Number of authority set changes proven on the way.
+	pub set_changes: u32,
+	/// Authority set that should be applied starting from block.
+	pub new_authorities: AuthorityList,
+	/// Committee key that signed the justification.
+	pub committee_key: PublicKey,
+}
+
+/// Single fragment of proof-of-finality.
+#[derive(Debug, PartialEq, Encode, Decode)]
+struct FinalityProofFragment<Header: HeaderT> {
+	/// The hash of block F for which justification is provided.
+	pub block: Header::Hash,
+	/// Justification of the block F.
+	pub justification: Justification,
+	/// The set of headers in the range (U; F] that we believe are unknown to the caller. Ordered.
+	pub unknown_headers: Vec<Header>,
+	/// Optional proof of the authority set stored at the `block`.
+	pub authorities_proof: Option<StorageProof>,
+}
+
+/// Proof of finality is the ordered set of finality fragments, where:
+/// - last fragment provides justification for the best possible block from the requested range;
+/// - all other fragments provide justifications for authority set or committee key changes
+///   within requested range.
+type FinalityProof<Header> = Vec<FinalityProofFragment<Header>>;
+
+/// Finality proof request data.
+#[derive(Debug, Encode, Decode)]
+enum FinalityProofRequest<H: Encode + Decode> {
+	/// Original version of the request.
+	Original(OriginalFinalityProofRequest<H>),
+}
+
+/// Original version of finality proof request.
+#[derive(Debug, Encode, Decode)]
+struct OriginalFinalityProofRequest<H: Encode + Decode> {
+	/// Hash of the last known finalized block.
+	///
+	/// The first justification in the proof must be signed by the set and key active after this block.
+	pub last_finalized: H,
+}
+
+/// Prepare data blob associated with finality proof request.
+pub(crate) fn make_finality_proof_request<H: Encode + Decode>(last_finalized: H) -> Vec<u8> {
+	FinalityProofRequest::Original(OriginalFinalityProofRequest {
+		last_finalized,
+	}).encode()
+}
+
+/// Check that `justification` is a `BadgerFullJustification` of the block `hash`, signed by
+/// the committee key `known`, or by a key it attests with enough members of `authorities`.
+///
+/// Returns `BadJustification` if the justification is well-formed but can't be checked with
+/// what the caller knows, which is what happens when it missed a set or key change.
+/// Returns the committee key otherwise.
+pub fn check_justification<Block: BlockT>(
+	justification: &Justification,
+	hash: &Block::Hash,
+	known: Option<&PublicKey>,
+	authorities: &[AuthorityId],
+) -> ClientResult<PublicKey> {
+	let just = BadgerFullJustification::<Block>::decode(&mut &justification[..])
+		.map_err(|_| ClientError::JustificationDecode)?;
+	if just.hash != *hash {
+		return Err(ClientError::Msg(format!("Justification is not for block {:?}", hash)));
+	}
+	just.check(known, authorities).ok_or_else(|| ClientError::BadJustification(
+		format!("Justification for {:?} is not signed by the known committee", hash),
+	))
+}
+
+/// Whether `justification` introduces a new committee key.
+fn attests_key<Block: BlockT>(justification: &Justification) -> bool {
+	BadgerFullJustification::<Block>::decode(&mut &justification[..])
+		.map(|just| just.key.is_some())
+		.unwrap_or(false)
+}
+
+/// Prepare proof-of-finality for the best possible block in the range: (begin; end].
+///
+/// It is assumed that the caller already have a proof-of-finality for the block 'begin'.
+/// It is assumed that the caller already knows all blocks in the range (begin; end].
+///
+/// Returns None if there are no finalized blocks unknown to the caller.
+pub(crate) fn prove_finality<Block: BlockT<Hash=H256>, B: BlockchainBackend<Block>>(
+	blockchain: &B,
+	authorities_provider: &dyn AuthoritySetGetter<Block>,
+	begin: Block::Hash,
+	end: Block::Hash,
+) -> ClientResult<Option<Vec<u8>>> {
+	let begin_id = BlockId::Hash(begin);
+	let begin_number = blockchain.expect_block_number_from_id(&begin_id)?;
+
+	// early-return if we sure that there are no blocks finalized AFTER begin block
+	let info = blockchain.info();
+	if info.finalized_number <= begin_number {
+		trace!(
+			target: "badger",
+			"Requested finality proof for descendant of #{} while we only have finalized #{}. Returning empty proof.",
+			begin_number,
+			info.finalized_number,
+		);
+
+		return Ok(None);
+	}
+
+	// check if blocks range is valid. It is the caller responsibility to ensure
+	// that it only asks peers that know about whole blocks range
+	let end_number = blockchain.expect_block_number_from_id(&BlockId::Hash(end))?;
+	if begin_number + One::one() > end_number {
+		return Err(ClientError::Backend(
+			format!("Cannot generate finality proof for invalid range: {}..{}", begin_number, end_number),
+		));
+	}
+
+	// early-return if we sure that the block is NOT a part of canonical chain
+	let canonical_begin = blockchain.expect_block_hash_from_id(&BlockId::Number(begin_number))?;
+	if begin != canonical_begin {
+		return Err(ClientError::Backend(
+			format!("Cannot generate finality proof for non-canonical block: {}", begin),
+		));
+	}
+
+	// iterate justifications && try to prove finality
+	let mut current_authorities = authorities_provider.authorities(&begin_id)?;
+	let mut current_number = begin_number + One::one();
+	let mut finality_proof = Vec::new();
+	let mut unknown_headers = Vec::new();
+	let mut latest_proof_fragment = None;
+	loop {
+		let current_id = BlockId::Number(current_number);
+
+		// check if header is unknown to the caller
+		if current_number > end_number {
+			let unknown_header = blockchain.expect_header(current_id)?;
+			unknown_headers.push(unknown_header);
+		}
+
+		if let Some(justification) = blockchain.justification(current_id)? {
+			// check if the current block enacts new authority set
+			let new_authorities = authorities_provider.authorities(&current_id)?;
+			let new_authorities_proof = if current_authorities != new_authorities {
+				current_authorities = new_authorities;
+				Some(authorities_provider.prove_authorities(&current_id)?)
+			} else {
+				None
+			};
+
+			let current = blockchain.expect_block_hash_from_id(&current_id)?;
+			let proof_fragment = FinalityProofFragment {
+				block: current,
+				justification,
+				unknown_headers: ::std::mem::replace(&mut unknown_headers, Vec::new()),
+				authorities_proof: new_authorities_proof,
+			};
+
+			// append justification to finality proof if required
+			let justifies_end_block = current_number >= end_number;
+			let justifies_authority_set_change = proof_fragment.authorities_proof.is_some()
+				|| attests_key::<Block>(&proof_fragment.justification);
+			if justifies_end_block || justifies_authority_set_change {
+				finality_proof.push(proof_fragment);
+				latest_proof_fragment = None;
+			} else {
+				latest_proof_fragment = Some(proof_fragment);
+			}
+
+			if justifies_end_block || finality_proof.len() == MAX_FRAGMENTS_IN_PROOF {
+				break;
+			}
+		}
+
+		// we can't provide more justifications
+		if current_number == info.finalized_number {
+			// append last justification - even if we can't generate finality proof for
+			// the end block, we try to generate it for the latest possible block
+			if let Some(latest_proof_fragment) = latest_proof_fragment.take() {
+				finality_proof.push(latest_proof_fragment);
+			}
+			break;
+		}
+
+		current_number = current_number + One::one();
+	}
+
+	if finality_proof.is_empty() {
+		trace!(
+			target: "badger",
+			"No justifications found when making finality proof for {}. Returning empty proof.",
+			end,
+		);
+
+		Ok(None)
+	} else {
+		trace!(
+			target: "badger",
+			"Built finality proof for {} of {} fragments. Last fragment for {}.",
+			end,
+			finality_proof.len(),
+			finality_proof.last().expect("checked that !finality_proof.is_empty(); qed").block,
+		);
+
+		Ok(Some(finality_proof.encode()))
+	}
+}
+
+/// Check Badger proof-of-finality, starting from the authority set and committee key known
+/// to the caller.
+///
+/// Returns the vector of headers that MUST be validated + imported
+/// AND if at least one of those headers is invalid, all other MUST be considered invalid.
+pub(crate) fn check_finality_proof<Block: BlockT<Hash=H256>, B: BlockchainBackend<Block>>(
+	blockchain: &B,
+	current_authorities: AuthorityList,
+	current_key: Option<PublicKey>,
+	authorities_provider: &dyn AuthoritySetChecker<Block>,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityEffects<Block::Header>> {
+	let proof = FinalityProof::<Block::Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	// empty proof can't prove anything
+	if proof.is_empty() {
+		return Err(ClientError::BadJustification("empty proof of finality".into()));
+	}
+
+	let last_fragment_index = proof.len() - 1;
+	let mut authorities = current_authorities;
+	let mut key = current_key;
+	let mut set_changes = 0;
+	let mut effects = None;
+	for (proof_fragment_index, proof_fragment) in proof.into_iter().enumerate() {
+		// check that proof is non-redundant. The proof still can be valid, but
+		// we do not want peer to spam us with redundant data
+		if proof_fragment_index != last_fragment_index {
+			let has_unknown_headers = !proof_fragment.unknown_headers.is_empty();
+			let has_new_authorities = proof_fragment.authorities_proof.is_some()
+				|| attests_key::<Block>(&proof_fragment.justification);
+			if has_unknown_headers || !has_new_authorities {
+				return Err(ClientError::BadJustification("redundant proof of finality".into()));
+			}
+		}
+
+		// verify justification using previous authorities set and key
+		let committee_key = check_justification::<Block>(
+			&proof_fragment.justification,
+			&proof_fragment.block,
+			key.as_ref(),
+			&authorities,
+		)?;
+		key = Some(committee_key.clone());
+
+		// and now verify new authorities proof (if provided)
+		if let Some(new_authorities_proof) = proof_fragment.authorities_proof {
+			// the proof is either generated using known header, or the last unknown
+			// header (because we only generate proofs for headers with justifications)
+			let header = match proof_fragment.unknown_headers.iter().rev().next().cloned() {
+				Some(header) => header,
+				None => blockchain.expect_header(BlockId::Hash(proof_fragment.block))?,
+			};
+			authorities = authorities_provider.check_authorities_proof(
+				proof_fragment.block,
+				header,
+				new_authorities_proof,
+			)?;
+			set_changes += 1;
+		}
+
+		effects = Some(FinalityEffects {
+			headers_to_import: proof_fragment.unknown_headers,
+			block: proof_fragment.block,
+			justification: proof_fragment.justification,
+			set_changes,
+			new_authorities: authorities.clone(),
+			committee_key,
+		});
+	}
+
+	let effects = effects.expect("proof is not empty; every iteration sets effects; qed");
+
+	telemetry!(CONSENSUS_INFO; "badger.finality_proof_ok";
+		"set_changes" => ?effects.set_changes, "finalized_header_hash" => ?effects.block);
+
+	Ok(effects)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use test_client::runtime::{Block, Header, H256};
+	use test_client::sc_client::in_mem::Blockchain as InMemoryBlockchain;
+	use sc_api::NewBlockState;
+	use substrate_primitives::crypto::Pair;
+	use badger::crypto::SecretKey;
+	use badger_primitives::AuthorityPair;
+	use crate::communication::gossip::{BadgerAuthCommit, BadgerKeyAttestation};
+	use super::*;
+
+	type FinalityProof = super::FinalityProof<Header>;
+
+	impl<GetAuthorities, ProveAuthorities> AuthoritySetGetter<Block> for (GetAuthorities, ProveAuthorities)
+		where
+			GetAuthorities: Send + Sync + Fn(BlockId<Block>) -> ClientResult<AuthorityList>,
+			ProveAuthorities: Send + Sync + Fn(BlockId<Block>) -> ClientResult<StorageProof>,
+	{
+		fn authorities(&self, block: &BlockId<Block>) -> ClientResult<AuthorityList> {
+			self.0(*block)
+		}
+
+		fn prove_authorities(&self, block: &BlockId<Block>) -> ClientResult<StorageProof> {
+			self.1(*block)
+		}
+	}
+
+	pub(crate) struct ClosureAuthoritySetChecker<Closure>(pub Closure);
+
+	impl<Closure> AuthoritySetChecker<Block> for ClosureAuthoritySetChecker<Closure>
+		where
+			Closure: Send + Sync + Fn(H256, Header, StorageProof) -> ClientResult<AuthorityList>,
+	{
+		fn check_authorities_proof(
+			&self,
+			hash: H256,
+			header: Header,
+			proof: StorageProof,
+		) -> ClientResult<AuthorityList> {
+			self.0(hash, header, proof)
+		}
+	}
+
+	pub(crate) fn header(number: u64) -> Header {
+		let parent_hash = match number {
+			0 => Default::default(),
+			_ => header(number - 1).hash(),
+		};
+		Header::new(number, H256::from_low_u64_be(0), H256::from_low_u64_be(0), parent_hash, Default::default())
+	}
+
+	fn number_of(block: BlockId<Block>) -> u64 {
+		match block {
+			BlockId::Number(number) => number,
+			BlockId::Hash(hash) => (0..16).find(|n| header(*n).hash() == hash).expect("test header"),
+		}
+	}
+
+	pub(crate) fn authority_pairs(seeds: &[u8]) -> Vec<AuthorityPair> {
+		seeds.iter().map(|seed| AuthorityPair::from_seed(&[*seed; 32])).collect()
+	}
+
+	pub(crate) fn authority_ids(pairs: &[AuthorityPair]) -> AuthorityList {
+		pairs.iter().map(|pair| pair.public()).collect()
+	}
+
+	/// Justification of `hash` signed by the committee key `secret`, attested by `attesters`
+	/// if given.
+	pub(crate) fn justification(hash: H256, secret: &SecretKey, attesters: Option<&[AuthorityPair]>) -> Justification {
+		let key = attesters.map(|attesters| {
+			let public_key = secret.public_key().to_bytes().to_vec();
+			BadgerKeyAttestation {
+				commits: attesters.iter().map(|pair| BadgerAuthCommit {
+					validator: pair.public(),
+					sgn: pair.sign(&public_key),
+				}).collect(),
+				public_key,
+			}
+		});
+		BadgerFullJustification::<Block> {
+			hash,
+			sig: secret.sign(&hash.encode()).to_bytes().to_vec(),
+			key,
+		}.encode()
+	}
+
+	/// Encoded proof of a single fragment for `block`, without unknown headers.
+	pub(crate) fn finality_proof(
+		block: H256,
+		justification: Justification,
+		authorities_proof: Option<StorageProof>,
+	) -> Vec<u8> {
+		vec![FinalityProofFragment {
+			block,
+			justification,
+			unknown_headers: Vec::<Header>::new(),
+			authorities_proof,
+		}].encode()
+	}
+
+	// blocks 1..=3 are finalized, the set changes from `old` to `new` at block 2 and the
+	// committee key changes from `old_key` to `new_key` along with it
+	struct SetChange {
+		blockchain: InMemoryBlockchain<Block>,
+		old: Vec<AuthorityPair>,
+		new: Vec<AuthorityPair>,
+		old_key: SecretKey,
+		new_key: SecretKey,
+	}
+
+	fn set_change() -> SetChange {
+		let old = authority_pairs(&[1, 2, 3, 4]);
+		let new = authority_pairs(&[1, 2, 3, 5]);
+		let old_key = SecretKey::random();
+		let new_key = SecretKey::random();
+		let blockchain = InMemoryBlockchain::<Block>::new();
+		blockchain.insert(header(0).hash(), header(0), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(
+			header(1).hash(),
+			header(1),
+			Some(justification(header(1).hash(), &old_key, None)),
+			None,
+			NewBlockState::Final,
+		).unwrap();
+		blockchain.insert(
+			header(2).hash(),
+			header(2),
+			Some(justification(header(2).hash(), &new_key, Some(&new))),
+			None,
+			NewBlockState::Final,
+		).unwrap();
+		blockchain.insert(
+			header(3).hash(),
+			header(3),
+			Some(justification(header(3).hash(), &new_key, None)),
+			None,
+			NewBlockState::Final,
+		).unwrap();
+		SetChange { blockchain, old, new, old_key, new_key }
+	}
+
+	fn prove(change: &SetChange, begin: u64, end: u64) -> Option<Vec<u8>> {
+		let old = authority_ids(&change.old);
+		let new = authority_ids(&change.new);
+		prove_finality(
+			&change.blockchain,
+			&(
+				move |block: BlockId<Block>| Ok(if number_of(block) < 2 { old.clone() } else { new.clone() }),
+				|block: BlockId<Block>| {
+					assert_eq!(number_of(block), 2, "the set only changes at block 2");
+					Ok(StorageProof::new(vec![vec![2]]))
+				},
+			),
+			header(begin).hash(),
+			header(end).hash(),
+		).unwrap()
+	}
+
+	fn check(change: &SetChange, proof: Vec<u8>) -> ClientResult<FinalityEffects<Header>> {
+		let new = authority_ids(&change.new);
+		check_finality_proof(
+			&change.blockchain,
+			authority_ids(&change.old),
+			Some(change.old_key.public_key()),
+			&ClosureAuthoritySetChecker(move |hash: H256, _: Header, proof: StorageProof| {
+				assert_eq!(hash, header(2).hash());
+				assert_eq!(proof, StorageProof::new(vec![vec![2]]));
+				Ok(new.clone())
+			}),
+			proof,
+		)
+	}
+
+	#[test]
+	fn finality_proof_is_none_if_no_more_finalized_blocks() {
+		let change = set_change();
+		change.blockchain.insert(header(4).hash(), header(4), None, None, NewBlockState::Best).unwrap();
+		assert_eq!(prove(&change, 3, 4), None);
+	}
+
+	#[test]
+	fn finality_proof_across_set_change_is_checked() {
+		let change = set_change();
+		let proof = prove(&change, 0, 3).expect("blocks 1..=3 are finalized");
+
+		// the set and key change at 2 gets its own fragment, the block itself the last one
+		let fragments = FinalityProof::decode(&mut &proof[..]).unwrap();
+		assert_eq!(fragments.iter().map(|f| f.block).collect::<Vec<_>>(), vec![header(2).hash(), header(3).hash()]);
+		assert!(fragments[0].authorities_proof.is_some());
+		assert!(fragments[1].authorities_proof.is_none());
+
+		let effects = check(&change, proof).unwrap();
+		assert_eq!(effects.block, header(3).hash());
+		assert_eq!(effects.set_changes, 1);
+		assert_eq!(effects.new_authorities, authority_ids(&change.new));
+		assert_eq!(effects.committee_key, change.new_key.public_key());
+		assert!(effects.headers_to_import.is_empty());
+	}
+
+	#[test]
+	fn finality_proof_with_redundant_fragments_is_rejected() {
+		let change = set_change();
+		let fragment = |number: u64, unknown_headers: Vec<Header>| FinalityProofFragment {
+			block: header(number).hash(),
+			justification: change.blockchain.justification(BlockId::Number(number)).unwrap().unwrap(),
+			unknown_headers,
+			authorities_proof: None,
+		};
+
+		// block 1 changes neither the set nor the key
+		let proof = vec![fragment(1, Vec::new()), fragment(3, Vec::new())].encode();
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("redundant fragment accepted: {:?}", r),
+		}
+
+		// only the last fragment may carry headers
+		let mut first = fragment(2, vec![header(1), header(2)]);
+		first.authorities_proof = Some(StorageProof::new(vec![vec![2]]));
+		let proof = vec![first, fragment(3, Vec::new())].encode();
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("fragment with headers accepted: {:?}", r),
+		}
+	}
+
+	#[test]
+	fn finality_proof_with_bad_justification_is_rejected() {
+		let change = set_change();
+		let fragment = |block: H256, justification: Justification| finality_proof(block, justification, None);
+
+		// signed by a key the caller does not know, with nobody vouching for it
+		let proof = fragment(header(1).hash(), justification(header(1).hash(), &SecretKey::random(), None));
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("unknown key accepted: {:?}", r),
+		}
+
+		// signed by the known key, but for another block
+		let proof = fragment(header(1).hash(), justification(header(3).hash(), &change.old_key, None));
+		assert!(check(&change, proof).is_err());
+
+		// not a justification at all
+		let proof = fragment(header(1).hash(), vec![42]);
+		match check(&change, proof) {
+			Err(ClientError::JustificationDecode) => {},
+			r => panic!("garbage accepted: {:?}", r),
+		}
+	}
+
+	#[test]
+	fn finality_proof_with_wrong_key_attestation_is_rejected() {
+		let change = set_change();
+		let fragment = |justification: Justification| finality_proof(header(1).hash(), justification, None);
+		let forged_key = SecretKey::random();
+
+		// vouched for by a single member of the set, which might be the faulty one
+		let proof = fragment(justification(header(1).hash(), &forged_key, Some(&change.old[..1])));
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("key attested by one authority accepted: {:?}", r),
+		}
+
+		// vouched for by enough keys, none of which is in the set
+		let outsiders = authority_pairs(&[6, 7, 8, 9]);
+		let proof = fragment(justification(header(1).hash(), &forged_key, Some(&outsiders)));
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("key attested by outsiders accepted: {:?}", r),
+		}
+
+		// a properly attested key that did not sign the block
+		let mut just = BadgerFullJustification::<Block>::decode(
+			&mut &justification(header(1).hash(), &change.new_key, Some(&change.old))[..],
+		).unwrap();
+		just.sig = forged_key.sign(&header(1).hash().encode()).to_bytes().to_vec();
+		let proof = fragment(just.encode());
+		match check(&change, proof) {
+			Err(ClientError::BadJustification(_)) => {},
+			r => panic!("justification not signed by the attested key accepted: {:?}", r),
+		}
+	}
+}
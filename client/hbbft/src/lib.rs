@@ -5,13 +5,9 @@ use std::marker::{Send, Sync};
 use std::pin::Pin;
 //use std::str::FromStr;
 use std::{fmt::Debug, hash::Hash, marker::PhantomData, sync::Arc, time::Duration,time::Instant};
-use badger::crypto::{
-	 SecretKeyShare, //Signature,PublicKeyShare,PublicKey,SecretKeyPublicKeySet
-  };
 
 //use app_crypto::hbbft_thresh::Pair as HBPair;
 //use threshold_crypto::serde_impl::SerdeSecret;
-use bincode;
 use futures03::future::Future;
 use futures03::prelude::*;
 use futures03::task::Poll;
@@ -33,7 +29,7 @@ use inherents::InherentIdentifier;
 use keystore::KeyStorePtr;
 //use runtime_primitives::traits::Hash as THash;
 use runtime_primitives::traits::{
-	 Block as BlockT, Header, NumberFor, ProvideRuntimeApi,
+	 Block as BlockT, Header, NumberFor, One, ProvideRuntimeApi,
 };
 
 use runtime_primitives::{
@@ -45,8 +41,8 @@ use block_builder::{BlockBuilderApi};
 pub mod communication;
 use crate::communication::Network;
 use badger::ConsensusProtocol;
-use badger_primitives::{  HBBFT_AUTHORITIES_KEY};//AuthorityId,AuthorityPair
-use std::iter;
+use badger_primitives::{  HBBFT_FAULT_REPORTS_KEY, BadgerFaultReport};//AuthorityId,AuthorityPair
+use sp_offchain::STORAGE_PREFIX;
 
 //use client::backend::Backend;
 use sp_blockchain::{Result as ClientResult, Error as ClientError};
@@ -66,16 +62,18 @@ use consensus_common::import_queue::{
 	BasicQueue, BoxBlockImport, BoxFinalityProofImport, BoxJustificationImport, CacheKeyId,
 	Verifier,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use rand::seq::SliceRandom;
 use consensus_common::ImportResult;
 pub use consensus_common::SyncOracle;
 use consensus_common::BlockCheckParams;
 use consensus_common::{self, BlockImportParams, BlockOrigin, ForkChoiceStrategy, SelectChain, Error as ConsensusError,};
 use inherents::{InherentData, InherentDataProviders};
+use sp_timestamp::{InherentError as TIError, TimestampInherentData};
 //use network::PeerId;
 use runtime_primitives::traits::DigestFor;
 //use runtime_primitives::generic::DigestItem;
-use substrate_primitives::{Blake2Hasher, ExecutionContext, H256,storage::StorageKey,};
+use substrate_primitives::{Blake2Hasher, ExecutionContext, H256,offchain::OffchainStorage};
 use substrate_telemetry::{telemetry, CONSENSUS_INFO, CONSENSUS_WARN};
 //use transaction_pool::txpool::{self};
 use txp::TransactionPool;
@@ -88,6 +86,8 @@ use network::ClientHandle as NetClient;
 use communication::BlockPusherMaker;
 pub type BadgerImportQueue<B> = BasicQueue<B>;
 pub mod aux_store;
+pub mod finality_proof;
+pub mod light_import;
 pub mod rpc;
 pub struct BadgerWorker<C, I, SO, Inbound, B: BlockT,  A,Cl,BPM,Aux>
 where
@@ -125,31 +125,113 @@ where
 	Sig: Send + Sync,
 	Pub: Send + Sync,
 {
-	_client: Arc<C>,
+	client: Arc<C>,
+	authority_set: aux_store::BadgerSharedAuthoritySet,
+	/// Leave justifications to the light block import, which can ask for finality proofs
+	light: bool,
 	_pub: PhantomData<Pub>,
 	_sig: PhantomData<Sig>,
-	_inherent_data_providers: inherents::InherentDataProviders,
+	inherent_data_providers: inherents::InherentDataProviders,
 }
 
+/// How far into the future a block timestamp may be before the block is rejected.
+const MAX_TIMESTAMP_DRIFT_MILLIS: u64 = 60_000;
+
 impl<C, Pub, Sig> BadgerVerifier<C, Pub, Sig>
 where
 	Sig: Send + Sync,
 	Pub: Send + Sync,
 {
-	fn _check_inherents<B: BlockT>(
+	fn check_inherents<B: BlockT>(
 		&self,
-		_block: B,
-		_block_id: BlockId<B>,
-		_inherent_data: InherentData,
-		_timestamp_now: u64,
+		block: B,
+		block_id: BlockId<B>,
+		inherent_data: InherentData,
+		timestamp_now: u64,
 	) -> Result<(), String>
 	where
 		C: ProvideRuntimeApi,
 		C::Api: BlockBuilderApi<B>,
 	{
-		//empty for now
+		let inherent_res = self.client.runtime_api().check_inherents(
+			&block_id,
+			block,
+			inherent_data,
+		).map_err(|e| format!("Could not check inherents: {:?}", e))?;
+
+		if inherent_res.ok()
+		{
+			return Ok(());
+		}
+		inherent_res
+			.into_errors()
+			.try_for_each(|(i, e)| match TIError::try_from(&i, &e) {
+				// inherents are pushed as transactions, so a block may legitimately carry
+				// a timestamp slightly ahead of ours. Reject only if it is too far ahead.
+				Some(TIError::ValidAtTimestamp(timestamp)) =>
+				{
+					if timestamp > timestamp_now + MAX_TIMESTAMP_DRIFT_MILLIS
+					{
+						Err(format!("Rejecting block too far in future: {:?}", timestamp))
+					}
+					else
+					{
+						Ok(())
+					}
+				},
+				Some(TIError::Other(e)) => Err(format!("Runtime inherent error: {:?}", e)),
+				None => Err(self.inherent_data_providers.error_to_string(&i, &e)),
+			})
+	}
+
+	/// Check that the header extends a block we already have, with the next number.
+	fn check_parent<B: BlockT>(&self, header: &B::Header) -> Result<(), String>
+	where
+		C: HeaderBackend<B>,
+	{
+		let parent_hash = *header.parent_hash();
+		let parent = match self.client.header(BlockId::Hash(parent_hash))
+		{
+			Ok(Some(parent)) => parent,
+			Ok(None) => return Err(format!("Parent {:?} of {:?} unavailable. Cannot import", parent_hash, header.hash())),
+			Err(e) => return Err(format!("Could not fetch parent {:?}: {:?}", parent_hash, e)),
+		};
+		let expected = *parent.number() + One::one();
+		if *header.number() != expected
+		{
+			return Err(format!(
+				"Block {:?} has number {:?}, expected {:?} on top of {:?}",
+				header.hash(),
+				header.number(),
+				expected,
+				parent_hash
+			));
+		}
 		Ok(())
 	}
+
+	/// Check the attached `BadgerFullJustification` against the committee key, learning
+	/// the new key if the justification attests one.
+	fn check_justification<B: BlockT>(&self, hash: &B::Hash, justification: &Option<Justification>) -> Result<(), String>
+	where
+		C: AuxStore,
+	{
+		match justification
+		{
+			Some(just) =>
+			{
+				if self.authority_set.import_block_justification::<B, _>(hash, just, &*self.client)
+				{
+					Ok(())
+				}
+				else
+				{
+					Err(format!("Invalid justification for block {:?}", hash))
+				}
+			},
+			None => Err(format!("Block {:?} has no justification", hash)),
+		}
+	}
 }
 
 //Block:BlockT
@@ -297,7 +379,7 @@ impl<B, E, Block: BlockT<Hash=H256>, RA, SC> BlockImport<Block>
 
 impl<B: BlockT, C, Pub, Sig> Verifier<B> for BadgerVerifier<C, Pub, Sig>
 where
-	C: ProvideRuntimeApi + Send + Sync + sc_api::AuxStore + ProvideCache<B>,
+	C: ProvideRuntimeApi + Send + Sync + sc_api::AuxStore + ProvideCache<B> + HeaderBackend<B>,
 	C::Api: BlockBuilderApi<B>,
 	//DigestItemFor<B>: CompatibleDigestItem<P>,
 	Pub: Send + Sync + Hash + Eq + Clone + Decode + Encode + Debug,
@@ -308,11 +390,43 @@ where
 		origin: BlockOrigin,
 		header: B::Header,
 		justification: Option<Justification>,
-		body: Option<Vec<B::Extrinsic>>,
+		mut body: Option<Vec<B::Extrinsic>>,
 	) -> Result<(BlockImportParams<B>, Option<Vec<(CacheKeyId, Vec<u8>)>>), String> {
-		// dummy for the moment
-		//let hash = header.hash();
-		//let parent_hash = *header.parent_hash();
+		let hash = header.hash();
+		let parent_hash = *header.parent_hash();
+		match origin
+		{
+			BlockOrigin::Genesis | BlockOrigin::Own => {},
+			_ =>
+			{
+				self.check_parent::<B>(&header)?;
+				if !self.light
+				{
+					self.check_justification::<B>(&hash, &justification)?;
+				}
+
+				if let Some(inner_body) = body.take()
+				{
+					let inherent_data = self.inherent_data_providers
+						.create_inherent_data()
+						.map_err(|e| e.into_string())?;
+					let timestamp_now = inherent_data.timestamp_inherent_data().map_err(|e| e.into_string())?;
+					let block = B::new(header.clone(), inner_body);
+
+					self.check_inherents(
+						block.clone(),
+						BlockId::Hash(parent_hash),
+						inherent_data,
+						timestamp_now,
+					)?;
+
+					let (_, inner_body) = block.deconstruct();
+					body = Some(inner_body);
+				}
+			}
+		}
+		trace!(target: "badger", "Checked {:?}; importing.", hash);
+
 		let import_block = BlockImportParams {
 			origin,
 			header: header,
@@ -325,7 +439,6 @@ where
 			fork_choice: ForkChoiceStrategy::LongestChain,
 			import_existing:false,
 		};
-		info!("VERIFIER BADGER");
 		Ok((import_block, None))
 	}
 }
@@ -351,22 +464,81 @@ pub fn badger_import_queue<B, C, Pub, Sig>(
 	justification_import: Option<BoxJustificationImport<B>>,
 	finality_proof_import: Option<BoxFinalityProofImport<B>>,
 	client: Arc<C>,
+	authority_set: aux_store::BadgerSharedAuthoritySet,
 	inherent_data_providers: InherentDataProviders,
 ) -> Result<BadgerImportQueue<B>, consensus_common::Error>
 where
 	B: BlockT,
-	C: 'static + ProvideRuntimeApi + ProvideCache<B> + Send + Sync + AuxStore,
+	C: 'static + ProvideRuntimeApi + ProvideCache<B> + HeaderBackend<B> + Send + Sync + AuxStore,
 	C::Api: BlockBuilderApi<B>,
 	//DigestItemFor<B>: CompatibleDigestItem<P>,
 	Pub: Clone + Eq + Send + Sync + Hash + Debug + Encode + Decode + 'static,
 	Sig: Encode + Decode + Send + Sync + 'static,
+{
+	new_import_queue::<B, C, Pub, Sig>(
+		block_import,
+		justification_import,
+		finality_proof_import,
+		client,
+		authority_set,
+		inherent_data_providers,
+		false,
+	)
+}
+
+/// Start an import queue for a light client following the Badger consensus.
+///
+/// Justifications are checked by `finality_proof_import`, normally a `BadgerLightBlockImport`
+/// sharing `authority_set`, which requests finality proofs when the authority set changed.
+pub fn badger_light_import_queue<B, C, Pub, Sig>(
+	block_import: BoxBlockImport<B>,
+	finality_proof_import: BoxFinalityProofImport<B>,
+	client: Arc<C>,
+	authority_set: aux_store::BadgerSharedAuthoritySet,
+	inherent_data_providers: InherentDataProviders,
+) -> Result<BadgerImportQueue<B>, consensus_common::Error>
+where
+	B: BlockT,
+	C: 'static + ProvideRuntimeApi + ProvideCache<B> + HeaderBackend<B> + Send + Sync + AuxStore,
+	C::Api: BlockBuilderApi<B>,
+	Pub: Clone + Eq + Send + Sync + Hash + Debug + Encode + Decode + 'static,
+	Sig: Encode + Decode + Send + Sync + 'static,
+{
+	new_import_queue::<B, C, Pub, Sig>(
+		block_import,
+		None,
+		Some(finality_proof_import),
+		client,
+		authority_set,
+		inherent_data_providers,
+		true,
+	)
+}
+
+fn new_import_queue<B, C, Pub, Sig>(
+	block_import: BoxBlockImport<B>,
+	justification_import: Option<BoxJustificationImport<B>>,
+	finality_proof_import: Option<BoxFinalityProofImport<B>>,
+	client: Arc<C>,
+	authority_set: aux_store::BadgerSharedAuthoritySet,
+	inherent_data_providers: InherentDataProviders,
+	light: bool,
+) -> Result<BadgerImportQueue<B>, consensus_common::Error>
+where
+	B: BlockT,
+	C: 'static + ProvideRuntimeApi + ProvideCache<B> + HeaderBackend<B> + Send + Sync + AuxStore,
+	C::Api: BlockBuilderApi<B>,
+	Pub: Clone + Eq + Send + Sync + Hash + Debug + Encode + Decode + 'static,
+	Sig: Encode + Decode + Send + Sync + 'static,
 {
 	register_badger_inherent_data_provider(&inherent_data_providers, 1)?;
 	//initialize_authorities_cache(&*client)?;
 
 	let verifier = BadgerVerifier::<C, Pub, Sig> {
-		_client: client.clone(),
-		_inherent_data_providers:inherent_data_providers,
+		client: client.clone(),
+		authority_set,
+		light,
+		inherent_data_providers:inherent_data_providers,
 		_pub: PhantomData,
 		_sig: PhantomData,
 	};
@@ -379,38 +551,9 @@ where
 	))
 }
 
-use client::{
-	light::fetcher::{ StorageProof},//FetchChecker, RemoteReadRequest,
-};
-
-use badger_primitives::AuthorityList;
-/// Badger authority set getter? .
-pub trait AuthoritySetGetter<Block: BlockT>: Send + Sync {
-	/// Read HBBFT_AUTHORITIES_KEY from storage at given block.
-	fn authorities(&self, block: &BlockId<Block>) -> ClientResult<AuthorityList>;
-	/// Prove storage read of HBBFT_AUTHORITIES_KEY at given block.
-	fn prove_authorities(&self, block: &BlockId<Block>) -> ClientResult<StorageProof>;
-}
-
-/// Client-based implementation of AuthoritySetForFinalityProver.
-impl<B, E, Block: BlockT<Hash=H256>, RA> AuthoritySetGetter<Block> for Client<B, E, Block, RA>
-	where
-		B: Backend<Block, Blake2Hasher> + Send + Sync + 'static,
-		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
-		RA: Send + Sync,
-{
-	fn authorities(&self, block: &BlockId<Block>) -> ClientResult<AuthorityList> {
-		let storage_key = StorageKey(HBBFT_AUTHORITIES_KEY.to_vec());
-		self.storage(block, &storage_key)?
-			.and_then(|encoded| AuthorityList::decode(&mut encoded.0.as_slice()).ok())
-			.map(|versioned| versioned.into())
-			.ok_or(ClientError::InvalidAuthoritiesSet)
-	}
-
-	fn prove_authorities(&self, block: &BlockId<Block>) -> ClientResult<StorageProof> {
-		self.read_proof(block, iter::once(HBBFT_AUTHORITIES_KEY))
-	}
-}
+pub use finality_proof::{AuthoritySetGetter, FinalityProofProvider};
+pub use light_import::{light_block_import, BadgerLightBlockImport};
+pub use communication::batch_policy::{BatchToBlockPolicy, Leftover, OneBlockPerBatch, FillBlocks, SplitBatches};
 
 
 /// Configuration for the Badger service.
@@ -419,18 +562,12 @@ pub struct Config {
 	/// Some local identifier of the node.
 	pub name: Option<String>,
 	pub batch_size: u32,
+	/// How batches that don't fit into one block are handled
+	pub batch_policy: Arc<dyn BatchToBlockPolicy>,
 //	pub initial_validators: BTreeMap<PeerIdW, PublicKey>, replaced by session aspects
 //	pub node_indices: BTreeMap<PeerIdW, usize>, unnecessary
 }
 
-fn _secret_share_from_string(st: &str) -> Result<SecretKeyShare, Error> {
-	let data = hex::decode(st)?;
-	match bincode::deserialize(&data) {
-		Ok(val) => Ok( val ),
-		Err(_) => return Err(Error::Badger("secret key share binary invalid".to_string())),
-	}
-}
-
 impl Config {
 	fn _name(&self) -> &str {
 		self.name
@@ -531,6 +668,20 @@ pub struct BadgerStartParams<Block: BlockT<Hash = H256>, N: Network<Block>, X> {
 	ph: PhantomData<Block>,
 }
 
+/// Extrinsics contributed to consensus are contributed again if they are still in the pool
+/// after this long, in case the contribution was lost or the extrinsic was not applicable yet
+/// when its batch was turned into a block.
+const RESUBMIT_AFTER: Duration = Duration::from_secs(60);
+
+/// How often the pool is checked again while there is nothing to contribute.
+const TX_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Stream of this validator's contributions, taken from the ready transactions of the pool.
+///
+/// Each validator contributes a random subset of `batch_size / N` of the first `batch_size`
+/// ready transactions, as in the HoneyBadger paper, so that contributions rarely overlap.
+/// A new subset is only taken once the previous one left the pool, being either included in
+/// a block or banned as invalid.
 pub struct TxStream<A, B, E, RA, Block: BlockT<Hash = H256>>
 where
 	A: TransactionPool,
@@ -540,6 +691,41 @@ where
 {
 	pub transaction_pool: Arc<A>,
 	pub client: Arc<Client<B, E, Block, RA>>,
+	pub authority_set: aux_store::BadgerSharedAuthoritySet,
+	pub batch_size: usize,
+	/// Contributed extrinsics still in the pool, with the time they were contributed
+	pub submitted: HashMap<<A as TransactionPool>::Hash, Instant>,
+	/// Wakes us up to look at the pool again after returning `Pending`
+	pub retry: Option<Delay>,
+}
+
+// nothing is pinned in place, `poll_next` only mutates plain fields
+impl<A, B, E, RA, Block: BlockT<Hash = H256>> Unpin for TxStream<A, B, E, RA, Block>
+where
+	A: TransactionPool,
+	B: Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: CallExecutor<Block, Blake2Hasher> + Send + Sync + Clone + 'static,
+	RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
+{
+}
+
+impl<A, B, E, RA, Block: BlockT<Hash = H256>> TxStream<A, B, E, RA, Block>
+where
+	A: TransactionPool,
+	B: Backend<Block, Blake2Hasher> + Send + Sync + 'static,
+	E: CallExecutor<Block, Blake2Hasher> + Send + Sync + Clone + 'static,
+	RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
+{
+	/// The pool does not wake us when transactions arrive, so arm a timer before returning `Pending`.
+	fn retry_later(&mut self, cx: &mut futures03::task::Context) -> Poll<Option<TransactionSet>> {
+		loop {
+			let delay = self.retry.get_or_insert_with(|| Delay::new(TX_POLL_INTERVAL));
+			match Pin::new(delay).poll(cx) {
+				Poll::Pending => return Poll::Pending,
+				Poll::Ready(_) => self.retry = None,
+			}
+		}
+	}
 }
 
 impl<A, B, E, RA, Block: BlockT<Hash = H256>> Stream for TxStream<A, B, E, RA, Block>
@@ -553,23 +739,39 @@ where
 	type Item = TransactionSet;
 	fn poll_next(
 		self: Pin<&mut Self>,
-		_cx: &mut futures03::task::Context,
+		cx: &mut futures03::task::Context,
 	) -> Poll<Option<Self::Item>> {
 		trace!("BADgER! Polled stream!");
-		let pending_iterator = self.transaction_pool.ready();
-		let batch: Vec<_> = pending_iterator.map(|a| a.data().encode()).collect();
-		//let pending_iterator = self.transaction_pool.ready();
-	
-		let pending_iterator = self.transaction_pool.ready();
-		let hashes: Vec<_> = pending_iterator.map(|a| a.hash().clone()).collect();
-		if batch.len() == 0 {
-			return Poll::Pending;
+		let this = self.get_mut();
+		let ready: Vec<_> = this.transaction_pool.ready().collect();
+
+		// forget what was included, banned or not heard of for too long
+		let in_pool: HashSet<_> = ready.iter().map(|tx| tx.hash().clone()).collect();
+		let now = Instant::now();
+		this.submitted.retain(|hash, at| in_pool.contains(hash) && now.duration_since(*at) < RESUBMIT_AFTER);
+
+		let validators = this.authority_set.inner.read().current_authorities.len();
+		let share = std::cmp::max(1, this.batch_size / std::cmp::max(1, validators));
+		if this.submitted.len() >= share {
+			return this.retry_later(cx);
+		}
+		let candidates: Vec<_> = ready
+			.iter()
+			.filter(|tx| !this.submitted.contains_key(tx.hash()))
+			.take(this.batch_size)
+			.collect();
+		let chosen: Vec<_> = candidates
+			.choose_multiple(&mut rand::thread_rng(), share - this.submitted.len())
+			.cloned()
+			.collect();
+		if chosen.is_empty() {
+			return this.retry_later(cx);
+		}
+		info!("BADgER! Contributing {} of {} ready transactions", chosen.len(), ready.len());
+		for tx in chosen.iter() {
+			this.submitted.insert(tx.hash().clone(), now);
 		}
-		info!("BADgER! Ready stream!");
-		//let best_block_hash = self.client.info().chain.best_hash;
-		self.transaction_pool
-			.remove_invalid( &hashes);
-		Poll::Ready(Some(batch))
+		Poll::Ready(Some(chosen.iter().map(|tx| tx.data().encode()).collect()))
 	}
 }
 
@@ -645,7 +847,6 @@ where
 
 use sc_api::backend::Finalizer;
 use futures03::stream::StreamExt;
-use std::collections::VecDeque;
 use communication::BadgerTransaction;
 use block_builder::BlockBuilder;
 
@@ -660,7 +861,7 @@ use communication::BlockPushResult;
 
 
 
-pub struct BlockUtil<B, E, Block,  RA, SC,  I>
+pub struct BlockUtil<B, E, Block,  RA, SC,  I, A>
 where
 Block: BlockT<Hash = H256>,
 Block::Hash: Ord,
@@ -673,14 +874,16 @@ RA: Send + Sync + 'static,
 I: BlockImport<Block> + Send + Sync + 'static,
 RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 <Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: BlockBuilderApi<Block,Error=sp_blockchain::Error>,
-
+A: TransactionPool<Block = Block>,
 {
 	pub client: Arc<Client<B, E, Block, RA>>,
 	pub block_import:Arc<Mutex<I>>,
 	pub selch:SC,
+	/// Extrinsics that fail to apply are banned from it
+	pub transaction_pool: Arc<A>,
 	//phantom: PhantomData<BPusher<'a,Block,B,E,RA>>,
 }
-impl<B, E, Block,  RA, SC,  I>  BlockUtil<B,E,Block,RA,SC,I>
+impl<B, E, Block,  RA, SC,  I, A>  BlockUtil<B,E,Block,RA,SC,I,A>
 where
 Block: BlockT<Hash = H256>,
 Block::Hash: Ord,
@@ -693,6 +896,7 @@ RA: Send + Sync + 'static,
 I: BlockImport<Block> + Send + Sync + 'static,
 RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 <Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: BlockBuilderApi<Block,Error=sp_blockchain::Error>,
+A: TransactionPool<Block = Block>,
 {
    pub fn process_data(block_builder:&mut BlockBuilder<Block,Client<B, E, Block, RA>>,pending:&mut BadgerTransaction)->BlockPushResult
 	//pub fn process_data(&self, block_builder:&mut BlockBuilder<Block,Client<B, E, Block, RA>>,pending:&mut BadgerTransaction)->BlockPushResult
@@ -711,17 +915,21 @@ RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 			  return BlockPushResult::Pushed;
 		  }
   
-		  Err(sp_blockchain::Error::ApplyExtrinsicFailed(sp_blockchain::ApplyExtrinsicFailed::Validity(e)))
-		  if e.exhausted_resources() => { return BlockPushResult::BlockFull;  },
 		  Err(e) => {
-			  info!("[{:?}] Invalid transaction: {}", pending.len(), e);
-			  return BlockPushResult::BlockError;
+			  let result = communication::push_error_result(&e);
+			  match result {
+				  BlockPushResult::BlockError => info!("[{:?}] Invalid transaction: {}", pending.len(), e),
+				  BlockPushResult::Skipped => info!("[{:?}] Transaction not applicable yet: {}", pending.len(), e),
+				  _ => {},
+			  }
+			  return result;
 		  }
 	  }
 	}
+
 }
 
-impl<B, E, Block,  RA, SC,  I> BlockPusherMaker<Block,> for BlockUtil<B,E,Block,RA,SC,I>
+impl<B, E, Block,  RA, SC,  I, A> BlockPusherMaker<Block,> for BlockUtil<B,E,Block,RA,SC,I,A>
 where
 Block: BlockT<Hash = H256>,
 Block::Hash: Ord,
@@ -734,9 +942,23 @@ RA: Send + Sync + 'static,
 I: BlockImport<Block> + Send + Sync + 'static,
 RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 <Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: BlockBuilderApi<Block,Error=sp_blockchain::Error>,
+A: TransactionPool<Block = Block>,
 {
-	
-	fn process_all(&mut self,is: BlockId<Block>,digest:generic::Digest<Block::Hash>,locked: &mut VecDeque<BadgerTransaction>,batched: impl Iterator<Item=BadgerTransaction>) ->Result<Block,()>
+	fn report_rejected(&self, rejected: Vec<BadgerTransaction>)
+	{
+		let hashes: Vec<_> = rejected
+			.iter()
+			.filter_map(|data| <Block as BlockT>::Extrinsic::decode(&mut data.as_slice()).ok())
+			.map(|xt| self.transaction_pool.hash_of(&xt))
+			.collect();
+		if !hashes.is_empty()
+		{
+			info!("Banning {:?} rejected extrinsics", hashes.len());
+			self.transaction_pool.remove_invalid(&hashes);
+		}
+	}
+
+	fn process_all(&mut self,is: BlockId<Block>,digest:generic::Digest<Block::Hash>,txs: impl Iterator<Item=BadgerTransaction>) ->Result<(Block,Vec<BadgerTransaction>,Vec<BadgerTransaction>),()>
 	{
 		let mut block_builder = match self.client.new_block_at(&is, digest) 
 		{
@@ -747,69 +969,47 @@ RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 			}
 			
 		};
-		let mut block_done=false;
 		let mut cnt:u64 =0;
-  
-		while locked.len()>0
+		let mut rejected=Vec::new();
+		let mut leftover=Vec::new();
+		for mut pending in txs
 		{
-		  let mut trans=locked.pop_back().unwrap();
-		  match BlockUtil::<B,E,Block,RA,SC,I>::process_data(&mut block_builder,&mut trans)
-		  {
-			BlockPushResult::BlockFull =>
-			{
-			if cnt>0
-			{	
-			 locked.push_back(trans);
-			 block_done=true;
-			 break;
-			}
-			},
-			BlockPushResult::Pushed =>
-			{
-					cnt+=1;
-			},
-			_ => {}
-		  }
-		}
-		for mut pending in batched
-		{
-		 if block_done 
+		 if !leftover.is_empty()
 		 {
-		   locked.push_back(pending);
+		   leftover.push(pending);
+		   continue;
 		 }
-		 else
-		 {
-		  match BlockUtil::<B,E,Block,RA,SC,I>::process_data(&mut block_builder,&mut pending)
+		  match BlockUtil::<B,E,Block,RA,SC,I,A>::process_data(&mut block_builder,&mut pending)
 		  {
 			BlockPushResult::BlockFull =>
 			{
 			if cnt>0
 			 {
-			 locked.push_back(pending);
-			 block_done=true;
+			 leftover.push(pending);
 			 }
 			 else
 			 {
 			   info!("Overlarge transaction, ignoring");
+			   rejected.push(pending);
 			 }
 			},
 			BlockPushResult::Pushed =>
 			{
 					cnt+=1;
 			},
-			_ => {}
+			BlockPushResult::BlockError => rejected.push(pending),
+			BlockPushResult::Skipped => {},
+			BlockPushResult::InvalidData => {}
 		  } 
-		 }
 		} 
 		debug!("Block is done, proceed with proposing.");
-		let block= match block_builder.bake() {
-			Ok(val) => Ok(val),
+		match block_builder.bake() {
+			Ok(val) => Ok((val,leftover,rejected)),
 			Err(e) => {
 				warn!("Block baking error {:?}", e);
-				return Err(());
+				Err(())
 			}
-		 };
-      block
+		 }
 	}
   
 
@@ -881,8 +1081,32 @@ Block::Hash: Ord,
 }
 /// Run a HBBFT churn as a task. Provide configuration and a link to a
 /// block import worker that has already been instantiated with `block_import`.
+/// Leave fault reports in offchain storage, for the runtime's offchain worker to submit
+fn store_fault_reports<S: OffchainStorage, Block: BlockT>(storage: &mut S, reports: Vec<BadgerFaultReport<Block::Header>>)
+{
+	if reports.is_empty()
+	{
+		return;
+	}
+	// the offchain worker takes the stored reports concurrently
+	loop
+	{
+		let old = storage.get(STORAGE_PREFIX, HBBFT_FAULT_REPORTS_KEY);
+		let mut all: Vec<BadgerFaultReport<Block::Header>> = old
+			.as_ref()
+			.and_then(|data| Decode::decode(&mut &data[..]).ok())
+			.unwrap_or_default();
+		all.extend(reports.iter().cloned());
+		if storage.compare_and_set(STORAGE_PREFIX, HBBFT_FAULT_REPORTS_KEY, old.as_ref().map(|v| &v[..]), &all.encode())
+		{
+			return;
+		}
+	}
+}
+
 pub fn run_honey_badger<B, E, Block: BlockT<Hash = H256>, N, RA, SC, X, I, A,Sp>(
 	client: Arc<Client<B, E, Block, RA>>,
+	backend: Arc<B>,
 	t_pool: Arc<A>,
 	config: Config,
 	network: N,
@@ -894,7 +1118,8 @@ pub fn run_honey_badger<B, E, Block: BlockT<Hash = H256>, N, RA, SC, X, I, A,Sp>
 	executor:Sp,
 	receiver:ImportRx<Block>,
 	_node_key:Option<String>,
-	_dev_seed:Option<String>
+	_dev_seed:Option<String>,
+	status:rpc::SharedBadgerStatus,
 ) -> ClientResult<impl Future<Output = ()> + Send + Unpin>
 where
     Sp: futures03::task::Spawn + 'static,
@@ -908,8 +1133,7 @@ where
 	DigestFor<Block>: Encode,
 	RA: Send + Sync + 'static,
 	X: futures03::future::Future<Output = ()> + Send + Unpin,
-	A: TransactionPool + 'static,
-	<A as TransactionPool>::Block: BlockT<Hash = H256>,
+	A: TransactionPool<Block = Block> + 'static,
 	I: BlockImport<Block> + Send + Sync + 'static,
 	RA: ConstructRuntimeApi<Block, Client<B, E, Block, RA>>,
 	<Client<B, E, Block, RA> as ProvideRuntimeApi>::Api: BlockBuilderApi<Block,Error=sp_blockchain::Error>,
@@ -927,6 +1151,7 @@ where
 		},keystore.clone()
 	)?;
 	info!("Badger AUTH {:?}",&persistent_data.authority_set.inner);
+	let authority_set = persistent_data.authority_set.clone();
 
 	//let auth_ref:Arc<parking_lot::RwLock<aux_store::AuthoritySet>>=persistent_data.authority_set.inner.clone();
 	let ccln=client.clone();
@@ -951,13 +1176,15 @@ where
 			client: client.clone(),
 			block_import:block_import.clone(),
 			selch:selch.clone(),
-			
+			transaction_pool:t_pool.clone(),
 		};
 	let (network_bridge, network_startup) = NetworkBridge::new(network, config.clone(),keystore.clone(),persistent_data,client.clone(),
 	mjust, block_handler,Cwrap{client: cclient.clone()}, &executor
 	//,finalizer: Box<dyn FnMut( &B::Hash,Option<Justification>)->bool+Send+Sync>,     
    );
 	let net_arc=Arc::new(network_bridge);
+	status.set_provider(net_arc.clone());
+	status.set_control(net_arc.clone());
 	let blk_out=BadgerStream{ wrap: net_arc.clone()};
 	//let tx_in=net_arc.clone();
 
@@ -982,6 +1209,10 @@ where
 	let tx_out = TxStream {
 		transaction_pool: t_pool.clone(),
 		client: client.clone(),
+		authority_set: authority_set,
+		batch_size: config.batch_size as usize,
+		submitted: HashMap::new(),
+		retry: None,
 	};
 	let sender = tx_out.for_each(move |data: std::vec::Vec<std::vec::Vec<u8>>| {
 		{
@@ -999,6 +1230,19 @@ where
 	//let cblock_import = block_import.clone();
 	let ping_sel = selch.clone();
     let sec_net=net_arc.clone(); 
+	let timer_net=net_arc.clone();
+	let mut offchain_storage = backend.offchain_storage();
+	let timer=interval_at(Instant::now(),Duration::from_secs(1)).for_each(move |_| {
+		timer_net.on_timer();
+		let reports = timer_net.take_fault_reports();
+		match offchain_storage
+		{
+			Some(ref mut storage) => store_fault_reports::<_, Block>(storage, reports),
+			None if !reports.is_empty() => warn!("No offchain storage, dropping {} fault reports", reports.len()),
+			None => {},
+		}
+		future::ready(())
+	});
 	let importer=receiver.for_each(move |blki|
 	{
 		info!("External block import: {:?} {:?}",&blki.header.hash(),&blki.header.number());
@@ -1006,6 +1250,7 @@ where
 	 future::ready(())
 	});
 	let receiver = blk_out.for_each(move |batch| {
+		status.notify_batch(&batch);
 		net_arc.process_batch(batch);
 		 
 		future::ready(())
@@ -1025,7 +1270,7 @@ where
 //	let secr:SecretKey=bincode::deserialize(&keystore.read().key_pair_by_type::<AuthorityPair>(&ap.into(), app_crypto::key_types::HB_NODE).unwrap().to_raw_vec()).unwrap();
 //	info!("Badger AUTH  private {:?}",&secr);
 	
-	let with_start = network_startup.then(move |()|futures03::future::join(futures03::future::join(sender, receiver),importer));
+	let with_start = network_startup.then(move |()|futures03::future::join(futures03::future::join(sender, receiver),futures03::future::join(importer,timer)));
 	let ping_client = client.clone();
 	// Delay::new(Duration::from_secs(1)).then(|_| {
 	let ping =interval_at(Instant::now(),Duration::from_millis(11500)).for_each(move |_| {
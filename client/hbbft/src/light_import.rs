@@ -0,0 +1,366 @@
+//! Block import for light clients.
+//!
+//! Light clients do not take part in consensus and only learn about authority set changes
+//! through finality proofs served by full nodes (see `finality_proof`). A block whose
+//! justification is not signed by a quorum of the known set is imported unfinalized and a
+//! finality proof is requested for it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use log::{trace, warn};
+use parking_lot::RwLock;
+
+use parity_codec::Encode;
+use sp_blockchain::Error as ClientError;
+use sc_api::{Backend, CallExecutor, AuxStore, backend::Finalizer};
+use client::{Client, blockchain::HeaderBackend, well_known_cache_keys};
+use consensus_common::{
+	import_queue::Verifier,
+	BlockOrigin, BlockImport, FinalityProofImport, BlockImportParams, ImportResult, ImportedAux,
+	BlockCheckParams, Error as ConsensusError,
+};
+use network::config::{BoxFinalityProofRequestBuilder, FinalityProofRequestBuilder};
+use runtime_primitives::Justification;
+use runtime_primitives::generic::BlockId;
+use runtime_primitives::traits::{NumberFor, Block as BlockT, Header as HeaderT, DigestFor};
+use substrate_primitives::{H256, Blake2Hasher};
+use badger::crypto::PublicKey;
+
+use crate::aux_store::{self, BadgerSharedAuthoritySet};
+use crate::finality_proof::{
+	AuthoritySetChecker, check_finality_proof, check_justification, make_finality_proof_request,
+};
+
+/// Create light block importer.
+///
+/// `authority_set` should be the same set the light import queue verifies blocks with, so
+/// that both follow the changes learned from finality proofs.
+pub fn light_block_import<B, E, Block: BlockT<Hash=H256>, RA>(
+	client: Arc<Client<B, E, Block, RA>>,
+	backend: Arc<B>,
+	authority_set: BadgerSharedAuthoritySet,
+	authority_set_provider: Arc<dyn AuthoritySetChecker<Block>>,
+) -> BadgerLightBlockImport<B, E, Block, RA>
+	where
+		B: Backend<Block, Blake2Hasher> + 'static,
+		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
+		RA: Send + Sync,
+{
+	let last_finalized = client.info().finalized_hash;
+	BadgerLightBlockImport {
+		client,
+		backend,
+		authority_set,
+		authority_set_provider,
+		last_finalized: Arc::new(RwLock::new(last_finalized)),
+	}
+}
+
+/// A light block-import handler for Badger.
+///
+/// It is responsible for:
+/// - checking Badger justifications;
+/// - fetching finality proofs for blocks signed by an authority set we don't know yet.
+pub struct BadgerLightBlockImport<B, E, Block: BlockT<Hash=H256>, RA> {
+	client: Arc<Client<B, E, Block, RA>>,
+	backend: Arc<B>,
+	authority_set: BadgerSharedAuthoritySet,
+	authority_set_provider: Arc<dyn AuthoritySetChecker<Block>>,
+	last_finalized: Arc<RwLock<Block::Hash>>,
+}
+
+impl<B, E, Block: BlockT<Hash=H256>, RA> Clone for BadgerLightBlockImport<B, E, Block, RA> {
+	fn clone(&self) -> Self {
+		BadgerLightBlockImport {
+			client: self.client.clone(),
+			backend: self.backend.clone(),
+			authority_set: self.authority_set.clone(),
+			authority_set_provider: self.authority_set_provider.clone(),
+			last_finalized: self.last_finalized.clone(),
+		}
+	}
+}
+
+impl<B, E, Block: BlockT<Hash=H256>, RA> BadgerLightBlockImport<B, E, Block, RA> {
+	/// Create finality proof request builder.
+	pub fn create_finality_proof_request_builder(&self) -> BoxFinalityProofRequestBuilder<Block> {
+		Box::new(BadgerFinalityProofRequestBuilder(self.last_finalized.clone())) as _
+	}
+}
+
+impl<B, E, Block: BlockT<Hash=H256>, RA> BadgerLightBlockImport<B, E, Block, RA>
+	where
+		B: Backend<Block, Blake2Hasher> + 'static,
+		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
+		DigestFor<Block>: Encode,
+		RA: Send + Sync,
+{
+	/// Import the justification of an imported block, or ask for a finality proof if it
+	/// is not signed by the authority set we know.
+	fn import_justification(
+		&mut self,
+		hash: Block::Hash,
+		number: NumberFor<Block>,
+		justification: Justification,
+		mut imported_aux: ImportedAux,
+	) -> Result<ImportResult, ConsensusError> {
+		let authorities = self.authority_set.inner.read().current_authorities.clone();
+		let committee_key = self.authority_set.committee_key.read().clone();
+		match check_justification::<Block>(&justification, &hash, committee_key.as_ref(), &authorities) {
+			Ok(key) => {
+				trace!(target: "badger", "Justification for {} is valid. Finalizing the block.", hash);
+				self.note_committee_key(key)?;
+				self.finalize_block(hash, number, justification)
+			},
+			Err(ClientError::BadJustification(_)) => {
+				trace!(
+					target: "badger",
+					"Justification for {} is not valid within current authorities set. Requesting finality proof.",
+					hash,
+				);
+				imported_aux.needs_finality_proof = true;
+				Ok(ImportResult::Imported(imported_aux))
+			},
+			Err(e) => {
+				trace!(target: "badger", "Justification for {} is not valid. Bailing.", hash);
+				Err(ConsensusError::ClientImport(e.to_string()))
+			},
+		}
+	}
+
+	fn note_committee_key(&mut self, key: PublicKey) -> Result<(), ConsensusError> {
+		if self.authority_set.note_committee_key(key.clone()) {
+			aux_store::update_committee_key(&key, |insert| self.client.insert_aux(insert, &[]))
+				.map_err(|e| {
+					warn!(target: "badger", "Failed to write committee key to disk. Bailing.");
+					ConsensusError::ClientImport(e.to_string())
+				})?;
+		}
+		Ok(())
+	}
+
+	fn finalize_block(
+		&mut self,
+		hash: Block::Hash,
+		number: NumberFor<Block>,
+		justification: Justification,
+	) -> Result<ImportResult, ConsensusError> {
+		(&*self.client).finalize_block(BlockId::Hash(hash), Some(justification), true).map_err(|e| {
+			warn!(target: "badger", "Error applying finality to block {:?}: {:?}", (hash, number), e);
+			ConsensusError::ClientImport(e.to_string())
+		})?;
+		*self.last_finalized.write() = hash;
+
+		// we just finalized this block, so if we were importing it, it is now the new best
+		Ok(ImportResult::imported(true))
+	}
+}
+
+impl<B, E, Block: BlockT<Hash=H256>, RA> BlockImport<Block>
+	for BadgerLightBlockImport<B, E, Block, RA> where
+		B: Backend<Block, Blake2Hasher> + 'static,
+		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
+		DigestFor<Block>: Encode,
+		RA: Send + Sync,
+{
+	type Error = ConsensusError;
+
+	fn import_block(
+		&mut self,
+		mut block: BlockImportParams<Block>,
+		new_cache: HashMap<well_known_cache_keys::Id, Vec<u8>>,
+	) -> Result<ImportResult, Self::Error> {
+		let hash = block.post_header().hash();
+		let number = block.header.number().clone();
+
+		// we don't want to finalize on `inner.import_block`
+		let justification = block.justification.take();
+		let imported_aux = match (&*self.client).import_block(block, new_cache) {
+			Ok(ImportResult::Imported(aux)) => aux,
+			Ok(r) => return Ok(r),
+			Err(e) => return Err(ConsensusError::ClientImport(e.to_string())),
+		};
+
+		match justification {
+			Some(justification) => self.import_justification(hash, number, justification, imported_aux),
+			None => Ok(ImportResult::Imported(imported_aux)),
+		}
+	}
+
+	fn check_block(
+		&mut self,
+		block: BlockCheckParams<Block>,
+	) -> Result<ImportResult, Self::Error> {
+		self.client.check_block(block)
+	}
+}
+
+impl<B, E, Block: BlockT<Hash=H256>, RA> FinalityProofImport<Block>
+	for BadgerLightBlockImport<B, E, Block, RA> where
+		B: Backend<Block, Blake2Hasher> + 'static,
+		E: CallExecutor<Block, Blake2Hasher> + 'static + Clone + Send + Sync,
+		DigestFor<Block>: Encode,
+		RA: Send + Sync,
+{
+	type Error = ConsensusError;
+
+	fn import_finality_proof(
+		&mut self,
+		_hash: Block::Hash,
+		_number: NumberFor<Block>,
+		finality_proof: Vec<u8>,
+		verifier: &mut dyn Verifier<Block>,
+	) -> Result<(Block::Hash, NumberFor<Block>), Self::Error> {
+		let authorities = self.authority_set.inner.read().current_authorities.clone();
+		let committee_key = self.authority_set.committee_key.read().clone();
+		let finality_effects = check_finality_proof(
+			self.backend.blockchain(),
+			authorities,
+			committee_key,
+			&*self.authority_set_provider,
+			finality_proof,
+		).map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+
+		// try to import all new headers
+		for header_to_import in finality_effects.headers_to_import {
+			let (block_to_import, _) = verifier.verify(BlockOrigin::NetworkBroadcast, header_to_import, None, None)
+				.map_err(|e| ConsensusError::ClientImport(e))?;
+			assert!(block_to_import.justification.is_none(), "We have passed None as justification to verifier.verify");
+			self.import_block(block_to_import, HashMap::new())?;
+		}
+
+		// apply new authorities set before finalizing, so the justification of the
+		// next block is checked against it
+		if finality_effects.set_changes > 0 {
+			let mut set = self.authority_set.inner.write();
+			set.current_authorities = finality_effects.new_authorities;
+			set.set_id += finality_effects.set_changes;
+			aux_store::update_authority_set(&*set, |insert| self.client.insert_aux(insert, &[]))
+				.map_err(|e| {
+					warn!(target: "badger", "Failed to write updated authority set to disk. Bailing.");
+					ConsensusError::ClientImport(e.to_string())
+				})?;
+		}
+
+		self.note_committee_key(finality_effects.committee_key)?;
+
+		let finalized_block_hash = finality_effects.block;
+		let finalized_block_number = self.backend.blockchain()
+			.expect_block_number_from_id(&BlockId::Hash(finalized_block_hash))
+			.map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+		self.finalize_block(
+			finalized_block_hash,
+			finalized_block_number,
+			finality_effects.justification,
+		)?;
+
+		Ok((finalized_block_hash, finalized_block_number))
+	}
+}
+
+struct BadgerFinalityProofRequestBuilder<H>(Arc<RwLock<H>>);
+
+impl<B: BlockT<Hash=H256>> FinalityProofRequestBuilder<B> for BadgerFinalityProofRequestBuilder<B::Hash> {
+	fn build_request_data(&mut self, _hash: &B::Hash) -> Vec<u8> {
+		make_finality_proof_request(*self.0.read())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use badger::crypto::SecretKey;
+	use substrate_primitives::crypto::Pair;
+	use client::light::fetcher::StorageProof;
+	use consensus_common::ForkChoiceStrategy;
+	use test_client::runtime::{Block, Header};
+	use crate::aux_store::AuthoritySet;
+	use crate::finality_proof::tests::{
+		ClosureAuthoritySetChecker, authority_ids, authority_pairs, finality_proof, justification,
+	};
+
+	struct NoHeadersVerifier;
+
+	impl Verifier<Block> for NoHeadersVerifier {
+		fn verify(
+			&mut self,
+			_origin: BlockOrigin,
+			_header: Header,
+			_justification: Option<Justification>,
+			_body: Option<Vec<<Block as BlockT>::Extrinsic>>,
+		) -> Result<(BlockImportParams<Block>, Option<Vec<(well_known_cache_keys::Id, Vec<u8>)>>), String> {
+			unreachable!("the proof carries no headers to import")
+		}
+	}
+
+	fn block(header: Header, justification: Option<Justification>) -> BlockImportParams<Block> {
+		BlockImportParams {
+			origin: BlockOrigin::Own,
+			header,
+			justification,
+			post_digests: Vec::new(),
+			body: None,
+			finalized: false,
+			auxiliary: Vec::new(),
+			fork_choice: ForkChoiceStrategy::LongestChain,
+			allow_missing_state: true,
+			import_existing: false,
+		}
+	}
+
+	#[test]
+	fn finality_proof_import_applies_set_change_and_committee_key() {
+		let (client, backend) = test_client::new_light();
+		let client = Arc::new(client);
+		let old = authority_pairs(&[1, 2, 3, 4]);
+		let new = authority_ids(&authority_pairs(&[1, 2, 3, 5]));
+		let old_key = SecretKey::random();
+		let new_key = SecretKey::random();
+
+		let authority_set = BadgerSharedAuthoritySet::from(AuthoritySet {
+			current_authorities: authority_ids(&old),
+			self_id: old[0].public(),
+			set_id: 0,
+		});
+		authority_set.note_committee_key(old_key.public_key());
+		let checker_set = new.clone();
+		let mut import = light_block_import(
+			client.clone(),
+			backend,
+			authority_set.clone(),
+			Arc::new(ClosureAuthoritySetChecker(move |_: H256, _: Header, _: StorageProof| Ok(checker_set.clone()))),
+		);
+
+		// block 1 is signed by a key we don't know yet, so we ask for a finality proof
+		let header_1 = Header::new(1, Default::default(), Default::default(), client.info().best_hash, Default::default());
+		let hash_1 = header_1.hash();
+		let unattested = justification(hash_1, &new_key, None);
+		match import.import_block(block(header_1.clone(), Some(unattested)), HashMap::new()).unwrap() {
+			ImportResult::Imported(aux) => assert!(aux.needs_finality_proof),
+			r => panic!("block 1 not imported: {:?}", r),
+		}
+		assert_eq!(client.info().finalized_number, 0);
+
+		// the proof carries the attestation of the new key and the new set
+		let justification_1 = justification(hash_1, &new_key, Some(&old));
+
+		let proof = finality_proof(hash_1, justification_1, Some(StorageProof::new(vec![vec![1]])));
+		assert_eq!(
+			import.import_finality_proof(hash_1, 1, proof, &mut NoHeadersVerifier).unwrap(),
+			(hash_1, 1),
+		);
+		assert_eq!(client.info().finalized_hash, hash_1);
+		assert_eq!(authority_set.inner.read().current_authorities, new);
+		assert_eq!(authority_set.inner.read().set_id, 1);
+		assert_eq!(*authority_set.committee_key.read(), Some(new_key.public_key()));
+
+		// the next block is checked against the key learned from the proof
+		let header_2 = Header::new(2, Default::default(), Default::default(), hash_1, Default::default());
+		let justification_2 = justification(header_2.hash(), &new_key, None);
+		match import.import_block(block(header_2.clone(), Some(justification_2)), HashMap::new()).unwrap() {
+			ImportResult::Imported(aux) => assert!(!aux.needs_finality_proof),
+			r => panic!("block 2 not imported: {:?}", r),
+		}
+		assert_eq!(client.info().finalized_hash, header_2.hash());
+	}
+}
@@ -1,8 +1,19 @@
 use jsonrpc_core::{Error, ErrorCode, Result};
+use jsonrpc_core::futures::{future, sync::mpsc, Future, Sink, Stream};
 use jsonrpc_derive::rpc;
-use badger_primitives::{SignedAccountBinding,AuthorityPair,AccountBinding};
+use jsonrpc_pubsub::{typed::Subscriber, PubSubMetadata, SubscriptionId};
+use badger_primitives::{SignedAccountBinding,AuthorityPair,AccountBinding,ACCOUNT_BINDING_PURPOSE};
 use keystore::KeyStorePtr;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use badger::dynamic_honey_badger::ChangeState;
+use badger::honey_badger::EncryptionSchedule;
+use badger::crypto::Ciphertext;
+use badger_primitives::AuthorityId;
+use parity_codec::Decode;
+use std::net::SocketAddr;
+use crate::communication::BatchType;
 
 use substrate_primitives::{
 	Bytes
@@ -27,21 +38,55 @@ pub struct CallRequest<AccountId, Balance> {
 	input_data: Bytes,
 }*/
 
+/// Number of blocks a binding made by `badgerrpc_bindAccount` can be submitted in
+const BINDING_VALIDITY: u32 = 600;
+
+/// Batch notifications buffered per subscriber, later ones are dropped until the client catches up
+const BATCH_NOTIFICATION_BUFFER: usize = 64;
+
+/// Executor for the tasks forwarding notifications to subscribers.
+pub type TaskExecutor = Arc<dyn future::Executor<Box<dyn Future<Item = (), Error = ()> + Send>> + Send + Sync>;
+
 /// Contracts RPC methods.
 #[rpc]
 pub trait BadgerRpcApi<AccountId> {
-	/// Executes a call to a contract.
+	/// Signs a binding of `account` to the node key, for `submit_account_binding`.
 	///
-	/// This call is performed locally without submitting any transactions. Thus executing this
-	/// won't change any state. Nonetheless, the calling state-changing contracts is still possible.
-	///
-	/// This method is useful for calling getter-like methods on contracts.
+	/// The binding is only valid on this chain, expires after `BINDING_VALIDITY` blocks and
+	/// can be submitted once.
 	#[rpc(name = "badgerrpc_bindAccount")]
 	fn bind_account(
         &self,
         account:AccountId,
 	) -> Result<Bytes>;
 
+	/// Votes for a new validator set from the local node. Unsafe.
+	///
+	/// Takes SCALE-encoded authority ids.
+	#[rpc(name = "badgerrpc_voteForValidators")]
+	fn vote_for_validators(
+        &self,
+        authorities: Vec<Bytes>,
+	) -> Result<()>;
+
+	/// Votes for a new encryption schedule from the local node. Unsafe.
+	#[rpc(name = "badgerrpc_voteEncryptionSchedule")]
+	fn vote_encryption_schedule(
+        &self,
+        schedule: EncryptionScheduleParam,
+	) -> Result<()>;
+
+	/// Returns the change state of the last batch and our pending vote.
+	#[rpc(name = "badgerrpc_changeState")]
+	fn change_state(&self) -> Result<ChangeStateInfo>;
+
+	/// Submits an extrinsic encrypted to the master key of the validator set
+	/// (`keySetPublicKey` in `badgerrpc_status`). It is decrypted only after its batch is agreed.
+	///
+	/// Takes a bincode-encoded `threshold_crypto::Ciphertext` of the SCALE-encoded extrinsic.
+	#[rpc(name = "author_submitEncryptedExtrinsic")]
+	fn submit_encrypted_extrinsic(&self, extrinsic: Bytes) -> Result<()>;
+
 
 }
 
@@ -50,19 +95,76 @@ pub struct BadgerRpcCaller<C,B>
 {
     client: Arc<C>,
     keystore:KeyStorePtr,
+    shared: SharedBadgerStatus,
+    deny_unsafe: DenyUnsafe,
 	_marker: std::marker::PhantomData<B>,
 
 }
 
 impl<C,B> BadgerRpcCaller<C,B> {
 	/// Create new `Contracts` with the given reference to the client.
-	pub fn new(client: Arc<C>,keystore:KeyStorePtr) -> Self {
+	pub fn new(client: Arc<C>,keystore:KeyStorePtr,shared:SharedBadgerStatus,deny_unsafe:DenyUnsafe) -> Self {
 		BadgerRpcCaller {
             client,
             keystore,
+            shared,
+            deny_unsafe,
 			_marker: Default::default(),
 		}
 	}
+
+	fn control(&self) -> Result<Arc<dyn BadgerControl>> {
+		self.deny_unsafe.check()?;
+		match self.shared.control() {
+			Some(c) => Ok(c),
+			None => Err(not_running()),
+		}
+	}
+}
+
+fn not_running() -> Error {
+	Error {
+		code: ErrorCode::InternalError,
+		message: "Badger is not running".to_string(),
+		data: None
+	}
+}
+
+fn vote_error(e: String) -> Error {
+	Error {
+		code: ErrorCode::InternalError,
+		message: format!("Vote failed: {}", e),
+		data: None
+	}
+}
+
+/// Whether unsafe methods may be called.
+#[derive(Clone, Copy, Debug)]
+pub enum DenyUnsafe {
+	Yes,
+	No,
+}
+
+impl DenyUnsafe {
+	/// Unsafe methods are only allowed if the RPC servers listen on loopback interfaces.
+	pub fn from_interfaces(interfaces: &[Option<SocketAddr>]) -> Self {
+		if interfaces.iter().flatten().all(|addr| addr.ip().is_loopback()) {
+			DenyUnsafe::No
+		} else {
+			DenyUnsafe::Yes
+		}
+	}
+
+	pub fn check(&self) -> Result<()> {
+		match self {
+			DenyUnsafe::No => Ok(()),
+			DenyUnsafe::Yes => Err(Error {
+				code: ErrorCode::MethodNotFound,
+				message: "RPC call is unsafe to be called externally".to_string(),
+				data: None
+			}),
+		}
+	}
 }
 use crate::aux_store::GenesisAuthoritySetProvider;
 impl<C,Block,AccountId > BadgerRpcApi<AccountId,>
@@ -105,8 +207,12 @@ where
                 data: None
             })
         };
+        let info = self.client.info();
         let bind=AccountBinding
         {
+            purpose: ACCOUNT_BINDING_PURPOSE,
+            genesis_hash: info.genesis_hash,
+            expires_at: info.best_number + BINDING_VALIDITY.into(),
             self_pub_key: persistent_data.authority_set.inner.read().self_id.clone(),
             bound_account:account,
         };
@@ -119,8 +225,354 @@ where
         Ok(sgn.encode().into())
 
     }
+
+    fn vote_for_validators(
+        &self,
+        authorities: Vec<Bytes>,
+    ) -> Result<()>
+    {
+        let control = self.control()?;
+        let mut auths = Vec::with_capacity(authorities.len());
+        for raw in authorities.iter()
+        {
+            match AuthorityId::decode(&mut &raw[..])
+            {
+                Ok(id) => auths.push(id),
+                Err(_) => return Err(Error {
+                    code: ErrorCode::InvalidParams,
+                    message: "Invalid authority id".to_string(),
+                    data: None
+                }),
+            }
+        }
+        control.vote_for_validators(auths).map_err(vote_error)
+    }
+
+    fn vote_encryption_schedule(
+        &self,
+        schedule: EncryptionScheduleParam,
+    ) -> Result<()>
+    {
+        let control = self.control()?;
+        control.vote_change_encryption_schedule(schedule.into()).map_err(vote_error)
+    }
+
+    fn submit_encrypted_extrinsic(&self, extrinsic: Bytes) -> Result<()>
+    {
+        let ct: Ciphertext = match bincode::deserialize(&extrinsic[..])
+        {
+            Ok(ct) => ct,
+            Err(_) => return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: "Invalid ciphertext encoding".to_string(),
+                data: None
+            }),
+        };
+        if !ct.verify()
+        {
+            return Err(Error {
+                code: ErrorCode::InvalidParams,
+                message: "Invalid ciphertext".to_string(),
+                data: None
+            });
+        }
+        let control = match self.shared.control()
+        {
+            Some(c) => c,
+            None => return Err(not_running()),
+        };
+        control.submit_encrypted_transaction(ct).map_err(|e| Error {
+            code: ErrorCode::InternalError,
+            message: e,
+            data: None
+        })
+    }
+
+    fn change_state(&self) -> Result<ChangeStateInfo>
+    {
+        match self.shared.control()
+        {
+            Some(c) => Ok(c.change_state()),
+            None => Err(not_running()),
+        }
+    }
+}
+
+/// `EncryptionSchedule` as passed over RPC.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum EncryptionScheduleParam {
+	Always,
+	Never,
+	EveryNthEpoch(u32),
+	/// Encrypt for the first `on` epochs, then not for the next `off` epochs
+	TickTock(u32, u32),
 }
 
+impl From<EncryptionScheduleParam> for EncryptionSchedule {
+	fn from(p: EncryptionScheduleParam) -> Self {
+		match p {
+			EncryptionScheduleParam::Always => EncryptionSchedule::Always,
+			EncryptionScheduleParam::Never => EncryptionSchedule::Never,
+			EncryptionScheduleParam::EveryNthEpoch(n) => EncryptionSchedule::EveryNthEpoch(n),
+			EncryptionScheduleParam::TickTock(on, off) => EncryptionSchedule::TickTock(on, off),
+		}
+	}
+}
 
+impl From<EncryptionSchedule> for EncryptionScheduleParam {
+	fn from(e: EncryptionSchedule) -> Self {
+		match e {
+			EncryptionSchedule::Always => EncryptionScheduleParam::Always,
+			EncryptionSchedule::Never => EncryptionScheduleParam::Never,
+			EncryptionSchedule::EveryNthEpoch(n) => EncryptionScheduleParam::EveryNthEpoch(n),
+			EncryptionSchedule::TickTock(on, off) => EncryptionScheduleParam::TickTock(on, off),
+		}
+	}
+}
 
+/// A DynamicHoneyBadger change.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeInfo {
+	/// New validator set, hex-encoded authority ids
+	NodeChange(Vec<String>),
+	EncryptionSchedule(EncryptionScheduleParam),
+}
 
+/// Change state of the last batch.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeStateInfo {
+	/// "none", "inProgress" or "complete"
+	pub state: String,
+	pub change: Option<ChangeInfo>,
+	/// Our vote in the current era, if any
+	pub our_vote: Option<ChangeInfo>,
+}
+
+/// Control of the running consensus engine.
+pub trait BadgerControl: Send + Sync {
+	fn vote_for_validators(&self, authorities: Vec<AuthorityId>) -> std::result::Result<(), String>;
+	fn vote_change_encryption_schedule(&self, schedule: EncryptionSchedule) -> std::result::Result<(), String>;
+	fn change_state(&self) -> ChangeStateInfo;
+	fn submit_encrypted_transaction(&self, ct: Ciphertext) -> std::result::Result<(), String>;
+}
+
+/// Snapshot of the consensus engine state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BadgerStatus {
+	/// `BadgerState` variant
+	pub state: String,
+	/// Era of the next batch, if known
+	pub era: Option<u64>,
+	/// Epoch of the next batch, if known
+	pub epoch: Option<u64>,
+	pub set_id: u32,
+	pub authorities: Vec<String>,
+	/// Set id of the threshold keys in the keystore (`BadgerAuxCrypto`)
+	pub key_set_id: Option<u32>,
+	/// Master public key of the threshold key set
+	pub key_set_public_key: Option<String>,
+	pub connected_validators: Vec<String>,
+	/// Transactions waiting for Badger to start
+	pub queued_transactions: usize,
+	/// Transactions output in a batch that did not fit in a block
+	pub overflow_transactions: usize,
+	/// Transactions input to Badger and not yet output in a batch
+	pub pending_transactions: usize,
+	pub pending_justifications: Vec<PendingJustification>,
+}
+
+/// Block awaiting a quorum of justifications.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingJustification {
+	pub hash: String,
+	pub collected: usize,
+}
+
+/// Summary of a batch output by Badger.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchNotification {
+	pub era: u64,
+	pub epoch: u64,
+	pub transactions: usize,
+	/// "none", "inProgress" or "complete"
+	pub change: String,
+}
+
+impl<'a> From<&'a BatchType> for BatchNotification {
+	fn from(batch: &'a BatchType) -> Self {
+		BatchNotification {
+			era: batch.era(),
+			epoch: batch.epoch(),
+			transactions: batch.len(),
+			change: change_state_name(batch.change()).to_string(),
+		}
+	}
+}
+
+pub fn change_state_name<N>(change: &ChangeState<N>) -> &'static str {
+	match change {
+		ChangeState::None => "none",
+		ChangeState::InProgress(_) => "inProgress",
+		ChangeState::Complete(_) => "complete",
+	}
+}
+
+/// Something that can report the consensus engine state.
+pub trait BadgerIntrospect: Send + Sync {
+	fn status(&self) -> BadgerStatus;
+}
+
+/// Handle shared between the RPC and the running Badger instance.
+/// The RPC is built before Badger is started, so the provider is filled in later.
+#[derive(Clone, Default)]
+pub struct SharedBadgerStatus {
+	provider: Arc<RwLock<Option<Arc<dyn BadgerIntrospect>>>>,
+	control: Arc<RwLock<Option<Arc<dyn BadgerControl>>>>,
+	executor: Arc<RwLock<Option<TaskExecutor>>>,
+	subscribers: Arc<Mutex<Vec<(u64, mpsc::Sender<BatchNotification>)>>>,
+	next_id: Arc<Mutex<u64>>,
+}
+
+impl SharedBadgerStatus {
+	pub fn set_provider(&self, provider: Arc<dyn BadgerIntrospect>) {
+		*self.provider.write() = Some(provider);
+	}
+
+	pub fn status(&self) -> Option<BadgerStatus> {
+		self.provider.read().as_ref().map(|p| p.status())
+	}
+
+	pub fn set_control(&self, control: Arc<dyn BadgerControl>) {
+		*self.control.write() = Some(control);
+	}
+
+	pub fn control(&self) -> Option<Arc<dyn BadgerControl>> {
+		self.control.read().clone()
+	}
+
+	pub fn set_executor(&self, executor: TaskExecutor) {
+		*self.executor.write() = Some(executor);
+	}
+
+	/// Send `batch` to all subscribers, dropping the ones that went away.
+	/// Never blocks, a subscriber that does not keep up misses notifications.
+	pub fn notify_batch(&self, batch: &BatchType) {
+		let mut subs = self.subscribers.lock();
+		if subs.is_empty() {
+			return;
+		}
+		let notification = BatchNotification::from(batch);
+		let live = subs
+			.drain(..)
+			.filter_map(|(id, mut tx)| match tx.try_send(notification.clone()) {
+				Err(ref e) if e.is_disconnected() => None,
+				_ => Some((id, tx)),
+			})
+			.collect();
+		*subs = live;
+	}
+
+	fn subscribe(&self, subscriber: Subscriber<BatchNotification>) {
+		let executor = match self.executor.read().clone() {
+			Some(executor) => executor,
+			None => {
+				let _ = subscriber.reject(not_running());
+				return;
+			}
+		};
+		let id = {
+			let mut next = self.next_id.lock();
+			*next += 1;
+			*next
+		};
+		let sink = match subscriber.assign_id(SubscriptionId::Number(id)) {
+			Ok(sink) => sink,
+			Err(_) => return,
+		};
+		let (tx, rx) = mpsc::channel(BATCH_NOTIFICATION_BUFFER);
+		self.subscribers.lock().push((id, tx));
+		let shared = self.clone();
+		let forward = sink
+			.sink_map_err(|e| log::warn!("Error sending batch notification: {:?}", e))
+			.send_all(rx.map(Ok))
+			.then(move |_| {
+				shared.unsubscribe(id);
+				Ok(())
+			});
+		if executor.execute(Box::new(forward)).is_err() {
+			self.unsubscribe(id);
+		}
+	}
+
+	fn unsubscribe(&self, id: u64) -> bool {
+		let mut subs = self.subscribers.lock();
+		let before = subs.len();
+		subs.retain(|(sid, _)| *sid != id);
+		subs.len() != before
+	}
+}
+
+/// Consensus introspection RPC methods.
+#[rpc]
+pub trait BadgerStatusApi {
+	/// RPC metadata
+	type Metadata;
+
+	/// Returns the state of the consensus engine.
+	#[rpc(name = "badgerrpc_status")]
+	fn status(&self) -> Result<BadgerStatus>;
+
+	/// Batch output subscription
+	#[pubsub(subscription = "badgerrpc_batch", subscribe, name = "badgerrpc_subscribeBatches")]
+	fn subscribe_batches(&self, metadata: Self::Metadata, subscriber: Subscriber<BatchNotification>);
+
+	/// Unsubscribe from batch output subscription.
+	#[pubsub(subscription = "badgerrpc_batch", unsubscribe, name = "badgerrpc_unsubscribeBatches")]
+	fn unsubscribe_batches(&self, metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool>;
+}
+
+pub struct BadgerStatusRpc<M> {
+	shared: SharedBadgerStatus,
+	_marker: std::marker::PhantomData<M>,
+}
+
+impl<M> BadgerStatusRpc<M> {
+	pub fn new(shared: SharedBadgerStatus) -> Self {
+		BadgerStatusRpc {
+			shared,
+			_marker: Default::default(),
+		}
+	}
+}
+
+impl<M: PubSubMetadata> BadgerStatusApi for BadgerStatusRpc<M> {
+	type Metadata = M;
+
+	fn status(&self) -> Result<BadgerStatus> {
+		match self.shared.status() {
+			Some(st) => Ok(st),
+			None => Err(Error {
+				code: ErrorCode::InternalError,
+				message: "Badger is not running".to_string(),
+				data: None
+			}),
+		}
+	}
+
+	fn subscribe_batches(&self, _metadata: Self::Metadata, subscriber: Subscriber<BatchNotification>) {
+		self.shared.subscribe(subscriber);
+	}
+
+	fn unsubscribe_batches(&self, _metadata: Option<Self::Metadata>, id: SubscriptionId) -> Result<bool> {
+		match id {
+			SubscriptionId::Number(n) => Ok(self.shared.unsubscribe(n)),
+			_ => Ok(false),
+		}
+	}
+}
@@ -13,28 +13,36 @@ use sc_network_gossip::{ MessageIntent, ValidationResult, ValidatorContext};//Go
 use sp_runtime::traits::Block as BlockT;
 
 use super::{
-	message::{ConfirmPeersMessage, KeyGenMessage, SigGenMessage},
+	message::{ConfirmPeersMessage, KeyGenMessage, ReshareMessage, SigGenMessage},
 	peer::{PeerInfo, PeerState, Peers},
 };
 use crate::NodeConfig;
 
 const REBROADCAST_AFTER: Duration = Duration::from_secs(30);
 
-pub type RequestId = u64;
+pub use sp_mpc::RequestId;
 
+/// Hash of the peer set generating a key, see `Peers::get_hash`
+pub type PeersHash = u64;
+
+/// Key generation messages carry the id of the key request, which is also the id signing
+/// requests refer to the key by. Sessions for different ids never share state. A reshare
+/// session is identified by the id of the new key.
 #[derive(Debug, Clone, Encode, Decode, PartialEq)]
 pub enum GossipMessage {
-	ConfirmPeers(ConfirmPeersMessage, RequestId),
-	KeyGen(KeyGenMessage, RequestId),
-	SigGen(SigGenMessage, RequestId),
+	ConfirmPeers(ConfirmPeersMessage, RequestId, PeersHash),
+	KeyGen(KeyGenMessage, RequestId, PeersHash),
+	SigGen(SigGenMessage, RequestId, u16), // attempt, see `SigGenState`
+	Reshare(ReshareMessage, RequestId, PeersHash),
 }
 
 impl GossipMessage {
 	pub fn get_req_id(&self) -> RequestId {
 		match self {
-			GossipMessage::ConfirmPeers(_, id) => *id,
-			GossipMessage::KeyGen(_, id) => *id,
-			GossipMessage::SigGen(_, id) => *id,
+			GossipMessage::ConfirmPeers(_, id, _) => *id,
+			GossipMessage::KeyGen(_, id, _) => *id,
+			GossipMessage::SigGen(_, id, _) => *id,
+			GossipMessage::Reshare(_, id, _) => *id,
 		}
 	}
 }
@@ -114,69 +122,89 @@ impl Inner {
 		self.local_peer_id.to_base58()
 	}
 
-	pub fn local_state(&self) -> PeerState {
-		self.local_peer_info.state.clone()
+	pub fn local_state(&self, id: RequestId) -> PeerState {
+		self.local_peer_info.state(id)
+	}
+
+	pub fn is_local_awaiting_peers(&self, id: RequestId) -> bool {
+		self.local_state(id) == PeerState::AwaitingPeers
 	}
 
-	pub fn is_local_awaiting_peers(&self) -> bool {
-		self.local_peer_info.state == PeerState::AwaitingPeers
+	pub fn is_local_generating(&self, id: RequestId) -> bool {
+		self.local_state(id) == PeerState::Generating
 	}
 
-	pub fn is_local_generating(&self) -> bool {
-		self.local_peer_info.state == PeerState::Generating
+	pub fn is_local_complete(&self, id: RequestId) -> bool {
+		self.local_state(id) == PeerState::Complete
 	}
 
-	pub fn is_local_complete(&self) -> bool {
-		self.local_peer_info.state == PeerState::Complete
+	pub fn is_local_canceled(&self, id: RequestId) -> bool {
+		self.local_state(id) == PeerState::Canceled
 	}
 
-	pub fn is_local_canceled(&self) -> bool {
-		self.local_peer_info.state == PeerState::Canceled
+	pub fn set_local_awaiting_peers(&mut self, id: RequestId) {
+		self.set_local_state(id, PeerState::AwaitingPeers);
 	}
 
-	pub fn set_local_awaiting_peers(&mut self) {
-		self.set_local_state(PeerState::AwaitingPeers);
+	pub fn set_local_generating(&mut self, id: RequestId) {
+		self.set_local_state(id, PeerState::Generating);
 	}
 
-	pub fn set_local_generating(&mut self) {
-		self.set_local_state(PeerState::Generating);
+	pub fn set_local_complete(&mut self, id: RequestId) {
+		self.set_local_state(id, PeerState::Complete);
 	}
 
-	pub fn set_local_complete(&mut self) {
-		self.set_local_state(PeerState::Complete);
+	pub fn set_local_canceled(&mut self, id: RequestId) {
+		self.set_local_state(id, PeerState::Canceled);
 	}
 
-	pub fn set_local_canceled(&mut self) {
-		self.set_local_state(PeerState::Canceled);
+	pub fn set_peer_awaiting_peers(&mut self, who: &PeerId, id: RequestId) {
+		self.set_peer_state(who, id, PeerState::AwaitingPeers);
 	}
 
-	pub fn set_peer_awaiting_peers(&mut self, who: &PeerId) {
-		self.set_peer_state(who, PeerState::AwaitingPeers);
+	pub fn set_peer_generating(&mut self, who: &PeerId, id: RequestId) {
+		self.set_peer_state(who, id, PeerState::Generating);
 	}
 
-	pub fn set_peer_generating(&mut self, who: &PeerId) {
-		self.set_peer_state(who, PeerState::Generating);
+	pub fn set_peer_complete(&mut self, who: &PeerId, id: RequestId) {
+		self.set_peer_state(who, id, PeerState::Complete);
 	}
 
-	pub fn set_peer_complete(&mut self, who: &PeerId) {
-		self.set_peer_state(who, PeerState::Complete);
+	pub fn set_peer_canceled(&mut self, who: &PeerId, id: RequestId) {
+		self.set_peer_state(who, id, PeerState::Canceled);
 	}
 
-	pub fn set_peer_canceled(&mut self, who: &PeerId) {
-		self.set_peer_state(who, PeerState::Canceled);
+	pub fn set_local_state(&mut self, id: RequestId, state: PeerState) {
+		self.set_peer_state(&self.local_peer_id(), id, state.clone());
+		self.local_peer_info.states.insert(id, state);
 	}
 
-	pub fn set_local_state(&mut self, state: PeerState) {
-		self.set_peer_state(&self.local_peer_id(), state.clone());
-		self.local_peer_info.state = state;
+	pub fn set_peer_state(&mut self, who: &PeerId, id: RequestId, state: PeerState) {
+		self.peers.set_state(who, id, state);
 	}
 
-	pub fn set_peer_state(&mut self, who: &PeerId, state: PeerState) {
-		self.peers.set_state(who, state);
+	pub fn get_peer_state(&self, who: &PeerId, id: RequestId) -> Option<PeerState> {
+		self.peers.get_state(who, id)
+	}
+
+	/// Sessions we are in the middle of, they can't finish with a peer missing
+	pub fn cancel_unfinished(&mut self) {
+		let ids = self.local_peer_info.states.keys().cloned().collect::<Vec<_>>();
+		for id in ids {
+			if self.is_local_awaiting_peers(id) || self.is_local_generating(id) {
+				self.set_local_canceled(id);
+			}
+		}
 	}
 
-	pub fn get_peer_state(&self, who: &PeerId) -> Option<PeerState> {
-		self.peers.get_state(who)
+	/// Let canceled sessions start over once peers are back
+	pub fn retry_canceled(&mut self) {
+		let ids = self.local_peer_info.states.keys().cloned().collect::<Vec<_>>();
+		for id in ids {
+			if self.is_local_canceled(id) {
+				self.set_local_awaiting_peers(id);
+			}
+		}
 	}
 }
 
@@ -202,7 +230,7 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 
 		let mut inner = self.inner.write();
 		inner.add_peer(who.clone());
-		inner.set_local_awaiting_peers();
+		inner.retry_canceled();
 	}
 
 	fn peer_disconnected(&self, _context: &mut dyn ValidatorContext<Block>, who: &PeerId) {
@@ -211,7 +239,7 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 
 		let players = inner.config.players as usize;
 		if inner.get_peers_len() < players {
-			inner.set_local_canceled();
+			inner.cancel_unfinished();
 		}
 	}
 
@@ -223,8 +251,15 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 	) -> ValidationResult<Block::Hash> {
 		let gossip_msg = GossipMessage::decode(&mut data);
 		if let Ok(gossip_msg) = gossip_msg {
-			let req_id = gossip_msg.get_req_id();
-			let topic = super::bytes_topic::<Block>(&req_id.to_le_bytes());
+			// everything goes to the single stream `NetworkBridge::global` listens on
+			let topic = super::global_topic::<Block>();
+			match gossip_msg {
+				GossipMessage::SigGen(_, _, _) | GossipMessage::Reshare(_, _, _) => {
+					// signing and reshare rounds are sent to every party directly, nothing to propagate
+					return ValidationResult::ProcessAndDiscard(topic);
+				}
+				_ => {}
+			}
 			return ValidationResult::ProcessAndKeep(topic);
 		}
 		ValidationResult::Discard
@@ -267,14 +302,15 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 			if let Ok(gossip_msg) = gossip_msg {
 				let our_hash = inner.get_peers_hash();
 
-				let is_awaiting_peers = inner.is_local_awaiting_peers();
-				let is_generating = inner.is_local_generating();
+				let id = gossip_msg.get_req_id();
+				let is_awaiting_peers = inner.is_local_awaiting_peers(id);
+				let is_generating = inner.is_local_generating(id);
 
 				match gossip_msg {
-					GossipMessage::ConfirmPeers(_, all_peers_hash) => {
+					GossipMessage::ConfirmPeers(_, _, all_peers_hash) => {
 						return is_awaiting_peers && our_hash == all_peers_hash;
 					}
-					GossipMessage::KeyGen(_, all_peers_hash) => {
+					GossipMessage::KeyGen(_, _, all_peers_hash) => {
 						let is_valid = is_awaiting_peers || is_generating;
 						return is_valid && our_hash == all_peers_hash;
 					}
@@ -288,30 +324,32 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 	fn message_expired<'a>(&'a self) -> Box<dyn FnMut(Block::Hash, &[u8]) -> bool + 'a> {
 		Box::new(move |_topic, mut data| {
 			let inner = self.inner.read();
-			let is_complete = inner.is_local_complete();
-			let is_canceled = inner.is_local_canceled();
-
-			if is_complete || is_canceled {
-				return true;
-			}
-
-			let players = inner.config.players as usize;
-			if inner.peers.len() < players {
-				return false;
-			}
 
 			let gossip_msg = GossipMessage::decode(&mut data);
 			if let Ok(gossip_msg) = gossip_msg {
+				let id = gossip_msg.get_req_id();
+				let is_complete = inner.is_local_complete(id);
+				let is_canceled = inner.is_local_canceled(id);
+
+				if is_complete || is_canceled {
+					return true;
+				}
+
+				let players = inner.config.players as usize;
+				if inner.peers.len() < players {
+					return false;
+				}
+
 				println!("In `message_expired` of {:?}", inner.get_local_index());
 				let gmsg = gossip_msg.clone();
 				match gmsg {
-					GossipMessage::ConfirmPeers(cpm, _all_peers_hash) => match cpm {
+					GossipMessage::ConfirmPeers(cpm, _, _all_peers_hash) => match cpm {
 						ConfirmPeersMessage::Confirming(from) => {
 							println!("confirming from {:?}", from);
 						}
 						_ => {}
 					},
-					GossipMessage::KeyGen(kgm, _all_peers_hash) => match kgm {
+					GossipMessage::KeyGen(kgm, _, _all_peers_hash) => match kgm {
 						KeyGenMessage::CommitAndDecommit(from, _, _) => {
 							println!("com decom from {:?}", from);
 						}
@@ -332,7 +370,7 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 				println!("exit `message_expired` of {:?}", inner.get_local_index());
 
 				match gossip_msg {
-					GossipMessage::ConfirmPeers(cpm, all_peers_hash) => {
+					GossipMessage::ConfirmPeers(cpm, _, all_peers_hash) => {
 						match cpm {
 							ConfirmPeersMessage::Confirming(from_index) => {
 								let sender_id = inner.get_peer_id_by_index(from_index as usize);
@@ -350,17 +388,18 @@ impl<Block: BlockT> sc_network_gossip::Validator<Block> for GossipValidator<Bloc
 								}
 							}
 						}
-						let is_awaiting_peers = inner.is_local_awaiting_peers();
+						let is_awaiting_peers = inner.is_local_awaiting_peers(id);
 
 						return !is_awaiting_peers || is_canceled || our_hash != all_peers_hash;
 					}
-					GossipMessage::KeyGen(kgm, all_peers_hash) => {
+					GossipMessage::KeyGen(kgm, _, all_peers_hash) => {
 						let from_index = kgm.get_index() as usize;
 						let sender_id = inner.get_peer_id_by_index(from_index);
 
 						return our_hash != all_peers_hash || sender_id.is_none();
 					}
-					GossipMessage::SigGen(_, _) => return false,
+					GossipMessage::SigGen(_, _, _) => return false,
+					GossipMessage::Reshare(_, _, all_peers_hash) => return our_hash != all_peers_hash,
 				}
 			}
 			true
@@ -7,7 +7,7 @@ use curv::{
 		proofs::{sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof, sigma_dlog::DLogProof},
 		secret_sharing::feldman_vss::VerifiableSS,
 	},
-	FE,// GE,
+	FE, GE,
 };
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::{
 	mta::{MessageA, MessageB},
@@ -69,13 +69,27 @@ impl Decode for KeyGenMessage {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SigGenMessage {
-	Round1(SignBroadcastPhase1, MessageA),
-	Round2(MessageB, MessageB), // mb gamma, mb w
-	Round3(FE),
-	Round4(SignDecommitPhase1),
-	Round5(Phase5Com1, Phase5ADecom1, HomoELGamalProof),
-	Round6(Phase5Com2, Phase5DDecom2),
-	Round7(FE),
+	Round1(PeerIndex, SignBroadcastPhase1, MessageA),
+	Round2(PeerIndex, MessageB, MessageB), // mb gamma, mb w, sent to a single signer
+	Round3(PeerIndex, FE),                 // delta_i
+	Round4(PeerIndex, SignDecommitPhase1),
+	Round5(PeerIndex, Phase5Com1, Phase5ADecom1, HomoELGamalProof),
+	Round6(PeerIndex, Phase5Com2, Phase5DDecom2),
+	Round7(PeerIndex, FE), // s_i
+}
+
+impl SigGenMessage {
+	pub fn get_index(&self) -> PeerIndex {
+		match self {
+			Self::Round1(index, _, _) => *index,
+			Self::Round2(index, _, _) => *index,
+			Self::Round3(index, _) => *index,
+			Self::Round4(index, _) => *index,
+			Self::Round5(index, _, _, _) => *index,
+			Self::Round6(index, _, _) => *index,
+			Self::Round7(index, _) => *index,
+		}
+	}
 }
 
 impl Encode for SigGenMessage {
@@ -98,6 +112,45 @@ impl PartialEq for SigGenMessage {
 	}
 }
 
+/// Moving the shares of a key to the current peers, indexed by their position in the new committee
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReshareMessage {
+	// new index, paillier key for signing with the new shares, old index of the share held if any
+	Join(PeerIndex, KeyGenCommit, Option<PeerIndex>),
+	Deal(PeerIndex, u16, GE, VerifiableSS), // old index, old threshold, public key, vss of the dealt share
+	Share(PeerIndex, FE),                   // old index, sent to a single member of the new committee
+}
+
+impl ReshareMessage {
+	pub fn get_index(&self) -> PeerIndex {
+		match self {
+			Self::Join(index, _, _) => *index,
+			Self::Deal(index, _, _, _) => *index,
+			Self::Share(index, _) => *index,
+		}
+	}
+}
+
+impl Encode for ReshareMessage {
+	fn encode(&self) -> Vec<u8> {
+		let encoded = bincode::serialize(&self).unwrap();
+		Encode::encode(&encoded)
+	}
+}
+
+impl Decode for ReshareMessage {
+	fn decode<I: Input>(value: &mut I) -> Result<Self, CodecError> {
+		let decoded: Vec<u8> = Decode::decode(value)?;
+		bincode::deserialize(&decoded).map_err(|_| CodecError::from("bincode error"))
+	}
+}
+
+impl PartialEq for ReshareMessage {
+	fn eq(&self, other: &Self) -> bool {
+		self.encode() == other.encode()
+	}
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Encode, Decode, PartialEq)]
 pub enum ConfirmPeersMessage {
 	Confirming(PeerIndex), // from_index
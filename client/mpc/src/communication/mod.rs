@@ -29,6 +29,10 @@ pub(crate) fn bytes_topic<B: BlockT>(input: &[u8]) -> B::Hash {
 	<<B::Header as HeaderT>::Hashing as HashT>::hash(input)
 }
 
+pub(crate) fn global_topic<B: BlockT>() -> B::Hash {
+	bytes_topic::<B>(b"hash")
+}
+
 struct MessageSender<Block: BlockT> {
 	network: GossipEngine<Block>,
 	validator: Arc<GossipValidator<Block>>,
@@ -111,7 +115,7 @@ where
 		impl Stream<Item = MessageWithSender>,
 		impl Sink<MessageWithReceiver, Error = Error>,
 	) {
-		let topic = bytes_topic::<B>(b"hash"); // related with `fn validate` in gossip.rs
+		let topic = global_topic::<B>(); // related with `fn validate` in gossip.rs
 
 		let incoming = self.gossip_engine.messages_for(topic).filter_map(|notification| async {
 			let decoded = GossipMessage::decode(&mut &notification.message[..]);
@@ -130,8 +134,11 @@ where
 		(incoming.boxed(), outgoing)
 	}
 
-	pub fn start_key_gen(&self, _id: RequestId) {
+	pub fn start_key_gen(&self, id: RequestId) {
 		let inner = self.validator.inner.read();
+		if !inner.is_local_awaiting_peers(id) {
+			return;
+		}
 
 		let all_peers_len = inner.get_peers_len();
 		let players = inner.get_players() as usize;
@@ -141,7 +148,7 @@ where
 
 		let our_index = inner.get_local_index() as u16;
 		let all_peers_hash = inner.get_peers_hash();
-		let msg = GossipMessage::ConfirmPeers(ConfirmPeersMessage::Confirming(our_index), all_peers_hash);
+		let msg = GossipMessage::ConfirmPeers(ConfirmPeersMessage::Confirming(our_index), id, all_peers_hash);
 		let peers = inner.get_other_peers();
 		self.gossip_engine.send_message(peers, msg.encode());
 	}
@@ -1,10 +1,12 @@
-use std::collections::{hash_map::DefaultHasher, BTreeSet, HashMap};
+use std::collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap};
 use std::hash::{Hash, Hasher};
 use std::iter::Iterator;
 use std::str::FromStr;
 
 use sc_network::PeerId;
 
+use super::gossip::RequestId;
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum PeerState {
 	AwaitingPeers,
@@ -19,15 +21,22 @@ impl Default for PeerState {
 	}
 }
 
+/// State of a peer in every key generation session, sessions it hasn't joined yet are awaiting peers
 #[derive(Debug, Clone)]
 pub struct PeerInfo {
-	pub state: PeerState,
+	pub states: BTreeMap<RequestId, PeerState>,
+}
+
+impl PeerInfo {
+	pub fn state(&self, id: RequestId) -> PeerState {
+		self.states.get(&id).cloned().unwrap_or_default()
+	}
 }
 
 impl Default for PeerInfo {
 	fn default() -> Self {
 		Self {
-			state: PeerState::default(),
+			states: BTreeMap::new(),
 		}
 	}
 }
@@ -76,14 +85,14 @@ impl Peers {
 		self.map.keys()
 	}
 
-	pub fn get_state(&self, who: &PeerId) -> Option<PeerState> {
+	pub fn get_state(&self, who: &PeerId, id: RequestId) -> Option<PeerState> {
 		let peer = self.map.get(who);
-		peer.map(|p| p.state)
+		peer.map(|p| p.state(id))
 	}
 
-	pub fn set_state(&mut self, who: &PeerId, state: PeerState) {
+	pub fn set_state(&mut self, who: &PeerId, id: RequestId, state: PeerState) {
 		let peer = self.map.get_mut(who).expect("Peer not found!");
-		peer.state = state;
+		peer.states.insert(id, state);
 	}
 
 	pub fn get_hash(&self) -> u64 {
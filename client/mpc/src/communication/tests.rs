@@ -148,7 +148,7 @@ fn test_confirm_peer_message() {
 			};
 			let sender_id = id.clone();
 
-			let msg_to_send = GossipMessage::ConfirmPeers(ConfirmPeersMessage::Confirming(0), all_hash);
+			let msg_to_send = GossipMessage::ConfirmPeers(ConfirmPeersMessage::Confirming(0), 1, all_hash);
 			let msg_to_send_clone = msg_to_send.clone();
 
 			let send_message = tester.filter_network_events(move |event| match event {
@@ -1,10 +1,13 @@
 use std::{
-	collections::BTreeMap, fmt::Debug, hash::Hash, marker::PhantomData, pin::Pin, sync::Arc, thread, time::Duration,
+	collections::{BTreeMap, BTreeSet}, fmt::Debug, hash::Hash, marker::PhantomData, pin::Pin, sync::Arc, thread,
+	time::{Duration, Instant},
 };
 
 use curv::{
-	cryptographic_primitives::{proofs::sigma_dlog::DLogProof, secret_sharing::feldman_vss::VerifiableSS},
-	elliptic::curves::traits::ECPoint,
+	cryptographic_primitives::{
+		proofs::{sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof, sigma_dlog::DLogProof},
+		secret_sharing::feldman_vss::VerifiableSS,
+	},
 	FE, GE,
 };
 use futures::{
@@ -14,12 +17,17 @@ use futures::{
 	stream::StreamExt,
 	task::{Context, Poll, Spawn},
 };
-use log::{ error, info};//debug
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
-	KeyGenBroadcastMessage1 as KeyGenCommit, KeyGenDecommitMessage1 as KeyGenDecommit, Keys, Parameters, //PartyPrivate, SignKeys
-	SharedKeys, 
+use log::{error, trace}; //debug, info
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::{
+	mta::{MessageA, MessageB},
+	party_i::{
+		KeyGenBroadcastMessage1 as KeyGenCommit, KeyGenDecommitMessage1 as KeyGenDecommit, Keys, LocalSignature,
+		Parameters, Phase5ADecom1, Phase5Com1, Phase5Com2, Phase5DDecom2, SharedKeys, SignBroadcastPhase1,
+		SignDecommitPhase1, SignKeys,
+	},
 };
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 
 use sc_client::Client;
 use sc_client_api::{backend::Backend, BlockchainEvents, CallExecutor, ExecutionStrategy};
@@ -32,23 +40,22 @@ use sp_core::{
 	offchain::{OffchainStorage, },
 	Blake2Hasher, H256,
 };
-use sp_offchain::STORAGE_PREFIX;
 use sp_runtime::generic::OpaqueDigestItemId;
 use sp_runtime::traits::{Block as BlockT, Header};
 
-use sp_mpc::{get_storage_key, ConsensusLog, MpcRequest, OffchainStorageType,  MPC_ENGINE_ID}; //RequestId
+use sp_mpc::{ConsensusLog, GeneratedKeyId, MpcRequest, OffchainStorageType, RequestId, MPC_ENGINE_ID};
 
 mod communication;
 mod periodic_stream;
 mod signer;
 
 use communication::{
-	gossip::{GossipMessage, MessageWithSender},
-	message::{ConfirmPeersMessage, KeyGenMessage, PeerIndex, SigGenMessage},
+	gossip::{GossipMessage, MessageWithSender, PeersHash},
+	message::{ConfirmPeersMessage, KeyGenMessage, PeerIndex, ReshareMessage, SigGenMessage},
 	NetworkBridge,
 };
 use periodic_stream::PeriodicStream;
-use signer::Signer;
+use signer::{load_key_state, save_key_states, Signer};
 
 pub trait Network<B: BlockT>: GossipNetwork<B> + Clone + Send + 'static {
 	fn local_peer_id(&self) -> PeerId;
@@ -89,7 +96,8 @@ impl NodeConfig {
 	}
 }
 
-#[derive(Debug)]
+/// Key generation session of one key, kept as is in offchain storage once complete
+#[derive(Debug, Serialize, Deserialize)]
 pub struct KeyGenState {
 	pub complete: bool,
 	pub local_key: Option<Keys>,
@@ -106,6 +114,13 @@ impl KeyGenState {
 		self.shared_keys.clone().map(|sk| sk.y)
 	}
 
+	/// g^x_j of party `index`, the sum of what every vss of the key commits to for it
+	pub fn point_commitment(&self, index: PeerIndex) -> Option<GE> {
+		let mut points = self.vsss.values().map(|vss| vss.get_point_commitment(index as usize + 1));
+		let first = points.next()?;
+		Some(points.fold(first, |acc, point| acc + point))
+	}
+
 	pub fn reset(&mut self) {
 		*self = Self::default();
 	}
@@ -126,14 +141,223 @@ impl Default for KeyGenState {
 	}
 }
 
+/// How long one set of signers has to finish before the next set takes over
+pub const SIG_GEN_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a signing request is kept, the runtime expires it after 10 minutes
+pub const SIG_GEN_EXPIRY: Duration = Duration::from_secs(600);
+
+/// The most signing requests tracked at once, the runtime allows as many pending requests
+pub const MAX_SIG_STATES: usize = 64;
+
+/// The most messages kept for the next attempt of a request
+const MAX_NEXT_ATTEMPT_MESSAGES: usize = 64;
+
+/// Progress of one GG18 signing request.
+///
+/// Round messages are stored as they arrive, whatever round we are in ourselves, and
+/// `Signer` moves on once it has the messages of every other signer for the current round.
+/// Every `SIG_GEN_ATTEMPT_TIMEOUT` without a signature the next `t + 1` parties take over,
+/// so offline signers hold a request up for one attempt only.
 #[derive(Debug)]
-pub struct SigGenState {}
+pub struct SigGenState {
+	pub pk_id: Option<GeneratedKeyId>,
+	pub message: Option<Vec<u8>>,
+	pub signers: Vec<PeerIndex>,
+	pub sign_keys: Option<SignKeys>,
+	pub decommit: Option<SignDecommitPhase1>,
+	pub commits: BTreeMap<PeerIndex, (SignBroadcastPhase1, MessageA)>,
+	pub m_bs: BTreeMap<PeerIndex, (MessageB, MessageB)>,
+	pub betas: Vec<FE>,
+	pub nis: Vec<FE>,
+	pub sigma: Option<FE>,
+	pub deltas: BTreeMap<PeerIndex, FE>,
+	pub decommits: BTreeMap<PeerIndex, SignDecommitPhase1>,
+	pub local_sig: Option<LocalSignature>,
+	pub r: Option<GE>,
+	pub phase5a: BTreeMap<PeerIndex, (Phase5Com1, Phase5ADecom1, HomoELGamalProof)>,
+	pub phase5c: BTreeMap<PeerIndex, (Phase5Com2, Phase5DDecom2)>,
+	pub s_is: BTreeMap<PeerIndex, FE>,
+	pub complete: bool,
+	pub failed: bool,
+	pub attempt: u16,
+	pub next_attempt: Vec<SigGenMessage>,
+	pub started: Option<Instant>,
+	pub created: Instant,
+}
+
+impl SigGenState {
+	/// Signers of attempt `attempt`, the `threshold + 1` parties from index `attempt` on
+	pub fn signers_for_attempt(threshold: u16, players: u16, attempt: u16) -> Vec<PeerIndex> {
+		let mut signers = (0..=threshold)
+			.map(|k| ((attempt as u32 + k as u32) % players as u32) as PeerIndex)
+			.collect::<Vec<_>>();
+		signers.sort();
+		signers
+	}
+
+	pub fn start(&mut self, pk_id: GeneratedKeyId, message: Vec<u8>, threshold: u16, players: u16) {
+		if self.message.is_some() {
+			return;
+		}
+		self.pk_id = Some(pk_id);
+		self.message = Some(message);
+		self.started = Some(Instant::now());
+		self.signers = Self::signers_for_attempt(threshold, players, self.attempt);
+	}
+
+	/// The attempt every party should be at by now, `None` before we saw the request
+	pub fn due_attempt(&self) -> Option<u16> {
+		let elapsed = self.started?.elapsed().as_secs() / SIG_GEN_ATTEMPT_TIMEOUT.as_secs();
+		Some(elapsed.min(u16::max_value() as u64) as u16)
+	}
+
+	/// Give up on the current signers and start over with those of `attempt`
+	pub fn restart(&mut self, attempt: u16, threshold: u16, players: u16) {
+		let buffered = if attempt == self.attempt.wrapping_add(1) {
+			std::mem::replace(&mut self.next_attempt, Vec::new())
+		} else {
+			Vec::new()
+		};
+		*self = Self {
+			pk_id: self.pk_id,
+			message: self.message.take(),
+			signers: Self::signers_for_attempt(threshold, players, attempt),
+			attempt,
+			started: self.started,
+			created: self.created,
+			..Default::default()
+		};
+		for sgm in buffered {
+			self.receive(sgm);
+		}
+	}
+
+	/// Store a round message of `attempt`, messages of the next attempt are kept for later
+	pub fn receive_for(&mut self, attempt: u16, sgm: SigGenMessage) -> bool {
+		if attempt == self.attempt {
+			return self.receive(sgm);
+		}
+		if attempt == self.attempt.wrapping_add(1) && self.next_attempt.len() < MAX_NEXT_ATTEMPT_MESSAGES {
+			self.next_attempt.push(sgm);
+			return true;
+		}
+		false
+	}
+
+	/// Requests expire on chain, and messages of a request we never saw are kept one attempt only
+	pub fn is_expired(&self) -> bool {
+		match self.started {
+			Some(started) => started.elapsed() > SIG_GEN_EXPIRY,
+			None => self.created.elapsed() > SIG_GEN_ATTEMPT_TIMEOUT,
+		}
+	}
+
+	pub fn is_signer(&self, index: PeerIndex) -> bool {
+		self.signers.contains(&index)
+	}
+
+	/// Other signers, in the order every vector passed to the protocol uses
+	pub fn other_signers(&self, index: PeerIndex) -> Vec<PeerIndex> {
+		self.signers.iter().cloned().filter(|&i| i != index).collect()
+	}
+
+	/// Store a round message, `false` if we already had one from that signer
+	pub fn receive(&mut self, sgm: SigGenMessage) -> bool {
+		fn insert<V>(map: &mut BTreeMap<PeerIndex, V>, index: PeerIndex, value: V) -> bool {
+			if map.contains_key(&index) {
+				return false;
+			}
+			map.insert(index, value);
+			true
+		}
+
+		match sgm {
+			SigGenMessage::Round1(i, commit, m_a) => insert(&mut self.commits, i, (commit, m_a)),
+			SigGenMessage::Round2(i, m_b_gamma, m_b_w) => insert(&mut self.m_bs, i, (m_b_gamma, m_b_w)),
+			SigGenMessage::Round3(i, delta) => insert(&mut self.deltas, i, delta),
+			SigGenMessage::Round4(i, decommit) => insert(&mut self.decommits, i, decommit),
+			SigGenMessage::Round5(i, com, decom, proof) => insert(&mut self.phase5a, i, (com, decom, proof)),
+			SigGenMessage::Round6(i, com, decom) => insert(&mut self.phase5c, i, (com, decom)),
+			SigGenMessage::Round7(i, s_i) => insert(&mut self.s_is, i, s_i),
+		}
+	}
+
+	/// All signers except `index` have sent their message in `map`
+	pub fn has_all<V>(&self, map: &BTreeMap<PeerIndex, V>, index: PeerIndex) -> bool {
+		self.other_signers(index).iter().all(|i| map.contains_key(i))
+	}
+}
+
+impl Default for SigGenState {
+	fn default() -> Self {
+		Self {
+			pk_id: None,
+			message: None,
+			signers: Vec::new(),
+			sign_keys: None,
+			decommit: None,
+			commits: BTreeMap::new(),
+			m_bs: BTreeMap::new(),
+			betas: Vec::new(),
+			nis: Vec::new(),
+			sigma: None,
+			deltas: BTreeMap::new(),
+			decommits: BTreeMap::new(),
+			local_sig: None,
+			r: None,
+			phase5a: BTreeMap::new(),
+			phase5c: BTreeMap::new(),
+			s_is: BTreeMap::new(),
+			complete: false,
+			failed: false,
+			attempt: 0,
+			next_attempt: Vec::new(),
+			started: None,
+			created: Instant::now(),
+		}
+	}
+}
+
+/// Resharing of a key to the current peers.
+///
+/// Every member of the new committee says in its join which share of the old key it holds.
+/// Once all of them joined, the `t + 1` lowest old indices held deal a Lagrange weighted
+/// share of it, so any `t + 1` holders present move the key, and every member collects one
+/// dealt share from each of them. The new key is built in `Environment::keys` under the id
+/// of the reshare request, with `vsss` and `secret_shares` indexed by the old index of the
+/// dealer.
+#[derive(Debug, Default)]
+pub struct ReshareState {
+	pub old_id: Option<GeneratedKeyId>,
+	pub old_threshold: Option<u16>,
+	pub public_keys: BTreeMap<PeerIndex, GE>,
+	/// Old index of the share each member of the new committee holds
+	pub holders: BTreeMap<PeerIndex, PeerIndex>,
+	pub joined: bool,
+	pub dealt: bool,
+}
+
+impl ReshareState {
+	/// Old indices of the dealers, `None` until the old threshold is known and enough holders
+	/// joined. Only final once the whole new committee joined.
+	pub fn dealers(&self) -> Option<Vec<PeerIndex>> {
+		let old_threshold = self.old_threshold? as usize;
+		let held = self.holders.values().cloned().collect::<BTreeSet<_>>();
+		if held.len() <= old_threshold {
+			return None;
+		}
+		Some(held.into_iter().take(old_threshold + 1).collect())
+	}
+}
 
 pub(crate) struct Environment<B, E, Block: BlockT, RA, Storage> {
 	pub client: Arc<Client<B, E, Block, RA>>,
 	pub config: NodeConfig,
 	pub bridge: NetworkBridge<Block>,
-	pub state: Arc<RwLock<KeyGenState>>,
+	pub keys: Arc<RwLock<BTreeMap<GeneratedKeyId, KeyGenState>>>,
+	pub sig_states: Arc<RwLock<BTreeMap<RequestId, SigGenState>>>,
+	pub reshares: Arc<RwLock<BTreeMap<RequestId, ReshareState>>>,
 	pub offchain: Arc<RwLock<Storage>>,
 }
 
@@ -141,6 +365,7 @@ struct KeyGenWork<B, E, Block: BlockT, RA, Storage> {
 	key_gen: Pin<Box<dyn Future<Output = Result<(), Error>> + Send + Unpin>>,
 	env: Arc<Environment<B, E, Block, RA, Storage>>,
 	mpc_arg_rx: mpsc::UnboundedReceiver<MpcRequest>,
+	saved_keys: BTreeSet<GeneratedKeyId>,
 }
 
 impl<B, E, Block, RA, Storage> KeyGenWork<B, E, Block, RA, Storage>
@@ -159,13 +384,13 @@ where
 		offchain: Storage,
 		mpc_arg_rx: mpsc::UnboundedReceiver<MpcRequest>,
 	) -> Self {
-		let state = KeyGenState::default();
-
 		let env = Arc::new(Environment {
 			client,
 			config,
 			bridge,
-			state: Arc::new(RwLock::new(state)),
+			keys: Arc::new(RwLock::new(BTreeMap::new())),
+			sig_states: Arc::new(RwLock::new(BTreeMap::new())),
+			reshares: Arc::new(RwLock::new(BTreeMap::new())),
 			offchain: Arc::new(RwLock::new(offchain)),
 		});
 
@@ -173,6 +398,7 @@ where
 			key_gen: Box::pin(futures::future::pending()),
 			env,
 			mpc_arg_rx,
+			saved_keys: BTreeSet::new(),
 		};
 		work.rebuild(true);
 		work
@@ -191,9 +417,40 @@ where
 			MpcRequest::KeyGen(id) => {
 				self.env.bridge.start_key_gen(id);
 			}
-			_ => {}
+			MpcRequest::SigGen(req_id, pk_id, data) => {
+				self.load_key(pk_id);
+				// the rounds themselves are driven by `Signer`
+				let mut sig_states = self.env.sig_states.write();
+				let sig_state = sig_states.entry(req_id).or_default();
+				sig_state.start(pk_id, data, self.env.config.threshold, self.env.config.players);
+			}
+			MpcRequest::Reshare(req_id, pk_id) => {
+				// holders of the old key deal from it, everyone else only joins
+				self.load_key(pk_id);
+				let mut reshares = self.env.reshares.write();
+				reshares.entry(req_id).or_default().old_id = Some(pk_id);
+			}
+		}
+	}
+
+	/// Bring back a key generated before a restart
+	fn load_key(&mut self, id: GeneratedKeyId) {
+		let mut keys = self.env.keys.write();
+		if keys.contains_key(&id) {
+			return;
+		}
+
+		if let Some(state) = load_key_state(&*self.env.offchain.read(), id) {
+			keys.insert(id, state);
+			self.saved_keys.insert(id);
+			self.env.bridge.validator.inner.write().set_local_complete(id);
 		}
 	}
+
+	fn save_keys(&mut self) {
+		let keys = self.env.keys.read();
+		save_key_states(&keys, &mut self.saved_keys, &mut *self.env.offchain.write());
+	}
 }
 
 impl<B, E, Block, RA, Storage> Future for KeyGenWork<B, E, Block, RA, Storage>
@@ -224,30 +481,25 @@ where
 
 		match self.key_gen.poll_unpin(cx) {
 			Poll::Pending => {
+				self.save_keys();
 				{
-					let state = self.env.state.read();
+					let keys = self.env.keys.read();
 					let validator = self.env.bridge.validator.inner.read();
 
-					if state.complete {
-						let mut offchain_storage = self.env.offchain.write();
-
-						let lk = state.local_key.clone().unwrap();
-						let seed = bincode::serialize(&lk).unwrap();
-						offchain_storage.set(STORAGE_PREFIX, b"local_key", &seed);
+					for (id, state) in keys.iter().filter(|(_, state)| !state.complete) {
+						trace!(
+							"Index {:?} key {:?} state: commits {:?} decommits {:?} vss {:?} ss {:?}  proof {:?} has key {:?} peers hash {:?}",
+							validator.get_local_index(),
+							id,
+							state.commits.len(),
+							state.decommits.len(),
+							state.vsss.len(),
+							state.secret_shares.len(),
+							state.proofs.len(),
+							state.local_key.is_some(),
+							validator.get_peers_hash()
+						);
 					}
-
-					println!(
-						"INDEX {:?} state: commits {:?} decommits {:?} vss {:?} ss {:?}  proof {:?} has key {:?} complete {:?} peers hash {:?}",
-						validator.get_local_index(),
-						state.commits.len(),
-						state.decommits.len(),
-						state.vsss.len(),
-						state.secret_shares.len(),
-						state.proofs.len(),
-						state.local_key.is_some(),
-						state.complete,
-						validator.get_peers_hash()
-					);
 				};
 
 				return Poll::Pending;
@@ -325,25 +577,11 @@ where
 			.find_map(|l| match l {
 				ConsensusLog::RequestForSig(req_id, pk_id, data) => Some(MpcRequest::SigGen(req_id, pk_id, data)),
 				ConsensusLog::RequestForKey(id) => Some(MpcRequest::KeyGen(id)),
+				ConsensusLog::RequestForReshare(req_id, pk_id) => Some(MpcRequest::Reshare(req_id, pk_id)),
 			});
 
 		if let Some(arg) = arg {
-			match arg {
-				MpcRequest::SigGen(req_id, pk_id, mut data) => {
-					let req = MpcRequest::SigGen(req_id, pk_id, data.clone());
-					let _ = tx.unbounded_send(req);
-					if let Some(mut offchain_storage) = backend.offchain_storage() {
-						let key = get_storage_key(req_id, OffchainStorageType::Signature);
-						info!("key {:?} data {:?}", key, data);
-						let mut t = vec![1u8];
-						t.append(&mut data);
-						offchain_storage.set(STORAGE_PREFIX, &key, &t);
-					}
-				}
-				kg @ MpcRequest::KeyGen(_) => {
-					let _ = tx.unbounded_send(kg);
-				}
-			}
+			let _ = tx.unbounded_send(arg);
 		}
 
 		ready(())
@@ -1,5 +1,5 @@
 use std::{
-	collections::VecDeque,
+	collections::{BTreeMap, BTreeSet, VecDeque},
 	marker::{ Unpin}, //PhantomData
 	pin::Pin,
 	//str::FromStr,
@@ -10,16 +10,34 @@ use std::{
 use futures::prelude::{Future, Sink, Stream};
 use futures::stream::StreamExt;
 use futures::task::{Context, Poll};
-use log::{error, info};
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, Parameters};
+use curv::{
+	arithmetic::traits::Converter,
+	cryptographic_primitives::{
+		hashing::{hash_sha256::HSha256, traits::Hash},
+		proofs::sigma_dlog::DLogProof,
+		secret_sharing::feldman_vss::VerifiableSS,
+	},
+	elliptic::curves::traits::{ECPoint, ECScalar},
+	BigInt, FE, GE,
+};
+use log::{debug, error, info, trace};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::{
+	mta::{MessageA, MessageB},
+	party_i::{Keys, LocalSignature, Parameters, PartyPrivate, SharedKeys, SignKeys},
+};
 
 use sc_client_api::{backend::Backend,  CallExecutor};//BlockchainEvents
 use sc_network::PeerId;
 //use sc_network_gossip::Network;
 use sp_core::{offchain::OffchainStorage, Blake2Hasher, H256};
+use sp_mpc::{get_storage_key, GeneratedKeyId, OffchainStorageType};
+use sp_offchain::STORAGE_PREFIX;
 use sp_runtime::traits::Block as BlockT;
 
-use super::{ConfirmPeersMessage, Environment, Error, GossipMessage, KeyGenMessage, MessageWithSender, PeerIndex};
+use super::{
+	ConfirmPeersMessage, Environment, Error, GossipMessage, KeyGenMessage, KeyGenState, MessageWithSender, PeerIndex,
+	PeersHash, RequestId, ReshareMessage, ReshareState, SigGenMessage, SigGenState, MAX_SIG_STATES,
+};
 
 struct Buffered<Item, S>
 where
@@ -109,6 +127,307 @@ where
 	}
 }
 
+/// Messages of a signing round, each either broadcast or for a single signer
+type SigGenOutput = Vec<(SigGenMessage, Option<PeerIndex>)>;
+
+/// Run every GG18 signing round `sig_state` has the messages for.
+///
+/// Returns the messages to send and, once all seven rounds are done, the signature.
+fn sig_gen_rounds(
+	key_state: &KeyGenState,
+	sig_state: &mut SigGenState,
+	index: PeerIndex,
+) -> Result<(SigGenOutput, Option<Vec<u8>>), &'static str> {
+	let mut out = Vec::new();
+	let key = key_state.local_key.clone().ok_or("no local key")?;
+	let shared_keys = key_state.shared_keys.clone().ok_or("no shared key")?;
+	let others = sig_state.other_signers(index);
+	let s = sig_state.signers.iter().map(|&i| i as usize).collect::<Vec<_>>();
+
+	// round 1: commit to g^gamma_i and start MtA with k_i
+	if sig_state.sign_keys.is_none() {
+		// only the sharing parameters are used, which every vss of the key has
+		let vss = key_state.vsss.values().next().ok_or("no vss")?;
+		let private = PartyPrivate::set_private(key.clone(), shared_keys.clone());
+		let sign_keys = SignKeys::create(&private, vss, index as usize, &s);
+		let (commit, decommit) = sign_keys.phase1_broadcast();
+		let m_a = MessageA::a(&sign_keys.k_i, &key.ek);
+
+		sig_state.sign_keys = Some(sign_keys);
+		sig_state.decommit = Some(decommit);
+		out.push((SigGenMessage::Round1(index, commit, m_a), None));
+	}
+	let sign_keys = sig_state.sign_keys.clone().unwrap();
+
+	// round 2: answer the MtA of every other signer with gamma_i and w_i
+	if sig_state.betas.is_empty() && sig_state.has_all(&sig_state.commits, index) {
+		for &j in others.iter() {
+			let ek = &key_state.commits.get(&j).ok_or("no keygen commit of signer")?.e;
+			let m_a = sig_state.commits[&j].1.clone();
+			let (m_b_gamma, beta_gamma) = MessageB::b(&sign_keys.gamma_i, ek, m_a.clone());
+			let (m_b_w, beta_w) = MessageB::b(&sign_keys.w_i, ek, m_a);
+
+			sig_state.betas.push(beta_gamma);
+			sig_state.nis.push(beta_w);
+			out.push((SigGenMessage::Round2(index, m_b_gamma, m_b_w), Some(j)));
+		}
+	}
+
+	// round 3: finish MtA and broadcast delta_i
+	if sig_state.sigma.is_none() && !sig_state.betas.is_empty() && sig_state.has_all(&sig_state.m_bs, index) {
+		let vss = key_state.vsss.values().next().ok_or("no vss")?;
+		let mut alphas = Vec::new();
+		let mut mius = Vec::new();
+		for &j in others.iter() {
+			let (m_b_gamma, m_b_w) = &sig_state.m_bs[&j];
+			let alpha = m_b_gamma
+				.verify_proofs_get_alpha(&key.dk, &sign_keys.k_i)
+				.map_err(|_| "bad MtA answer for gamma")?;
+			let miu = m_b_w
+				.verify_proofs_get_alpha(&key.dk, &sign_keys.k_i)
+				.map_err(|_| "bad MtA answer for w")?;
+			// w_j is public through the keygen vss, gamma_j is checked in round 4
+			let xi_commitment = key_state.point_commitment(j).ok_or("no vss")?;
+			let g_w_j = Keys::update_commitments_to_xi(&xi_commitment, vss, j as usize, &s);
+			if m_b_w.b_proof.pk != g_w_j {
+				return Err("MtA answer for w does not use w_j");
+			}
+			alphas.push(alpha);
+			mius.push(miu);
+		}
+		let delta = sign_keys.phase2_delta_i(&alphas, &sig_state.betas);
+		sig_state.sigma = Some(sign_keys.phase2_sigma_i(&mius, &sig_state.nis));
+		sig_state.deltas.insert(index, delta);
+		out.push((SigGenMessage::Round3(index, delta), None));
+	}
+
+	// round 4: open the round 1 commitment
+	if sig_state.sigma.is_some()
+		&& !sig_state.decommits.contains_key(&index)
+		&& sig_state.has_all(&sig_state.deltas, index)
+	{
+		let decommit = sig_state.decommit.clone().unwrap();
+		sig_state.decommits.insert(index, decommit.clone());
+		out.push((SigGenMessage::Round4(index, decommit), None));
+	}
+
+	// round 5: compute R and the local signature, commit to it
+	if sig_state.local_sig.is_none()
+		&& sig_state.decommits.contains_key(&index)
+		&& sig_state.has_all(&sig_state.decommits, index)
+	{
+		let message = sig_state.message.clone().unwrap();
+		let deltas = sig_state.signers.iter().map(|i| sig_state.deltas[i]).collect::<Vec<_>>();
+		let delta_inv = SignKeys::phase3_reconstruct_delta(&deltas);
+		let b_proofs = others.iter().map(|j| &sig_state.m_bs[j].0.b_proof).collect::<Vec<&DLogProof>>();
+		let decommits = others.iter().map(|j| sig_state.decommits[j].clone()).collect::<Vec<_>>();
+		let commits = others.iter().map(|j| sig_state.commits[j].0.clone()).collect::<Vec<_>>();
+		let r = SignKeys::phase4(&delta_inv, &b_proofs, decommits, &commits).map_err(|_| "bad gamma_i decommit")?;
+		let r = r + sig_state.decommits[&index].g_gamma_i * &delta_inv;
+
+		let message_bn = HSha256::create_hash(&[&BigInt::from(&message[..])]);
+		let local_sig =
+			LocalSignature::phase5_local_sig(&sign_keys.k_i, &message_bn, &r, &sig_state.sigma.unwrap(), &shared_keys.y);
+		let (com, decom, proof) = local_sig.phase5a_broadcast_5b_zkproof();
+
+		sig_state.local_sig = Some(local_sig);
+		sig_state.r = Some(r);
+		sig_state.phase5a.insert(index, (com.clone(), decom.clone(), proof.clone()));
+		out.push((SigGenMessage::Round5(index, com, decom, proof), None));
+	}
+
+	// round 6: check the others' local signatures
+	if sig_state.local_sig.is_some()
+		&& !sig_state.phase5c.contains_key(&index)
+		&& sig_state.has_all(&sig_state.phase5a, index)
+	{
+		let local_sig = sig_state.local_sig.as_ref().unwrap();
+		let coms = others.iter().map(|j| sig_state.phase5a[j].0.clone()).collect::<Vec<_>>();
+		let decoms = others.iter().map(|j| sig_state.phase5a[j].1.clone()).collect::<Vec<_>>();
+		let proofs = others.iter().map(|j| sig_state.phase5a[j].2.clone()).collect::<Vec<_>>();
+		let v_i = sig_state.phase5a[&index].1.V_i;
+		let (com, decom) = local_sig
+			.phase5c(&decoms, &coms, &proofs, &v_i, &sig_state.r.unwrap())
+			.map_err(|_| "bad phase 5a decommit")?;
+
+		sig_state.phase5c.insert(index, (com.clone(), decom.clone()));
+		out.push((SigGenMessage::Round6(index, com, decom), None));
+	}
+
+	// round 7: release s_i
+	if sig_state.phase5c.contains_key(&index)
+		&& !sig_state.s_is.contains_key(&index)
+		&& sig_state.has_all(&sig_state.phase5c, index)
+	{
+		let local_sig = sig_state.local_sig.as_ref().unwrap();
+		let coms = sig_state.signers.iter().map(|i| sig_state.phase5c[i].0.clone()).collect::<Vec<_>>();
+		let decoms = sig_state.signers.iter().map(|i| sig_state.phase5c[i].1.clone()).collect::<Vec<_>>();
+		let decoms_5a = sig_state.signers.iter().map(|i| sig_state.phase5a[i].1.clone()).collect::<Vec<_>>();
+		let s_i = local_sig.phase5d(&decoms, &coms, &decoms_5a).map_err(|_| "bad phase 5c decommit")?;
+
+		sig_state.s_is.insert(index, s_i);
+		out.push((SigGenMessage::Round7(index, s_i), None));
+	}
+
+	if !sig_state.complete && sig_state.s_is.contains_key(&index) && sig_state.has_all(&sig_state.s_is, index) {
+		let local_sig = sig_state.local_sig.as_ref().unwrap();
+		let s_is = others.iter().map(|j| sig_state.s_is[j]).collect::<Vec<_>>();
+		let sig = local_sig.output_signature(&s_is).map_err(|_| "signature does not verify")?;
+		sig_state.complete = true;
+		return Ok((out, Some(encode_signature(&sig.r, &sig.s))));
+	}
+
+	Ok((out, None))
+}
+
+/// ECDSA signature as 32 byte big endian `r` followed by `s`
+fn encode_signature(r: &FE, s: &FE) -> Vec<u8> {
+	let mut encoded = Vec::with_capacity(64);
+	for x in [r, s].iter() {
+		let bytes = BigInt::to_vec(&x.to_big_int());
+		encoded.extend(std::iter::repeat(0u8).take(32 - bytes.len()));
+		encoded.extend(bytes);
+	}
+	encoded
+}
+
+/// Old index and threshold of the share of a complete key we hold
+fn held_share(old: &KeyGenState) -> Option<(PeerIndex, u16)> {
+	if !old.complete || old.shared_keys.is_none() {
+		return None;
+	}
+	let key = old.local_key.as_ref()?;
+	let vss = old.vsss.values().next()?;
+	Some((key.party_index as PeerIndex, vss.parameters.threshold as u16))
+}
+
+/// Deal the share of `old` to a (`threshold`, `players`) committee.
+///
+/// The Lagrange weighted shares of `dealers`, old indices of t + 1 holders of `old`, add up
+/// to the secret key. Returns the dealing to broadcast and the share of every new member.
+fn deal_share(
+	old: &KeyGenState,
+	dealers: &[PeerIndex],
+	threshold: u16,
+	players: u16,
+) -> Option<(ReshareMessage, Vec<FE>)> {
+	let key = old.local_key.as_ref()?;
+	let shared_keys = old.shared_keys.as_ref()?;
+	let vss = old.vsss.values().next()?;
+	let old_threshold = vss.parameters.threshold as u16;
+
+	let dealers = dealers.iter().map(|&i| i as usize).collect::<Vec<_>>();
+	if dealers.len() != old_threshold as usize + 1 || !dealers.contains(&key.party_index) {
+		return None;
+	}
+
+	let w_i = vss.map_share_to_new_params(key.party_index, &dealers) * shared_keys.x_i;
+	let (vss, shares) = VerifiableSS::share(threshold as usize, players as usize, &w_i);
+	let deal = ReshareMessage::Deal(key.party_index as PeerIndex, old_threshold, shared_keys.y, vss);
+	Some((deal, shares))
+}
+
+/// Store a reshare message, the first one of each kind from a party counts
+fn receive_reshare(reshare: &mut ReshareState, state: &mut KeyGenState, rsm: ReshareMessage) {
+	match rsm {
+		ReshareMessage::Join(i, commit, held) => {
+			if state.commits.contains_key(&i) {
+				return;
+			}
+			state.commits.insert(i, commit);
+			if let Some(old_index) = held {
+				reshare.holders.insert(i, old_index);
+			}
+		}
+		ReshareMessage::Deal(i, old_threshold, y, vss) => {
+			if *reshare.old_threshold.get_or_insert(old_threshold) != old_threshold {
+				return;
+			}
+			reshare.public_keys.entry(i).or_insert(y);
+			state.vsss.entry(i).or_insert(vss);
+		}
+		ReshareMessage::Share(i, share) => {
+			state.secret_shares.entry(i).or_insert(share);
+		}
+	}
+}
+
+/// Combine the shares dealt to us into our share of the reshared key.
+///
+/// The dealings must all be for the new committee and commit to secrets adding up to the
+/// secret key of the public key the dealers claim, so the public key stays the same.
+fn finish_reshare(
+	state: &mut KeyGenState,
+	public_keys: &BTreeMap<PeerIndex, GE>,
+	dealers: &[PeerIndex],
+	params: &Parameters,
+	index: PeerIndex,
+) -> Result<(), &'static str> {
+	// anything from outside the dealers is not part of the key
+	state.vsss.retain(|i, _| dealers.contains(i));
+	state.secret_shares.retain(|i, _| dealers.contains(i));
+
+	let y = *public_keys.get(&dealers[0]).ok_or("no dealing")?;
+	let mut x_i: Option<FE> = None;
+	let mut y_sum: Option<GE> = None;
+	for i in dealers.iter() {
+		if public_keys.get(i) != Some(&y) {
+			return Err("dealers disagree on the public key");
+		}
+		let vss = state.vsss.get(i).ok_or("no dealing")?;
+		let share = *state.secret_shares.get(i).ok_or("no dealt share")?;
+		if vss.parameters.threshold != params.threshold as usize
+			|| vss.parameters.share_count != params.share_count as usize
+		{
+			return Err("dealing for another committee");
+		}
+		vss.validate_share(&share, index as usize + 1).map_err(|_| "invalid dealt share")?;
+
+		x_i = Some(x_i.map_or(share, |x| x + share));
+		y_sum = Some(y_sum.map_or(vss.commitments[0], |sum| sum + vss.commitments[0]));
+	}
+	if y_sum != Some(y) {
+		return Err("dealings do not add up to the public key");
+	}
+
+	state.shared_keys = Some(SharedKeys { y, x_i: x_i.unwrap() });
+	Ok(())
+}
+
+/// Store every newly completed key under its own id, the local part and the shared public key
+pub(crate) fn save_key_states<S: OffchainStorage>(
+	keys: &BTreeMap<GeneratedKeyId, KeyGenState>,
+	saved: &mut BTreeSet<GeneratedKeyId>,
+	storage: &mut S,
+) {
+	for (&id, state) in keys.iter() {
+		if !state.complete || saved.contains(&id) {
+			continue;
+		}
+
+		let local_key = bincode::serialize(state).unwrap();
+		storage.set(
+			STORAGE_PREFIX,
+			&get_storage_key(id, OffchainStorageType::LocalSecretKey),
+			&local_key,
+		);
+		if let Some(pk) = state.shared_public_key() {
+			storage.set(
+				STORAGE_PREFIX,
+				&get_storage_key(id, OffchainStorageType::SharedPublicKey),
+				&pk.get_element().serialize(),
+			);
+		}
+		saved.insert(id);
+	}
+}
+
+/// The key `id` as stored by `save_key_states`
+pub(crate) fn load_key_state<S: OffchainStorage>(storage: &S, id: GeneratedKeyId) -> Option<KeyGenState> {
+	let stored = storage.get(STORAGE_PREFIX, &get_storage_key(id, OffchainStorageType::LocalSecretKey))?;
+	bincode::deserialize(&stored).ok()
+}
+
 pub(crate) struct Signer<B, E, Block: BlockT, RA, In, Out, Storage>
 where
 	In: Stream<Item = MessageWithSender>,
@@ -148,8 +467,16 @@ where
 	}
 
 	fn generate_shared_keys(&mut self) {
+		let ids = self.env.keys.read().keys().cloned().collect::<Vec<_>>();
+		for id in ids {
+			self.generate_shared_key(id);
+		}
+	}
+
+	fn generate_shared_key(&mut self, id: RequestId) {
 		let players = self.env.config.players as usize;
-		let mut state = self.env.state.write();
+		let mut keys = self.env.keys.write();
+		let state = keys.get_mut(&id).unwrap();
 		if state.complete
 			|| state.shared_keys.is_some()
 			|| state.vsss.len() != players
@@ -190,17 +517,196 @@ where
 		state.proofs.insert(i, proof.clone());
 		state.shared_keys = Some(shared_keys);
 
-		drop(state);
+		drop(keys);
 
 		println!("{:?} CREATE PROOF OK", key.party_index);
 
 		let proof_msg = KeyGenMessage::Proof(i, proof);
 		let validator = self.env.bridge.validator.inner.read();
 		let hash = validator.get_peers_hash();
-		self.global_out.push((GossipMessage::KeyGen(proof_msg, hash), None));
+		self.global_out.push((GossipMessage::KeyGen(proof_msg, id, hash), None));
+	}
+
+	fn generate_signatures(&mut self) {
+		let threshold = self.env.config.threshold;
+		let players = self.env.config.players;
+		let keys = self.env.keys.read();
+		let validator = self.env.bridge.validator.inner.read();
+		let index = validator.get_local_index() as PeerIndex;
+
+		let mut sig_states = self.env.sig_states.write();
+		sig_states.retain(|req_id, sig_state| {
+			let pending = !sig_state.complete && !sig_state.is_expired();
+			if !pending {
+				debug!("Dropping signature generation of {:?}", req_id);
+			}
+			pending
+		});
+		for (&req_id, sig_state) in sig_states.iter_mut() {
+			if let Some(attempt) = sig_state.due_attempt().filter(|&attempt| attempt > sig_state.attempt) {
+				info!("Signature generation of {:?} timed out, retrying with other signers", req_id);
+				sig_state.restart(attempt, threshold, players);
+			}
+			if sig_state.failed || !sig_state.is_signer(index) {
+				continue;
+			}
+			let key_state = match sig_state.pk_id.and_then(|pk_id| keys.get(&pk_id)) {
+				Some(key_state) if key_state.complete => key_state,
+				_ => continue,
+			};
+
+			match sig_gen_rounds(key_state, sig_state, index) {
+				Ok((msgs, sig)) => {
+					for (sgm, to) in msgs {
+						let receiver = to.and_then(|i| validator.get_peer_id_by_index(i as usize));
+						self.global_out.push((GossipMessage::SigGen(sgm, req_id, sig_state.attempt), receiver));
+					}
+					if let Some(sig) = sig {
+						info!("Signature generation of {:?} complete", req_id);
+						let key = get_storage_key(req_id, OffchainStorageType::Signature);
+						self.env.offchain.write().set(STORAGE_PREFIX, &key, &sig);
+					}
+				}
+				Err(e) => {
+					// the next attempt goes without us if we are to blame
+					error!("Signature generation of {:?} failed: {:?}", req_id, e);
+					sig_state.failed = true;
+				}
+			}
+		}
+	}
+
+	fn generate_reshares(&mut self) {
+		let ids = self.env.reshares.read().keys().cloned().collect::<Vec<_>>();
+		for id in ids {
+			self.generate_reshare(id);
+		}
+	}
+
+	fn generate_reshare(&mut self, id: RequestId) {
+		let params = self.env.config.get_params();
+		let players = self.env.config.players;
+		let mut reshares = self.env.reshares.write();
+		let reshare = reshares.get_mut(&id).unwrap();
+		let old_id = match reshare.old_id {
+			Some(old_id) => old_id,
+			// messages arrived before we saw the request
+			None => return,
+		};
+
+		let mut keys = self.env.keys.write();
+		let mut validator = self.env.bridge.validator.inner.write();
+		if validator.is_local_complete(id) || validator.is_local_canceled(id) {
+			return;
+		}
+		let index = validator.get_local_index() as PeerIndex;
+		let hash = validator.get_peers_hash();
+
+		if reshare.joined && validator.is_local_awaiting_peers(id) {
+			// canceled on a disconnect and retried since, start over
+			*reshare = ReshareState {
+				old_id: Some(old_id),
+				..Default::default()
+			};
+			keys.remove(&id);
+		}
+
+		// every member of the new committee needs its own paillier key to sign with, and
+		// tells which share of the old key it holds
+		if !reshare.joined {
+			let held = keys.get(&old_id).and_then(held_share);
+			let key = Keys::create(index as usize);
+			let (commit, _) = key.phase1_broadcast_phase3_proof_of_correct_key();
+			let join = ReshareMessage::Join(index, commit, held.map(|(old_index, _)| old_index));
+			if let Some((_, old_threshold)) = held {
+				reshare.old_threshold = Some(old_threshold);
+			}
+			let state = keys.entry(id).or_default();
+			receive_reshare(reshare, state, join.clone());
+			state.local_key = Some(key);
+			reshare.joined = true;
+			validator.set_local_generating(id);
+			self.global_out.push((GossipMessage::Reshare(join, id, hash), None));
+		}
+
+		// the dealers are settled once the whole new committee joined
+		let dealers = match keys.get(&id) {
+			Some(state) if state.commits.len() == players as usize => reshare.dealers(),
+			_ => None,
+		};
+		let dealers = match dealers {
+			Some(dealers) => dealers,
+			None => return,
+		};
+
+		if !reshare.dealt {
+			reshare.dealt = true;
+			let dealing = keys
+				.get(&old_id)
+				.filter(|old| old.complete)
+				.and_then(|old| deal_share(old, &dealers, params.threshold, players));
+			if let Some((deal, shares)) = dealing {
+				let old_index = deal.get_index();
+				let state = keys.entry(id).or_default();
+				receive_reshare(reshare, state, deal.clone());
+				receive_reshare(reshare, state, ReshareMessage::Share(old_index, shares[index as usize]));
+				self.global_out.push((GossipMessage::Reshare(deal, id, hash), None));
+
+				for (i, &share) in shares.iter().enumerate() {
+					if i == index as usize {
+						continue;
+					}
+					// never broadcast a share
+					if let Some(peer) = validator.get_peer_id_by_index(i) {
+						let share_msg = ReshareMessage::Share(old_index, share);
+						self.global_out.push((GossipMessage::Reshare(share_msg, id, hash), Some(peer)));
+					}
+				}
+			}
+		}
+
+		let state = match keys.get_mut(&id) {
+			Some(state) => state,
+			None => return,
+		};
+		if state.complete || !dealers.iter().all(|i| state.vsss.contains_key(i) && state.secret_shares.contains_key(i)) {
+			return;
+		}
+
+		match finish_reshare(state, &reshare.public_keys, &dealers, &params, index) {
+			Ok(()) => {
+				info!("Reshare of key {:?} into {:?} complete", old_id, id);
+				state.complete = true;
+				validator.set_local_complete(id);
+			}
+			Err(e) => {
+				error!("Reshare of key {:?} into {:?} failed: {:?}", old_id, id, e);
+				state.reset();
+				validator.set_local_canceled(id);
+			}
+		}
 	}
 
-	fn handle_cpm(&mut self, cpm: ConfirmPeersMessage, sender: PeerId, all_peers_hash: u64) -> bool {
+	fn handle_rsm(&mut self, rsm: ReshareMessage, id: RequestId) -> bool {
+		let mut reshares = self.env.reshares.write();
+		let mut keys = self.env.keys.write();
+		// the request may reach other parties first, keep their messages until we see the log
+		receive_reshare(reshares.entry(id).or_default(), keys.entry(id).or_default(), rsm);
+		true
+	}
+
+	fn handle_sgm(&mut self, sgm: SigGenMessage, req_id: RequestId, attempt: u16) -> bool {
+		let mut sig_states = self.env.sig_states.write();
+		if !sig_states.contains_key(&req_id) && sig_states.len() >= MAX_SIG_STATES {
+			debug!("Too many signing requests, dropping message of {:?}", req_id);
+			return true;
+		}
+		// requests may reach other signers first, keep their messages until we see the log
+		sig_states.entry(req_id).or_default().receive_for(attempt, sgm);
+		true
+	}
+
+	fn handle_cpm(&mut self, cpm: ConfirmPeersMessage, id: RequestId, sender: PeerId, all_peers_hash: PeersHash) -> bool {
 		let players = self.env.config.players;
 
 		match cpm {
@@ -223,6 +729,7 @@ where
 				self.global_out.push((
 					GossipMessage::ConfirmPeers(
 						ConfirmPeersMessage::Confirmed(validator.local_string_peer_id()),
+						id,
 						all_peers_hash,
 					),
 					Some(sender),
@@ -242,14 +749,14 @@ where
 				// }
 
 				{
-					let state = self.env.state.read();
-					if state.local_key.is_some() {
+					let keys = self.env.keys.read();
+					if keys.get(&id).map_or(false, |state| state.local_key.is_some()) {
 						return true;
 					}
 				}
 
 				let mut validator = self.env.bridge.validator.inner.write();
-				validator.set_peer_generating(&sender);
+				validator.set_peer_generating(&sender, id);
 
 				if validator.get_peers_len() == players as usize {
 					let local_index = validator.get_local_index();
@@ -259,19 +766,20 @@ where
 					let index = local_index as PeerIndex;
 
 					{
-						let mut state = self.env.state.write();
+						let mut keys = self.env.keys.write();
+						let state = keys.entry(id).or_default();
 						state.commits.insert(index, commit.clone());
 						state.decommits.insert(index, decommit.clone());
 						state.local_key = Some(key);
-						validator.set_local_generating();
-						println!("LOCAL KEY GEN OF {:?}", index);
+						validator.set_local_generating(id);
+						debug!("Local key generation of {:?} for {:?}", index, id);
 						drop(validator);
 					}
 
 					let cad_msg = KeyGenMessage::CommitAndDecommit(index, commit, decommit);
 					self.global_out.push(
 						// broadcast
-						(GossipMessage::KeyGen(cad_msg, all_peers_hash), None),
+						(GossipMessage::KeyGen(cad_msg, id, all_peers_hash), None),
 					);
 				}
 			}
@@ -279,13 +787,15 @@ where
 		true
 	}
 
-	fn handle_kgm(&mut self, kgm: KeyGenMessage, all_peers_hash: u64) -> bool {
+	fn handle_kgm(&mut self, kgm: KeyGenMessage, id: RequestId, all_peers_hash: PeersHash) -> bool {
 		let players = self.env.config.players;
+		// messages of a session may arrive before we confirmed it ourselves
+		let mut keys = self.env.keys.write();
+		let state = keys.entry(id).or_default();
 
 		match kgm {
 			KeyGenMessage::CommitAndDecommit(from_index, commit, decommit) => {
 				println!("CAD MSG from {:?}", from_index);
-				let mut state = self.env.state.write();
 				if state.local_key.is_none() {
 					return false;
 				}
@@ -326,10 +836,10 @@ where
 					state.vsss.insert(index as PeerIndex, vss.clone());
 					state.secret_shares.insert(index as PeerIndex, share);
 
-					drop(state);
+					drop(keys);
 
 					self.global_out.push((
-						GossipMessage::KeyGen(KeyGenMessage::VSS(index as PeerIndex, vss), all_peers_hash),
+						GossipMessage::KeyGen(KeyGenMessage::VSS(index as PeerIndex, vss), id, all_peers_hash),
 						None,
 					));
 
@@ -340,13 +850,12 @@ where
 							let ss_msg = KeyGenMessage::SecretShare(index as PeerIndex, ss);
 							let peer = validator.get_peer_id_by_index(i);
 							self.global_out
-								.push((GossipMessage::KeyGen(ss_msg, all_peers_hash), peer));
+								.push((GossipMessage::KeyGen(ss_msg, id, all_peers_hash), peer));
 						}
 					}
 				}
 			}
 			KeyGenMessage::VSS(from_index, vss) => {
-				let mut state = self.env.state.write();
 				if state.vsss.contains_key(&from_index) {
 					return true;
 				}
@@ -354,7 +863,6 @@ where
 				state.vsss.insert(from_index, vss.clone());
 			}
 			KeyGenMessage::SecretShare(from_index, ss) => {
-				let mut state = self.env.state.write();
 				if state.secret_shares.contains_key(&from_index) {
 					return true;
 				}
@@ -362,7 +870,6 @@ where
 				state.secret_shares.insert(from_index, ss.clone());
 			}
 			KeyGenMessage::Proof(from_index, proof) => {
-				let mut state = self.env.state.write();
 				println!("RECV PROOF from {:?}", from_index);
 
 				state.proofs.insert(from_index, proof.clone());
@@ -382,12 +889,12 @@ where
 						info!("Key generation complete");
 						println!("key gen complete");
 						state.complete = true;
-						validator.set_local_complete();
+						validator.set_local_complete(id);
 					} else {
 						// reset everything?
 						error!("Key generation failed");
 						state.reset();
-						validator.set_local_canceled();
+						validator.set_local_canceled(id);
 					}
 				}
 			}
@@ -397,31 +904,45 @@ where
 
 	fn handle_incoming(&mut self, msg: GossipMessage, sender: Option<PeerId>) -> bool {
 		match msg {
-			GossipMessage::ConfirmPeers(cpm, all_peers_hash) => {
+			GossipMessage::ConfirmPeers(cpm, id, all_peers_hash) => {
 				let validator = self.env.bridge.validator.inner.read();
 				let _our_hash = validator.get_peers_hash();
 
-				println!("cpm msg local state {:?}", validator.local_state());
+				trace!("Confirm peers message, local state {:?}", validator.local_state(id));
 
 				if sender.is_none() {
 					return false;
 				}
 				drop(validator);
-				return self.handle_cpm(cpm, sender.unwrap(), all_peers_hash);
+				return self.handle_cpm(cpm, id, sender.unwrap(), all_peers_hash);
 			}
-			GossipMessage::KeyGen(kgm, all_peers_hash) => {
+			GossipMessage::KeyGen(kgm, id, all_peers_hash) => {
 				println!("recv key gen msg");
 				let validator = self.env.bridge.validator.inner.read();
-				println!("kgm local state {:?}", validator.local_state());
+				trace!("Key generation message, local state {:?}", validator.local_state(id));
+
+				if validator.is_local_complete(id) || validator.is_local_canceled(id) {
+					return true;
+				}
 
-				if validator.is_local_complete() || validator.is_local_canceled() {
+				drop(validator);
+				return self.handle_kgm(kgm, id, all_peers_hash);
+			}
+			GossipMessage::SigGen(sgm, req_id, attempt) => {
+				return self.handle_sgm(sgm, req_id, attempt);
+			}
+			GossipMessage::Reshare(rsm, id, all_peers_hash) => {
+				let validator = self.env.bridge.validator.inner.read();
+				if validator.is_local_complete(id)
+					|| validator.is_local_canceled(id)
+					|| validator.get_peers_hash() != all_peers_hash
+				{
 					return true;
 				}
 
 				drop(validator);
-				return self.handle_kgm(kgm, all_peers_hash);
+				return self.handle_rsm(rsm, id);
 			}
-			GossipMessage::SigGen(_, _) => {}
 		}
 
 		true
@@ -460,6 +981,8 @@ where
 		}
 
 		self.generate_shared_keys();
+		self.generate_signatures();
+		self.generate_reshares();
 
 		// send all messages generated above
 
@@ -475,3 +998,308 @@ where
 		Poll::Pending
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_core::offchain::storage::InMemOffchainStorage;
+
+	fn key_states(threshold: u16, players: u16) -> Vec<KeyGenState> {
+		let params = Parameters {
+			threshold,
+			share_count: players,
+		};
+		let keys = (0..players as usize).map(Keys::create).collect::<Vec<_>>();
+		let (commits, decommits): (Vec<_>, Vec<_>) =
+			keys.iter().map(|k| k.phase1_broadcast_phase3_proof_of_correct_key()).unzip();
+
+		let mut vsss = Vec::new();
+		let mut secret_shares = Vec::new();
+		for key in keys.iter() {
+			let (vss, shares, _) = key
+				.phase1_verify_com_phase3_verify_correct_key_phase2_distribute(&params, &decommits, &commits)
+				.unwrap();
+			vsss.push(vss);
+			secret_shares.push(shares);
+		}
+		let points = decommits.iter().map(|x| x.y_i).collect::<Vec<_>>();
+
+		keys.into_iter()
+			.enumerate()
+			.map(|(i, key)| {
+				let shares = secret_shares.iter().map(|ss| ss[i]).collect::<Vec<_>>();
+				let (shared_keys, _) = key
+					.phase2_verify_vss_construct_keypair_phase3_pok_dlog(&params, &points, &shares, &vsss, i + 1)
+					.unwrap();
+
+				let mut state = KeyGenState::default();
+				for j in 0..players as usize {
+					let index = j as PeerIndex;
+					state.commits.insert(index, commits[j].clone());
+					state.decommits.insert(index, decommits[j].clone());
+					state.vsss.insert(index, vsss[j].clone());
+				}
+				state.local_key = Some(key);
+				state.shared_keys = Some(shared_keys);
+				state.complete = true;
+				state
+			})
+			.collect()
+	}
+
+	/// Reshare to a fresh (threshold, holders.len()) committee, its member j holding `holders[j]`
+	/// of the old key if any
+	fn reshare(holders: &[Option<&KeyGenState>], threshold: u16) -> Vec<Result<KeyGenState, &'static str>> {
+		let players = holders.len() as u16;
+		let params = Parameters {
+			threshold,
+			share_count: players,
+		};
+		let keys = (0..players as usize).map(Keys::create).collect::<Vec<_>>();
+		let joins = keys
+			.iter()
+			.zip(holders.iter())
+			.enumerate()
+			.map(|(j, (key, old))| {
+				let commit = key.phase1_broadcast_phase3_proof_of_correct_key().0;
+				let held = old.and_then(held_share).map(|(old_index, _)| old_index);
+				ReshareMessage::Join(j as PeerIndex, commit, held)
+			})
+			.collect::<Vec<_>>();
+
+		let mut reshares = holders
+			.iter()
+			.map(|old| ReshareState {
+				old_threshold: old.and_then(held_share).map(|(_, old_threshold)| old_threshold),
+				..Default::default()
+			})
+			.collect::<Vec<_>>();
+		let mut states = (0..players).map(|_| KeyGenState::default()).collect::<Vec<_>>();
+		for (reshare, state) in reshares.iter_mut().zip(states.iter_mut()) {
+			for join in joins.iter() {
+				receive_reshare(reshare, state, join.clone());
+			}
+		}
+		let dealings = holders
+			.iter()
+			.zip(reshares.iter())
+			.filter_map(|(old, reshare)| deal_share((*old)?, &reshare.dealers()?, threshold, players))
+			.collect::<Vec<_>>();
+
+		keys.into_iter()
+			.zip(reshares.into_iter().zip(states.into_iter()))
+			.enumerate()
+			.map(|(j, (key, (mut reshare, mut state)))| {
+				for (deal, shares) in dealings.iter() {
+					receive_reshare(&mut reshare, &mut state, deal.clone());
+					receive_reshare(&mut reshare, &mut state, ReshareMessage::Share(deal.get_index(), shares[j]));
+				}
+				state.local_key = Some(key);
+
+				let dealers = reshare.dealers().ok_or("no dealers")?;
+				finish_reshare(&mut state, &reshare.public_keys, &dealers, &params, j as PeerIndex)?;
+				state.complete = true;
+				Ok(state)
+			})
+			.collect()
+	}
+
+	/// Run the signing rounds between the signers of `attempt`, returns what each of them got
+	fn sign(key_states: &[KeyGenState], threshold: u16, attempt: u16) -> Vec<Option<Vec<u8>>> {
+		let players = key_states.len() as u16;
+		let signers = SigGenState::signers_for_attempt(threshold, players, attempt);
+		let mut sig_states = signers
+			.iter()
+			.map(|_| {
+				let mut state = SigGenState::default();
+				state.start(0, b"message".to_vec(), threshold, players);
+				if attempt > 0 {
+					state.restart(attempt, threshold, players);
+				}
+				state
+			})
+			.collect::<Vec<_>>();
+		let mut sigs = vec![None; signers.len()];
+
+		// every iteration is one network round trip
+		for _ in 0..10 {
+			let mut sent = Vec::new();
+			for (k, state) in sig_states.iter_mut().enumerate() {
+				if state.complete {
+					continue;
+				}
+				let i = signers[k];
+				let (msgs, sig) = sig_gen_rounds(&key_states[i as usize], state, i).unwrap();
+				if sig.is_some() {
+					sigs[k] = sig;
+				}
+				sent.extend(msgs.into_iter().map(|(msg, to)| (i, msg, to)));
+			}
+			for (from, msg, to) in sent {
+				for (k, state) in sig_states.iter_mut().enumerate() {
+					let j = signers[k];
+					if j != from && to.map_or(true, |to| to == j) {
+						state.receive(msg.clone());
+					}
+				}
+			}
+		}
+
+		sigs
+	}
+
+	#[test]
+	fn test_sig_gen_rounds() {
+		let key_states = key_states(1, 3);
+		let sigs = sign(&key_states, 1, 0);
+
+		assert!(sigs[0].is_some());
+		assert_eq!(sigs[0].as_ref().unwrap().len(), 64);
+		assert_eq!(sigs[0], sigs[1]);
+	}
+
+	/// Save the keys of every party into its own storage
+	fn save_all(
+		keys: &[BTreeMap<GeneratedKeyId, KeyGenState>],
+		saved: &mut [BTreeSet<GeneratedKeyId>],
+		storages: &mut [InMemOffchainStorage],
+	) {
+		for (party, keys) in keys.iter().enumerate() {
+			save_key_states(keys, &mut saved[party], &mut storages[party]);
+		}
+	}
+
+	#[test]
+	fn test_concurrent_key_gens_are_stored_under_their_ids() {
+		let (first, second): (GeneratedKeyId, GeneratedKeyId) = (1, 2);
+		// every party runs both sessions at once, neither is complete yet
+		let mut keys = key_states(1, 3)
+			.into_iter()
+			.zip(key_states(1, 3))
+			.map(|(mut a, mut b)| {
+				a.complete = false;
+				b.complete = false;
+				let mut keys = BTreeMap::new();
+				keys.insert(first, a);
+				keys.insert(second, b);
+				keys
+			})
+			.collect::<Vec<_>>();
+		let mut saved = vec![BTreeSet::new(); 3];
+		let mut storages = vec![InMemOffchainStorage::default(); 3];
+
+		save_all(&keys, &mut saved, &mut storages);
+		assert!(storages.iter().all(|storage| load_key_state(storage, first).is_none()));
+		assert!(storages.iter().all(|storage| load_key_state(storage, second).is_none()));
+
+		// the later session completes first
+		for party in keys.iter_mut() {
+			party.get_mut(&second).unwrap().complete = true;
+		}
+		save_all(&keys, &mut saved, &mut storages);
+		assert!(storages.iter().all(|storage| load_key_state(storage, first).is_none()));
+		assert!(storages.iter().all(|storage| load_key_state(storage, second).is_some()));
+
+		for party in keys.iter_mut() {
+			party.get_mut(&first).unwrap().complete = true;
+		}
+		save_all(&keys, &mut saved, &mut storages);
+
+		for id in [first, second].iter() {
+			let loaded = storages
+				.iter()
+				.map(|storage| load_key_state(storage, *id).unwrap())
+				.collect::<Vec<_>>();
+			for (party, stored) in loaded.iter().enumerate() {
+				let state = &keys[party][id];
+				assert!(stored.complete);
+				assert_eq!(stored.shared_public_key(), state.shared_public_key());
+				assert_eq!(stored.shared_keys.as_ref().map(|k| k.x_i), state.shared_keys.as_ref().map(|k| k.x_i));
+			}
+			let sigs = sign(&loaded, 1, 0);
+			assert!(sigs[0].is_some());
+			assert_eq!(sigs[0], sigs[1]);
+		}
+		assert_ne!(keys[0][&first].shared_public_key(), keys[0][&second].shared_public_key());
+	}
+
+	#[test]
+	fn test_next_attempt_takes_other_signers() {
+		assert_eq!(SigGenState::signers_for_attempt(1, 3, 0), vec![0, 1]);
+		assert_eq!(SigGenState::signers_for_attempt(1, 3, 1), vec![1, 2]);
+		assert_eq!(SigGenState::signers_for_attempt(1, 3, 2), vec![0, 2]);
+		assert_eq!(SigGenState::signers_for_attempt(1, 3, 3), vec![0, 1]);
+
+		// party 0 went offline, the parties of the next attempt sign without it
+		let key_states = key_states(1, 3);
+		let sigs = sign(&key_states, 1, 1);
+		assert!(sigs[0].is_some());
+		assert_eq!(sigs[0], sigs[1]);
+	}
+
+	#[test]
+	fn test_messages_of_next_attempt_are_kept() {
+		let mut state = SigGenState::default();
+		state.start(0, b"message".to_vec(), 1, 3);
+		let delta = SigGenMessage::Round3(2, FE::new_random());
+
+		assert!(!state.receive_for(2, delta.clone()));
+		assert!(state.receive_for(1, delta));
+		assert!(state.deltas.is_empty());
+
+		state.restart(1, 1, 3);
+		assert_eq!(state.signers, vec![1, 2]);
+		assert!(state.deltas.contains_key(&2));
+		assert!(state.next_attempt.is_empty());
+	}
+
+	#[test]
+	fn test_reshare_keeps_public_key() {
+		let old = key_states(1, 3);
+		let holders = old.iter().map(Some).chain(std::iter::once(None)).collect::<Vec<_>>();
+		let new = reshare(&holders, 2).into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+		for state in new.iter() {
+			assert_eq!(state.shared_public_key(), old[0].shared_public_key());
+		}
+		let sigs = sign(&new, 2, 0);
+		assert!(sigs.iter().all(|sig| sig.is_some()));
+	}
+
+	#[test]
+	fn test_reshare_rejects_bad_dealing() {
+		let mut old = key_states(1, 3);
+		// a dealer with a wrong share of the key
+		let x_i = old[1].shared_keys.as_ref().unwrap().x_i;
+		old[1].shared_keys.as_mut().unwrap().x_i = x_i + x_i;
+
+		let holders = old.iter().map(Some).collect::<Vec<_>>();
+		for res in reshare(&holders, 1) {
+			assert_eq!(res.err(), Some("dealings do not add up to the public key"));
+		}
+	}
+
+	#[test]
+	fn test_reshare_without_first_holder() {
+		let old = key_states(1, 3);
+		// party 0 left, a new member took its place and the others moved
+		let holders = vec![Some(&old[2]), None, Some(&old[1])];
+		let new = reshare(&holders, 1).into_iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+		for state in new.iter() {
+			assert_eq!(state.shared_public_key(), old[0].shared_public_key());
+		}
+		let sigs = sign(&new, 1, 0);
+		assert!(sigs[0].is_some());
+		assert_eq!(sigs[0], sigs[1]);
+	}
+
+	#[test]
+	fn test_reshare_waits_for_enough_holders() {
+		let old = key_states(1, 3);
+		let holders = vec![Some(&old[2]), None, None];
+		for res in reshare(&holders, 1) {
+			assert_eq!(res.err(), Some("no dealers"));
+		}
+	}
+}
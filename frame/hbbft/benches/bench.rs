@@ -0,0 +1,300 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Execution time of every Badger dispatchable, to derive their weights from.
+//!
+//! The vote calls walk the whole authority map, so they are measured over several validator
+//! set sizes. Every iteration starts from freshly built storage, only the dispatch is timed.
+
+use badger_primitives::{
+  AccountBinding, AuthorityId, AuthorityPair, AuthoritySignature, EquivocationProof, FaultReport, SignedAccountBinding,
+  SignedFaultReport, ACCOUNT_BINDING_PURPOSE,
+};
+use codec::Encode;
+use criterion::{criterion_group, criterion_main, Bencher, Criterion};
+use frame_support::{
+  dispatch::Dispatchable, impl_outer_dispatch, impl_outer_origin, parameter_types, weights::Weight,
+};
+use pallet_badger::{Call as BadgerCall, Module, Trait};
+use primitives::{Pair, H256};
+use sp_runtime::{
+  impl_opaque_keys,
+  testing::{Header, TestXt},
+  traits::{BlakeTwo256, ConvertInto, Header as HeaderT, IdentityLookup},
+  Perbill,
+};
+
+impl_outer_origin! {
+  pub enum Origin for Runtime {}
+}
+
+impl_outer_dispatch! {
+  pub enum Call for Runtime where origin: Origin {
+    badger::Badger,
+  }
+}
+
+impl_opaque_keys! {
+  pub struct SessionKeys {
+    pub badger: Badger,
+  }
+}
+
+parameter_types! {
+  pub const BlockHashCount: u64 = 250;
+  pub const MaximumBlockWeight: Weight = 4 * 1024 * 1024;
+  pub const MaximumBlockLength: u32 = 4 * 1024 * 1024;
+  pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+  pub const DisabledValidatorsThreshold: Perbill = Perbill::from_percent(33);
+  pub const SessionPeriod: u64 = 10;
+  pub const MaxBindingLifetime: u64 = 200;
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Runtime;
+impl system::Trait for Runtime
+{
+  type Origin = Origin;
+  type Index = u64;
+  type BlockNumber = u64;
+  type Call = Call;
+  type Hash = H256;
+  type Hashing = BlakeTwo256;
+  type AccountId = u64;
+  type Lookup = IdentityLookup<Self::AccountId>;
+  type Header = Header;
+  type Event = ();
+  type BlockHashCount = BlockHashCount;
+  type MaximumBlockWeight = MaximumBlockWeight;
+  type MaximumBlockLength = MaximumBlockLength;
+  type AvailableBlockRatio = AvailableBlockRatio;
+  type Version = ();
+  type ModuleToIndex = ();
+}
+
+impl session::Trait for Runtime
+{
+  type ShouldEndSession = Badger;
+  type OnSessionEnding = Badger;
+  type SessionHandler = (Badger,);
+  type ValidatorId = u64;
+  type ValidatorIdOf = ConvertInto;
+  type Keys = SessionKeys;
+  type Event = ();
+  type SelectInitialValidators = ();
+  type DisabledValidatorsThreshold = DisabledValidatorsThreshold;
+}
+
+impl session::historical::Trait for Runtime
+{
+  type FullIdentification = u64;
+  type FullIdentificationOf = ConvertInto;
+}
+
+impl Trait for Runtime
+{
+  type Event = ();
+  type Call = Call;
+  type SubmitTransaction = system::offchain::TransactionSubmitter<(), Call, TestXt<Call, ()>>;
+  type ReportEquivocation = ();
+  type ElectedValidators = ();
+  type SessionPeriod = SessionPeriod;
+  type MaxBindingLifetime = MaxBindingLifetime;
+}
+
+type Badger = Module<Runtime>;
+
+/// Validator set sizes the vote calls are measured with
+const SET_SIZES: [u64; 4] = [4, 16, 64, 256];
+
+fn pair(account: u64) -> AuthorityPair
+{
+  let mut seed = [0u8; 32];
+  seed[..8].copy_from_slice(&account.to_le_bytes());
+  AuthorityPair::from_seed(&seed)
+}
+
+fn authority(account: u64) -> AuthorityId
+{
+  pair(account).public()
+}
+
+fn binding(account: u64) -> SignedAccountBinding<u64, H256, u64>
+{
+  let data = AccountBinding {
+    purpose: ACCOUNT_BINDING_PURPOSE,
+    genesis_hash: system::Module::<Runtime>::block_hash(0),
+    expires_at: 100,
+    self_pub_key: authority(account),
+    bound_account: account,
+  };
+  let sig = data.using_encoded(|encoded| pair(account).sign(encoded));
+  SignedAccountBinding { data: data, sig: sig }
+}
+
+/// Storage with validators `1..=validators` and `extra` more accounts bound to node keys
+fn new_test_ext(validators: u64, extra: u64) -> runtime_io::TestExternalities
+{
+  let mut t = system::GenesisConfig::default().build_storage::<Runtime>().unwrap();
+  session::GenesisConfig::<Runtime> {
+    keys: (1..=validators)
+      .map(|acc| (acc, SessionKeys { badger: authority(acc) }))
+      .collect(),
+  }
+  .assimilate_storage(&mut t)
+  .unwrap();
+  let mut ext: runtime_io::TestExternalities = t.into();
+  ext.execute_with(|| {
+    system::Module::<Runtime>::set_block_number(1);
+    for acc in validators + 1..=validators + extra
+    {
+      dispatch(BadgerCall::submit_account_binding(binding(acc)), Origin::signed(acc));
+    }
+  });
+  ext
+}
+
+fn dispatch(call: BadgerCall<Runtime>, origin: Origin)
+{
+  call.dispatch(origin).expect("benchmarked call must succeed");
+}
+
+/// Time `call` on storage prepared by `setup`, the call itself is built beforehand
+fn bench_call<S, C>(b: &mut Bencher, setup: S, call: C)
+where
+  S: Fn() -> runtime_io::TestExternalities,
+  C: Fn() -> (BadgerCall<Runtime>, Origin),
+{
+  b.iter_with_setup(
+    || {
+      let mut ext = setup();
+      let call = ext.execute_with(|| call());
+      (ext, call)
+    },
+    |(mut ext, (call, origin))| ext.execute_with(|| dispatch(call, origin)),
+  )
+}
+
+fn account_binding(c: &mut Criterion)
+{
+  c.bench_function("submit_account_binding", |b| {
+    bench_call(
+      b,
+      || new_test_ext(4, 0),
+      || (BadgerCall::submit_account_binding(binding(5)), Origin::signed(5)),
+    )
+  });
+  c.bench_function("remove_account_binding", |b| {
+    bench_call(
+      b,
+      || new_test_ext(4, 1),
+      || (BadgerCall::remove_account_binding(), Origin::signed(5)),
+    )
+  });
+}
+
+fn votes(c: &mut Criterion)
+{
+  c.bench_function_over_inputs(
+    "vote_to_add",
+    |b, &n| {
+      bench_call(
+        b,
+        move || new_test_ext(n, 1),
+        move || (BadgerCall::vote_to_add(n + 1), Origin::signed(1)),
+      )
+    },
+    SET_SIZES.to_vec(),
+  );
+  c.bench_function_over_inputs(
+    "vote_to_remove",
+    |b, &n| {
+      bench_call(
+        b,
+        move || new_test_ext(n, 0),
+        move || (BadgerCall::vote_to_remove(n), Origin::signed(1)),
+      )
+    },
+    SET_SIZES.to_vec(),
+  );
+  c.bench_function_over_inputs(
+    "vote_to_change",
+    |b, &n| {
+      bench_call(
+        b,
+        move || new_test_ext(n, n),
+        move || (BadgerCall::vote_to_change((n + 1..=2 * n).collect()), Origin::signed(1)),
+      )
+    },
+    SET_SIZES.to_vec(),
+  );
+}
+
+fn signed_header(signer: u64, parent: u8) -> (Header, AuthoritySignature)
+{
+  let header = Header {
+    parent_hash: H256::repeat_byte(parent),
+    number: 1,
+    state_root: Default::default(),
+    extrinsics_root: Default::default(),
+    digest: Default::default(),
+  };
+  let sig = pair(signer).sign(&header.hash().encode());
+  (header, sig)
+}
+
+fn fault_reports(c: &mut Criterion)
+{
+  c.bench_function("report_fault", |b| {
+    bench_call(
+      b,
+      || new_test_ext(4, 0),
+      || {
+        let report = FaultReport {
+          offender: authority(2),
+          reporter: authority(1),
+          at: 1,
+        };
+        let sig = report.using_encoded(|encoded| pair(1).sign(encoded));
+        (BadgerCall::report_fault(SignedFaultReport { report: report, sig: sig }), Origin::NONE)
+      },
+    )
+  });
+  c.bench_function("report_equivocation", |b| {
+    bench_call(
+      b,
+      || new_test_ext(4, 0),
+      || {
+        let proof = EquivocationProof {
+          offender: authority(2),
+          first: signed_header(2, 1),
+          second: signed_header(2, 2),
+        };
+        (BadgerCall::report_equivocation(proof), Origin::NONE)
+      },
+    )
+  });
+}
+
+fn send_log(c: &mut Criterion)
+{
+  c.bench_function("send_log", |b| {
+    bench_call(b, || new_test_ext(4, 0), || (BadgerCall::send_log(), Origin::signed(1)))
+  });
+}
+
+criterion_group!(benches, account_binding, votes, fault_reports, send_log);
+criterion_main!(benches);
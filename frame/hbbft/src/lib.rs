@@ -19,7 +19,15 @@
 //! This manages the Badger authority set ready for the native code, or does it? We'll see.
 //! These authorities are only for GRANDPA finality, not for consensus overall.
 //!
-//! In the future, it will also handle misbehavior reports, and on-chain
+//! The committee is chosen by `Trait::ElectedValidators`, `staking` in `nodehb`. Sessions end
+//! every `Trait::SessionPeriod` blocks so elections keep happening; an elected set differing
+//! from the current one is announced to the client with `ConsensusLog::NotifyChangedSet`, and
+//! the validators vote it into DynamicHoneyBadger. The session only takes the new set once the
+//! change completed and the client marked a block with `BadgerPreRuntime::ValidatorsChanged`.
+//!
+//! Misbehaviour observed by the client is reported as a `HoneyBadgerEquivocation` offence:
+//! conflicting signed blocks are checked on chain, other faults only count once more
+//! validators report them than may be faulty. In the future, it will also handle on-chain
 //! finality notifications.
 //!
 //! For full integration with GRANDPA, the `GrandpaApi` should be implemented.
@@ -29,20 +37,31 @@
 
 // re-export since this is necessary for `impl_apis` in runtime.
 //pub use substrate_badger_primitives as fg_primitives;
-use badger_primitives::{AuthorityId, SignedAccountBinding,BadgerPreRuntime};
+use badger_primitives::{AccountBinding, AuthorityId, SignedAccountBinding,BadgerPreRuntime, ACCOUNT_BINDING_PURPOSE};
 use badger_primitives::{HBBFT_AUTHORITIES_KEY, HBBFT_AUTHORITIES_MAP_KEY};
+use badger_primitives::{BadgerFaultReport, EquivocationProof, SignedFaultReport, HBBFT_FAULT_REPORTS_KEY};
 use codec::{self as codec,  Encode,Decode }; //Decode,Error,Codec,
 use rstd::collections::btree_map::BTreeMap;
 use rstd::prelude::*;
 use session::OnSessionEnding;
+use session::historical::IdentificationTuple;
+use primitives::offchain::StorageKind;
 //use frame_support::dispatch::DispatchError;
 use sp_runtime::{
   generic::{DigestItem, },
+  traits::{Convert, Hash, One, SaturatedConversion, Zero},
+  transaction_validity::{InvalidTransaction, TransactionPriority, TransactionValidity, ValidTransaction},
+  Perbill, RuntimeDebug,
   //traits::Zero,
  // Perbill,
 };//OpaqueDigestItemId
 use frame_support::{
-  decl_event, decl_module, decl_storage, dispatch::DispatchResult, storage,  storage::StorageValue,
+  debug, decl_error, decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure, storage,  storage::StorageValue,
+  traits::Get,
+};
+use sp_staking::{
+  offence::{Kind, Offence, ReportOffence},
+  SessionIndex,
 };
 //storage::StorageMap,
 
@@ -56,31 +75,72 @@ use app_crypto::RuntimeAppPublic;
 
 use badger_primitives::{ConsensusLog, HBBFT_ENGINE_ID};
 //pub use fg_primitives::{AuthorityId, ConsensusLog};
-use system::ensure_signed; //DigestOf
+use system::{ensure_none, ensure_signed, offchain::SubmitUnsignedTransaction}; //DigestOf
 
 //#[derive(Decode, Encode, PartialEq, Eq, Clone,Hash)]
 //pub type AuthorityId = ([u8; 32],[u8; 16]);
 
-/// An consensus log item for BADGER.
 mod mock;
+mod tests;
 
-pub trait Trait: system::Trait + session::Trait
+pub trait Trait: system::Trait + session::historical::Trait
 {
   /// The event type of this module.
-  type Event: From<Event> + Into<<Self as system::Trait>::Event>;
+  type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+
+  /// A dispatchable call type.
+  type Call: From<Call<Self>>;
+
+  /// Submits the fault reports the client leaves in offchain storage.
+  type SubmitTransaction: SubmitUnsignedTransaction<Self, <Self as Trait>::Call>;
+
+  /// Where equivocations and confirmed faults are reported.
+  type ReportEquivocation: ReportOffence<
+    Self::AccountId,
+    IdentificationTuple<Self>,
+    HoneyBadgerEquivocation<IdentificationTuple<Self>>,
+  >;
+
+  /// Elects the accounts the committee is chosen from at the end of a session, if it is time to.
+  type ElectedValidators: OnSessionEnding<Self::AccountId>;
+
+  /// Number of blocks after which a session ends even if the committee did not change.
+  type SessionPeriod: Get<Self::BlockNumber>;
+
+  /// Number of blocks a binding may stay valid for after it is submitted. Spent bindings are
+  /// kept until they expire, so this bounds how long they stay in storage.
+  type MaxBindingLifetime: Get<Self::BlockNumber>;
 }
 
 decl_event!(
-  // #[derive(Debug)]
-  pub enum Event
+  pub enum Event<T>
+  where
+    AccountId = <T as system::Trait>::AccountId,
   {
     /// New authority set has been applied.
     NewAuthorities(Vec<AuthorityId>),
+    /// (account, node key) An account was bound to a node key.
+    AccountBound(AccountId, AuthorityId),
+    /// (account, node key) An account binding was removed.
+    AccountUnbound(AccountId, AuthorityId),
+    /// (voter, accounts) A validator voted for a new validator set.
+    VoteCast(AccountId, Vec<AccountId>),
+    /// The validators completed a change to this set, it takes over with the next session.
+    SetChangeScheduled(Vec<AuthorityId>),
+    /// A validator signed two blocks of the same height.
+    EquivocationReported(AuthorityId),
+    /// (offender, reporter) A validator reported a fault of another one.
+    FaultReported(AuthorityId, AuthorityId),
+    /// A new committee was elected, validators will vote it in.
+    CommitteeElected(Vec<AuthorityId>),
   }
 );
 
 const HB_DEDUP_KEY_PREFIX: &[u8] = b":hbbft:votes";
 
+/// Smallest committee DynamicHoneyBadger can tolerate a fault in
+const MIN_COMMITTEE_SIZE: usize = 4;
+
 
 decl_storage! {
   trait Store for Module<T: Trait> as BadgerFinality {
@@ -96,186 +156,307 @@ decl_storage! {
 	 /// The number of changes (both in terms of keys and underlying economic responsibilities)
     /// in the "set" of Badger validators from genesis.
     CurrentSetId get(current_set_id) build(|_| 0): u64;
+
+    /// Validators that reported a fault of the given offender, per session
+    FaultReporters: double_map SessionIndex, blake2_256(AuthorityId) => Vec<AuthorityId>;
+
+    /// The block the current session started at, older fault reports are stale
+    SessionStart get(session_start): T::BlockNumber;
+
+    /// Elected committee the validators have not completed the change to yet
+    PendingCommittee get(pending_committee): Option<Vec<AuthorityId>>;
+
+    /// Hashes of the account bindings already submitted, with the block they expire at
+    SpentBindings get(spent_binding): map T::Hash => Option<T::BlockNumber>;
+
+    /// Hashes of the spent bindings that expire at the given block, pruned once it passed
+    BindingsExpiring: map T::BlockNumber => Vec<T::Hash>;
   }
   add_extra_genesis {
     config(authorities): Vec<AuthorityId>;
     build(|config| Module::<T>::initialize_authorities(&config.authorities,&BTreeMap::new()))
   }
 }
-use runtime_io::misc::print_utf8;
+decl_error! {
+  /// Error for the badger module.
+  pub enum Error for Module<T: Trait> {
+    /// The account has no node key bound to it.
+    NotBound,
+    /// The account or the node key is bound already.
+    AlreadyBound,
+    /// The binding is not signed by the node key it binds.
+    BadBindingSignature,
+    /// The binding was signed for a different account than the submitting one.
+    BindingForOtherAccount,
+    /// The signature was not made for account binding.
+    NotABinding,
+    /// The binding was made for another chain.
+    BindingForOtherChain,
+    /// The binding expired.
+    BindingExpired,
+    /// The binding expires later than `MaxBindingLifetime` blocks from now.
+    BindingLifetimeTooLong,
+    /// The binding was submitted before.
+    BindingAlreadyUsed,
+    /// The account is not bound to a node key of the validator set.
+    NotValidator,
+    /// The account voted for is a validator already.
+    AlreadyValidator,
+    /// The account voted against is not in the validator set.
+    NotInValidatorSet,
+    /// The validator set would become smaller than DynamicHoneyBadger can tolerate.
+    SetTooSmall,
+    /// The vote names more than one account.
+    TooManyAccounts,
+    /// A validator has no account bound to its node key.
+    ValidatorNotBound,
+    /// The equivocation is at a block that does not exist yet.
+    FutureEquivocation,
+    /// The headers are not conflicting blocks signed by the offender.
+    BadEquivocationProof,
+    /// The offender is not bound to an account.
+    OffenderNotBound,
+    /// The fault was observed before the current session or in the future.
+    StaleFaultReport,
+    /// Offender and reporter must be two different current validators.
+    FaultNotBetweenValidators,
+    /// The fault report is not signed by the reporter.
+    BadFaultReportSignature,
+    /// The reporter reported this offender in this session already.
+    FaultAlreadyReported,
+  }
+}
+
 decl_module! {
   pub struct Module<T: Trait> for enum Call where origin: T::Origin {
+    type Error = Error<T>;
+
     fn deposit_event() = default;
 
-    fn on_finalize(_block_number: T::BlockNumber) {
+	fn on_initialize(now: T::BlockNumber) {
+		// bindings that expired are rejected as such, no need to remember them as spent
+		if !now.is_zero()
+		{
+			for hash in <BindingsExpiring<T>>::take(now - One::one())
+			{
+				<SpentBindings<T>>::remove(hash);
+			}
+		}
+	}
 
+	fn offchain_worker(_now: T::BlockNumber) {
+		debug::RuntimeLogger::init();
+		Self::submit_fault_reports();
+	}
 
+	/// Report a validator that signed two blocks of the same height
+	fn report_equivocation(origin, proof: EquivocationProof<T::Header>) -> DispatchResult
+	{
+		ensure_none(origin)?;
+		Self::check_equivocation(&proof)?;
+		let offender = Self::identify(&proof.offender).ok_or(Error::<T>::OffenderNotBound)?;
+		Self::deposit_event(RawEvent::EquivocationReported(proof.offender.clone()));
+		Self::report(Vec::new(), offender, proof.number().saturated_into::<u64>());
+		Ok(())
 	}
-	
-	///Remove account binding for submitting account
-	fn remove_account_binding(origin) -> DispatchResult
+
+	/// Report a fault one validator observed in another, filed as an offence once more
+	/// validators reported it than may be faulty
+	fn report_fault(origin, report: SignedFaultReport) -> DispatchResult
 	{
-		let who=ensure_signed(origin)?;
-		let mut auth_map:BTreeMap<T::AccountId,AuthorityId>=storage::unhashed::get_or_default::<BTreeMap<T::AccountId,AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
-		match auth_map.remove(&who)	
+		ensure_none(origin)?;
+		Self::check_fault_report(&report)?;
+		let session = <session::Module<T>>::current_index();
+		let SignedFaultReport { report, .. } = report;
+		let mut reporters = <FaultReporters>::get(session, &report.offender);
+		ensure!(!reporters.contains(&report.reporter), Error::<T>::FaultAlreadyReported);
+		reporters.push(report.reporter.clone());
+		<FaultReporters>::insert(session, &report.offender, &reporters);
+		Self::deposit_event(RawEvent::FaultReported(report.offender.clone(), report.reporter));
+
+		// at least one of max_faulty + 1 reporters is honest
+		if reporters.len() == Self::max_faulty() + 1
 		{
-			Some(_) => {
-				storage::unhashed::put(
-					HBBFT_AUTHORITIES_MAP_KEY,
-					&auth_map,
-				  );				
-				Ok(()) 
-				 },
-				 None => {Err("Account not bound".into())}
-
+			if let Some(offender) = Self::identify(&report.offender)
+			{
+				let reporters = reporters.iter().filter_map(|r| Self::authority_to_account(r)).collect();
+				Self::report(reporters, offender, Self::session_start().saturated_into::<u64>());
+			}
 		}
+		Ok(())
 	}
-    fn submit_account_binding(origin,binding: SignedAccountBinding<T::AccountId>) -> DispatchResult
-    {
-     let who=ensure_signed(origin)?;
-     let signature_valid = binding.data.using_encoded(|encoded_data| {
-    binding.data.self_pub_key.verify(&encoded_data, &binding.sig)
-     });
-     if !signature_valid {return Err("Invalid signature on binder".into());}
-     if who != binding.data.bound_account
-     {
-	//print_utf8(b"ERROR: Invalid account trying to use binder");
-	#[cfg(feature = "std")]
-	 print!("EA: invalid account {:?} ",&binding.data.bound_account,);
-	
-    return Err("Invalid account trying to use binder".into());
-     }
-     if Self::check_either_present(&who,&binding.data.self_pub_key)
-     {
-	 // print_utf8(b"ERROR: Account or node already bound");
-	 #[cfg(feature = "std")]
-	 print!("EA: already bound {:?} ",&binding.data.bound_account,);
-	
-      return Err("Account or node already bound".into());
-     }
-	 let mut auth_map:BTreeMap<T::AccountId,AuthorityId>=storage::unhashed::take_or_default::<BTreeMap<T::AccountId,AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
-	 #[cfg(feature = "std")]
-	 print!("EXECUTED vote to bind {:?} ",&binding.data.bound_account,);
-	 auth_map.insert(binding.data.bound_account,binding.data.self_pub_key);
-     storage::unhashed::put(
-      HBBFT_AUTHORITIES_MAP_KEY,
-      &auth_map,
-    );
-    print_utf8(b"Success binding");
-     //binding.data.self_pub_key.ver
-     Ok(())
+
+	/// Remove the account binding of the submitting account
+	fn remove_account_binding(origin) -> DispatchResult
+	{
+		let who = ensure_signed(origin)?;
+		let mut auth_map = Self::auth_map();
+		let auth = auth_map.remove(&who).ok_or(Error::<T>::NotBound)?;
+		storage::unhashed::put(HBBFT_AUTHORITIES_MAP_KEY, &auth_map);
+		Self::deposit_event(RawEvent::AccountUnbound(who, auth));
+		Ok(())
 	}
-	
 
-  //	Self::deposit_log(ConsensusLog::Resume(delay));
-  fn send_log(_origin) ->DispatchResult
-  {
-    print_utf8(b"RUNEXTR");
-  //let who =	ensure_signed(origin)?;
-  //session::Module<Runtime>;
-  //<session::Module<T>>::queued_keys();
-  Self::deposit_log(ConsensusLog::VoteChangeSet(AuthorityId::default(),vec![ AuthorityId::default()]));
-  Ok(())
-  }
-  
+	/// Bind the submitting account to a node key, with a binding signed by that key
+	fn submit_account_binding(origin, binding: SignedAccountBinding<T::AccountId, T::Hash, T::BlockNumber>) -> DispatchResult
+	{
+		let who = ensure_signed(origin)?;
+		let signature_valid = binding.data.using_encoded(|encoded_data| {
+			binding.data.self_pub_key.verify(&encoded_data, &binding.sig)
+		});
+		ensure!(signature_valid, Error::<T>::BadBindingSignature);
+		Self::check_binding_fresh(&binding.data)?;
+		ensure!(who == binding.data.bound_account, Error::<T>::BindingForOtherAccount);
+		ensure!(!Self::check_either_present(&who, &binding.data.self_pub_key), Error::<T>::AlreadyBound);
+
+		let hash = T::Hashing::hash_of(&binding.data);
+		<SpentBindings<T>>::insert(hash, binding.data.expires_at);
+		<BindingsExpiring<T>>::append_or_insert(binding.data.expires_at, &[hash][..]);
+		let mut auth_map = Self::auth_map();
+		auth_map.insert(who.clone(), binding.data.self_pub_key.clone());
+		storage::unhashed::put(HBBFT_AUTHORITIES_MAP_KEY, &auth_map);
+		Self::deposit_event(RawEvent::AccountBound(who, binding.data.self_pub_key));
+		Ok(())
+	}
 
-    /// vote to add authority
-	pub fn vote_to_add(origin, new_auth_id: T::AccountId)->DispatchResult
+	fn send_log(_origin) -> DispatchResult
 	{
-		let who=ensure_signed(origin)?;
-		#[cfg(feature = "std")]
-		print!("EXECUTE vote to add{:?}",new_auth_id);
-		Self::update_vote_set(&who,vec![new_auth_id],|c_auths,new_ids|
-			{
-				if new_ids.len()>1
-				{
-					///panic!("Invalid ids provided");
-					#[cfg(feature = "std")]
-					print!("ERROR: Invalid ids provided");
-					Err("Invalid ids provided")?;
-				}
-				let new_auth=new_ids[0];
-				if c_auths.iter().find(|x| *x==new_auth).is_some()
-				{
-					#[cfg(feature = "std")]
-					print!("Account voted for already validator");
-					return Err("Account voted for already validator".into()); 
-				}
-				c_auths.push(new_auth.clone());
-				Ok(())
-			}
-		)	  
+		Self::deposit_log(ConsensusLog::VoteChangeSet(AuthorityId::default(), vec![AuthorityId::default()]));
+		Ok(())
 	}
 
-	    /// vote to remove authority
-		pub fn vote_to_remove(origin, new_auth_id: T::AccountId)->DispatchResult
-		{
-			let who=ensure_signed(origin)?;
-			#[cfg(feature = "std")]
-			print!("EA: vote_to_remove");
-			Self::update_vote_set(&who,vec![new_auth_id],|c_auths,new_ids|
-				{
-					#[cfg(feature = "std")]
-					print!("EA: in_update");
-					if new_ids.len()>1
-					{
-						#[cfg(feature = "std")]
-						print!("EA: Not in val set {:?}",&c_auths);
-						Err("Invalid ids provided")?;
-					}
-					let new_auth=new_ids[0];
-					let pos = match c_auths.iter().position(|x| *x == *new_auth)
-					{
-						Some(ps) =>ps,
-						None => {
-							#[cfg(feature = "std")]
-							print!("EA: Not in val set {:?}",&c_auths);
-							return Err("Account voted against already not in validator set".into()) } 
-					};
-					if c_auths.len()<=4
-					{
-						#[cfg(feature = "std")]
-						print!("EA: Val set {:?} too small!",&c_auths);
-							
-						return Err("Cannot reduce validator set further".into());
-					}
-					c_auths.remove(pos);	
-					Ok(())
-				}
-			)	  
-		}
+	/// Vote to add the validator bound to `new_auth_id`
+	pub fn vote_to_add(origin, new_auth_id: T::AccountId) -> DispatchResult
+	{
+		let who = ensure_signed(origin)?;
+		Self::update_vote_set(&who, vec![new_auth_id], |c_auths, new_ids| {
+			ensure!(new_ids.len() == 1, Error::<T>::TooManyAccounts);
+			let new_auth = new_ids[0];
+			ensure!(!c_auths.contains(new_auth), Error::<T>::AlreadyValidator);
+			c_auths.push(new_auth.clone());
+			Ok(())
+		})
+	}
 
+	/// Vote to remove the validator bound to `new_auth_id`
+	pub fn vote_to_remove(origin, new_auth_id: T::AccountId) -> DispatchResult
+	{
+		let who = ensure_signed(origin)?;
+		Self::update_vote_set(&who, vec![new_auth_id], |c_auths, new_ids| {
+			ensure!(new_ids.len() == 1, Error::<T>::TooManyAccounts);
+			let pos = c_auths.iter().position(|x| x == new_ids[0]).ok_or(Error::<T>::NotInValidatorSet)?;
+			ensure!(c_auths.len() > MIN_COMMITTEE_SIZE, Error::<T>::SetTooSmall);
+			c_auths.remove(pos);
+			Ok(())
+		})
+	}
 
-	    /// vote to replace authority set
-		pub fn vote_to_change(origin, new_auth_ids: Vec<T::AccountId>)
-		{
-			let who=ensure_signed(origin)?;
-			let res=Self::update_vote_set(&who,new_auth_ids,|c_auths,new_ids|
-				{
-					if new_ids.len()<4
-					{
-						return Err("Not enough validators in new set".into()); 
-					}
-					c_auths.clear();
-					for id in new_ids.into_iter()
-					{
-                     c_auths.push(id.clone());
-					}
-					Ok(())
-				}
-			);
-			match res
-			{
-				Ok(_) =>{},
-				Err(e) =>{
-					return Err(e.into()); 	
-				}
-			}	  
-		}
+	/// Vote to replace the validator set with the validators bound to `new_auth_ids`
+	pub fn vote_to_change(origin, new_auth_ids: Vec<T::AccountId>) -> DispatchResult
+	{
+		let who = ensure_signed(origin)?;
+		Self::update_vote_set(&who, new_auth_ids, |c_auths, new_ids| {
+			ensure!(new_ids.len() >= MIN_COMMITTEE_SIZE, Error::<T>::SetTooSmall);
+			*c_auths = new_ids.into_iter().cloned().collect();
+			Ok(())
+		})
+	}
+  }
+}
 
-  //fn vote_for_auth
-  //account_to_authority
+/// A Badger validator that signed conflicting blocks, or misbehaved in the protocol according
+/// to more validators than may be faulty.
+#[derive(RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Clone, PartialEq, Eq))]
+pub struct HoneyBadgerEquivocation<Offender>
+{
+  /// Height of the conflicting blocks, or the first block of the session for protocol faults
+  pub time_slot: u64,
+  /// The session index in which the offence was reported.
+  pub session_index: SessionIndex,
+  /// The size of the validator set at the time of the offence.
+  pub validator_set_count: u32,
+  /// The misbehaving validator.
+  pub offender: Offender,
 }
+
+impl<Offender: Clone> Offence<Offender> for HoneyBadgerEquivocation<Offender>
+{
+  const ID: Kind = *b"hbbft:equivocati";
+  type TimeSlot = u64;
+
+  fn offenders(&self) -> Vec<Offender>
+  {
+    vec![self.offender.clone()]
+  }
+
+  fn session_index(&self) -> SessionIndex
+  {
+    self.session_index
+  }
+
+  fn validator_set_count(&self) -> u32
+  {
+    self.validator_set_count
+  }
+
+  fn time_slot(&self) -> Self::TimeSlot
+  {
+    self.time_slot
+  }
+
+  fn slash_fraction(offenders_count: u32, validator_set_count: u32) -> Perbill
+  {
+    // the formula is min((3k / n)^2, 1)
+    let x = Perbill::from_rational_approximation(3 * offenders_count, validator_set_count);
+    x.square()
+  }
+}
+
+/// How long fault report transactions stay in the pool
+const FAULT_REPORT_LONGEVITY: u64 = 64;
+
+#[allow(deprecated)]
+impl<T: Trait> frame_support::unsigned::ValidateUnsigned for Module<T>
+{
+  type Call = Call<T>;
+
+  fn validate_unsigned(call: &Self::Call) -> TransactionValidity
+  {
+    let provides = match call
+    {
+      Call::report_equivocation(proof) =>
+      {
+        if Self::check_equivocation(proof).is_err()
+        {
+          return InvalidTransaction::BadProof.into();
+        }
+        (&b"hbbft:equivocation"[..], &proof.offender, proof.number()).encode()
+      }
+      Call::report_fault(signed) =>
+      {
+        if Self::check_fault_report(signed).is_err()
+        {
+          return InvalidTransaction::BadProof.into();
+        }
+        let session = <session::Module<T>>::current_index();
+        let report = &signed.report;
+        if <FaultReporters>::get(session, &report.offender).contains(&report.reporter)
+        {
+          return InvalidTransaction::Stale.into();
+        }
+        (&b"hbbft:fault"[..], session, &report.offender, &report.reporter).encode()
+      }
+      _ => return InvalidTransaction::Call.into(),
+    };
+    Ok(ValidTransaction {
+      priority: TransactionPriority::max_value(),
+      requires: vec![],
+      provides: vec![provides],
+      longevity: FAULT_REPORT_LONGEVITY,
+      propagate: true,
+    })
+  }
 }
 
 impl<T: Trait> sp_runtime::BoundToRuntimeAppPublic for Module<T>
@@ -286,58 +467,36 @@ impl<T: Trait> sp_runtime::BoundToRuntimeAppPublic for Module<T>
 impl<T: Trait> Module<T>
 {
 
+	/// Apply `how` to the current validator set and record the result as the vote of `who`
 	pub fn update_vote_set(who:&T::AccountId,new_auth_ids: Vec<T::AccountId>,mut how: impl FnMut(&mut Vec<AuthorityId>, Vec<&AuthorityId>)->DispatchResult )->DispatchResult
 	{
-		if let Some(our_authid)= Self::is_account_authority(&who)
+		let our_authid = Self::is_account_authority(&who).ok_or(Error::<T>::NotValidator)?;
+		let auth_map = Self::auth_map();
+		let mut new_auths: Vec<&AuthorityId> = Vec::new();
+		for id in new_auth_ids.iter()
 		{
-		   let     auth_map:BTreeMap<T::AccountId,AuthorityId>=storage::unhashed::get_or_default::<BTreeMap<T::AccountId,AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
-		   let mut new_auths:Vec<&AuthorityId>=Vec::new();
-		   for id in new_auth_ids.iter()
-		   {
-			if let Some(new_auth)=auth_map.get(id)
-			{
-                new_auths.push(new_auth);
-			}   
-			else
-			{
-				#[cfg(feature = "std")]
-				print!("EA: One of the accounts does not have bound authority {:?}",id);
-				#[cfg(feature = "std")]
-			    print!("EA: No authority! {:?}",&auth_map);
-				return Err("One of the accounts does not have bound authority".into());
-			}
-		   }
-			   let mut c_auths=Self::badger_authorities();
-			   #[cfg(feature = "std")]
-			   print!("EA:  Current athorities: {:?}\n ",&c_auths);
-			   how(&mut c_auths,new_auths)?;
-			   let mut cmap:Vec<T::AccountId>=Vec::new();
-			   for auth in c_auths.iter()
-			   {
-				   let acc=match auth_map.iter().find( |(_,au)| *au==auth )
-				   {
-					   Some(dat) =>dat.0.clone(),
-   
-					   None => 
-					   {
-						#[cfg(feature = "std")]
-						print!("EA: Not all validators mapped: {:?} {:?}\n",&auth,&auth_map);
-						return Err("Internal error: not all validators mapped".into())
-					    },
-				   };
-					 cmap.push(acc);
-			   }
-			   <Votes<T>>::insert(HB_DEDUP_KEY_PREFIX, who,cmap);
-			   Self::deposit_log(ConsensusLog::VoteChangeSet(our_authid.clone(),c_auths));
-              Ok(())
-		   }
-		   else
-		   {
-			#[cfg(feature = "std")]
-			print!("Account not validator");
-			return Err("Account not validator".into());   
-		   }
+			new_auths.push(auth_map.get(id).ok_or(Error::<T>::NotBound)?);
+		}
+		let mut c_auths = Self::badger_authorities();
+		how(&mut c_auths, new_auths)?;
+		let mut cmap: Vec<T::AccountId> = Vec::new();
+		for auth in c_auths.iter()
+		{
+			let (acc, _) = auth_map.iter().find(|(_, au)| *au == auth).ok_or(Error::<T>::ValidatorNotBound)?;
+			cmap.push(acc.clone());
+		}
+		<Votes<T>>::insert(HB_DEDUP_KEY_PREFIX, who, &cmap);
+		Self::deposit_event(RawEvent::VoteCast(who.clone(), cmap));
+		Self::deposit_log(ConsensusLog::VoteChangeSet(our_authid, c_auths));
+		Ok(())
 	}
+
+  /// Accounts bound to node keys
+  fn auth_map() -> BTreeMap<T::AccountId, AuthorityId>
+  {
+    storage::unhashed::get_or_default::<BTreeMap<T::AccountId, AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY)
+  }
+
    pub fn is_account_authority(acc: &T::AccountId) ->Option<AuthorityId>
    {
 	let  auth_map:BTreeMap<T::AccountId,AuthorityId>=storage::unhashed::get_or_default::<BTreeMap<T::AccountId,AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
@@ -359,6 +518,108 @@ impl<T: Trait> Module<T>
   {
     storage::unhashed::get_or_default::<Vec<AuthorityId>>(HBBFT_AUTHORITIES_KEY).into()
   }
+  pub fn authority_to_account(auth: &AuthorityId) -> Option<T::AccountId>
+  {
+    let auth_map: BTreeMap<T::AccountId, AuthorityId> =
+      storage::unhashed::get_or_default::<BTreeMap<T::AccountId, AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
+    auth_map.iter().find(|(_, v)| *v == auth).map(|(k, _)| k.clone())
+  }
+
+  /// The number of current validators that may be faulty
+  fn max_faulty() -> usize
+  {
+    Self::badger_authorities().len().saturating_sub(1) / 3
+  }
+
+  /// Full identification of the validator bound to `auth`, if there is one
+  fn identify(auth: &AuthorityId) -> Option<IdentificationTuple<T>>
+  {
+    let validator = T::ValidatorIdOf::convert(Self::authority_to_account(auth)?)?;
+    let full = T::FullIdentificationOf::convert(validator.clone())?;
+    Some((validator, full))
+  }
+
+  fn check_equivocation(proof: &EquivocationProof<T::Header>) -> Result<(), Error<T>>
+  {
+    if proof.number() > <system::Module<T>>::block_number()
+    {
+      return Err(Error::<T>::FutureEquivocation);
+    }
+    if !proof.check()
+    {
+      return Err(Error::<T>::BadEquivocationProof);
+    }
+    Ok(())
+  }
+
+  fn check_fault_report(signed: &SignedFaultReport) -> Result<(), Error<T>>
+  {
+    let report = &signed.report;
+    let now = <system::Module<T>>::block_number().saturated_into::<u64>();
+    if report.at > now || report.at < Self::session_start().saturated_into::<u64>()
+    {
+      return Err(Error::<T>::StaleFaultReport);
+    }
+    let authorities = Self::badger_authorities();
+    if report.offender == report.reporter || !authorities.contains(&report.offender) || !authorities.contains(&report.reporter)
+    {
+      return Err(Error::<T>::FaultNotBetweenValidators);
+    }
+    if !signed.verify()
+    {
+      return Err(Error::<T>::BadFaultReportSignature);
+    }
+    Ok(())
+  }
+
+  fn report(reporters: Vec<T::AccountId>, offender: IdentificationTuple<T>, time_slot: u64)
+  {
+    let offence = HoneyBadgerEquivocation {
+      time_slot: time_slot,
+      session_index: <session::Module<T>>::current_index(),
+      validator_set_count: Self::badger_authorities().len() as u32,
+      offender: offender,
+    };
+    T::ReportEquivocation::report_offence(reporters, offence);
+  }
+
+  /// Submit the fault reports the client left in offchain storage
+  fn submit_fault_reports()
+  {
+    let stored = match runtime_io::offchain::local_storage_get(StorageKind::PERSISTENT, HBBFT_FAULT_REPORTS_KEY)
+    {
+      Some(data) => data,
+      None => return,
+    };
+    let reports = Vec::<BadgerFaultReport<T::Header>>::decode(&mut &stored[..]).unwrap_or_default();
+    if reports.is_empty()
+    {
+      return;
+    }
+    // the client may be appending at the same time
+    let emptied = Vec::<BadgerFaultReport<T::Header>>::new().encode();
+    if !runtime_io::offchain::local_storage_compare_and_set(
+      StorageKind::PERSISTENT,
+      HBBFT_FAULT_REPORTS_KEY,
+      Some(stored),
+      &emptied,
+    )
+    {
+      return;
+    }
+    for report in reports.into_iter()
+    {
+      let call = match report
+      {
+        BadgerFaultReport::Equivocation(proof) => Call::report_equivocation(proof),
+        BadgerFaultReport::Fault(signed) => Call::report_fault(signed),
+      };
+      if T::SubmitTransaction::submit_unsigned(call).is_err()
+      {
+        debug::warn!("Could not submit fault report");
+      }
+    }
+  }
   pub fn account_to_authority(acc: &T::AccountId) -> Option<AuthorityId>
   {
     let auth_map: BTreeMap<T::AccountId, AuthorityId> =
@@ -382,6 +643,34 @@ impl<T: Trait> Module<T>
     auth_map.iter().find(|(_, v)| **v == *auth).is_some()
   }
 
+  /// Check that `binding` was made for account binding on this chain, has not expired, does
+  /// not live longer than `MaxBindingLifetime` and was not submitted before.
+  fn check_binding_fresh(binding: &AccountBinding<T::AccountId, T::Hash, T::BlockNumber>) -> Result<(), Error<T>>
+  {
+    if binding.purpose != ACCOUNT_BINDING_PURPOSE
+    {
+      return Err(Error::<T>::NotABinding);
+    }
+    if binding.genesis_hash != <system::Module<T>>::block_hash(T::BlockNumber::zero())
+    {
+      return Err(Error::<T>::BindingForOtherChain);
+    }
+    let now = <system::Module<T>>::block_number();
+    if binding.expires_at < now
+    {
+      return Err(Error::<T>::BindingExpired);
+    }
+    if binding.expires_at > now + T::MaxBindingLifetime::get()
+    {
+      return Err(Error::<T>::BindingLifetimeTooLong);
+    }
+    if <SpentBindings<T>>::exists(T::Hashing::hash_of(binding))
+    {
+      return Err(Error::<T>::BindingAlreadyUsed);
+    }
+    Ok(())
+  }
+
   /// Set the current set of authorities, along with their respective weights.
   fn set_badger_authorities(authorities: &Vec<AuthorityId>, auth_map: &BTreeMap<T::AccountId, AuthorityId>)
   {
@@ -404,6 +693,31 @@ impl<T: Trait> Module<T>
 
 
 
+  /// Note the accounts elected for the next era, and announce the committee they make up if it
+  /// differs from the current one. Accounts without a bound authority can't take part.
+  fn note_election(elected: Vec<T::AccountId>)
+  {
+    let committee: Vec<AuthorityId> = elected.iter().filter_map(|acc| Self::account_to_authority(acc)).collect();
+    if committee.len() < MIN_COMMITTEE_SIZE
+    {
+      debug::warn!("Elected committee of {} is too small, keeping the current one", committee.len());
+      return;
+    }
+    if Self::same_committee(&committee, &Self::badger_authorities())
+    {
+      PendingCommittee::kill();
+      return;
+    }
+    PendingCommittee::put(&committee);
+    Self::deposit_event(RawEvent::CommitteeElected(committee.clone()));
+    Self::deposit_log(ConsensusLog::NotifyChangedSet(committee));
+  }
+
+  fn same_committee(a: &[AuthorityId], b: &[AuthorityId]) -> bool
+  {
+    a.len() == b.len() && a.iter().all(|x| b.contains(x))
+  }
+
   /// vote to completely change authority set
 
   /// Deposit one of this module's logs.
@@ -416,7 +730,7 @@ impl<T: Trait> Module<T>
 
 impl<T: Trait> session::ShouldEndSession<T::BlockNumber> for Module<T>
 {
-  fn should_end_session(_now: T::BlockNumber) -> bool
+  fn should_end_session(now: T::BlockNumber) -> bool
   {
 	  //just check if session shift pre_digest was provided
 	  	let maybe_pre_digest:Vec<_> = <system::Module<T>>::digest()
@@ -434,26 +748,31 @@ impl<T: Trait> session::ShouldEndSession<T::BlockNumber> for Module<T>
 				match v
 				{
 					BadgerPreRuntime::ValidatorsChanged(_) => {return true;}
+					BadgerPreRuntime::BatchContinues => {}
 				}
 			}
 
-	//Self::do_initialize(now);
-	//storage::unhashed::take(HBBFT_AUTHORITIES_KEY, authorities);
-    return false; //for now. TODO
-                  //Self::should_epoch_change(now)
+	// end sessions regularly too, so that elections happen
+	now.saturating_sub(Self::session_start()) >= T::SessionPeriod::get()
   }
 }
 
-type SessionIndex = u32;
-
 impl<T: Trait> OnSessionEnding<T::AccountId> for Module<T>
 {
 
 	/// We ignore when session *should* start since when the pre_runtime is issued session *is* over in the consensus engine
-  fn on_session_ending(_ending: SessionIndex, _start_session: SessionIndex) -> Option<Vec<T::AccountId>>
+  fn on_session_ending(ending: SessionIndex, start_session: SessionIndex) -> Option<Vec<T::AccountId>>
   {
-	let auth_map: BTreeMap<T::AccountId, AuthorityId> =
-	storage::unhashed::get_or_default::<BTreeMap<T::AccountId, AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
+	match T::ElectedValidators::on_session_ending(ending, start_session)
+	{
+		Some(elected) => Self::note_election(elected),
+		// remind the validators in case the change did not go through
+		None => if let Some(pending) = Self::pending_committee()
+		{
+			Self::deposit_log(ConsensusLog::NotifyChangedSet(pending));
+		},
+	}
+	let auth_map = Self::auth_map();
 
 	let maybe_pre_digest:Vec<_> = <system::Module<T>>::digest()
 	.logs
@@ -469,26 +788,21 @@ impl<T: Trait> OnSessionEnding<T::AccountId> for Module<T>
 	{
 		match v
 		{
-			BadgerPreRuntime::ValidatorsChanged(valids) => 
-			     {
-					#[cfg(feature = "std")]
-					print!("EA: valichange: {:?}",&valids);
-					 let mut ret:Vec<T::AccountId>=Vec::new();
-				 for authid in valids.into_iter()
-				 {
-				   let mp=auth_map.iter().find(|(_,value)| **value==authid);
-				   if let Some(accid)=mp
-				   {
-				  ret.push(accid.0.clone());
-				   }
-				   else
-                  {
-                    //this is ERROR XD
-                    } 
-
-				 }
-				 return Some(ret);
+			BadgerPreRuntime::ValidatorsChanged(valids) =>
+			{
+				let mut ret: Vec<T::AccountId> = Vec::new();
+				for authid in valids.iter()
+				{
+					match auth_map.iter().find(|(_, value)| *value == authid)
+					{
+						Some((accid, _)) => ret.push(accid.clone()),
+						None => debug::warn!("Validator {:?} has no bound account", authid),
+					}
 				}
+				Self::deposit_event(RawEvent::SetChangeScheduled(valids));
+				return Some(ret);
+			}
+			BadgerPreRuntime::BatchContinues => {}
 		}
 	}
 
@@ -511,15 +825,12 @@ where
     Self::initialize_authorities(&authorities, &auth_map);
   }
 
-  fn on_new_session<'a, I: 'a>(changed: bool, _validators: I, queued_validators: I)
+  fn on_new_session<'a, I: 'a>(_changed: bool, _validators: I, queued_validators: I)
   where
     I: Iterator<Item = (&'a T::AccountId, AuthorityId)>,
   {
-	let auth_map: BTreeMap<T::AccountId, AuthorityId> =
-	storage::unhashed::get_or_default::<BTreeMap<T::AccountId, AuthorityId>>(HBBFT_AUTHORITIES_MAP_KEY).into();
+	let auth_map = Self::auth_map();
 
-	#[cfg(feature = "std")]
-	print!("EA: New session : {:?}",&changed);
     // Always issue a change if `session` says that the validators have changed.
     // Even if their session keys are the same as before, the underyling economic
     // identities have changed.
@@ -534,12 +845,19 @@ match auth_map.get(v)
 }
 	}).collect::<Vec<_>>();
 
-	  #[cfg(feature = "std")]
-	  print!("EA: Next authorities: {:?}",&next_authorities);
-
 
+	  SessionStart::<T>::put(<system::Module<T>>::block_number());
+	  if Self::pending_committee().map_or(false, |pending| Self::same_committee(&pending, &next_authorities))
+	  {
+		PendingCommittee::kill();
+	  }
+	  // sessions also end periodically, the set only changes when the validators do
+	  if Self::same_committee(&Self::badger_authorities(), &next_authorities)
+	  {
+		return;
+	  }
 	  storage::unhashed::put(HBBFT_AUTHORITIES_KEY, &next_authorities);//update
-      Self::deposit_log(ConsensusLog::NotifyChangedSet(next_authorities));
+      Self::deposit_event(RawEvent::NewAuthorities(next_authorities));
       CurrentSetId::mutate(|s| {
         *s += 1;
         *s
@@ -552,6 +870,11 @@ match auth_map.get(v)
     //SetIdSession::insert(current_set_id, &session_index);
   }
 
+  fn on_before_session_ending()
+  {
+    <FaultReporters>::remove_prefix(&<session::Module<T>>::current_index());
+  }
+
   fn on_disabled(_i: usize)
   {
     //hbbft cannot be disabled
@@ -18,37 +18,97 @@
 
 #![cfg(test)]
 
-use crate::{AuthorityId, ConsensusLog, GenesisConfig, Module, Trait};
+use std::cell::RefCell;
+
+use crate::{AuthorityId, ConsensusLog, HoneyBadgerEquivocation, Module, Trait};
+use badger_primitives::{AccountBinding, AuthorityPair, SignedAccountBinding, ACCOUNT_BINDING_PURPOSE, HBBFT_ENGINE_ID};
 use codec::{Decode, Encode};
-use primitives::{Blake2Hasher, H256};
-use runtime_io;
-use sr_primitives::{
-  testing::{Header, UintAuthorityId},
-  traits::IdentityLookup,
+use frame_support::{impl_outer_dispatch, impl_outer_event, impl_outer_origin, parameter_types, weights::Weight};
+use primitives::{Pair, H256};
+use sp_runtime::{
+  impl_opaque_keys,
+  testing::{Header, TestXt},
+  traits::{BlakeTwo256, ConvertInto, IdentityLookup},
   DigestItem, Perbill,
 };
-use srml_support::{impl_outer_event, impl_outer_origin, parameter_types};
-use substrate_finality_grandpa_primitives::GRANDPA_ENGINE_ID;
+use sp_staking::{offence::ReportOffence, SessionIndex};
 
 impl_outer_origin! {
   pub enum Origin for Test {}
 }
 
-pub fn grandpa_log(log: ConsensusLog<u64>) -> DigestItem<H256>
+impl_outer_dispatch! {
+  pub enum Call for Test where origin: Origin {
+    badger::Badger,
+  }
+}
+
+mod badger
 {
-  DigestItem::Consensus(GRANDPA_ENGINE_ID, log.encode())
+  pub use crate::Event;
+}
+
+impl_outer_event! {
+  pub enum TestEvent for Test {
+    badger<T>,
+    session,
+  }
+}
+
+impl_opaque_keys! {
+  pub struct MockSessionKeys {
+    pub badger: Badger,
+  }
+}
+
+pub fn badger_log(log: ConsensusLog) -> DigestItem<H256>
+{
+  DigestItem::Consensus(HBBFT_ENGINE_ID, log.encode())
+}
+
+thread_local! {
+  pub static ELECTED: RefCell<Option<Vec<u64>>> = RefCell::new(None);
+  pub static OFFENCES: RefCell<Vec<(Vec<u64>, Offence)>> = RefCell::new(vec![]);
+}
+
+/// Hands out the accounts in `ELECTED` once, like staking at the end of an era.
+pub struct TestElection;
+impl session::OnSessionEnding<u64> for TestElection
+{
+  fn on_session_ending(_ending: SessionIndex, _start: SessionIndex) -> Option<Vec<u64>>
+  {
+    ELECTED.with(|l| l.borrow_mut().take())
+  }
+}
+
+pub fn elect(accounts: Vec<u64>)
+{
+  ELECTED.with(|l| *l.borrow_mut() = Some(accounts));
+}
+
+/// An extrinsic type used for tests.
+pub type Extrinsic = TestXt<Call, ()>;
+type SubmitTransaction = system::offchain::TransactionSubmitter<(), Call, Extrinsic>;
+type IdentificationTuple = (u64, u64);
+pub type Offence = HoneyBadgerEquivocation<IdentificationTuple>;
+
+/// A mock offence report handler.
+pub struct OffenceHandler;
+impl ReportOffence<u64, IdentificationTuple, Offence> for OffenceHandler
+{
+  fn report_offence(reporters: Vec<u64>, offence: Offence)
+  {
+    OFFENCES.with(|l| l.borrow_mut().push((reporters, offence)));
+  }
 }
 
 // Workaround for https://github.com/rust-lang/rust/issues/26925 . Remove when sorted.
 #[derive(Clone, PartialEq, Eq, Debug, Decode, Encode)]
 pub struct Test;
-impl Trait for Test
-{
-  type Event = TestEvent;
-}
+
 parameter_types! {
   pub const BlockHashCount: u64 = 250;
-  pub const MaximumBlockWeight: u32 = 1024;
+  pub const MaximumBlockWeight: Weight = 1024;
   pub const MaximumBlockLength: u32 = 2 * 1024;
   pub const AvailableBlockRatio: Perbill = Perbill::one();
 }
@@ -57,50 +117,117 @@ impl system::Trait for Test
   type Origin = Origin;
   type Index = u64;
   type BlockNumber = u64;
-  type Call = ();
+  type Call = Call;
   type Hash = H256;
-  type Hashing = ::sr_primitives::traits::BlakeTwo256;
+  type Hashing = BlakeTwo256;
   type AccountId = u64;
   type Lookup = IdentityLookup<Self::AccountId>;
   type Header = Header;
-  type WeightMultiplierUpdate = ();
   type Event = TestEvent;
   type BlockHashCount = BlockHashCount;
   type MaximumBlockWeight = MaximumBlockWeight;
   type MaximumBlockLength = MaximumBlockLength;
   type AvailableBlockRatio = AvailableBlockRatio;
   type Version = ();
+  type ModuleToIndex = ();
 }
 
-mod grandpa
+parameter_types! {
+  pub const DisabledValidatorsThreshold: Perbill = Perbill::from_percent(33);
+}
+impl session::Trait for Test
 {
-  pub use crate::Event;
+  type ShouldEndSession = Badger;
+  type OnSessionEnding = Badger;
+  type SessionHandler = (Badger,);
+  type ValidatorId = u64;
+  type ValidatorIdOf = ConvertInto;
+  type Keys = MockSessionKeys;
+  type Event = TestEvent;
+  type SelectInitialValidators = ();
+  type DisabledValidatorsThreshold = DisabledValidatorsThreshold;
 }
 
-impl_outer_event! {
-  pub enum TestEvent for Test {
-    grandpa,
+impl session::historical::Trait for Test
+{
+  type FullIdentification = u64;
+  type FullIdentificationOf = ConvertInto;
+}
+
+parameter_types! {
+  pub const SessionPeriod: u64 = 10;
+  pub const MaxBindingLifetime: u64 = 200;
+}
+impl Trait for Test
+{
+  type Event = TestEvent;
+  type Call = Call;
+  type SubmitTransaction = SubmitTransaction;
+  type ReportEquivocation = OffenceHandler;
+  type ElectedValidators = TestElection;
+  type SessionPeriod = SessionPeriod;
+  type MaxBindingLifetime = MaxBindingLifetime;
+}
+
+/// The node key of the validator behind `account`
+pub fn pair(account: u64) -> AuthorityPair
+{
+  AuthorityPair::from_seed(&[account as u8; 32])
+}
+
+pub fn authority(account: u64) -> AuthorityId
+{
+  pair(account).public()
+}
+
+pub fn authorities(accounts: &[u64]) -> Vec<AuthorityId>
+{
+  accounts.iter().map(|acc| authority(*acc)).collect()
+}
+
+pub type TestBinding = AccountBinding<u64, H256, u64>;
+
+/// Binding of `account` to the node key of `signer` for this chain, valid until block 100
+pub fn binding_data(signer: u64, account: u64) -> TestBinding
+{
+  AccountBinding {
+    purpose: ACCOUNT_BINDING_PURPOSE,
+    genesis_hash: System::block_hash(0),
+    expires_at: 100,
+    self_pub_key: authority(signer),
+    bound_account: account,
   }
 }
 
-pub fn to_authorities(vec: Vec<(u64, u64)>) -> Vec<(AuthorityId, u64)>
+pub fn sign_binding(signer: u64, data: TestBinding) -> SignedAccountBinding<u64, H256, u64>
+{
+  let sig = data.using_encoded(|encoded| pair(signer).sign(encoded));
+  SignedAccountBinding { data: data, sig: sig }
+}
+
+/// Binding of `account` to the node key of `signer`, signed by it
+pub fn binding(signer: u64, account: u64) -> SignedAccountBinding<u64, H256, u64>
 {
-  vec
-    .into_iter()
-    .map(|(id, weight)| (UintAuthorityId(id).to_public_key::<AuthorityId>(), weight))
-    .collect()
+  sign_binding(signer, binding_data(signer, account))
 }
 
-pub fn new_test_ext(authorities: Vec<(u64, u64)>) -> runtime_io::TestExternalities<Blake2Hasher>
+/// Start with `validators` bound to their node keys through session genesis
+pub fn new_test_ext(validators: Vec<u64>) -> runtime_io::TestExternalities
 {
   let mut t = system::GenesisConfig::default().build_storage::<Test>().unwrap();
-  GenesisConfig {
-    authorities: to_authorities(authorities),
+  session::GenesisConfig::<Test> {
+    keys: validators
+      .iter()
+      .map(|acc| (*acc, MockSessionKeys { badger: authority(*acc) }))
+      .collect(),
   }
-  .assimilate_storage::<Test>(&mut t)
+  .assimilate_storage(&mut t)
   .unwrap();
+  ELECTED.with(|l| *l.borrow_mut() = None);
+  OFFENCES.with(|l| l.borrow_mut().clear());
   t.into()
 }
 
 pub type System = system::Module<Test>;
-pub type Grandpa = Module<Test>;
+pub type Session = session::Module<Test>;
+pub type Badger = Module<Test>;
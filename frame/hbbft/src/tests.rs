@@ -0,0 +1,444 @@
+// Copyright 2017-2020 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tests for the module.
+
+#![cfg(test)]
+
+use super::*;
+use crate::mock::*;
+use frame_support::{assert_noop, assert_ok};
+use primitives::H256;
+use session::ShouldEndSession;
+use sp_runtime::{testing::Digest, traits::{BlakeTwo256, OnInitialize}};
+
+fn validators_changed(accounts: &[u64])
+{
+  let digest = Digest {
+    logs: vec![DigestItem::PreRuntime(
+      HBBFT_ENGINE_ID,
+      BadgerPreRuntime::ValidatorsChanged(authorities(accounts)).encode(),
+    )],
+  };
+  System::initialize(&1, &Default::default(), &Default::default(), &digest);
+}
+
+fn has_log(log: ConsensusLog) -> bool
+{
+  System::digest().logs.contains(&badger_log(log))
+}
+
+fn has_event(event: Event<Test>) -> bool
+{
+  System::events().iter().any(|r| r.event == TestEvent::badger(event.clone()))
+}
+
+fn bind(account: u64)
+{
+  assert_ok!(Badger::submit_account_binding(Origin::signed(account), binding(account, account)));
+}
+
+#[test]
+fn genesis_binds_session_keys()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert_eq!(Badger::badger_authorities(), authorities(&[1, 2, 3, 4]));
+    assert_eq!(Badger::account_to_authority(&3), Some(authority(3)));
+    assert_eq!(Badger::authority_to_account(&authority(3)), Some(3));
+    assert_eq!(Badger::is_account_authority(&3), Some(authority(3)));
+  });
+}
+
+#[test]
+fn valid_binding_is_stored()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    assert_eq!(Badger::account_to_authority(&5), Some(authority(5)));
+    assert!(has_event(RawEvent::AccountBound(5, authority(5))));
+    // bound, but not a validator
+    assert_eq!(Badger::is_account_authority(&5), None);
+  });
+}
+
+#[test]
+fn forged_binding_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let mut forged = binding(6, 5);
+    forged.data.self_pub_key = authority(5);
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), forged),
+      Error::<Test>::BadBindingSignature
+    );
+
+    // a valid signature over another account can't be reused
+    let mut tampered = binding(5, 5);
+    tampered.data.bound_account = 6;
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(6), tampered),
+      Error::<Test>::BadBindingSignature
+    );
+    assert_eq!(Badger::account_to_authority(&5), None);
+  });
+}
+
+#[test]
+fn binding_for_another_account_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(6), binding(5, 5)),
+      Error::<Test>::BindingForOtherAccount
+    );
+  });
+}
+
+#[test]
+fn replayed_binding_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), binding(5, 5)),
+      Error::<Test>::BindingAlreadyUsed
+    );
+    // the node key is taken as well
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(6), binding(5, 6)),
+      Error::<Test>::AlreadyBound
+    );
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(1), binding(7, 1)),
+      Error::<Test>::AlreadyBound
+    );
+  });
+}
+
+#[test]
+fn binding_is_not_replayed_after_removal()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let signed = binding(5, 5);
+    assert_ok!(Badger::submit_account_binding(Origin::signed(5), signed.clone()));
+    assert_eq!(Badger::spent_binding(BlakeTwo256::hash_of(&signed.data)), Some(100));
+    assert_ok!(Badger::remove_account_binding(Origin::signed(5)));
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), signed),
+      Error::<Test>::BindingAlreadyUsed
+    );
+
+    // a fresh binding from the node works
+    let mut data = binding_data(5, 5);
+    data.expires_at = 101;
+    assert_ok!(Badger::submit_account_binding(Origin::signed(5), sign_binding(5, data)));
+  });
+}
+
+#[test]
+fn spent_binding_is_pruned_after_expiry()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let signed = binding(5, 5);
+    let hash = BlakeTwo256::hash_of(&signed.data);
+    assert_ok!(Badger::submit_account_binding(Origin::signed(5), signed.clone()));
+
+    Badger::on_initialize(100);
+    assert_eq!(Badger::spent_binding(hash), Some(100));
+    Badger::on_initialize(101);
+    assert_eq!(Badger::spent_binding(hash), None);
+
+    // the pruned binding is still rejected, as expired
+    System::set_block_number(101);
+    assert_ok!(Badger::remove_account_binding(Origin::signed(5)));
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), signed),
+      Error::<Test>::BindingExpired
+    );
+  });
+}
+
+#[test]
+fn binding_for_another_chain_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let mut data = binding_data(5, 5);
+    data.genesis_hash = H256::repeat_byte(1);
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), sign_binding(5, data)),
+      Error::<Test>::BindingForOtherChain
+    );
+  });
+}
+
+#[test]
+fn binding_with_wrong_purpose_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let mut data = binding_data(5, 5);
+    data.purpose = *b"otheruse";
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), sign_binding(5, data)),
+      Error::<Test>::NotABinding
+    );
+  });
+}
+
+#[test]
+fn expired_binding_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    let signed = binding(5, 5);
+    System::set_block_number(101);
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), signed),
+      Error::<Test>::BindingExpired
+    );
+  });
+}
+
+#[test]
+fn binding_living_too_long_is_rejected()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    System::set_block_number(10);
+    let mut data = binding_data(5, 5);
+    data.expires_at = 211;
+    assert_noop!(
+      Badger::submit_account_binding(Origin::signed(5), sign_binding(5, data.clone())),
+      Error::<Test>::BindingLifetimeTooLong
+    );
+
+    // the longest lifetime allowed is fine
+    data.expires_at = 210;
+    assert_ok!(Badger::submit_account_binding(Origin::signed(5), sign_binding(5, data)));
+  });
+}
+
+#[test]
+fn binding_can_be_removed()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    assert_ok!(Badger::remove_account_binding(Origin::signed(5)));
+    assert_eq!(Badger::account_to_authority(&5), None);
+    assert!(has_event(RawEvent::AccountUnbound(5, authority(5))));
+    assert_noop!(Badger::remove_account_binding(Origin::signed(5)), Error::<Test>::NotBound);
+  });
+}
+
+#[test]
+fn vote_to_add_records_vote_and_logs()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    assert_ok!(Badger::vote_to_add(Origin::signed(1), 5));
+    assert_eq!(<Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, 1), vec![1, 2, 3, 4, 5]);
+    assert!(has_log(ConsensusLog::VoteChangeSet(authority(1), authorities(&[1, 2, 3, 4, 5]))));
+    assert!(has_event(RawEvent::VoteCast(1, vec![1, 2, 3, 4, 5])));
+    // votes are kept per validator
+    assert!(<Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, 2).is_empty());
+    // the set only changes once the client completed the change
+    assert_eq!(Badger::badger_authorities(), authorities(&[1, 2, 3, 4]));
+  });
+}
+
+#[test]
+fn vote_to_add_is_validated()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert_noop!(
+      Badger::vote_to_add(Origin::signed(1), 5),
+      Error::<Test>::NotBound
+    );
+    bind(5);
+    assert_noop!(
+      Badger::vote_to_add(Origin::signed(1), 2),
+      Error::<Test>::AlreadyValidator
+    );
+    assert_noop!(Badger::vote_to_add(Origin::signed(5), 5), Error::<Test>::NotValidator);
+    assert_noop!(Badger::vote_to_add(Origin::signed(9), 5), Error::<Test>::NotValidator);
+  });
+}
+
+#[test]
+fn vote_to_remove_respects_minimal_set()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert_noop!(
+      Badger::vote_to_remove(Origin::signed(1), 4),
+      Error::<Test>::SetTooSmall
+    );
+  });
+  new_test_ext(vec![1, 2, 3, 4, 5]).execute_with(|| {
+    assert_ok!(Badger::vote_to_remove(Origin::signed(1), 5));
+    assert_eq!(<Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, 1), vec![1, 2, 3, 4]);
+    assert!(has_log(ConsensusLog::VoteChangeSet(authority(1), authorities(&[1, 2, 3, 4]))));
+  });
+}
+
+#[test]
+fn vote_to_remove_non_validator_fails()
+{
+  new_test_ext(vec![1, 2, 3, 4, 5]).execute_with(|| {
+    bind(6);
+    assert_noop!(
+      Badger::vote_to_remove(Origin::signed(1), 6),
+      Error::<Test>::NotInValidatorSet
+    );
+  });
+}
+
+#[test]
+fn vote_to_change_needs_enough_validators()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    bind(6);
+    assert_noop!(
+      Badger::vote_to_change(Origin::signed(1), vec![1, 5, 6]),
+      Error::<Test>::SetTooSmall
+    );
+    assert_ok!(Badger::vote_to_change(Origin::signed(1), vec![1, 2, 5, 6]));
+    assert_eq!(<Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, 1), vec![1, 2, 5, 6]);
+    assert!(has_log(ConsensusLog::VoteChangeSet(authority(1), authorities(&[1, 2, 5, 6]))));
+  });
+}
+
+#[test]
+fn votes_of_each_validator_are_kept()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    // 2f + 1 = 3 of 4 validators agree, the fourth one votes for something else
+    for v in 1..4
+    {
+      assert_ok!(Badger::vote_to_add(Origin::signed(v), 5));
+    }
+    bind(6);
+    assert_ok!(Badger::vote_to_add(Origin::signed(4), 6));
+    let agreeing = (1..5)
+      .filter(|v| <Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, v) == vec![1, 2, 3, 4, 5])
+      .count();
+    assert_eq!(agreeing, 3);
+    // a later vote replaces the earlier one
+    assert_ok!(Badger::vote_to_add(Origin::signed(1), 6));
+    assert_eq!(<Votes<Test>>::get(HB_DEDUP_KEY_PREFIX, 1), vec![1, 2, 3, 4, 6]);
+  });
+}
+
+#[test]
+fn session_ends_periodically()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert!(!Badger::should_end_session(1));
+    assert!(!Badger::should_end_session(SessionPeriod::get() - 1));
+    assert!(Badger::should_end_session(SessionPeriod::get()));
+  });
+}
+
+#[test]
+fn session_ends_when_validators_changed()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    validators_changed(&[1, 2, 3, 4]);
+    assert!(Badger::should_end_session(1));
+  });
+}
+
+#[test]
+fn changed_validators_are_handed_to_session()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    validators_changed(&[1, 2, 3, 4, 5]);
+    assert_eq!(Badger::on_session_ending(0, 2), Some(vec![1, 2, 3, 4, 5]));
+    assert!(has_event(RawEvent::SetChangeScheduled(authorities(&[1, 2, 3, 4, 5]))));
+  });
+}
+
+#[test]
+fn no_change_keeps_validators()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    assert_eq!(Badger::on_session_ending(0, 2), None);
+  });
+}
+
+#[test]
+fn session_rotation_applies_new_set()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    validators_changed(&[1, 2, 3, 4, 5]);
+    Session::rotate_session();
+    assert_eq!(Badger::badger_authorities(), authorities(&[1, 2, 3, 4, 5]));
+    assert_eq!(Badger::current_set_id(), 1);
+    assert_eq!(Badger::session_start(), 1);
+    assert!(has_event(RawEvent::NewAuthorities(authorities(&[1, 2, 3, 4, 5]))));
+  });
+}
+
+#[test]
+fn periodic_session_keeps_set_id()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    Session::rotate_session();
+    Session::rotate_session();
+    assert_eq!(Badger::badger_authorities(), authorities(&[1, 2, 3, 4]));
+    assert_eq!(Badger::current_set_id(), 0);
+    assert!(!has_event(RawEvent::NewAuthorities(authorities(&[1, 2, 3, 4]))));
+  });
+}
+
+#[test]
+fn election_is_announced()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    bind(5);
+    elect(vec![1, 2, 3, 5]);
+    assert_eq!(Badger::on_session_ending(0, 2), None);
+    let committee = authorities(&[1, 2, 3, 5]);
+    assert_eq!(Badger::pending_committee(), Some(committee.clone()));
+    assert!(has_log(ConsensusLog::NotifyChangedSet(committee.clone())));
+
+    // reminded until the change went through
+    System::initialize(&2, &Default::default(), &Default::default(), &Default::default());
+    assert_eq!(Badger::on_session_ending(1, 3), None);
+    assert!(has_log(ConsensusLog::NotifyChangedSet(committee.clone())));
+
+    validators_changed(&[1, 2, 3, 5]);
+    Session::rotate_session();
+    assert_eq!(Badger::badger_authorities(), committee);
+    assert_eq!(Badger::pending_committee(), None);
+  });
+}
+
+#[test]
+fn small_or_unchanged_election_is_ignored()
+{
+  new_test_ext(vec![1, 2, 3, 4]).execute_with(|| {
+    // 6 has no bound node key, leaving a committee of three
+    elect(vec![1, 2, 3, 6]);
+    Badger::on_session_ending(0, 2);
+    assert_eq!(Badger::pending_committee(), None);
+
+    elect(vec![4, 3, 2, 1]);
+    Badger::on_session_ending(1, 3);
+    assert_eq!(Badger::pending_committee(), None);
+    assert!(System::digest().logs.is_empty());
+  });
+}
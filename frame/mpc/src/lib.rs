@@ -11,29 +11,67 @@ use sp_runtime::{
 	//traits::{ Member, One, SimpleArithmetic, StaticLookup, Zero}, //IdentifyAccount 
 	RuntimeDebug,
 };
-use sp_std::{collections::btree_set::BTreeSet, prelude::*};
+use sp_std::{
+	collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+	prelude::*,
+};
 use support::{
-	debug, decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure, traits::Time, Parameter,
+	debug, decl_event, decl_module, decl_storage, dispatch::DispatchResult, ensure,
+	traits::{Currency, Get, OnUnbalanced, ReservableCurrency, Time},
+	Parameter,
 };
 use system::{
-	ensure_signed,
+	ensure_root, ensure_signed,
 	offchain::{ SubmitSignedTransaction}, //CreateTransaction
 };
 
 pub use sp_mpc::{crypto, get_storage_key, ConsensusLog, MpcRequest, OffchainStorageType, KEY_TYPE, MPC_ENGINE_ID};
 
-#[derive(Encode, Decode)]
+mod mock;
+mod tests;
+
+#[derive(Encode, Decode, RuntimeDebug, PartialEq)]
 pub enum MpcResult {
 	KeyGen { req_id: u64, pk: Vec<u8> },
 	SigGen { req_id: u64, pk_id: u64, sig: Vec<u8> },
 }
 
+/// Hash the mpc client signs: `data` passes through a big integer first, which drops its leading
+/// zero bytes
+pub fn message_hash(data: &[u8]) -> [u8; 32] {
+	let start = data.iter().position(|b| *b != 0).unwrap_or(data.len());
+	sp_io::hashing::sha2_256(&data[start..])
+}
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+type NegativeImbalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::NegativeImbalance;
+
 pub trait Trait: system::Trait {
 	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
 
 	type Call: From<Call<Self>>;
 
 	type SubmitTransaction: SubmitSignedTransaction<Self, <Self as Trait>::Call>;
+
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// Reserved from the requester until the request is answered or expires
+	type RequestDeposit: Get<BalanceOf<Self>>;
+
+	/// Blocks a request stays pending before it expires
+	type RequestTimeout: Get<Self::BlockNumber>;
+
+	/// What to do with deposits of requests that could never be answered
+	type Slashed: OnUnbalanced<NegativeImbalanceOf<Self>>;
+
+	/// The maximum length of the data to sign
+	type MaxPayloadLength: Get<usize>;
+
+	/// The maximum number of pending requests
+	type MaxPendingRequests: Get<usize>;
+
+	/// The maximum number of pending requests of a single account, so that no one takes all
+	type MaxRequestsPerAccount: Get<u32>;
 }
 
 decl_storage! {
@@ -42,13 +80,28 @@ decl_storage! {
 
 		Results get(fn result_of): map u64 => Option<MpcResult>;
 
-		Requests get(fn request_of): map u64 => Option<MpcRequest>; // TODO: request timeout?
+		Requests get(fn request_of): map u64 => Option<MpcRequest>;
+
+		/// Requester and reserved deposit of a pending request
+		Deposits: map u64 => Option<(T::AccountId, BalanceOf<T>)>;
+
+		/// Requests expiring at the end of a block
+		Deadlines: map T::BlockNumber => Vec<u64>;
+
+		/// Number of pending requests of an account
+		PendingCount get(fn pending_count): map T::AccountId => u32;
 
 		PendingReqIds: BTreeSet<u64>;
 
 		ActiveKeyIds: BTreeSet<u64>;
 
 		RetiredKeyIds: BTreeSet<u64>;
+
+		/// Threshold t of generated keys, must match the one configured in the mpc client
+		Threshold get(fn threshold) config(): u16 = 1;
+
+		/// Public keys submitted by authorities for a key or reshare request
+		KeySubmissions: map u64 => BTreeMap<T::AccountId, Vec<u8>>;
 	}
 }
 
@@ -57,51 +110,94 @@ decl_module! {
 		fn deposit_event() = default;
 
 		fn request_key(origin, req_id: u64) -> DispatchResult {
-			let _who = ensure_signed(origin)?;
-			ensure!(!<Requests>::exists(req_id), "req id exists");
-			ensure!(!<Results>::exists(req_id), "req id exists");
+			let who = ensure_signed(origin)?;
 
-			<PendingReqIds>::mutate(|ids| {
-				ids.insert(req_id);
-			});
+			Self::add_request(who, req_id, MpcRequest::KeyGen(req_id))?;
 			Self::send_keygen_log(req_id);
-			// deposit event
 			Ok(())
 		}
 
+		/// The key is recorded once t + 1 authorities submitted the same public key
 		pub fn save_key(origin, req_id: u64, data: Vec<u8>) -> DispatchResult {
-			let _who = ensure_signed(origin)?;
-			ensure!(<Requests>::exists(req_id), "req id does not exist");
+			let who = ensure_signed(origin)?;
+			ensure!(Self::is_authority(&who), "not an authority");
 			ensure!(!<Results>::exists(req_id), "req id exists");
+			let old_id = match Self::request_of(req_id) {
+				Some(MpcRequest::KeyGen(_)) => None,
+				Some(MpcRequest::Reshare(_, old_id)) => Some(old_id),
+				_ => return Err("req id does not exist".into()),
+			};
+			if let Some(MpcResult::KeyGen { pk, .. }) = old_id.and_then(|id| Self::result_of(id)) {
+				ensure!(pk == data, "reshared key differs");
+			}
+
+			let mut submissions = <KeySubmissions<T>>::get(req_id);
+			ensure!(!submissions.contains_key(&who), "already submitted");
+			if submissions.values().any(|pk| *pk != data) {
+				// either this authority or an earlier one did not follow the protocol
+				Self::deposit_event(RawEvent::KeyConflict(req_id, who.clone()));
+			}
+			submissions.insert(who, data.clone());
+
+			let matching = submissions.values().filter(|pk| **pk == data).count();
+			if matching <= Self::threshold() as usize {
+				<KeySubmissions<T>>::insert(req_id, submissions);
+				return Ok(());
+			}
+
+			// remove req
+			<KeySubmissions<T>>::remove(req_id);
+			Self::remove_request(req_id);
+
+			// save res
+			<Results>::insert(req_id, MpcResult::KeyGen { req_id, pk: data });
+			<ActiveKeyIds>::mutate(|ids| {
+				ids.insert(req_id);
+			});
+			match old_id {
+				Some(old_id) => Self::retire_key(old_id, req_id),
+				None => Self::deposit_event(RawEvent::KeyGenerated(req_id)),
+			}
+			Ok(())
+		}
+
+		/// Only authorities may move a key to the current peers
+		fn reshare_key(origin, req_id: u64, pk_id: u64) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(Self::is_authority(&who), "not an authority");
+			ensure!(ActiveKeyIds::get().contains(&pk_id), "key is not active");
+
+			Self::add_request(who, req_id, MpcRequest::Reshare(req_id, pk_id))?;
+			Self::send_reshare_log(req_id, pk_id);
 			Ok(())
 		}
 
 		fn request_sig(origin, req_id: u64, pk_id: u64, data: Vec<u8>) -> DispatchResult {
 			let who = ensure_signed(origin)?;
-			ensure!(!<Requests>::exists(req_id), "req id exists");
-			ensure!(!<Results>::exists(req_id), "req id exists");
+			ensure!(data.len() <= T::MaxPayloadLength::get(), "data too long");
+			ensure!(ActiveKeyIds::get().contains(&pk_id), "key is not active");
 
-			<PendingReqIds>::mutate(|ids| {
-				ids.insert(req_id);
-			});
-			<Requests>::insert(req_id, MpcRequest::SigGen(req_id, pk_id, data.clone()));
+			Self::add_request(who, req_id, MpcRequest::SigGen(req_id, pk_id, data.clone()))?;
 			Self::send_siggen_log(req_id, pk_id, data);
-			Self::deposit_event(RawEvent::MpcRequest(
-				req_id, who
-			));
 			Ok(())
 		}
 
+		/// The signature is recorded only if it verifies against the requested key
 		pub fn save_sig(origin, req_id: u64, pk_id: u64, sig: Vec<u8>) -> DispatchResult {
-			let who = ensure_signed(origin)?; // more restriction?
-			ensure!(<Requests>::exists(req_id), "req id does not exist");
+			let who = ensure_signed(origin)?;
+			ensure!(Self::is_authority(&who), "not an authority");
+			let data = match Self::request_of(req_id) {
+				Some(MpcRequest::SigGen(_, id, data)) => {
+					ensure!(id == pk_id, "wrong key id");
+					data
+				}
+				_ => return Err("req id does not exist".into()),
+			};
 			ensure!(!<Results>::exists(req_id), "req id exists");
+			ensure!(Self::verify_sig(pk_id, &data, &sig), "bad signature");
 
 			// remove req
-			<PendingReqIds>::mutate(|ids| {
-				ids.remove(&req_id);
-			});
-			<Requests>::remove(req_id);
+			Self::remove_request(req_id);
 
 			// save res
 			<Results>::insert(req_id, MpcResult::SigGen { req_id, pk_id, sig });
@@ -112,15 +208,32 @@ decl_module! {
 			Ok(())
 		}
 
+		fn on_finalize(now: T::BlockNumber) {
+			for req_id in <Deadlines<T>>::take(now) {
+				Self::expire_request(req_id);
+			}
+		}
+
 		fn offchain_worker(_now: T::BlockNumber) {
 			debug::RuntimeLogger::init();
 			let req_ids = PendingReqIds::get();
 			for id in req_ids {
+				let pk_id = match Self::request_of(id) {
+					Some(MpcRequest::SigGen(_, pk_id, _)) => pk_id,
+					Some(MpcRequest::KeyGen(_)) | Some(MpcRequest::Reshare(_, _)) => {
+						let key = get_storage_key(id, OffchainStorageType::SharedPublicKey);
+						if let Some(pk) = local_storage_get(StorageKind::PERSISTENT, &key) {
+							Self::call_save_key(id, pk);
+						}
+						continue;
+					}
+					None => continue,
+				};
 				let key = get_storage_key(id, OffchainStorageType::Signature);
 				debug::warn!("key {:?}", key);
 				if let Some(value) = local_storage_get(StorageKind::PERSISTENT, &key) {
 					// StorageKind::LOCAL ?
-					Self::call_save_sig(id, 0, value);
+					Self::call_save_sig(id, pk_id, value);
 					debug::warn!("insert ok");
 				} else {
 					debug::warn!("nothing");
@@ -128,8 +241,9 @@ decl_module! {
 			}
 		}
 
+		/// Authorities decide on generated keys, so only root may add them
 		pub fn add_authority(origin, who: T::AccountId) -> DispatchResult {
-			let _me = ensure_signed(origin)?; // ensure root?
+			ensure_root(origin)?;
 
 			if !Self::is_authority(&who){
 				<Authorities<T>>::mutate(|l| l.insert(who));
@@ -144,39 +258,167 @@ decl_event!(
 	pub enum Event<T>
 	where
 		AccountId = <T as system::Trait>::AccountId,
+		Balance = BalanceOf<T>,
 	{
 		// id, requester
 		MpcRequest(u64, AccountId),
 		// id, responser
 		MpcResponse(u64, AccountId),
+		// id
+		KeyGenerated(u64),
+		// id, authority whose public key differs from an earlier submission
+		KeyConflict(u64, AccountId),
+		// retired key id, new key id
+		KeyReshared(u64, u64),
+		// id, requester, refunded deposit
+		RequestExpired(u64, AccountId, Balance),
+		// id, requester, slashed deposit
+		RequestSlashed(u64, AccountId, Balance),
 	}
 );
 
 impl<T: Trait> Module<T> {
-	fn submit_signed(call: Call<T>) {
-		let res = T::SubmitTransaction::submit_signed(call);
+	fn submit_signed_from(call: Call<T>, accounts: Vec<T::AccountId>) {
+		let res = T::SubmitTransaction::submit_signed_from(call, accounts);
 
-		if res.is_empty() {
-			debug::error!("No local accounts found.");
-		} else {
+		if !res.is_empty() {
 			debug::info!("Sent transactions from: {:?}", res);
 		}
 	}
 
+	/// Submit the signature from the local authorities, others may not save it
 	fn call_save_sig(req_id: u64, pk_id: u64, sig: Vec<u8>) {
 		let call = Call::save_sig(req_id, pk_id, sig);
-		Self::submit_signed(call);
+		Self::submit_signed_from(call, Self::authorities().into_iter().collect());
 	}
 
+	/// Submit the key from every local authority that has not submitted it yet
 	fn call_save_key(req_id: u64, pk: Vec<u8>) {
+		let submissions = <KeySubmissions<T>>::get(req_id);
+		let accounts = Self::authorities()
+			.into_iter()
+			.filter(|who| !submissions.contains_key(who))
+			.collect::<Vec<_>>();
+		if accounts.is_empty() {
+			return;
+		}
 		let call = Call::save_key(req_id, pk);
-		Self::submit_signed(call);
+		Self::submit_signed_from(call, accounts);
 	}
 
 	fn is_authority(who: &T::AccountId) -> bool {
 		Self::authorities().contains(who)
 	}
 
+	/// Whether `sig`, the 64 byte `r` and `s` output by the mpc client, signs `data` with key `pk_id`
+	fn verify_sig(pk_id: u64, data: &[u8], sig: &[u8]) -> bool {
+		let pk = match Self::result_of(pk_id) {
+			Some(MpcResult::KeyGen { pk, .. }) => pk,
+			_ => return false,
+		};
+		if sig.len() != 64 {
+			return false;
+		}
+		let msg = message_hash(data);
+		let mut rsv = [0u8; 65];
+		rsv[..64].copy_from_slice(sig);
+		// the recovery id is not part of the signature, try both
+		(0..2).any(|v| {
+			rsv[64] = v;
+			sp_io::crypto::secp256k1_ecdsa_recover_compressed(&rsv, &msg)
+				.map(|recovered| recovered[..] == pk[..])
+				.unwrap_or(false)
+		})
+	}
+
+	/// Reserve the deposit of a new request and track it until it is answered or expires
+	fn add_request(who: T::AccountId, req_id: u64, request: MpcRequest) -> DispatchResult {
+		ensure!(!<Requests>::exists(req_id), "req id exists");
+		ensure!(!<Results>::exists(req_id), "req id exists");
+		ensure!(PendingReqIds::get().len() < T::MaxPendingRequests::get(), "too many pending requests");
+		ensure!(
+			Self::pending_count(&who) < T::MaxRequestsPerAccount::get(),
+			"too many pending requests of account"
+		);
+
+		let deposit = T::RequestDeposit::get();
+		T::Currency::reserve(&who, deposit)?;
+		let deadline = <system::Module<T>>::block_number() + T::RequestTimeout::get();
+
+		<PendingReqIds>::mutate(|ids| {
+			ids.insert(req_id);
+		});
+		<Requests>::insert(req_id, request);
+		<Deposits<T>>::insert(req_id, (who.clone(), deposit));
+		<PendingCount<T>>::mutate(&who, |count| *count += 1);
+		<Deadlines<T>>::mutate(deadline, |ids| ids.push(req_id));
+		Self::deposit_event(RawEvent::MpcRequest(
+			req_id, who
+		));
+		Ok(())
+	}
+
+	/// Drop an answered request and refund its deposit
+	fn remove_request(req_id: u64) {
+		<PendingReqIds>::mutate(|ids| {
+			ids.remove(&req_id);
+		});
+		<Requests>::remove(req_id);
+		if let Some((who, deposit)) = <Deposits<T>>::take(req_id) {
+			Self::release_slot(&who);
+			T::Currency::unreserve(&who, deposit);
+		}
+	}
+
+	fn release_slot(who: &T::AccountId) {
+		<PendingCount<T>>::mutate(who, |count| *count = count.saturating_sub(1));
+	}
+
+	/// Drop a request nobody answered in time.
+	///
+	/// The deposit is refunded unless the request could never be answered, i.e. a signature
+	/// with a key that is not active.
+	fn expire_request(req_id: u64) {
+		let request = match <Requests>::take(req_id) {
+			Some(request) => request,
+			// answered in time
+			None => return,
+		};
+		<PendingReqIds>::mutate(|ids| {
+			ids.remove(&req_id);
+		});
+		<KeySubmissions<T>>::remove(req_id);
+
+		let (who, deposit) = match <Deposits<T>>::take(req_id) {
+			Some(deposit) => deposit,
+			None => return,
+		};
+		Self::release_slot(&who);
+		let unanswerable = match request {
+			MpcRequest::SigGen(_, pk_id, _) => !ActiveKeyIds::get().contains(&pk_id),
+			_ => false,
+		};
+		if unanswerable {
+			let (imbalance, _) = T::Currency::slash_reserved(&who, deposit);
+			T::Slashed::on_unbalanced(imbalance);
+			Self::deposit_event(RawEvent::RequestSlashed(req_id, who, deposit));
+		} else {
+			T::Currency::unreserve(&who, deposit);
+			Self::deposit_event(RawEvent::RequestExpired(req_id, who, deposit));
+		}
+	}
+
+	/// The new id holds the same key, the old shares are no longer used
+	fn retire_key(old_id: u64, new_id: u64) {
+		<ActiveKeyIds>::mutate(|ids| {
+			ids.remove(&old_id);
+		});
+		<RetiredKeyIds>::mutate(|ids| {
+			ids.insert(old_id);
+		});
+		Self::deposit_event(RawEvent::KeyReshared(old_id, new_id));
+	}
+
 	fn send_keygen_log(id: u64) {
 		Self::deposit_log(ConsensusLog::RequestForKey(id));
 	}
@@ -185,6 +427,10 @@ impl<T: Trait> Module<T> {
 		Self::deposit_log(ConsensusLog::RequestForSig(req_id, pk_id, data));
 	}
 
+	fn send_reshare_log(req_id: u64, pk_id: u64) {
+		Self::deposit_log(ConsensusLog::RequestForReshare(req_id, pk_id));
+	}
+
 	fn deposit_log(log: ConsensusLog) {
 		let log: DigestItem<T::Hash> = DigestItem::Consensus(MPC_ENGINE_ID, log.encode());
 		<system::Module<T>>::deposit_log(log.into());
@@ -0,0 +1,179 @@
+//! Test utilities
+
+#![cfg(test)]
+
+use crate::{GenesisConfig, Module, Trait};
+use codec::Encode;
+use sp_core::H256;
+use sp_runtime::{
+	testing::{Header, TestXt},
+	traits::{BlakeTwo256, IdentifyAccount, IdentityLookup},
+	Perbill,
+};
+use support::{impl_outer_dispatch, impl_outer_event, impl_outer_origin, parameter_types, weights::Weight};
+use system::offchain::{CreateTransaction, SignAndSubmitTransaction, Signer, SubmitSignedTransaction};
+
+impl_outer_origin! {
+	pub enum Origin for Test {}
+}
+
+impl_outer_dispatch! {
+	pub enum Call for Test where origin: Origin {
+		mpc::Mpc,
+	}
+}
+
+mod mpc {
+	pub use crate::Event;
+}
+
+impl_outer_event! {
+	pub enum TestEvent for Test {
+		mpc<T>,
+		balances<T>,
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Test;
+
+parameter_types! {
+	pub const BlockHashCount: u64 = 250;
+	pub const MaximumBlockWeight: Weight = 1024;
+	pub const MaximumBlockLength: u32 = 2 * 1024;
+	pub const AvailableBlockRatio: Perbill = Perbill::one();
+}
+
+impl system::Trait for Test {
+	type Origin = Origin;
+	type Index = u64;
+	type BlockNumber = u64;
+	type Call = Call;
+	type Hash = H256;
+	type Hashing = BlakeTwo256;
+	type AccountId = u64;
+	type Lookup = IdentityLookup<Self::AccountId>;
+	type Header = Header;
+	type Event = TestEvent;
+	type BlockHashCount = BlockHashCount;
+	type MaximumBlockWeight = MaximumBlockWeight;
+	type MaximumBlockLength = MaximumBlockLength;
+	type AvailableBlockRatio = AvailableBlockRatio;
+	type Version = ();
+	type ModuleToIndex = ();
+}
+
+parameter_types! {
+	pub const ExistentialDeposit: u64 = 0;
+	pub const TransferFee: u64 = 0;
+	pub const CreationFee: u64 = 0;
+}
+
+impl balances::Trait for Test {
+	type Balance = u64;
+	type OnFreeBalanceZero = ();
+	type OnNewAccount = ();
+	type Event = TestEvent;
+	type TransferPayment = ();
+	type DustRemoval = ();
+	type ExistentialDeposit = ExistentialDeposit;
+	type TransferFee = TransferFee;
+	type CreationFee = CreationFee;
+}
+
+pub type Extrinsic = TestXt<Call, ()>;
+
+/// Key of a local account, the tests have none so nothing is ever submitted offchain
+#[derive(Clone)]
+pub struct TestAccount(u64);
+
+impl IdentifyAccount for TestAccount {
+	type AccountId = u64;
+
+	fn into_account(self) -> u64 {
+		self.0
+	}
+}
+
+impl Signer<TestAccount, ()> for TestAccount {
+	fn sign<Payload: Encode>(_public: TestAccount, _payload: &Payload) -> Option<()> {
+		None
+	}
+}
+
+impl CreateTransaction<Test, Extrinsic> for Test {
+	type Public = TestAccount;
+	type Signature = ();
+
+	fn create_transaction<F: Signer<TestAccount, ()>>(
+		call: Call,
+		_public: TestAccount,
+		account: u64,
+		_nonce: u64,
+	) -> Option<(Call, (u64, ()))> {
+		Some((call, (account, ())))
+	}
+}
+
+pub struct SubmitTransaction;
+
+impl SignAndSubmitTransaction<Test, Call> for SubmitTransaction {
+	type Extrinsic = Extrinsic;
+	type CreateTransaction = Test;
+	type Signer = TestAccount;
+}
+
+impl SubmitSignedTransaction<Test, Call> for SubmitTransaction {
+	type SignAndSubmit = Self;
+
+	fn find_local_keys(_accounts: Option<impl IntoIterator<Item = u64>>) -> Vec<(u64, TestAccount)> {
+		Vec::new()
+	}
+}
+
+parameter_types! {
+	pub const RequestDeposit: u64 = 10;
+	pub const RequestTimeout: u64 = 5;
+	pub const MaxPayloadLength: usize = 32;
+	pub const MaxPendingRequests: usize = 4;
+	pub const MaxRequestsPerAccount: u32 = 2;
+}
+
+impl Trait for Test {
+	type Event = TestEvent;
+	type Call = Call;
+	type SubmitTransaction = SubmitTransaction;
+	type Currency = Balances;
+	type RequestDeposit = RequestDeposit;
+	type RequestTimeout = RequestTimeout;
+	type Slashed = ();
+	type MaxPayloadLength = MaxPayloadLength;
+	type MaxPendingRequests = MaxPendingRequests;
+	type MaxRequestsPerAccount = MaxRequestsPerAccount;
+}
+
+/// Accounts 1 to 9 hold 100 each, `authorities` are added by root
+pub fn new_test_ext(authorities: Vec<u64>) -> sp_io::TestExternalities {
+	let mut t = system::GenesisConfig::default().build_storage::<Test>().unwrap();
+	balances::GenesisConfig::<Test> {
+		balances: (1..10).map(|who| (who, 100)).collect(),
+		vesting: vec![],
+	}
+	.assimilate_storage(&mut t)
+	.unwrap();
+	GenesisConfig { threshold: 1 }.assimilate_storage::<Test>(&mut t).unwrap();
+
+	let mut ext: sp_io::TestExternalities = t.into();
+	ext.execute_with(|| {
+		// no events are kept at genesis
+		System::set_block_number(1);
+		for who in authorities {
+			Mpc::add_authority(Origin::ROOT, who).unwrap();
+		}
+	});
+	ext
+}
+
+pub type System = system::Module<Test>;
+pub type Balances = balances::Module<Test>;
+pub type Mpc = Module<Test>;
@@ -0,0 +1,231 @@
+//! Tests for the module.
+
+#![cfg(test)]
+
+use super::*;
+use crate::mock::*;
+use sp_runtime::traits::{BadOrigin, OnFinalize};
+use support::{assert_noop, assert_ok};
+
+fn has_event(event: Event<Test>) -> bool {
+	System::events().iter().any(|r| r.event == TestEvent::mpc(event.clone()))
+}
+
+fn save_key(who: u64, req_id: u64, pk: &[u8]) -> DispatchResult {
+	Mpc::save_key(Origin::signed(who), req_id, pk.to_vec())
+}
+
+fn request_sig(who: u64, req_id: u64, pk_id: u64) -> DispatchResult {
+	Mpc::request_sig(Origin::signed(who), req_id, pk_id, b"data".to_vec())
+}
+
+fn save_sig(who: u64, req_id: u64, pk_id: u64, sig: Vec<u8>) -> DispatchResult {
+	Mpc::save_sig(Origin::signed(who), req_id, pk_id, sig)
+}
+
+fn secret_key() -> secp256k1::SecretKey {
+	secp256k1::SecretKey::parse(&[7u8; 32]).unwrap()
+}
+
+/// Compressed public key, as the mpc client submits it
+fn public_key() -> Vec<u8> {
+	secp256k1::PublicKey::from_secret_key(&secret_key()).serialize_compressed().to_vec()
+}
+
+/// `r` and `s` of a signature of `data`, as the mpc client submits them
+fn sign(data: &[u8]) -> Vec<u8> {
+	let (sig, _) = secp256k1::sign(&secp256k1::Message::parse(&message_hash(data)), &secret_key());
+	sig.serialize().to_vec()
+}
+
+/// Key `req_id`, requested by account 9 and saved by authorities 1 and 2
+fn generate_key(req_id: u64) {
+	assert_ok!(Mpc::request_key(Origin::signed(9), req_id));
+	assert_ok!(save_key(1, req_id, &public_key()));
+	assert_ok!(save_key(2, req_id, &public_key()));
+}
+
+#[test]
+fn only_root_adds_authorities() {
+	new_test_ext(vec![]).execute_with(|| {
+		assert_noop!(Mpc::add_authority(Origin::signed(1), 1), BadOrigin);
+		assert_ok!(Mpc::add_authority(Origin::ROOT, 1));
+		assert!(Mpc::authorities().contains(&1));
+	});
+}
+
+#[test]
+fn key_is_saved_once_threshold_plus_one_match() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		assert_ok!(Mpc::request_key(Origin::signed(9), 7));
+		assert_eq!(Balances::reserved_balance(&9), 10);
+
+		assert_ok!(save_key(1, 7, b"pk"));
+		assert_eq!(Mpc::result_of(7), None);
+		assert_ok!(save_key(2, 7, b"pk"));
+		assert_eq!(Mpc::result_of(7), Some(MpcResult::KeyGen { req_id: 7, pk: b"pk".to_vec() }));
+		assert!(has_event(RawEvent::KeyGenerated(7)));
+		assert_eq!(Balances::reserved_balance(&9), 0);
+
+		// the request is answered, late submissions are rejected
+		assert_noop!(save_key(3, 7, b"pk"), "req id exists");
+	});
+}
+
+#[test]
+fn conflicting_key_is_flagged() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		assert_ok!(Mpc::request_key(Origin::signed(9), 7));
+
+		assert_ok!(save_key(1, 7, b"pk"));
+		assert_ok!(save_key(2, 7, b"other"));
+		assert!(has_event(RawEvent::KeyConflict(7, 2)));
+		assert_eq!(Mpc::result_of(7), None);
+
+		// the honest majority still decides
+		assert_ok!(save_key(3, 7, b"pk"));
+		assert_eq!(Mpc::result_of(7), Some(MpcResult::KeyGen { req_id: 7, pk: b"pk".to_vec() }));
+	});
+}
+
+#[test]
+fn duplicate_key_submission_is_rejected() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		assert_ok!(Mpc::request_key(Origin::signed(9), 7));
+
+		assert_ok!(save_key(1, 7, b"pk"));
+		assert_noop!(save_key(1, 7, b"pk"), "already submitted");
+		assert_noop!(save_key(1, 7, b"other"), "already submitted");
+		assert_eq!(Mpc::result_of(7), None);
+	});
+}
+
+#[test]
+fn only_authorities_submit_keys_and_reshare() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		assert_ok!(Mpc::request_key(Origin::signed(9), 7));
+		assert_noop!(save_key(4, 7, b"pk"), "not an authority");
+
+		assert_ok!(save_key(1, 7, b"pk"));
+		assert_ok!(save_key(2, 7, b"pk"));
+		assert_noop!(Mpc::reshare_key(Origin::signed(9), 8, 7), "not an authority");
+		assert_ok!(Mpc::reshare_key(Origin::signed(1), 8, 7));
+	});
+}
+
+#[test]
+fn signature_with_inactive_key_is_rejected() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		assert_noop!(request_sig(5, 1, 7), "key is not active");
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+	});
+}
+
+#[test]
+fn only_authorities_save_signatures() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+
+		assert_noop!(save_sig(4, 1, 7, sign(b"data")), "not an authority");
+		assert_ok!(save_sig(1, 1, 7, sign(b"data")));
+		assert_eq!(Mpc::result_of(1), Some(MpcResult::SigGen { req_id: 1, pk_id: 7, sig: sign(b"data") }));
+		assert!(has_event(RawEvent::MpcResponse(1, 1)));
+	});
+}
+
+#[test]
+fn signature_not_verifying_against_the_key_is_rejected() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+
+		assert_noop!(save_sig(1, 1, 7, sign(b"other")), "bad signature");
+		assert_noop!(save_sig(1, 1, 7, b"sig".to_vec()), "bad signature");
+		assert_noop!(save_sig(1, 1, 8, sign(b"data")), "wrong key id");
+		assert_eq!(Mpc::result_of(1), None);
+
+		assert_ok!(save_sig(2, 1, 7, sign(b"data")));
+	});
+}
+
+#[test]
+fn message_hash_ignores_leading_zero_bytes() {
+	assert_eq!(message_hash(b"\0\0data"), message_hash(b"data"));
+	assert_ne!(message_hash(b"data\0"), message_hash(b"data"));
+}
+
+#[test]
+fn deposit_is_refunded_once_answered() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+		assert_eq!(Balances::free_balance(&5), 90);
+		assert_eq!(Balances::reserved_balance(&5), 10);
+		assert_eq!(Mpc::pending_count(&5), 1);
+
+		assert_ok!(save_sig(1, 1, 7, sign(b"data")));
+		assert_eq!(Balances::free_balance(&5), 100);
+		assert_eq!(Balances::reserved_balance(&5), 0);
+		assert_eq!(Mpc::pending_count(&5), 0);
+	});
+}
+
+#[test]
+fn pending_requests_are_capped() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+		assert_ok!(request_sig(5, 2, 7));
+		assert_noop!(request_sig(5, 3, 7), "too many pending requests of account");
+
+		assert_ok!(request_sig(6, 3, 7));
+		assert_ok!(request_sig(6, 4, 7));
+		assert_noop!(request_sig(8, 5, 7), "too many pending requests");
+
+		// answering a request frees its slot
+		assert_ok!(save_sig(1, 1, 7, sign(b"data")));
+		assert_ok!(request_sig(5, 5, 7));
+	});
+}
+
+#[test]
+fn expired_request_is_refunded_and_frees_its_slot() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+
+		Mpc::on_finalize(5);
+		assert!(Mpc::request_of(1).is_some());
+		Mpc::on_finalize(6);
+		assert!(Mpc::request_of(1).is_none());
+		assert!(has_event(RawEvent::RequestExpired(1, 5, 10)));
+		assert_eq!(Balances::free_balance(&5), 100);
+		assert_eq!(Mpc::pending_count(&5), 0);
+		assert_noop!(
+			save_sig(1, 1, 7, sign(b"data")),
+			"req id does not exist"
+		);
+	});
+}
+
+#[test]
+fn request_for_retired_key_is_slashed_on_expiry() {
+	new_test_ext(vec![1, 2, 3]).execute_with(|| {
+		generate_key(7);
+		assert_ok!(request_sig(5, 1, 7));
+
+		// the key moves to id 8, nobody signs with 7 any more
+		assert_ok!(Mpc::reshare_key(Origin::signed(1), 8, 7));
+		assert_ok!(save_key(1, 8, &public_key()));
+		assert_ok!(save_key(2, 8, &public_key()));
+		assert!(has_event(RawEvent::KeyReshared(7, 8)));
+
+		Mpc::on_finalize(6);
+		assert!(has_event(RawEvent::RequestSlashed(1, 5, 10)));
+		assert_eq!(Balances::free_balance(&5), 90);
+		assert_eq!(Balances::reserved_balance(&5), 0);
+		assert_eq!(Mpc::pending_count(&5), 0);
+	});
+}
@@ -9,6 +9,7 @@ extern crate alloc;
 
 use sp_runtime::{ConsensusEngineId, };
 use sp_runtime::{RuntimeDebug, };
+use sp_runtime::traits::Header as HeaderT;
 
 pub mod app {
 	use app_crypto::{app_crypto, hbbft_thresh, key_types::HB_NODE};
@@ -92,7 +93,10 @@ pub enum ConsensusLog {
 pub enum BadgerPreRuntime
 {
 	#[codec(index = "1")]
-    ValidatorsChanged(Vec<AuthorityId>)
+    ValidatorsChanged(Vec<AuthorityId>),
+	/// The batch of this block continues in the next block, which finalizes both
+	#[codec(index = "2")]
+	BatchContinues,
 }
 
 impl ConsensusLog {
@@ -122,19 +126,33 @@ impl ConsensusLog {
 }
 
 
+/// Tag at the start of every account binding, so a node key signature over one can't be
+/// passed off as anything else
+pub const ACCOUNT_BINDING_PURPOSE: [u8; 8] = *b"hbbdbind";
+
 #[derive(Decode, Encode, PartialEq, Eq, Clone,RuntimeDebug)]
-pub struct AccountBinding<AccountId>
+pub struct AccountBinding<AccountId, Hash, BlockNumber>
 where AccountId:Encode+Decode+core::fmt::Debug,
+	Hash:Encode+Decode+core::fmt::Debug,
+	BlockNumber:Encode+Decode+core::fmt::Debug,
 {
+  /// Always `ACCOUNT_BINDING_PURPOSE`
+  pub purpose:[u8; 8],
+  /// Genesis hash of the chain the binding is made for
+  pub genesis_hash:Hash,
+  /// Last block the binding may be submitted at
+  pub expires_at:BlockNumber,
   pub self_pub_key:AuthorityId,
   pub bound_account:AccountId,
 }
 
 #[derive(Decode, Encode, PartialEq, Eq, Clone,RuntimeDebug)]
-pub struct  SignedAccountBinding<AccountId>
-where AccountId:Encode+Decode+core::fmt::Debug
+pub struct  SignedAccountBinding<AccountId, Hash, BlockNumber>
+where AccountId:Encode+Decode+core::fmt::Debug,
+	Hash:Encode+Decode+core::fmt::Debug,
+	BlockNumber:Encode+Decode+core::fmt::Debug,
 {
-	pub data:AccountBinding<AccountId>,
+	pub data:AccountBinding<AccountId, Hash, BlockNumber>,
 	pub sig:AuthoritySignature,
 }
 
@@ -142,6 +160,81 @@ where AccountId:Encode+Decode+core::fmt::Debug
 
 
 
+/// Offchain storage key under which the client leaves fault reports for the runtime to submit
+pub const HBBFT_FAULT_REPORTS_KEY: &'static [u8] = b"hbbft::fault_reports";
+
+/// A validator signing two different blocks of the same height.
+#[derive(Decode, Encode, PartialEq, Eq, Clone, RuntimeDebug)]
+pub struct EquivocationProof<H>
+{
+	pub offender: AuthorityId,
+	pub first: (H, AuthoritySignature),
+	pub second: (H, AuthoritySignature),
+}
+
+impl<H: HeaderT> EquivocationProof<H>
+{
+	/// The height both blocks were signed at
+	pub fn number(&self) -> H::Number
+	{
+		*self.first.0.number()
+	}
+
+	/// Check that both headers are of the same height, differ, and are signed by the offender.
+	pub fn check(&self) -> bool
+	{
+		if self.first.0.number() != self.second.0.number()
+		{
+			return false;
+		}
+		let (first, second) = (self.first.0.hash(), self.second.0.hash());
+		if first == second
+		{
+			return false;
+		}
+		use app_crypto::RuntimeAppPublic;
+		self.offender.verify(&first.encode(), &self.first.1) && self.offender.verify(&second.encode(), &self.second.1)
+	}
+}
+
+/// Misbehaviour a validator observed locally, such as an invalid Badger message.
+///
+/// Unlike an equivocation this cannot be checked by others, so it only counts once more
+/// validators report the same offender than may be faulty.
+#[derive(Decode, Encode, PartialEq, Eq, Clone, RuntimeDebug)]
+pub struct FaultReport
+{
+	pub offender: AuthorityId,
+	pub reporter: AuthorityId,
+	/// Block number at which the fault was observed
+	pub at: u64,
+}
+
+#[derive(Decode, Encode, PartialEq, Eq, Clone, RuntimeDebug)]
+pub struct SignedFaultReport
+{
+	pub report: FaultReport,
+	pub sig: AuthoritySignature,
+}
+
+impl SignedFaultReport
+{
+	pub fn verify(&self) -> bool
+	{
+		use app_crypto::RuntimeAppPublic;
+		self.report.reporter.verify(&self.report.encode(), &self.sig)
+	}
+}
+
+#[derive(Decode, Encode, PartialEq, Eq, Clone, RuntimeDebug)]
+pub enum BadgerFaultReport<H>
+{
+	#[codec(index = "1")]
+	Equivocation(EquivocationProof<H>),
+	#[codec(index = "2")]
+	Fault(SignedFaultReport),
+}
+
 decl_runtime_apis! {
 	#[api_version(2)]
 	pub trait BadgerApi {
@@ -21,6 +21,8 @@ pub enum ConsensusLog {
 	RequestForSig(u64, u64, Vec<u8>),
 	#[codec(index = "2")]
 	RequestForKey(u64),
+	#[codec(index = "3")]
+	RequestForReshare(u64, u64), // id, key id to reshare
 }
 
 pub type RequestId = u64;
@@ -33,6 +35,7 @@ pub type AuthorityId = crypto::Public;
 pub enum MpcRequest {
 	KeyGen(RequestId),
 	SigGen(RequestId, GeneratedKeyId, Vec<u8>), // id, generated key id, data
+	Reshare(RequestId, GeneratedKeyId), // id, key id to move to the current authorities
 }
 
 pub enum OffchainStorageType {
@@ -47,6 +50,9 @@ Keygen:
 
 Siggen:
 	get local key, run sig gen, save sig
+
+Reshare:
+	get local key, deal its share to the new committee, save the new local key under the new id
 */
 
 pub fn get_storage_key(id: u64, ost: OffchainStorageType) -> Vec<u8> {
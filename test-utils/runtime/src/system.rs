@@ -275,9 +275,12 @@ fn execute_transfer_backend(tx: &Transfer) -> ApplyExtrinsicResult {
 	// check nonce
 	let nonce_key = tx.from.to_keyed_vec(NONCE_OF);
 	let expected_nonce: u64 = storage::hashed::get_or(&blake2_256, &nonce_key, 0);
-	if !(tx.nonce == expected_nonce) {
+	if tx.nonce < expected_nonce {
 		return Err(InvalidTransaction::Stale.into());
 	}
+	if tx.nonce > expected_nonce {
+		return Err(InvalidTransaction::Future.into());
+	}
 
 	// increment nonce in storage
 	storage::hashed::put(&blake2_256, &nonce_key, &(expected_nonce + 1));